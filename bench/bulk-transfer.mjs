@@ -0,0 +1,48 @@
+// One large echoed stream over loopback. The read loop drains several chunks per wakeup, this
+// tracks how many chunks and runtime wakeups a MiB takes. `MIB` and `WRITE_KIB` scale the run
+import {
+  closeServer,
+  connect,
+  initialize,
+  lib,
+  measure,
+  setting,
+  startServer,
+} from "./common.mjs";
+
+const mib = setting("MIB", 256);
+const write = new Uint8Array(setting("WRITE_KIB", 1024) * 1024);
+
+// Worker threads of the native runtime leaving their park, only known with the `runtime-metrics`
+// feature
+const unparks = () => lib.runtime_metrics?.()?.unparkCount;
+
+const server = startServer();
+const connection = await connect(server);
+
+let received;
+let wakeups;
+
+await measure(`${mib} MiB echoed on one stream`, async () => {
+  const before = unparks();
+  const { stream, closed } = initialize(await lib.create_stream(connection));
+
+  for (let written = 0; written < mib * 2 ** 20; written += write.byteLength) {
+    await lib.write_stream(stream, write);
+  }
+  await lib.close_write(stream);
+
+  received = await closed;
+  wakeups = before === undefined ? undefined : unparks() - before;
+
+  return { MiB: received.bytes / 2 ** 20 };
+});
+
+const receivedMib = received.bytes / 2 ** 20;
+console.log(`  chunks/MiB: ${(received.chunks / receivedMib).toFixed(1)}`);
+if (wakeups !== undefined) {
+  console.log(`  runtime wakeups/MiB: ${(wakeups / receivedMib).toFixed(1)}`);
+}
+
+await lib.close_connection(connection, 0, new Uint8Array());
+closeServer(server);
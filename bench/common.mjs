@@ -0,0 +1,105 @@
+// Shared setup of the benchmarks, which run the native exports against the test server. Needs a
+// release build with the test server, see the `bench` script
+import { createRequire } from "node:module";
+import { monitorEventLoopDelay } from "node:perf_hooks";
+
+const require = createRequire(import.meta.url);
+export const lib = require(process.env.QUIC_LIB ?? "../dist/lib.node");
+
+// Positive integer from the environment, so runs can be scaled without editing the benchmark
+export const setting = (name, fallback) => {
+  const value = Number(process.env[name] ?? fallback);
+
+  if (!Number.isInteger(value) || value < 1) {
+    throw new RangeError(`${name} must be a positive integer, got ${process.env[name]}`);
+  }
+
+  return value;
+};
+
+export const startServer = (options) => lib.__test_server_start(options);
+
+export const closeServer = (started) => lib.__test_server_close(started.server);
+
+export const connect = ({ port, certificate }, options = {}) =>
+  lib.connect(
+    port,
+    "127.0.0.1",
+    "localhost",
+    () => {},
+    () => {},
+    () => {},
+    undefined,
+    [new TextEncoder().encode(certificate)],
+    undefined,
+    options
+  );
+
+// Initializes a stream, `closed` resolves with the number of bytes and chunks it received
+export const initialize = (partial) => {
+  let bytes = 0;
+  let chunks = 0;
+  let resolve, reject;
+  const closed = new Promise((...settle) => ([resolve, reject] = settle));
+
+  const stream = lib.initialize_stream(
+    partial,
+    (chunk) => {
+      bytes += chunk.byteLength;
+      chunks++;
+    },
+    // Refers to `stream`, which keeps it from being garbage collected before it closed
+    (reason, error) => (error ? reject(error) : resolve({ bytes, chunks, stream })),
+    reject
+  );
+
+  return { stream, closed };
+};
+
+// Alive tasks of the native runtime, only known with the `runtime-metrics` feature
+const aliveTasks = () => lib.runtime_metrics?.()?.aliveTasks;
+
+// Runs `run` and prints how long it took along with the CPU time, memory and event loop delay it
+// cost. `run` returns the amount of work it did, keyed by unit, which is printed per second
+export const measure = async (name, run) => {
+  const delay = monitorEventLoopDelay({ resolution: 10 });
+  delay.enable();
+
+  let peakRss = process.memoryUsage.rss();
+  let peakTasks = aliveTasks();
+  const sampler = setInterval(() => {
+    peakRss = Math.max(peakRss, process.memoryUsage.rss());
+    peakTasks = peakTasks === undefined ? undefined : Math.max(peakTasks, aliveTasks() ?? 0);
+  }, 10);
+
+  const rss = peakRss;
+  const cpu = process.cpuUsage();
+  const started = process.hrtime.bigint();
+
+  const work = await run();
+
+  const seconds = Number(process.hrtime.bigint() - started) / 1e9;
+  const { user, system } = process.cpuUsage(cpu);
+  clearInterval(sampler);
+  delay.disable();
+
+  const results = {
+    seconds: seconds.toFixed(3),
+    ...Object.fromEntries(
+      Object.entries(work).map(([unit, amount]) => [`${unit}/s`, (amount / seconds).toFixed(1)])
+    ),
+    "cpu user (ms)": (user / 1e3).toFixed(0),
+    "cpu system (ms)": (system / 1e3).toFixed(0),
+    "peak rss growth (MiB)": ((peakRss - rss) / 2 ** 20).toFixed(1),
+    "loop delay p99 (ms)": (delay.percentile(99) / 1e6).toFixed(2),
+    "loop delay max (ms)": (delay.max / 1e6).toFixed(2),
+  };
+  if (peakTasks !== undefined) {
+    results["peak native tasks"] = peakTasks;
+  }
+
+  console.log(name);
+  for (const [key, value] of Object.entries(results)) {
+    console.log(`  ${key}: ${value}`);
+  }
+};
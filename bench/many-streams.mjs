@@ -0,0 +1,59 @@
+// Many short-lived echoed streams on one connection. Every stream event goes through the channel
+// of its connection, so this tracks the cost of dispatching them. `STREAMS`, `CONCURRENCY` and
+// `PAYLOAD` scale the run
+import {
+  closeServer,
+  connect,
+  initialize,
+  lib,
+  measure,
+  setting,
+  startServer,
+} from "./common.mjs";
+
+const streams = setting("STREAMS", 10_000);
+const concurrency = setting("CONCURRENCY", 100);
+const payload = new Uint8Array(setting("PAYLOAD", 64));
+
+const server = startServer();
+const connection = await connect(server);
+
+// Handles alive besides the connection: Node's active resources, the streams the connection still
+// tracks, and the tasks of the native runtime (only known with the `runtime-metrics` feature).
+// Streams which are done should leave nothing behind
+const handles = () => ({
+  resources: process.getActiveResourcesInfo().length,
+  streams: lib.open_streams(connection),
+  tasks: lib.runtime_metrics?.()?.aliveTasks,
+});
+
+const before = handles();
+
+await measure(`${streams} streams, ${concurrency} at a time`, async () => {
+  let started = 0;
+
+  const worker = async () => {
+    while (started < streams) {
+      started++;
+
+      const { stream, closed } = initialize(await lib.create_stream(connection));
+      await lib.write_stream(stream, payload);
+      await lib.close_write(stream);
+      await closed;
+    }
+  };
+
+  await Promise.all(Array.from({ length: concurrency }, worker));
+
+  return { streams };
+});
+
+const after = handles();
+for (const [kind, count] of Object.entries(before)) {
+  if (count !== undefined) {
+    console.log(`  ${kind} before/after: ${count}/${after[kind]}`);
+  }
+}
+
+await lib.close_connection(connection, 0, new Uint8Array());
+closeServer(server);
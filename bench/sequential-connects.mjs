@@ -0,0 +1,26 @@
+// Connections made one after the other, each closed before the next starts. The root store and
+// certificate verifier are shared between connections, so this tracks what a handshake costs
+// beyond them. `CONNECTS` scales the run
+import {
+  closeServer,
+  connect,
+  lib,
+  measure,
+  setting,
+  startServer,
+} from "./common.mjs";
+
+const connects = setting("CONNECTS", 1000);
+
+const server = startServer();
+
+await measure(`${connects} sequential connects`, async () => {
+  for (let i = 0; i < connects; i++) {
+    const connection = await connect(server);
+    await lib.close_connection(connection, 0, new Uint8Array());
+  }
+
+  return { connects };
+});
+
+closeServer(server);
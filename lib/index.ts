@@ -4,11 +4,246 @@ const require = createRequire(import.meta.url);
 
 const lib = require("./lib.node");
 
+/**
+ * Stable code set as the `code` property of every error thrown, rejected, or passed to an `onError` callback by the native layer
+ */
+export type QuicErrorCode =
+  | "ERR_QUIC_CONNECT_TIMEOUT"
+  | "ERR_QUIC_CONNECT"
+  | "ERR_INVALID_SERVER_NAME"
+  | "ERR_ENDPOINT_CLOSED"
+  | "ERR_TOO_MANY_CONNECTIONS"
+  | "ERR_UNSUPPORTED_VERSION"
+  | "ERR_INVALID_REMOTE_ADDRESS"
+  | "ERR_QUIC_CONNECTION_LOST"
+  | "ERR_STREAM_RESET"
+  | "ERR_STREAM_STOPPED"
+  | "ERR_STREAM_FINISHED"
+  | "ERR_STREAM_WRITE_AFTER_FINISH"
+  | "ERR_ZERO_RTT_REJECTED"
+  | "ERR_ILLEGAL_ORDERED_READ"
+  | "ERR_QUIC_TLS"
+  | "ERR_QUIC_IO"
+  | "ERR_QUIC_INVALID_ARGUMENT"
+  | "ERR_QUIC_INVALID_STATE"
+  | "ERR_HANDLE_CONSUMED"
+  | "ERR_CONNECTION_CLOSED"
+  | "ERR_CLIENT_SHUT_DOWN"
+  | "ERR_QUIC_POOL_FULL"
+  | "ERR_CANCELLED"
+  | "ERR_PROXY_CONNECT"
+  | "ERR_PROXY_AUTH"
+  | "ERR_PROXY_REJECTED"
+  | "ERR_PROXY_PROTOCOL";
+
+/**
+ * Every error code the native layer can produce, mapped to itself, along with the {@link ConnectionErrorKind} constants
+ */
+export const errorCodes: { readonly [Code in QuicErrorCode]: Code } & {
+  readonly ConnectionErrorKind: typeof ConnectionErrorKind;
+} = lib.errorCodes;
+
+export type QuicError = Error & {
+  code: QuicErrorCode;
+  /** Name of the native operation which failed, like `write_stream` or `connect` */
+  operation?: string;
+  /** Set for errors of stream operations, the same id as returned by `getDetails` */
+  streamId?: bigint;
+  /** Application error code sent by the peer when a stream was reset or stopped */
+  errorCode?: bigint;
+  /** `false` when retrying the operation can never succeed */
+  recoverable?: boolean;
+  /** Set when the error was caused by the connection being lost */
+  kind?: ConnectionErrorKind;
+  /** Same details as passed to `onClose`, set when an operation failed because the connection closed */
+  closeReason?: CloseDetails;
+  /** Range of the stream a write made with `writeNowait` would have taken up, set when it failed */
+  byteStart?: bigint;
+  byteEnd?: bigint;
+  /** Reply code of a SOCKS5 proxy which refused to relay, set with `ERR_PROXY_REJECTED` */
+  replyCode?: number;
+  /** Why the handle can't be used anymore, set with `ERR_HANDLE_CONSUMED` */
+  handleState?: "consumed" | "finalized";
+  connectionId?: number;
+  label?: string;
+} & Partial<TransportErrorDetails>;
+
+// Errors thrown by this wrapper itself, with the same `code` property as native errors
+const quicError = <E extends Error>(error: E, code: QuicErrorCode): E & QuicError =>
+  Object.assign(error, { code });
+
+/**
+ * Which variant of connection error ended a connection. Set as `kind` on close details and on connection errors.
+ * `TIMED_OUT` means the network went away (idle timeout), `APPLICATION_CLOSED` that the peer closed the connection deliberately.
+ * `TRANSPORT_ERROR` covers both local transport errors and the peer closing the connection with a transport error code
+ */
+export const ConnectionErrorKind: {
+  readonly TIMED_OUT: "timedOut";
+  readonly TRANSPORT_ERROR: "transportError";
+  readonly APPLICATION_CLOSED: "applicationClosed";
+  readonly RESET: "reset";
+  readonly VERSION_MISMATCH: "versionMismatch";
+  readonly LOCALLY_CLOSED: "locallyClosed";
+  readonly CIDS_EXHAUSTED: "cidsExhausted";
+  /** Closed locally by `autoCloseIdleMs`, only set on close details */
+  readonly IDLE_AUTO_CLOSE: "idleAutoClose";
+} = lib.ConnectionErrorKind;
+
+export type ConnectionErrorKind =
+  (typeof ConnectionErrorKind)[keyof typeof ConnectionErrorKind];
+
+export type CloseDetails = {
+  connectionId: number;
+  label: string;
+  reason: string;
+  kind: ConnectionErrorKind;
+  /** Error code of the CONNECTION_CLOSE frame, only set when the peer closed the connection */
+  errorCode?: bigint;
+  /** Raw reason phrase of the CONNECTION_CLOSE frame, only set when the peer closed the connection */
+  reasonBytes?: Uint8Array;
+  /**
+   * Whether the endpoint finished draining before `onClose` was called, see `drainTimeoutMs`.
+   * Always `false` for connections on a shared endpoint, which is not waited for
+   */
+  drained: boolean;
+} & Partial<TransportErrorDetails>;
+
+/**
+ * Set on close details and connection errors when `kind` is `transportError`
+ */
+export type TransportErrorDetails = {
+  transportErrorCode: bigint;
+  /** IETF name of the code, like `FLOW_CONTROL_ERROR`. `null` for unknown codes */
+  transportErrorName: string | null;
+  /** Name of the frame type which triggered the transport error, if known */
+  frameType: string | null;
+  transportReason: string;
+  /** Whether the transport error was sent by the peer rather than detected locally */
+  remote: boolean;
+};
+
+/**
+ * - `connected`: the connection is usable
+ * - `draining`: the connection is closing, or was closed by the peer, but has not finished tearing down
+ * - `closed`: the connection is closed and `onClose` has been called
+ */
+export type ConnectionState = "connected" | "draining" | "closed";
+
+/**
+ * Transport parameters advertised by the peer, as far as they can be observed.
+ * Parameters which can't be observed are `null`. Fields listed in `derived` are computed from the
+ * peer's parameters combined with local state (e.g. the current MTU), rather than being the raw value
+ */
+export type PeerParameters = {
+  maxDatagramFrameSize: number | null;
+  datagramsSupported: boolean;
+  maxConcurrentBidiStreams: number | null;
+  maxConcurrentUniStreams: number | null;
+  initialMaxData: number | null;
+  maxIdleTimeout: number | null;
+  activeMigrationDisabled: boolean | null;
+  derived: (keyof PeerParameters)[];
+};
+
+export type CongestionEventKind =
+  | "slowStartExit"
+  | "congestionEvent"
+  | "recovered";
+
+export type CongestionSnapshot = {
+  connectionId: number;
+  label: string;
+  cwnd: number;
+  rttMs: number;
+  congestionEvents: number;
+  /** Not exposed by the underlying QUIC implementation, always `null` */
+  bytesInFlight: number | null;
+};
+
+/** Passed to `onBufferedBytesWarning` */
+export type BufferedBytesWarning = {
+  connectionId: number;
+  label: string;
+  bufferedBytes: number;
+  maxBufferedBytes: number;
+};
+
+export type RttHistogram = {
+  count: number;
+  p50Ms: number;
+  p90Ms: number;
+  p99Ms: number;
+  maxMs: number;
+  /** Only contains buckets with at least one observation */
+  buckets: { lowerMs: number; upperMs: number; count: number }[];
+};
+
+export type ConnectionDetails = {
+  connectionId: number;
+  label: string;
+  remoteAddress: string;
+  localAddress: string | null;
+  localIp: string | null;
+  alpnProtocol: string | null;
+  serverName: string | null;
+  version: number;
+  /** Whether the TLS handshake resumed a session from an earlier connection to the same server */
+  handshakeKind: "full" | "resumed";
+  /** Receive window in bytes, as last set with `setReceiveWindow` */
+  receiveWindow: bigint;
+  /** Bytes of received stream data queued for `onData` callbacks which didn't run yet */
+  bufferedBytes: number;
+  /** Whether the TLS configuration of the connection is FIPS compliant, only ever `true` in `crypto-fips` builds */
+  fips: boolean;
+  /** Congestion controller of the connection, as set with `ConnectOptions.congestionControl` */
+  congestionControl: CongestionControl;
+  /** Settings the kernel applied to the UDP socket, `null` when the connection was made on a shared `endpoint` or through a `proxy` */
+  socket: SocketDetails | null;
+};
+
+/**
+ * Effective settings of a UDP socket, which may differ from the requested ones. On Linux the buffer sizes are doubled for bookkeeping,
+ * and clamped to `net.core.wmem_max` and `net.core.rmem_max`
+ */
+export type SocketDetails = {
+  sendBufferSize: number;
+  recvBufferSize: number;
+  /** DSCP read back from the socket, only set when `ConnectOptions.dscp` was */
+  dscp?: number;
+  /** Datagrams sent and received with a single system call, more than 1 when segmentation (GSO) and receive offload (GRO) are in use */
+  gsoSegments: number;
+  groSegments: number;
+};
+
+/** Congestion control algorithms supported by `ConnectOptions.congestionControl` */
+export type CongestionControl = "cubic" | "newreno" | "bbr";
+
+export type GracefulCloseResult = {
+  /** Every stream finished before the timeout */
+  graceful: boolean;
+  remainingStreams: number;
+  /** The connection was already closed or closing, nothing was done */
+  alreadyClosed: boolean;
+};
+
+/**
+ * What closing a stream actually did. `alreadyClosed` is `true` when the call did nothing
+ */
+export type StreamCloseResult = {
+  sendFinished: boolean;
+  alreadyClosed: boolean;
+  receiveStopped: boolean;
+};
+
 export type ConnectOptions = {
   hostname: string;
   port: number;
-  onClose: (this: Connection, reason: string) => void;
-  onError: (this: Connection, error: Error) => void;
+  /**
+   * Included as `label` in close details, errors, and event payloads, and attached to the native logs of this connection
+   */
+  label?: string;
+  onClose: (this: Connection, reason: string, details: CloseDetails) => void;
+  onError: (this: Connection, error: QuicError) => void;
   onStream: (this: Connection, partialStream: PartialStream) => void;
   alpnProtocols?: string[];
   certificateAuthorities?: Uint8Array[];
@@ -16,10 +251,401 @@ export type ConnectOptions = {
     certificate: Buffer;
     key: Buffer;
   };
+  /**
+   * Called when the congestion controller changes state. Detected by periodically sampling the
+   * connection statistics, so events are reported with a delay of up to 100ms and short-lived
+   * transitions may be missed
+   */
+  onCongestionEvent?: (
+    this: Connection,
+    kind: CongestionEventKind,
+    snapshot: CongestionSnapshot
+  ) => void;
+  /**
+   * Length of the trailing window used by `getThroughput`. Defaults to 1000ms, at most 60 seconds
+   */
+  throughputWindowMs?: number;
+  /**
+   * Record the RTT every 100ms into a histogram, which can be read with `getRttHistogram`
+   */
+  rttHistogram?: boolean;
+  /**
+   * Fire `onCwndWarning` when the congestion window stays below `thresholdBytes` for at least `sustainMs`.
+   * Fires at most once until the window is back at or above the threshold
+   */
+  cwndWarning?: { thresholdBytes: number; sustainMs: number };
+  onCwndWarning?: (this: Connection, snapshot: CongestionSnapshot) => void;
+  /**
+   * Endpoint to create the connection on, so multiple connections share one UDP socket.
+   * By default every connection gets its own endpoint
+   */
+  endpoint?: Endpoint;
+  /**
+   * SOCKS5 proxy to relay the connection's UDP traffic through, using UDP ASSOCIATE. The connection gets an endpoint of its own,
+   * so this can't be combined with `endpoint`. Failures reject with `ERR_PROXY_CONNECT`, `ERR_PROXY_AUTH`, `ERR_PROXY_REJECTED` or `ERR_PROXY_PROTOCOL`.
+   * The header the proxy needs in front of every datagram, 10 bytes for an IPv4 server or 22 for IPv6, is taken off the `mtu` sizes, though none goes below 1200
+   */
+  proxy?: ProxyOptions;
+  /**
+   * Local IP address to bind the connection's UDP socket on, e.g. to send from one address of a multi-homed host.
+   * It has to be of the same family as the remote address, and link-local IPv6 addresses need a zone like `fe80::2%eth0`.
+   * Defaults to the unspecified address
+   */
+  localAddress?: string;
+  /**
+   * Local UDP port to bind on, e.g. for NAT hole punching. Defaults to a random port. Bind failures, like a port already in use,
+   * reject with `ERR_QUIC_IO` and the system's error message. `localAddress` of the connection details reports where the socket was bound.
+   * Like `interface`, these can't be combined with `endpoint` or `proxy`
+   */
+  localPort?: number;
+  /**
+   * Bind an IPv6 socket, or with `false` an IPv4 one, instead of one of the remote address's family. An IPv6 socket reaches IPv4 peers
+   * through IPv4-mapped addresses, which needs a dual-stack socket. Can't contradict the family of `localAddress`
+   */
+  ipv6?: boolean;
+  /**
+   * Sets `IPV6_V6ONLY` of an IPv6 socket, `false` makes it dual-stack. By default the system's setting is kept, which differs between
+   * platforms, except a dual-stack socket is requested to reach an IPv4 peer from an IPv6 socket. Rejects with `ERR_QUIC_IO` on an IPv4 socket,
+   * or when the platform doesn't support the setting
+   */
+  ipv6Only?: boolean;
+  /**
+   * Name of the network interface to send from, like `eth1`, regardless of the routing table. Uses `SO_BINDTODEVICE` on Linux,
+   * which needs `CAP_NET_RAW` or root on older kernels, and `IP_BOUND_IF` on macOS. Other platforms reject with `ERR_QUIC_IO`.
+   * The connection gets an endpoint of its own, so this can't be combined with `endpoint` or `proxy`
+   */
+  interface?: string;
+  /**
+   * `SO_SNDBUF` and `SO_RCVBUF` of the connection's UDP socket in bytes, a larger receive buffer keeps the kernel from dropping datagrams
+   * at high throughput. The kernel may apply less, `socket` of the connection details reports the effective sizes. Failing to set them rejects.
+   * Like `interface`, these can't be combined with `endpoint` or `proxy`
+   */
+  udpSendBufferSize?: number;
+  udpRecvBufferSize?: number;
+  /**
+   * Differentiated services codepoint every datagram is marked with, between 0 and 63, e.g. 46 for expedited forwarding.
+   * Datagrams are sent one at a time without ECN then, since the QUIC stack would otherwise overwrite the marking with its ECN bits.
+   * Not supported on Windows, and some platforms need elevated privileges for the higher priority classes, both reject with `ERR_QUIC_IO`
+   */
+  dscp?: number;
+  /**
+   * `false` turns off UDP segmentation and receive offload (GSO and GRO), e.g. to work around kernels which corrupt offloaded datagrams.
+   * Datagrams are sent one at a time without ECN then. By default offload is used where the platform supports it,
+   * `gsoSegments` and `groSegments` of the socket details tell whether it is
+   */
+  udpOffload?: boolean;
+  /**
+   * How long `onClose` may be delayed while the endpoint drains after the connection closed.
+   * `onClose` is called regardless once it expires, with `drained: false`. Defaults to 3000
+   */
+  drainTimeoutMs?: number;
+  /** Abandons the connection attempt when cancelled, rejecting with `ERR_CANCELLED` */
+  cancelToken?: CancelToken;
+  /** Aborts the connection attempt, or closes the connection once it was established, when cancelled */
+  controller?: ConnectController;
+  /**
+   * Abandons the handshake once it took longer, rejecting with `ERR_QUIC_CONNECT_TIMEOUT`. `0` or leaving it out waits until
+   * the idle timeout expires, see `idleTimeoutMs`. Doesn't include the DNS lookup of `connect`
+   */
+  timeoutMs?: number;
+  /**
+   * Closes the connection once it went this long without open streams, with error code `0` and reason `idle`.
+   * `onClose` is called with `kind: "idleAutoClose"`. Streams which are being opened count as open, so they are never cut off
+   */
+  autoCloseIdleMs?: number;
+  /**
+   * Keep-alive sent to stop the connection from timing out, every `intervalMs` (default 1000). `intervalMs: null` disables it,
+   * e.g. when the application sends heartbeats of its own.
+   * With `onlyWhenActive` it is only sent while streams are open, so idle connections time out
+   */
+  keepAlive?: { intervalMs?: number | null; onlyWhenActive?: boolean };
+  /**
+   * Closes the connection once nothing was received from the server for this long, `onClose` is called with `kind: "timedOut"` then.
+   * `null` disables it, so only the keep-alive or the application notices a dead server. Defaults to quinn's 30 seconds
+   */
+  idleTimeoutMs?: number | null;
+  /** Congestion control algorithm of the connection, defaults to `"cubic"`. Reported back as `congestionControl` of the connection details */
+  congestionControl?: CongestionControl;
+  /**
+   * RTT assumed until the handshake measured it, between 1 and 60000. Defaults to quinn's 333ms, on links with a much larger RTT,
+   * like satellite links, setting it close to the actual RTT avoids spurious retransmissions during the handshake
+   */
+  initialRttMs?: number;
+  /**
+   * UDP payload sizes in bytes, between 1200 and 65527. Datagrams start out at `initial` (default 1200) and MTU discovery probes for larger ones
+   * every `intervalMs` (default 600000), up to `upperBound` (default 1452). `min` is the size assumed to always get through, a black hole drops back to it.
+   * `initial` can't be below `min` and is raised to it when left out, `upperBound` can't be below either of them.
+   * Setting them to the MTU of the path, e.g. of a tunnel, keeps probes from being black-holed. The current MTU is reported as `stats.path.currentMtu` by `debugDump`.
+   * `discovery: false` turns MTU discovery off and pins the size to `initial`, which is then required, so no larger datagram is ever sent and no probes either
+   */
+  mtu?: {
+    initial?: number;
+    min?: number;
+    upperBound?: number;
+    intervalMs?: number;
+    discovery?: boolean;
+  };
+  /**
+   * Asks the server to send fewer acknowledgements, which lowers the overhead of high packet rates. Only has an effect when the server
+   * supports the ACK frequency extension. `maxAckDelayMs` has to be below 16384, each field left out keeps quinn's default
+   */
+  ackFrequency?: {
+    ackElicitingThreshold?: number | bigint;
+    maxAckDelayMs?: number;
+    reorderingThreshold?: number | bigint;
+  };
+  /**
+   * Initial congestion window in bytes, of whichever `congestionControl` is used. Between 2400 and 2^32 - 1.
+   * A larger window lets the first requests on a low latency path go out without waiting for acknowledgements. The resulting window is reported as `stats.path.cwnd` by `debugDump`
+   */
+  initialWindowBytes?: number;
+  /**
+   * Connection-level flow control windows in bytes, shared by all streams on top of their own limits. Each defaults to quinn's when left out.
+   * `receiveWindowBytes` can be changed later with `setReceiveWindow`
+   */
+  receiveWindowBytes?: number | bigint;
+  sendWindowBytes?: number | bigint;
+  /**
+   * How many bidirectional streams the server may have open at once, defaults to quinn's 100. `0` keeps the server from opening any,
+   * so `onStream` is only called for unidirectional streams. Larger values than 2^53 - 1 have to be given as a BigInt. Can be changed later with `setStreamLimits`
+   */
+  maxConcurrentBidiStreams?: number | bigint;
+  /** Same as `maxConcurrentBidiStreams` for unidirectional streams */
+  maxConcurrentUniStreams?: number | bigint;
+  /**
+   * Hands streams the server opens in a burst over together instead of one at a time, `onStream` is still called once per stream in the order they were accepted.
+   * A batch is handed over once it has `maxStreams` streams (default 64), was started `maxDelayMs` ago (default 10), or no other stream is waiting, so a single stream isn't delayed
+   */
+  streamBatch?: { maxStreams?: number; maxDelayMs?: number };
+  /**
+   * Limit on the received stream data which is queued for `onData` callbacks that didn't run yet, across all streams of the connection.
+   * Once it's exceeded nothing is read from any of the streams, so flow control holds the server back, until less than half of it is queued.
+   * Unlimited by default
+   */
+  maxBufferedBytes?: number;
+  /**
+   * Writes larger than this are handed to the QUIC stack in chunks, so a single large write doesn't hold up the writes of other streams.
+   * `write` still resolves once all of it was written. Defaults to 256 KiB
+   */
+  writeChunkBytes?: number;
+  /**
+   * Keeps the last this many events of the connection, like streams opening and congestion events, for `Connection.debugDump`.
+   * Between 1 and 65536, no events are kept by default
+   */
+  eventLogSize?: number;
+  /**
+   * Whether address validation tokens the server sent in NEW_TOKEN frames are stored and sent on later connections to the same server name,
+   * which lets it skip address validation. Together with session resumption this keeps reconnects at one round trip. The tokens are shared
+   * by all connections of the process. Defaults to `true`
+   */
+  reuseTokens?: boolean;
+  /** Called the first time `maxBufferedBytes` is exceeded */
+  onBufferedBytesWarning?: (this: Connection, warning: BufferedBytesWarning) => void;
+};
+
+/** SOCKS5 proxy for `ConnectOptions.proxy`. `username` and `password` are sent with username/password authentication, and have to be given together */
+export type ProxyOptions = {
+  host: string;
+  port: number;
+  username?: string;
+  password?: string;
+};
+
+/** Replacement callbacks for `Connection.setCallbacks` */
+export type ConnectionCallbacks = Partial<
+  Pick<ConnectOptions, "onStream" | "onClose" | "onError">
+>;
+
+/**
+ * Cancels pending operations it was passed to, which reject with `ERR_CANCELLED` and the reason as `reason`.
+ * One token can be passed to any number of operations, once cancelled it stays cancelled
+ */
+export class CancelToken {
+  /** @internal */
+  readonly token: unknown = lib.create_cancel_token();
+
+  /** Returns `false` if the token was already cancelled */
+  cancel(reason?: string): boolean {
+    return lib.cancel_token(this.token, reason);
+  }
+}
+
+/**
+ * Aborts the `connect` it is passed to as `controller`, rejecting it with `ERR_CANCELLED`. The endpoint of the attempt is closed,
+ * unless it was given as `endpoint`. Once the connection was established, `cancel` closes it instead
+ */
+export class ConnectController {
+  /** @internal */
+  readonly controller: unknown = lib.create_connect_controller();
+
+  /** Returns `false` if it was already cancelled */
+  cancel(): boolean {
+    return lib.cancel_connect(this.controller);
+  }
+}
+
+export type EndpointOptions = {
+  /** Bind to an IPv6 socket instead of an IPv4 one. Defaults to `false`, ignored when `fd` is given */
+  ipv6?: boolean;
+  /**
+   * Sets `IPV6_V6ONLY` of the IPv6 socket, `false` makes it dual-stack so it can connect to IPv4 addresses as well.
+   * By default the system's setting is kept, which differs between platforms. Throws `ERR_QUIC_INVALID_ARGUMENT` without `ipv6`,
+   * and can't be combined with `fd`
+   */
+  ipv6Only?: boolean;
+  /**
+   * Use an existing, bound UDP socket instead of binding a new one: a file descriptor, or a `SOCKET` handle on Windows.
+   * The handle is duplicated, so it stays owned by the caller and can be closed without affecting the endpoint.
+   * The duplicate shares its state with the caller's handle though, which is switched to non-blocking mode.
+   * Only IPv4 and IPv6 sockets are accepted. Anything else sharing the socket competes with the endpoint for incoming packets
+   */
+  fd?: number;
+};
+
+/**
+ * A UDP socket which can be shared by multiple connections. Can only connect to addresses of its own IP family, unless it is dual-stack
+ */
+export class Endpoint {
+  /** @internal */
+  readonly endpoint: unknown;
+  readonly ipv6: boolean;
+  /** Set for IPv6 endpoints created with `ipv6Only: false` */
+  readonly dualStack: boolean;
+
+  constructor(options: EndpointOptions = {}) {
+    this.endpoint = lib.create_endpoint({
+      ipv6: options.ipv6 ?? false,
+      ipv6Only: options.ipv6Only,
+      fd: options.fd,
+    });
+    this.ipv6 =
+      options.fd === undefined
+        ? options.ipv6 ?? false
+        : this.getLocalAddress().address.includes(":");
+    this.dualStack = this.ipv6 && options.ipv6Only === false;
+  }
+
+  /**
+   * Immediately closes all connections on this endpoint with an application error code.
+   * Closing an already closed endpoint does nothing
+   */
+  close(errorCode: number | bigint = 0, reason?: string): void {
+    lib.close_endpoint(this.endpoint, errorCode, encodeReason(reason));
+  }
+
+  /**
+   * Resolves once all connections on this endpoint have drained. Call after `close` to make
+   * sure the socket is released
+   */
+  async waitIdle(): Promise<void> {
+    return lib.wait_endpoint_idle(this.endpoint);
+  }
+
+  /**
+   * Moves this endpoint to a newly bound UDP socket, e.g. after a network change. The connections on it migrate to the new path.
+   * If binding fails the promise rejects and the current socket stays in use
+   */
+  async rebind(options?: RebindOptions): Promise<LocalAddress> {
+    return lib.rebind(this.endpoint, options);
+  }
+
+  getLocalAddress(): LocalAddress {
+    return lib.local_address(this.endpoint);
+  }
+}
+
+export type RebindOptions = {
+  /** Defaults to the unspecified address of the family of the current socket */
+  localAddress?: string;
+  /** Defaults to a random port */
+  localPort?: number;
+};
+
+export type LocalAddress = {
+  address: string;
+  port: number;
+  /** Set for connections made with `ConnectOptions.interface` */
+  interface?: string;
 };
 
-export const rawConnect = async (
-  options: ConnectOptions & { ipAddress: string }
+const encodeReason = (reason?: string): Uint8Array | null =>
+  reason ? new TextEncoder().encode(reason) : null;
+
+export type HandshakeTimings = {
+  /** Time spent resolving the hostname. Only present when the connection was created with `connect` */
+  dnsMs?: number;
+  /** Time spent in the QUIC/TLS handshake */
+  handshakeMs: number;
+  /** Time from the start of the native connect until the handshake completed. Doesn't include `dnsMs`, which came before it */
+  totalMs: number;
+};
+
+// Hands a stream accepted by the native layer to `onStream`, closing it if `onStream` did not initialize it
+const deliverStream = (
+  connection: Connection,
+  onStream: ConnectOptions["onStream"],
+  rawPartialStream: unknown,
+  isUnidirectional: boolean
+) => {
+  const partialStream = new PartialStream(
+    connection,
+    rawPartialStream,
+    isUnidirectional
+  );
+
+  onStream.call(connection, partialStream);
+
+  if (!partialStream.isInitialized) {
+    partialStream
+      .initialize({ onClose: () => {}, onData: () => {}, onError: () => {} })
+      .close()
+      .catch(() => {});
+
+    throw new Error("Partial stream has not been initialized");
+  }
+};
+
+// With `streamBatch` the native layer passes an array of `{ stream, details }` instead of a single stream
+type RawAcceptedStream = {
+  stream: unknown;
+  details: { id: bigint; is0rtt: boolean; isUnidirectional: boolean };
+};
+
+const deliverStreams = (
+  connection: Connection,
+  onStream: ConnectOptions["onStream"],
+  raw: unknown,
+  isUnidirectional: boolean
+) => {
+  if (!Array.isArray(raw)) {
+    deliverStream(connection, onStream, raw, isUnidirectional);
+    return;
+  }
+
+  // Every stream of the batch is handed over even if an earlier one failed, the first error is rethrown afterwards
+  let failed = false;
+  let error: unknown;
+
+  for (const { stream, details } of raw as RawAcceptedStream[]) {
+    try {
+      deliverStream(connection, onStream, stream, details.isUnidirectional);
+    } catch (e) {
+      if (!failed) {
+        failed = true;
+        error = e;
+      }
+    }
+  }
+
+  if (failed) {
+    throw error;
+  }
+};
+
+// Arguments of the native `connect` and `connect_persistent`. The callbacks are called with `connection()` as `this`
+const connectArguments = (
+  options: ConnectOptions & { ipAddress: string },
+  connection: () => Connection
 ) => {
   let alpnProtocols;
 
@@ -46,82 +672,913 @@ export const rawConnect = async (
   const handleNewStream = (
     rawPartialStream: unknown,
     isUnidirectional: boolean
-  ) => {
-    const partialStream = new PartialStream(
-      fullConnection,
+  ) =>
+    deliverStreams(
+      connection(),
+      options.onStream,
       rawPartialStream,
       isUnidirectional
     );
 
-    options.onStream.call(fullConnection, partialStream);
-
-    if (!partialStream.isInitialized) {
-      partialStream
-        .initialize({ onClose: () => {}, onData: () => {}, onError: () => {} })
-        .close()
-        .catch(() => {});
-
-      throw new Error("Partial stream has not been initialized");
-    }
-  };
-
-  const connection = await lib.connect(
+  return [
     options.port,
     options.ipAddress,
     options.hostname,
     handleNewStream,
     (...args: Parameters<ConnectOptions["onClose"]>) =>
-      options.onClose.apply(fullConnection, args),
+      options.onClose.apply(connection(), args),
     (...args: Parameters<ConnectOptions["onError"]>) =>
-      options.onError.apply(fullConnection, args),
+      options.onError.apply(connection(), args),
     alpnProtocols,
     options.certificateAuthorities,
-    clientAuthentication
+    clientAuthentication,
+    {
+      label: options.label,
+      onCongestionEvent:
+        options.onCongestionEvent &&
+        ((...args: Parameters<NonNullable<ConnectOptions["onCongestionEvent"]>>) =>
+          options.onCongestionEvent!.apply(connection(), args)),
+      throughputWindowMs: options.throughputWindowMs,
+      rttHistogram: options.rttHistogram,
+      cwndWarning: options.cwndWarning,
+      onCwndWarning:
+        options.onCwndWarning &&
+        ((...args: Parameters<NonNullable<ConnectOptions["onCwndWarning"]>>) =>
+          options.onCwndWarning!.apply(connection(), args)),
+      endpoint: options.endpoint?.endpoint,
+      proxy: options.proxy,
+      localAddress: options.localAddress,
+      localPort: options.localPort,
+      ipv6: options.ipv6,
+      ipv6Only: options.ipv6Only,
+      interface: options.interface,
+      udpSendBufferSize: options.udpSendBufferSize,
+      udpRecvBufferSize: options.udpRecvBufferSize,
+      dscp: options.dscp,
+      udpOffload: options.udpOffload,
+      drainTimeoutMs: options.drainTimeoutMs,
+      cancelToken: options.cancelToken?.token,
+      controller: options.controller?.controller,
+      timeoutMs: options.timeoutMs,
+      autoCloseIdleMs: options.autoCloseIdleMs,
+      keepAlive: options.keepAlive,
+      idleTimeoutMs: options.idleTimeoutMs,
+      congestionControl: options.congestionControl,
+      initialRttMs: options.initialRttMs,
+      ackFrequency: options.ackFrequency,
+      mtu: options.mtu,
+      initialWindowBytes: options.initialWindowBytes,
+      receiveWindowBytes: options.receiveWindowBytes,
+      sendWindowBytes: options.sendWindowBytes,
+      maxConcurrentBidiStreams: options.maxConcurrentBidiStreams,
+      maxConcurrentUniStreams: options.maxConcurrentUniStreams,
+      streamBatch: options.streamBatch,
+      maxBufferedBytes: options.maxBufferedBytes,
+      writeChunkBytes: options.writeChunkBytes,
+      eventLogSize: options.eventLogSize,
+      reuseTokens: options.reuseTokens,
+      onBufferedBytesWarning:
+        options.onBufferedBytesWarning &&
+        ((...args: Parameters<NonNullable<ConnectOptions["onBufferedBytesWarning"]>>) =>
+          options.onBufferedBytesWarning!.apply(connection(), args)),
+    },
+  ] as const;
+};
+
+type AddressConnectOptions = ConnectOptions & {
+  /** IPv4 or IPv6 address, link-local IPv6 ones with a zone like `fe80::1%eth0` or `fe80::1%2` */
+  ipAddress: string;
+};
+
+// `dnsMs` is how long resolving `ipAddress` took, when `connect` did that
+const connectAddress = async (options: AddressConnectOptions, dnsMs?: number) => {
+  let fullConnection: Connection;
+
+  const connection = await lib.connect(
+    ...connectArguments(options, () => fullConnection)
   );
 
-  const fullConnection = new Connection(connection);
+  fullConnection = new Connection(connection, dnsMs);
 
   return fullConnection;
 };
 
-export const connect = async (options: ConnectOptions): Promise<Connection> => {
+export const rawConnect = (options: AddressConnectOptions) => connectAddress(options);
+
+// Resolves `options.hostname` to an address of the family `options.endpoint` can connect to
+const lookupHostname = async (options: ConnectOptions) => {
   const { lookup } = await import("node:dns/promises");
 
-  const address = await lookup(options.hostname);
+  const lookupStart = performance.now();
+  const address = await lookup(
+    options.hostname,
+    options.endpoint && !options.endpoint.dualStack
+      ? { family: options.endpoint.ipv6 ? 6 : 4 }
+      : {}
+  );
+
+  return { address, dnsMs: performance.now() - lookupStart };
+};
+
+export const connect = async (options: ConnectOptions): Promise<Connection> => {
+  const { address, dnsMs } = await lookupHostname(options);
+
+  return connectAddress(
+    {
+      ...options,
+      ipAddress: address.address,
+    },
+    dnsMs
+  );
+};
+
+export type ReconnectOptions = {
+  /** Delay before the first attempt to reconnect, doubled after every failed attempt. Defaults to 100ms */
+  initialDelayMs?: number;
+  /** Upper bound of the delay between attempts. Defaults to 10 seconds */
+  maxDelayMs?: number;
+  /** Attempts after a single close before giving up and calling `onClose`. Unlimited by default */
+  maxAttempts?: number;
+  /** Called before every attempt, with the details of the close that caused it */
+  onReconnecting?: (
+    this: PersistentConnection,
+    attempt: number,
+    reason: CloseDetails
+  ) => void;
+  onReconnected?: (this: PersistentConnection, connection: Connection) => void;
+};
+
+/**
+ * A connection which is re-established whenever it closes for any reason other than an application close, by either side,
+ * or `autoCloseIdleMs`. This is done by the native layer: every connection is created on the same endpoint,
+ * and TLS sessions are resumed where the server allows it.
+ *
+ * Streams do not survive a reconnect: they are closed along with the connection they were created on,
+ * new ones have to be created on the new `connection`. `onClose` is only called once the persistent connection gives up or is closed
+ */
+export class PersistentConnection {
+  private current!: Connection;
+
+  /** @internal */
+  constructor(private readonly persistent: unknown) {}
+
+  /** The current underlying connection, replaced after every reconnect */
+  get connection(): Connection {
+    return this.current;
+  }
+
+  /** Closes the current connection and stops reconnecting */
+  async close(
+    errorCode?: number | bigint,
+    reason?: string
+  ): Promise<{ alreadyClosed: boolean; drained: boolean }> {
+    lib.close_persistent(this.persistent);
+
+    return this.current.close(errorCode, reason);
+  }
+
+  /** @internal */
+  replace(connection: Connection) {
+    this.current = connection;
+  }
+}
+
+/**
+ * Connect and keep reconnecting with exponential backoff when the connection is lost, see {@link PersistentConnection}.
+ * Rejects if the first attempt fails
+ */
+export const connectPersistent = async (
+  options: ConnectOptions,
+  reconnectOptions: ReconnectOptions = {}
+): Promise<PersistentConnection> => {
+  const { address, dnsMs } = await lookupHostname(options);
+
+  let persistent: PersistentConnection;
+
+  const { promise, persistent: handle } = lib.connect_persistent(
+    ...connectArguments(
+      { ...options, ipAddress: address.address },
+      () => persistent.connection
+    ),
+    {
+      initialDelayMs: reconnectOptions.initialDelayMs,
+      maxDelayMs: reconnectOptions.maxDelayMs,
+      maxAttempts: reconnectOptions.maxAttempts,
+      onReconnecting: (attempt: number, reason: CloseDetails) =>
+        reconnectOptions.onReconnecting?.call(persistent, attempt, reason),
+      onReconnected: (rawConnection: unknown) => {
+        persistent.replace(new Connection(rawConnection));
+        reconnectOptions.onReconnected?.call(persistent, persistent.connection);
+      },
+    }
+  );
+  persistent = new PersistentConnection(handle);
+
+  const connection = new Connection(await promise, dnsMs);
+  persistent.replace(connection);
+
+  return persistent;
+};
+
+export type BundleOptions = {
+  /** Number of connections in the bundle */
+  connections: number;
+  /** Replace members which close for any reason other than an application close, by either side. Defaults to `false` */
+  repair?: boolean;
+  /** Called whenever a member closes, before it is replaced */
+  onMemberClose?: (
+    this: ConnectionBundle,
+    connection: Connection,
+    reason: string,
+    details: CloseDetails
+  ) => void;
+};
+
+/**
+ * Several connections to the same server, with streams spread across them. Each connection has its own congestion
+ * controller, which helps bulk transfers on lossy long-haul paths.
+ *
+ * `onClose` is only called once every member is gone, with the details of the last one. `onStream` and `onError` are called for every member
+ */
+export class ConnectionBundle {
+  private readonly members = new Set<Connection>();
+  private repairing = 0;
+  private closed = false;
+
+  /** @internal */
+  constructor(
+    private readonly options: ConnectOptions,
+    private readonly bundleOptions: BundleOptions
+  ) {}
+
+  /** The open members of the bundle */
+  get connections(): Connection[] {
+    return [...this.members];
+  }
+
+  /**
+   * Opens a bidirectional stream on the member with the fewest open streams.
+   * Rejects with `ERR_CONNECTION_CLOSED` when no member is connected
+   */
+  async createStream(
+    options: StreamOptions,
+    cancelToken?: CancelToken
+  ): Promise<Stream> {
+    let least: Connection | undefined;
+    let leastStreams = Infinity;
+
+    for (const connection of this.members) {
+      const streams = connection.openStreams;
+
+      if (connection.state === "connected" && streams < leastStreams) {
+        least = connection;
+        leastStreams = streams;
+      }
+    }
+
+    if (!least) {
+      throw quicError(new Error("The bundle has no open connections"), "ERR_CONNECTION_CLOSED");
+    }
+
+    return least.createStream(options, cancelToken);
+  }
+
+  /** Closes every member and stops repairing */
+  async close(errorCode?: number | bigint, reason?: string): Promise<void> {
+    this.closed = true;
+
+    await Promise.all(
+      this.connections.map((connection) => connection.close(errorCode, reason))
+    );
+  }
+
+  /** @internal */
+  async establish() {
+    const connection: Connection = await connect({
+      ...this.options,
+      onClose: (reason, details) =>
+        this.handleClose(connection, reason, details),
+    });
+
+    this.members.add(connection);
+  }
+
+  private handleClose(
+    connection: Connection,
+    reason: string,
+    details: CloseDetails
+  ) {
+    this.members.delete(connection);
+    this.bundleOptions.onMemberClose?.call(this, connection, reason, details);
+
+    const repair =
+      this.bundleOptions.repair &&
+      !this.closed &&
+      details.kind !== ConnectionErrorKind.APPLICATION_CLOSED &&
+      details.kind !== ConnectionErrorKind.LOCALLY_CLOSED &&
+      details.kind !== ConnectionErrorKind.IDLE_AUTO_CLOSE;
+
+    if (!repair) {
+      this.finishIfEmpty(connection, reason, details);
+      return;
+    }
+
+    this.repairing++;
+    this.establish()
+      .catch(() => {})
+      .finally(() => {
+        this.repairing--;
+        this.finishIfEmpty(connection, reason, details);
+      });
+  }
+
+  private finishIfEmpty(
+    connection: Connection,
+    reason: string,
+    details: CloseDetails
+  ) {
+    if (this.members.size === 0 && this.repairing === 0) {
+      this.closed = true;
+      this.options.onClose.call(connection, reason, details);
+    }
+  }
+}
+
+/**
+ * Open `connections` connections to the same server as one {@link ConnectionBundle}.
+ * Rejects if any of them fails, closing the others. Rejects with `ERR_QUIC_INVALID_ARGUMENT` when `connections` isn't a positive integer
+ */
+export const connectBundle = async (
+  options: ConnectOptions,
+  bundleOptions: BundleOptions
+): Promise<ConnectionBundle> => {
+  if (!Number.isInteger(bundleOptions.connections) || bundleOptions.connections < 1) {
+    throw quicError(
+      new RangeError("connections must be a positive integer"),
+      "ERR_QUIC_INVALID_ARGUMENT"
+    );
+  }
+
+  const bundle = new ConnectionBundle(options, bundleOptions);
+
+  const results = await Promise.allSettled(
+    Array.from({ length: bundleOptions.connections }, () => bundle.establish())
+  );
+  const failed = results.find(
+    (result): result is PromiseRejectedResult => result.status === "rejected"
+  );
+
+  if (failed) {
+    await bundle.close();
+    throw failed.reason;
+  }
+
+  return bundle;
+};
+
+export type PoolOptions = {
+  /** Most connections kept open at once. Idle connections are evicted to make room. Unlimited by default */
+  maxSize?: number;
+  /** Close connections which have not been acquired for this long. Defaults to 30 seconds */
+  idleEvictMs?: number;
+  onError?: ConnectOptions["onError"];
+  onStream?: ConnectOptions["onStream"];
+};
+
+export type PoolConnectOptions = Omit<
+  ConnectOptions,
+  "onClose" | "onError" | "onStream"
+>;
 
-  return rawConnect({ ...options, ipAddress: address.address });
+export type PoolStats = {
+  /** Open connections, including those that are acquired */
+  size: number;
+  idle: number;
+  /** Connections still being established */
+  pending: number;
+  hits: number;
+  misses: number;
+};
+
+/**
+ * Shares one connection per destination between everyone acquiring it, keyed by hostname, port, ALPN protocols,
+ * trusted certificate authorities and client certificate. The options a connection behaves differently with are part of the key as well:
+ * `endpoint`, `proxy`, the local socket options, the transport options like `idleTimeoutMs`, `keepAlive` or `mtu`, `autoCloseIdleMs`,
+ * `streamBatch`, `maxBufferedBytes` and `writeChunkBytes`. An acquire which differs in any of them gets a connection of its own.
+ * The others, like `label`, `timeoutMs` or the monitoring options, are taken from the acquire that connects. The connections are pooled by the native layer:
+ * concurrent acquires for the same destination wait for the same connection attempt, and connections that have not
+ * been acquired for `idleEvictMs` are closed there
+ */
+export class ConnectionPool {
+  private readonly pool: unknown;
+  // Every acquire of a pooled connection resolves with the same native handle, and gets the same `Connection`
+  private readonly connections = new WeakMap<object, Connection>();
+
+  constructor(private readonly options: PoolOptions = {}) {
+    this.pool = lib.create_pool({
+      maxSize: options.maxSize,
+      idleEvictMs: options.idleEvictMs,
+    });
+  }
+
+  /**
+   * Returns the pooled connection to the destination, connecting if there is none.
+   * Every acquire has to be paired with a `release`. Rejects with `ERR_QUIC_POOL_FULL` if the pool reached `maxSize`
+   * and none of its connections is idle
+   */
+  async acquire(options: PoolConnectOptions): Promise<Connection> {
+    const { address, dnsMs } = await lookupHostname(options);
+
+    let connection: Connection;
+
+    const rawConnection = await lib.pool_acquire(
+      this.pool,
+      ...connectArguments(
+        {
+          ...options,
+          ipAddress: address.address,
+          onClose: () => {},
+          onError: (error) => this.options.onError?.call(connection, error),
+          onStream: (partialStream) => {
+            if (this.options.onStream) {
+              this.options.onStream.call(connection, partialStream);
+            } else {
+              partialStream.initialize({
+                onClose: () => {},
+                onData: () => {},
+                onError: () => {},
+              }).close().catch(() => {});
+            }
+          },
+        },
+        () => connection
+      )
+    );
+
+    const pooled = this.connections.get(rawConnection);
+    if (pooled) {
+      connection = pooled;
+    } else {
+      connection = new Connection(rawConnection, dnsMs);
+      this.connections.set(rawConnection, connection);
+    }
+
+    return connection;
+  }
+
+  /** Gives back a connection from `acquire`. It is closed once it has been idle for `idleEvictMs` */
+  release(connection: Connection) {
+    lib.pool_release(this.pool, connection.connection);
+  }
+
+  stats(): PoolStats {
+    return lib.pool_stats(this.pool);
+  }
+
+  /** Closes every pooled connection, including acquired ones */
+  async close() {
+    await lib.pool_close(this.pool);
+  }
+}
+
+export type BuildInfo = {
+  /** Version of the native addon */
+  version: string;
+  /** Crypto provider TLS is done with, selected by the `crypto-ring` (default) or `crypto-aws-lc` cargo feature */
+  cryptoProvider: "ring" | "aws-lc-rs";
+  /** Built with the `crypto-fips` feature, which uses the FIPS validated build of aws-lc-rs */
+  fips: boolean;
+};
+
+/** Describes how the loaded native addon was built */
+export const buildInfo = (): BuildInfo => {
+  return lib.build_info();
+};
+
+export type InitOptions = {
+  /**
+   * Number of worker threads of the native runtime, defaults to one per CPU core.
+   * `0` runs all connections on a single dedicated thread, which uses the least resources
+   */
+  workerThreads?: number;
+  /** Name of the runtime's threads, as shown by debuggers and `/proc` */
+  threadName?: string;
+  /** Stack size of the runtime's threads in bytes */
+  threadStackSize?: number;
+};
+
+/**
+ * Configures the native runtime. Has to be called before the first endpoint or connection is created,
+ * afterwards it throws `ERR_QUIC_INVALID_STATE`
+ */
+export const init = (options: InitOptions = {}) => {
+  lib.init(options);
+};
+
+export type FlushReport = {
+  connectionId: number;
+  label: string;
+  /** Finished streams whose data was not acknowledged by the peer before the timeout */
+  pendingStreams: number;
+  /** Streams whose write-end is still open. They are not waited for, call `closeWrite` first to include them */
+  unfinishedStreams: number;
+};
+
+/**
+ * Waits for the peer to acknowledge the data written to the finished streams of every open connection, or `timeoutMs` (default 5000) to expire.
+ * Only covers the connections created on the calling thread. Meant to be called before exiting, e.g. from a `beforeExit` handler
+ */
+export const flushAll = async (
+  options: { timeoutMs?: number } = {}
+): Promise<FlushReport[]> => {
+  return lib.flush_all(options);
+};
+
+/**
+ * Closes every endpoint and connection, waits up to a few seconds for them to drain and then stops the native runtime.
+ * The runtime is not recreated: afterwards every call that needs it, including `connect`, throws `ERR_CLIENT_SHUT_DOWN`.
+ * Calling it again resolves immediately.
+ *
+ * The runtime is shared with `worker_threads`, so this affects the connections of every thread. Connections created in a worker
+ * are closed by themselves when the worker terminates
+ */
+export const shutdown = async (): Promise<void> => {
+  return lib.shutdown();
+};
+
+export type RuntimeMetrics = {
+  workers: number;
+  /** Summed over all workers */
+  parkCount: number;
+  unparkCount: number;
+  globalQueueDepth: number;
+  /** Tasks queued on the workers themselves, summed over all of them */
+  localQueueDepth: number;
+  blockingThreads: number;
+  idleBlockingThreads: number;
+  blockingQueueDepth: number;
+  /** Native tasks which were spawned and didn't complete yet */
+  aliveTasks: number;
+};
+
+/**
+ * Scheduler metrics of the native runtime, to tell whether delayed callbacks are caused by the native side or by JavaScript.
+ * Only available when the addon was built with `pnpm build-rust-runtime-metrics`, `null` otherwise or while the runtime isn't running
+ */
+export const getRuntimeMetrics = (): RuntimeMetrics | null => {
+  return lib.runtime_metrics?.() ?? null;
+};
+
+/**
+ * Write the logs of the native layer to stderr. The filter uses the `RUST_LOG` syntax, e.g. `"info"` or `"warn,quinn=trace"`.
+ * Can only be called once per process
+ */
+export const initLogging = (filter: string) => {
+  lib.init_logging(filter);
+};
+
+/**
+ * Change the filter of the native logs at runtime. Requires `initLogging` to have been called
+ */
+export const setLogLevel = (filter: string) => {
+  lib.set_log_level(filter);
 };
 
 export type StreamOptions = {
-  onData: (this: Stream, packet: Uint8Array) => void;
-  onClose: (this: Stream, reason: string) => void;
-  onError: (this: Stream, error: Error) => void;
+  /**
+   * `error` describes why the stream was closed, e.g. `ERR_STREAM_RESET` with the peer's `errorCode`.
+   * It is `undefined` when the stream finished cleanly or was closed locally
+   */
+  onClose: (this: Stream, reason: string, error?: QuicError) => void;
+  onError: (this: Stream, error: QuicError) => void;
+  /** Called once `writeNowait` returned `false` and the queued data went below the high-water mark again */
+  onDrain?: (this: Stream) => void;
+} & (
+  | { onData: (this: Stream, packet: Uint8Array) => void }
+  | {
+      /**
+       * Received data is written to this buffer instead of being passed to `onData`, read it with `readRingBuffer`.
+       * It starts with `RING_BUFFER_HEADER_BYTES` of bookkeeping, the rest has to be a power of two bytes long.
+       * While it's full nothing is read from the stream, so flow control holds the peer back.
+       * It can't be used by two streams at once
+       */
+      ringBuffer: SharedArrayBuffer;
+      /** Called once data arrives after `readRingBuffer` returned `null` */
+      onReadable: (this: Stream) => void;
+    }
+);
+
+/** Bytes at the start of a `ringBuffer` which are used as four 32-bit slots: head, tail, state and waiting */
+export const RING_BUFFER_HEADER_BYTES = 16;
+
+const RingBufferSlot = { HEAD: 0, TAIL: 1, STATE: 2, WAITING: 3 } as const;
+/** Flags of the waiting slot: JavaScript waits for data, or the stream waits for room */
+const RingBufferWaiting = { DATA: 1, ROOM: 2 } as const;
+
+/** Value of the state slot of a `ringBuffer` */
+export const RingBufferState = {
+  OPEN: 0,
+  /** The stream ended, cleanly or because it was closed locally */
+  ENDED: 1,
+  /** The stream ended with an error, as passed to `onClose` */
+  FAILED: 2,
+} as const;
+export type RingBufferState =
+  (typeof RingBufferState)[keyof typeof RingBufferState];
+
+/**
+ * Takes everything that was written to a stream's `ringBuffer` so far. Returns `null` when it is empty,
+ * `onReadable` is called once there is more, unless `ringBufferState` is no longer `OPEN`
+ */
+export const readRingBuffer = (buffer: SharedArrayBuffer): Uint8Array | null => {
+  const slots = new Int32Array(buffer, 0, 4);
+  const data = new Uint8Array(buffer, RING_BUFFER_HEADER_BYTES);
+
+  const tail = Atomics.load(slots, RingBufferSlot.TAIL) >>> 0;
+  let head = Atomics.load(slots, RingBufferSlot.HEAD) >>> 0;
+
+  if (head === tail) {
+    // Set before checking again, data which arrives in between is either seen here or announced by `onReadable`
+    Atomics.or(slots, RingBufferSlot.WAITING, RingBufferWaiting.DATA);
+    head = Atomics.load(slots, RingBufferSlot.HEAD) >>> 0;
+
+    if (head === tail) {
+      return null;
+    }
+
+    Atomics.and(slots, RingBufferSlot.WAITING, ~RingBufferWaiting.DATA);
+  }
+
+  const length = (head - tail) >>> 0;
+  const start = tail % data.length;
+  const first = Math.min(length, data.length - start);
+
+  const result = new Uint8Array(length);
+  result.set(data.subarray(start, start + first));
+  result.set(data.subarray(0, length - first), first);
+
+  Atomics.store(slots, RingBufferSlot.TAIL, (tail + length) | 0);
+
+  // The stream stopped reading while the buffer was full, now there's room again
+  if (Atomics.and(slots, RingBufferSlot.WAITING, ~RingBufferWaiting.ROOM) & RingBufferWaiting.ROOM) {
+    lib.ring_buffer_drained(new Uint8Array(buffer, 0, RING_BUFFER_HEADER_BYTES));
+  }
+
+  return result;
 };
 
+/** Whether the stream of a `ringBuffer` still writes to it */
+export const ringBufferState = (buffer: SharedArrayBuffer): RingBufferState =>
+  Atomics.load(new Int32Array(buffer, 0, 4), RingBufferSlot.STATE) as RingBufferState;
+
 export class Connection {
-  private connection: unknown;
+  /** @internal */
+  readonly connection: unknown;
+  /** @internal */
+  readonly dnsMs?: number;
 
-  constructor(connection: undefined) {
+  constructor(connection: undefined, dnsMs?: number) {
     this.connection = connection;
+    this.dnsMs = dnsMs;
   }
 
-  async createStream(options: StreamOptions): Promise<Stream> {
-    const partialStream = await lib.create_stream(this.connection);
+  /**
+   * Opens a bidirectional stream. Waits when the peer's stream limit is reached, `cancelToken` can be used to give up
+   */
+  async createStream(
+    options: StreamOptions,
+    cancelToken?: CancelToken
+  ): Promise<Stream> {
+    const partialStream = await lib.create_stream(
+      this.connection,
+      cancelToken?.token
+    );
 
     return new PartialStream(this, partialStream, false).initialize(options);
   }
 
-  async close(errorCode?: number, reason?: string) {
-    const fullReason = reason ?? "";
-    const buffer =
-      fullReason.length > 0 ? new TextEncoder().encode(fullReason) : null;
+  /**
+   * Closes the connection with an application error code. Codes above `Number.MAX_SAFE_INTEGER` have to be passed as a BigInt,
+   * codes above 2^62 - 1 are rejected with a RangeError.
+   * Closing an already closed connection does nothing and resolves with `alreadyClosed: true`.
+   *
+   * Resolves once the close was sent and the endpoint drained, or `timeoutMs` (default 1000) expired, `drained` tells which.
+   * On a shared endpoint it resolves right away with `drained: false`, use `Endpoint.waitIdle` instead
+   */
+  async close(
+    errorCode?: number | bigint,
+    reason?: string,
+    timeoutMs?: number
+  ): Promise<{ alreadyClosed: boolean; drained: boolean }> {
+    return lib.close_connection(
+      this.connection,
+      errorCode ?? 0,
+      encodeReason(reason),
+      timeoutMs
+    );
+  }
+
+  /**
+   * Like `close`, but first waits for the streams created by `createStream` and `onStream` to finish: the send side finished
+   * by this side and acknowledged by the peer, the receive side read until its end or stopped.
+   * New streams can't be created meanwhile, and the peer can't open any.
+   * Closes anyway once `timeoutMs` (default 10 seconds) expires, in that case `graceful` is `false` and `remainingStreams` tells how many were still open
+   */
+  async closeGraceful(
+    errorCode?: number | bigint,
+    reason?: string,
+    options: { timeoutMs?: number } = {}
+  ): Promise<GracefulCloseResult> {
+    return lib.close_connection_graceful(
+      this.connection,
+      errorCode ?? 0,
+      encodeReason(reason),
+      options
+    );
+  }
+
+  /**
+   * Immediately closes the endpoint this connection was created on, including all other connections sharing it.
+   * Closing an already closed endpoint does nothing
+   */
+  closeEndpoint(errorCode: number | bigint = 0, reason?: string): void {
+    lib.close_endpoint(this.connection, errorCode, encodeReason(reason));
+  }
+
+  /**
+   * Resolves once all connections on the endpoint this connection was created on have drained
+   */
+  async waitEndpointIdle(): Promise<void> {
+    return lib.wait_endpoint_idle(this.connection);
+  }
+
+  /**
+   * Lets the process exit while this connection, including its streams, is still open, like `net.Socket.unref`.
+   * Callbacks are still called as long as something else keeps the process alive
+   */
+  unref(): this {
+    lib.unref_connection(this.connection);
+    return this;
+  }
+
+  /**
+   * Undoes `unref`, the open connection keeps the process alive again. This is the default
+   */
+  ref(): this {
+    lib.ref_connection(this.connection);
+    return this;
+  }
+
+  /**
+   * Changes how many bidirectional and unidirectional streams the server may have open at once, without reconnecting.
+   * Limits are 62-bit, values above `Number.MAX_SAFE_INTEGER` have to be passed as a BigInt.
+   *
+   * Lowering a limit does not close streams that are already open, the server is just not granted new ones until enough of them are done.
+   * Throws `ERR_CONNECTION_CLOSED` while a graceful close is in progress
+   */
+  setStreamLimits(limits: { bidi?: number | bigint; uni?: number | bigint }): void {
+    lib.set_stream_limits(this.connection, limits);
+  }
+
+  /**
+   * Limits how much data the server may have in flight to us across all streams, e.g. to react to memory pressure. Can be raised again later.
+   * Takes effect with the next flow control update: shrinking it below what was already granted only takes effect once the server used up that credit.
+   * The current value is reported as `receiveWindow` by `getDetails`
+   */
+  setReceiveWindow(bytes: number | bigint): void {
+    lib.set_receive_window(this.connection, bytes);
+  }
+
+  /**
+   * Resolves with the same details passed to `onClose` once the connection has closed.
+   * Resolves immediately if the connection is already closed
+   */
+  async closed(): Promise<CloseDetails> {
+    return lib.await_close(this.connection);
+  }
+
+  /**
+   * Returns why the connection was closed or `null` if it is still open
+   */
+  getCloseReason(): CloseDetails | null {
+    return lib.close_reason(this.connection);
+  }
+
+  /**
+   * Replaces the `onStream`, `onClose` and `onError` callbacks passed to `connect`, e.g. to hand the connection to its long-lived owner.
+   * Callbacks which are not given are kept. Every event is delivered to exactly one of the old or the new callback
+   */
+  setCallbacks(callbacks: ConnectionCallbacks): void {
+    const { onStream, onClose, onError } = callbacks;
 
-    await lib.close_connection(this.connection, errorCode ?? 0, buffer);
+    lib.set_connection_callbacks(this.connection, {
+      onStream:
+        onStream &&
+        ((rawPartialStream: unknown, isUnidirectional: boolean) =>
+          deliverStreams(this, onStream, rawPartialStream, isUnidirectional)),
+      onClose:
+        onClose &&
+        ((...args: Parameters<ConnectOptions["onClose"]>) =>
+          onClose.apply(this, args)),
+      onError:
+        onError &&
+        ((...args: Parameters<ConnectOptions["onError"]>) =>
+          onError.apply(this, args)),
+    });
+  }
+
+  get state(): ConnectionState {
+    return lib.connection_state(this.connection);
+  }
+
+  /**
+   * Streams created by `createStream` or accepted by `onStream` which are still being read or written, including partial streams
+   */
+  get openStreams(): number {
+    return lib.open_streams(this.connection);
+  }
+
+  getPeerParameters(): PeerParameters {
+    return lib.peer_parameters(this.connection);
+  }
+
+  /**
+   * Percentage of sent packets that were lost within the trailing `windowMs` (at most 60 seconds),
+   * or during the whole connection when no window is given.
+   * Returns `null` until enough samples have been collected to cover the window
+   */
+  getLossRate(windowMs?: number): number | null {
+    return lib.loss_rate(this.connection, windowMs);
+  }
+
+  /**
+   * Bytes per second sent and received over the trailing window configured with `throughputWindowMs`
+   */
+  getThroughput(): { sendBps: number; recvBps: number } {
+    return lib.throughput(this.connection);
+  }
+
+  /**
+   * Percentiles of the RTT observations. Requires the `rttHistogram` connect option.
+   * With `reset` the histogram is cleared after reading it
+   */
+  getRttHistogram(options?: { reset?: boolean }): RttHistogram {
+    return lib.rtt_histogram(this.connection, options);
+  }
+
+  getDetails(): ConnectionDetails {
+    return lib.connection_details(this.connection);
+  }
+
+  /**
+   * Collects the internal state of the connection into a JSON serializable object, for attaching to bug reports.
+   * Contains the details, statistics, open streams and queue sizes, plus the last events with `ConnectOptions.eventLogSize`.
+   * Safe to call on closed connections, never contains key material
+   */
+  debugDump(): Record<string, unknown> {
+    return lib.debug_dump(this.connection);
+  }
+
+  /**
+   * Moves the endpoint of this connection to a newly bound UDP socket, see `Endpoint.rebind`.
+   * Affects every connection sharing the endpoint. Rejects with `ERR_QUIC_INVALID_STATE` for connections made through a `proxy`
+   */
+  async rebind(options?: RebindOptions): Promise<LocalAddress> {
+    return lib.rebind(this.connection, options);
+  }
+
+  getLocalAddress(): LocalAddress {
+    return lib.local_address(this.connection);
   }
 
   getRemoteIp() {
     return lib.get_remote(this.connection);
   }
+
+  /**
+   * Process-unique identifier of this connection. Also included in close details and errors as `connectionId`
+   */
+  get id(): number {
+    return lib.connection_id(this.connection);
+  }
+
+  /**
+   * Timings of the connection setup, measured with a monotonic clock in the native layer
+   */
+  getHandshakeTimings(): HandshakeTimings {
+    const timings = lib.handshake_timings(this.connection);
+
+    return this.dnsMs === undefined
+      ? timings
+      : { dnsMs: this.dnsMs, ...timings };
+  }
+
+  /**
+   * The QUIC version used by this connection. `name` is `null` for versions without a well-known name
+   */
+  getVersion(): { version: number; name: string | null } {
+    return lib.negotiated_version(this.connection);
+  }
 }
 
 export class PartialStream {
@@ -151,6 +1608,21 @@ export class PartialStream {
     return this.writeClosed;
   }
 
+  /**
+   * Rejects the stream without initializing it: the peer is asked to stop sending and the write-end is reset, both with `errorCode` (default 0).
+   * Cheaper than initializing a stream only to close it, and can be called from within `onStream`. The partial stream can't be initialized afterwards
+   *
+   * Error codes are 62-bit, codes above `Number.MAX_SAFE_INTEGER` have to be passed as a BigInt
+   */
+  discard(errorCode: number | bigint = 0): void {
+    if (this.initialized) {
+      throw new Error("Already initialized!");
+    }
+
+    lib.discard_stream(this.partialStream, errorCode);
+    this.initialized = true;
+  }
+
   /**
    * Turn the partial stream into a full stream
    */
@@ -161,12 +1633,15 @@ export class PartialStream {
 
     const stream = lib.initialize_stream(
       this.partialStream,
-      (...args: Parameters<StreamOptions["onData"]>) =>
-        options.onData.apply(fullStream, args),
+      "ringBuffer" in options
+        ? () => options.onReadable.call(fullStream)
+        : (packet: Uint8Array) => options.onData.call(fullStream, packet),
       (...args: Parameters<StreamOptions["onClose"]>) =>
         options.onClose.apply(fullStream, args),
       (...args: Parameters<StreamOptions["onError"]>) =>
-        options.onError.apply(fullStream, args)
+        options.onError.apply(fullStream, args),
+      "ringBuffer" in options ? new Uint8Array(options.ringBuffer) : undefined,
+      options.onDrain && (() => options.onDrain?.call(fullStream))
     );
 
     this.initialized = true;
@@ -222,30 +1697,93 @@ export class Stream {
 
   /**
    * Attempts to write the whole packet to the stream
+   *
+   * Rejects with a {@link QuicError}:
+   * - `ERR_STREAM_STOPPED`: the peer stopped the stream, with its application error code as `errorCode`
+   * - `ERR_STREAM_WRITE_AFTER_FINISH`: `closeWrite`, `close`, or `reset` was already called on this stream
+   * - `ERR_STREAM_FINISHED`: the write-end was already closed
+   * - `ERR_ZERO_RTT_REJECTED`: the data was sent as 0-RTT data and rejected by the peer
+   * - any connection error code, with `kind` set, when the connection was lost
+   * - `ERR_CANCELLED`: `cancelToken` was cancelled first. Part of the packet may have been written already
+   *
+   * `byteOffset` and `byteLength` select part of `packet` without creating a `subarray`. Throws a RangeError when they're out of its bounds
    */
-  async write(packet: Uint8Array): Promise<void> {
-    if (packet.length > 0) {
-      await lib.write_stream(this.stream, packet);
+  async write(
+    packet: Uint8Array | ArrayBuffer,
+    cancelToken?: CancelToken,
+    byteOffset?: number,
+    byteLength?: number
+  ): Promise<void> {
+    if (packet.byteLength > 0 || byteOffset !== undefined || byteLength !== undefined) {
+      await lib.write_stream(this.stream, packet, cancelToken?.token, byteOffset, byteLength);
     }
   }
 
   /**
-   * Closed the full stream
+   * Queues `packet` without waiting for it to be written, so many small writes don't each take a round trip through the event loop.
+   * Returns `false` once 64 KiB or more are queued, the caller should then wait for `onDrain` before writing more.
+   *
+   * Throws like `write` does when the stream can't be written to anymore. Errors which happen later are passed to `onError`,
+   * with the range of the stream the failed write would have taken up as `byteStart` and `byteEnd`.
+   * Only the first failure is reported, everything queued after it is dropped
+   *
+   * `byteOffset` and `byteLength` select part of `packet` like they do for `write`
    */
-  async close(errorCode = 0) {
-    await lib.close_stream(this.stream, errorCode);
+  writeNowait(packet: Uint8Array | ArrayBuffer, byteOffset?: number, byteLength?: number): boolean {
+    if (packet.byteLength === 0 && byteOffset === undefined && byteLength === undefined) {
+      return true;
+    }
+
+    return lib.write_stream_nowait(this.stream, packet, byteOffset, byteLength);
   }
 
   /**
-   * Closed the write-end of the bidirectional stream turning it into a unidirectional stream
+   * Closed the full stream. Safe to call multiple times, the result describes what this call did
+   *
+   * Error codes are 62-bit, codes above `Number.MAX_SAFE_INTEGER` have to be passed as a BigInt
    */
-  async closeWrite() {
-    if (this.writeClosed) {
-      return;
-    }
+  async close(errorCode: number | bigint = 0): Promise<StreamCloseResult> {
+    const result = await lib.close_stream(this.stream, errorCode);
+    this.writeClosed = true;
+
+    return result;
+  }
+
+  /**
+   * Immediately tears the stream down instead of leaving it to the garbage collector: the read-end is stopped and the write-end reset,
+   * both with `errorCode` (default 0). `onClose` is called with `"destroyed"` as the reason, unless the stream was already closed.
+   * Every later call on the stream rejects with `ERR_HANDLE_CONSUMED`
+   */
+  async destroy(options: { errorCode?: number | bigint } = {}): Promise<void> {
+    await lib.destroy_stream(this.stream, options);
+    this.writeClosed = true;
+  }
+
+  /**
+   * Abruptly ends the write-end of the stream, the peer receives `errorCode` as the reset code.
+   * Doesn't wait for pending writes, they reject with `ERR_STREAM_FINISHED`, even one waiting for flow control
+   */
+  async reset(errorCode: number | bigint) {
+    await lib.reset_stream(this.stream, errorCode);
+    this.writeClosed = true;
+  }
 
-    await lib.close_write(this.stream);
+  /**
+   * Stops reading from the stream and asks the peer to stop sending with `errorCode`
+   */
+  stopRead(errorCode: number | bigint) {
+    lib.stop_read(this.stream, errorCode);
+  }
+
+  /**
+   * Closed the write-end of the bidirectional stream turning it into a unidirectional stream.
+   * Safe to call multiple times, resolves with `alreadyClosed: true` if the write-end was already closed
+   */
+  async closeWrite(): Promise<StreamCloseResult> {
+    const result = await lib.close_write(this.stream);
     this.writeClosed = true;
+
+    return result;
   }
 
   getConnection() {
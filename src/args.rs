@@ -0,0 +1,135 @@
+use neon::{prelude::*, types::JsBigInt};
+
+use crate::errors::{Detail, NativeError};
+
+/// A JavaScript type an argument can be validated against
+pub trait Expected: Value {
+    /// Used in the error message, e.g. "expected port to be a number"
+    const DESCRIPTION: &'static str;
+}
+
+impl Expected for JsNumber {
+    const DESCRIPTION: &'static str = "a number";
+}
+
+impl Expected for JsString {
+    const DESCRIPTION: &'static str = "a string";
+}
+
+impl Expected for JsBoolean {
+    const DESCRIPTION: &'static str = "a boolean";
+}
+
+impl Expected for JsFunction {
+    const DESCRIPTION: &'static str = "a function";
+}
+
+impl Expected for JsArray {
+    const DESCRIPTION: &'static str = "an array";
+}
+
+impl Expected for JsObject {
+    const DESCRIPTION: &'static str = "an object";
+}
+
+impl Expected for JsUint8Array {
+    const DESCRIPTION: &'static str = "a Uint8Array";
+}
+
+// Describes the type of a value for error messages. Close to `typeof`, but arrays, `null` and
+//  `Uint8Array`s are called out since those are the usual mix-ups
+pub fn type_of<'a, C: Context<'a>>(cx: &mut C, value: Handle<JsValue>) -> &'static str {
+    if value.is_a::<JsUndefined, _>(cx) {
+        "undefined"
+    } else if value.is_a::<JsNull, _>(cx) {
+        "null"
+    } else if value.is_a::<JsBoolean, _>(cx) {
+        "boolean"
+    } else if value.is_a::<JsNumber, _>(cx) {
+        "number"
+    } else if value.is_a::<JsString, _>(cx) {
+        "string"
+    } else if value.is_a::<JsBigInt, _>(cx) {
+        "bigint"
+    } else if value.is_a::<JsFunction, _>(cx) {
+        "function"
+    } else if value.is_a::<JsArray, _>(cx) {
+        "array"
+    } else if value.is_a::<JsUint8Array, _>(cx) {
+        "Uint8Array"
+    } else {
+        "object"
+    }
+}
+
+/// Throws a `TypeError` like "connect: expected options.port to be a number, got string"
+pub fn check<'a, C: Context<'a>, T: Expected>(
+    cx: &mut C,
+    function: &str,
+    name: &str,
+    value: Handle<'a, JsValue>,
+) -> JsResult<'a, T> {
+    match value.downcast::<T, _>(cx) {
+        Ok(value) => Ok(value),
+        Err(_) => {
+            let actual = type_of(cx, value);
+
+            NativeError::type_error(format!(
+                "{function}: expected {name} to be {}, got {actual}",
+                T::DESCRIPTION
+            ))
+            .with("operation", Detail::String(function.to_owned()))
+            .throw(cx)
+        }
+    }
+}
+
+/// Required positional argument. A missing argument is reported as `undefined`
+pub fn argument<'a, T: Expected>(
+    cx: &mut FunctionContext<'a>,
+    function: &str,
+    index: usize,
+    name: &str,
+) -> JsResult<'a, T> {
+    let value = match cx.argument_opt(index) {
+        Some(value) => value,
+        None => cx.undefined().upcast(),
+    };
+
+    check(cx, function, name, value)
+}
+
+fn is_nullish<'a, C: Context<'a>>(cx: &mut C, value: Handle<JsValue>) -> bool {
+    value.is_a::<JsUndefined, _>(cx) || value.is_a::<JsNull, _>(cx)
+}
+
+/// Optional positional argument. Returns `None` when it is missing, `undefined` or `null`
+pub fn optional_argument<'a, T: Expected>(
+    cx: &mut FunctionContext<'a>,
+    function: &str,
+    index: usize,
+    name: &str,
+) -> NeonResult<Option<Handle<'a, T>>> {
+    match cx.argument_opt(index) {
+        Some(value) if !is_nullish(cx, value) => check(cx, function, name, value).map(Some),
+        _ => Ok(None),
+    }
+}
+
+/// Optional property of an options object. Returns `None` when it is `undefined` or `null`.
+///  `object_name` is used to build the full name in error messages, e.g. "options.cwndWarning"
+pub fn optional_property<'a, C: Context<'a>, T: Expected>(
+    cx: &mut C,
+    function: &str,
+    object: Handle<JsObject>,
+    object_name: &str,
+    key: &str,
+) -> NeonResult<Option<Handle<'a, T>>> {
+    let value: Handle<JsValue> = object.get(cx, key)?;
+
+    if is_nullish(cx, value) {
+        return Ok(None);
+    }
+
+    check(cx, function, &format!("{object_name}.{key}"), value).map(Some)
+}
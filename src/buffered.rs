@@ -0,0 +1,78 @@
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use tokio::sync::Notify;
+
+/// Bytes of stream data sent over a connection's channel which JavaScript didn't receive yet.
+///  With a limit, reading stops on all of the connection's streams once it's exceeded, until the
+///  level drops below half of it
+#[derive(Debug, Default)]
+pub struct BufferedBytes {
+    level: AtomicUsize,
+    limit: Option<usize>,
+    paused: AtomicBool,
+    // Set the first time the limit is exceeded, the warning is only sent once
+    warned: AtomicBool,
+    resumed: Notify,
+}
+
+impl BufferedBytes {
+    pub fn new(limit: Option<usize>) -> Self {
+        Self {
+            limit,
+            ..Default::default()
+        }
+    }
+
+    pub fn level(&self) -> usize {
+        self.level.load(Ordering::Acquire)
+    }
+
+    pub fn limit(&self) -> Option<usize> {
+        self.limit
+    }
+
+    /// Counts `bytes` which were sent to JavaScript. Returns `true` the first time this exceeds the
+    ///  limit
+    pub fn add(&self, bytes: usize) -> bool {
+        let level = self.level.fetch_add(bytes, Ordering::AcqRel) + bytes;
+
+        match self.limit {
+            Some(limit) if level > limit => {
+                self.paused.store(true, Ordering::Release);
+
+                !self.warned.swap(true, Ordering::AcqRel)
+            }
+            _ => false,
+        }
+    }
+
+    /// Called once JavaScript received `bytes`, or they were dropped
+    pub fn remove(&self, bytes: usize) {
+        let level = self.level.fetch_sub(bytes, Ordering::AcqRel) - bytes;
+
+        let Some(limit) = self.limit else {
+            return;
+        };
+
+        if level < limit / 2 && self.paused.swap(false, Ordering::AcqRel) {
+            self.resumed.notify_waiters();
+        }
+    }
+
+    /// Resolves right away unless the limit was exceeded, otherwise once the level dropped below
+    ///  the low-water mark
+    pub async fn wait(&self) {
+        loop {
+            let resumed = self.resumed.notified();
+            tokio::pin!(resumed);
+            // Registered before checking, so a `remove` in between isn't missed
+            resumed.as_mut().enable();
+
+            if !self.paused.load(Ordering::Acquire) {
+                return;
+            }
+
+            resumed.await;
+        }
+    }
+}
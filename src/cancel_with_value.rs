@@ -18,9 +18,18 @@ impl<T: Clone + Default> CancelWithValue<T> {
 }
 
 impl<T: Clone> CancelWithValue<T> {
-    pub fn cancel(&self, value: T) {
-        *self.value.write().unwrap() = value;
+    // Returns `false` without changing the value if it was already cancelled
+    pub fn cancel(&self, value: T) -> bool {
+        let mut guard = self.value.write().unwrap();
+
+        if self.token.is_cancelled() {
+            return false;
+        }
+
+        *guard = value;
         self.token.cancel();
+
+        true
     }
 
     pub async fn cancelled(&self) -> T {
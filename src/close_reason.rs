@@ -0,0 +1,156 @@
+use std::sync::Arc;
+
+use neon::{prelude::*, types::JsBigInt};
+use quinn::ConnectionError;
+use tokio::sync::watch;
+
+use crate::{
+    errors::{transport_error_details, ConnectionErrorKind, Detail, ErrorCode, NativeError},
+    tag::ConnectionTag,
+};
+
+/// Why a connection was closed. This is what gets delivered to `on_close`, `await_close` and
+///  `close_reason`, so all of them agree on the contents.
+#[derive(Clone, Debug)]
+pub struct CloseReason {
+    pub tag: ConnectionTag,
+    pub error: ConnectionError,
+    /// Whether the endpoint finished sending and receiving everything for the connection before
+    ///  `on_close` was called. Only known once the close task is done, `false` until then
+    pub drained: bool,
+    /// Whether the connection was closed by `autoCloseIdleMs`, reported as the `idleAutoClose` kind
+    pub idle_auto_close: bool,
+}
+
+impl CloseReason {
+    pub fn new(tag: ConnectionTag, error: ConnectionError) -> Self {
+        Self {
+            tag,
+            error,
+            drained: false,
+            idle_auto_close: false,
+        }
+    }
+
+    /// The `kind` of the close details
+    pub fn kind(&self) -> ConnectionErrorKind {
+        match self.idle_auto_close {
+            true => ConnectionErrorKind::IdleAutoClose,
+            false => ConnectionErrorKind::from(&self.error),
+        }
+    }
+
+    pub fn to_object<'a, C: Context<'a>>(&self, cx: &mut C) -> JsResult<'a, JsObject> {
+        let result = cx.empty_object();
+
+        let reason = cx.string(self.error.to_string());
+        let kind = cx.string(self.kind().as_str());
+        let drained = cx.boolean(self.drained);
+
+        self.tag.apply(cx, result)?;
+        result.set(cx, "reason", reason)?;
+        result.set(cx, "kind", kind)?;
+        result.set(cx, "drained", drained)?;
+
+        // The reason phrase is arbitrary bytes, `reason` only contains a lossy display version
+        let close = match &self.error {
+            ConnectionError::ApplicationClosed(close) => {
+                Some((close.error_code.into_inner(), &close.reason))
+            }
+            ConnectionError::ConnectionClosed(close) => {
+                Some((u64::from(close.error_code), &close.reason))
+            }
+            _ => None,
+        };
+
+        if let Some((error_code, reason_bytes)) = close {
+            let error_code = JsBigInt::from_u64(cx, error_code);
+            let reason_bytes = JsUint8Array::from_slice(cx, reason_bytes)?;
+
+            result.set(cx, "errorCode", error_code)?;
+            result.set(cx, "reasonBytes", reason_bytes)?;
+        }
+
+        for (key, value) in transport_error_details(&self.error) {
+            let value = value.to_js(cx)?;
+            result.set(cx, key, value)?;
+        }
+
+        Ok(result)
+    }
+}
+
+/// Why a connection closed, as seen from anything belonging to it. Streams hold one so operations
+///  which fail because the connection went away can reject with the same details as `on_close`.
+#[derive(Clone)]
+pub struct CloseWatch {
+    tag: ConnectionTag,
+    connection: Arc<quinn::Connection>,
+    // Set by the close task once the connection is closed and `on_close` is about to be called
+    pub closed: watch::Receiver<Option<CloseReason>>,
+}
+
+impl CloseWatch {
+    pub fn new(
+        tag: ConnectionTag,
+        connection: Arc<quinn::Connection>,
+        closed: watch::Receiver<Option<CloseReason>>,
+    ) -> Self {
+        Self {
+            tag,
+            connection,
+            closed,
+        }
+    }
+
+    pub fn tag(&self) -> &ConnectionTag {
+        &self.tag
+    }
+
+    // Prefers the reason recorded by the close task so the result matches what `on_close` received.
+    //  Falls back to quinn when the connection is closed, but the close task did not get to it yet.
+    pub fn reason(&self) -> Option<CloseReason> {
+        (*self.closed.borrow()).clone().or_else(|| {
+            self.connection
+                .close_reason()
+                .map(|error| CloseReason::new(self.tag.clone(), error))
+        })
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.closed.borrow().is_some()
+    }
+
+    /// Why the connection closed, once the close task recorded it
+    pub async fn wait_closed(&self) -> CloseReason {
+        let mut closed = self.closed.clone();
+        let _ = closed.wait_for(Option::is_some).await;
+
+        // The close task is aborted when the connection is garbage collected, which closes it
+        self.reason()
+            .unwrap_or_else(|| CloseReason::new(self.tag.clone(), ConnectionError::LocallyClosed))
+    }
+
+    /// Checked by exports which need a usable connection, before doing any work. Fails as soon
+    ///  as the connection starts closing, not only once `on_close` was called
+    pub fn ensure_open(&self) -> Result<(), NativeError> {
+        match self.reason() {
+            None => Ok(()),
+            Some(reason) => Err(NativeError::new(
+                ErrorCode::ConnectionClosed,
+                format!("The connection is closed: {}", reason.error),
+            )
+            .with("closeReason", Detail::CloseReason(Box::new(reason)))),
+        }
+    }
+
+    /// Error for an operation that failed because the connection closed, with the close details
+    ///  attached as `closeReason`
+    pub fn error(&self, error: &ConnectionError) -> NativeError {
+        let reason = self
+            .reason()
+            .unwrap_or_else(|| CloseReason::new(self.tag.clone(), error.clone()));
+
+        NativeError::from(error).with("closeReason", Detail::CloseReason(Box::new(reason)))
+    }
+}
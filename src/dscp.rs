@@ -0,0 +1,79 @@
+use std::io;
+
+/// Sets the DSCP of every datagram sent on `socket`, the upper six bits of `IP_TOS` and
+///  `IPV6_TCLASS`. The ECN bits are left at zero
+#[cfg(unix)]
+pub fn set(socket: &socket2::Socket, dscp: u8, ipv6: bool) -> io::Result<()> {
+    let tos = u32::from(dscp) << 2;
+
+    if !ipv6 {
+        return socket.set_tos(tos);
+    }
+
+    socket.set_tclass_v6(tos)?;
+    // A dual-stack socket sends to IPv4-mapped addresses with the IPv4 option, which not every
+    //  platform lets an IPv6 socket set
+    let _ = socket.set_tos(tos);
+
+    Ok(())
+}
+
+// Setting `IP_TOS` has no effect on Windows, marking packets needs the QoS API there
+#[cfg(not(unix))]
+pub fn set(_socket: &socket2::Socket, _dscp: u8, _ipv6: bool) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "DSCP marking is not supported on this platform",
+    ))
+}
+
+/// DSCP the kernel applied to `socket`
+#[cfg(unix)]
+pub fn get(socket: &socket2::Socket, ipv6: bool) -> io::Result<u8> {
+    let tos = if ipv6 {
+        socket.tclass_v6()?
+    } else {
+        socket.tos()?
+    };
+
+    Ok((tos >> 2) as u8)
+}
+
+#[cfg(not(unix))]
+pub fn get(_socket: &socket2::Socket, _ipv6: bool) -> io::Result<u8> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "DSCP marking is not supported on this platform",
+    ))
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    fn socket(domain: socket2::Domain) -> socket2::Socket {
+        socket2::Socket::new(domain, socket2::Type::DGRAM, None).unwrap()
+    }
+
+    #[test]
+    fn round_trips_through_the_socket() {
+        // Expedited forwarding
+        const EF: u8 = 46;
+
+        let ipv4 = socket(socket2::Domain::IPV4);
+        set(&ipv4, EF, false).unwrap();
+        assert_eq!(get(&ipv4, false).unwrap(), EF);
+
+        let ipv6 = socket(socket2::Domain::IPV6);
+        set(&ipv6, EF, true).unwrap();
+        assert_eq!(get(&ipv6, true).unwrap(), EF);
+    }
+
+    #[test]
+    fn leaves_the_ecn_bits_at_zero() {
+        let socket = socket(socket2::Domain::IPV4);
+        set(&socket, 0b11_1111, false).unwrap();
+
+        assert_eq!(socket.tos().unwrap() & 0b11, 0);
+    }
+}
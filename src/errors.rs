@@ -0,0 +1,588 @@
+use neon::{prelude::*, types::JsBigInt};
+use quinn::{ClosedStream, ConnectionError, ReadError, TransportErrorCode, WriteError};
+
+use crate::{close_reason::CloseReason, quic::ClientError, tag::ConnectionTag};
+
+/// Stable codes set as the `code` property on every error produced by the native layer.
+///  Unlike the messages, these don't change between quinn versions.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ErrorCode {
+    /// The handshake did not complete in time
+    ConnectTimeout,
+    /// The connection could not be set up, e.g. because of an invalid configuration
+    Connect,
+    /// The hostname is not a valid TLS server name
+    InvalidServerName,
+    /// The endpoint used for the connection is shutting down
+    EndpointClosed,
+    /// The endpoint ran out of connection IDs
+    TooManyConnections,
+    /// The configured QUIC version is not supported
+    UnsupportedVersion,
+    /// The remote address can't be connected to, e.g. port 0 or an IPv6 address from an IPv4 socket
+    InvalidRemoteAddress,
+    /// The connection was lost or closed while the operation was in progress
+    ConnectionLost,
+    /// The peer reset the stream, the application error code is set as `errorCode`
+    StreamReset,
+    /// The peer stopped the stream, the application error code is set as `errorCode`
+    StreamStopped,
+    /// The stream was already finished or reset locally
+    StreamFinished,
+    /// A write was attempted after the write-end was closed by this side
+    WriteAfterFinish,
+    /// 0-RTT data was rejected by the peer
+    ZeroRttRejected,
+    /// An unordered read was done on a stream after which it was read in order
+    IllegalOrderedRead,
+    /// Certificates, keys or the TLS handshake
+    Tls,
+    /// Socket and file operations
+    Io,
+    /// An argument was of the wrong type or out of range
+    InvalidArgument,
+    /// Misuse of the API, like reading from a stream out of order
+    InvalidState,
+    /// A handle was used after it was consumed, e.g. initializing a partial stream twice
+    HandleConsumed,
+    /// The operation needs an open connection, but it was already closed
+    ConnectionClosed,
+    /// `shutdown` was called, the module can't be used anymore
+    ClientShutDown,
+    /// Every connection of the pool is acquired and it has reached `maxSize`
+    PoolFull,
+    /// The cancel token passed to the operation was cancelled, the reason is set as `reason`
+    Cancelled,
+    /// The SOCKS5 proxy couldn't be reached, or closed the control connection
+    ProxyConnect,
+    /// The SOCKS5 proxy rejected the credentials, or wants an unsupported authentication method
+    ProxyAuth,
+    /// The SOCKS5 proxy refused UDP ASSOCIATE, its reply code is set as `replyCode`
+    ProxyRejected,
+    /// The SOCKS5 proxy sent an invalid response
+    ProxyProtocol,
+}
+
+impl ErrorCode {
+    // Has to list every variant, `constants` is the table exported to JavaScript
+    const ALL: [ErrorCode; 27] = [
+        ErrorCode::ConnectTimeout,
+        ErrorCode::Connect,
+        ErrorCode::InvalidServerName,
+        ErrorCode::EndpointClosed,
+        ErrorCode::TooManyConnections,
+        ErrorCode::UnsupportedVersion,
+        ErrorCode::InvalidRemoteAddress,
+        ErrorCode::ConnectionLost,
+        ErrorCode::StreamReset,
+        ErrorCode::StreamStopped,
+        ErrorCode::StreamFinished,
+        ErrorCode::WriteAfterFinish,
+        ErrorCode::ZeroRttRejected,
+        ErrorCode::IllegalOrderedRead,
+        ErrorCode::Tls,
+        ErrorCode::Io,
+        ErrorCode::InvalidArgument,
+        ErrorCode::InvalidState,
+        ErrorCode::HandleConsumed,
+        ErrorCode::ConnectionClosed,
+        ErrorCode::ClientShutDown,
+        ErrorCode::PoolFull,
+        ErrorCode::Cancelled,
+        ErrorCode::ProxyConnect,
+        ErrorCode::ProxyAuth,
+        ErrorCode::ProxyRejected,
+        ErrorCode::ProxyProtocol,
+    ];
+
+    /// Object mapping every code to itself, with the `ConnectionErrorKind` constants nested
+    pub fn constants<'a, C: Context<'a>>(cx: &mut C) -> JsResult<'a, JsObject> {
+        let result = cx.empty_object();
+
+        for code in Self::ALL {
+            let value = cx.string(code.as_str());
+            result.set(cx, code.as_str(), value)?;
+        }
+
+        let kinds = ConnectionErrorKind::constants(cx)?;
+        result.set(cx, "ConnectionErrorKind", kinds)?;
+
+        Ok(result)
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ErrorCode::ConnectTimeout => "ERR_QUIC_CONNECT_TIMEOUT",
+            ErrorCode::Connect => "ERR_QUIC_CONNECT",
+            ErrorCode::InvalidServerName => "ERR_INVALID_SERVER_NAME",
+            ErrorCode::EndpointClosed => "ERR_ENDPOINT_CLOSED",
+            ErrorCode::TooManyConnections => "ERR_TOO_MANY_CONNECTIONS",
+            ErrorCode::UnsupportedVersion => "ERR_UNSUPPORTED_VERSION",
+            ErrorCode::InvalidRemoteAddress => "ERR_INVALID_REMOTE_ADDRESS",
+            ErrorCode::ConnectionLost => "ERR_QUIC_CONNECTION_LOST",
+            ErrorCode::StreamReset => "ERR_STREAM_RESET",
+            ErrorCode::StreamStopped => "ERR_STREAM_STOPPED",
+            ErrorCode::StreamFinished => "ERR_STREAM_FINISHED",
+            ErrorCode::WriteAfterFinish => "ERR_STREAM_WRITE_AFTER_FINISH",
+            ErrorCode::ZeroRttRejected => "ERR_ZERO_RTT_REJECTED",
+            ErrorCode::IllegalOrderedRead => "ERR_ILLEGAL_ORDERED_READ",
+            ErrorCode::Tls => "ERR_QUIC_TLS",
+            ErrorCode::Io => "ERR_QUIC_IO",
+            ErrorCode::InvalidArgument => "ERR_QUIC_INVALID_ARGUMENT",
+            ErrorCode::InvalidState => "ERR_QUIC_INVALID_STATE",
+            ErrorCode::HandleConsumed => "ERR_HANDLE_CONSUMED",
+            ErrorCode::ConnectionClosed => "ERR_CONNECTION_CLOSED",
+            ErrorCode::ClientShutDown => "ERR_CLIENT_SHUT_DOWN",
+            ErrorCode::PoolFull => "ERR_QUIC_POOL_FULL",
+            ErrorCode::Cancelled => "ERR_CANCELLED",
+            ErrorCode::ProxyConnect => "ERR_PROXY_CONNECT",
+            ErrorCode::ProxyAuth => "ERR_PROXY_AUTH",
+            ErrorCode::ProxyRejected => "ERR_PROXY_REJECTED",
+            ErrorCode::ProxyProtocol => "ERR_PROXY_PROTOCOL",
+        }
+    }
+}
+
+/// The JavaScript constructor used for an error
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ErrorKind {
+    Error,
+    TypeError,
+    RangeError,
+}
+
+/// A value attached to an error as an extra property. Owned and `Send`, so it can be created on
+///  the runtime and turned into a JavaScript value once back on the main thread.
+#[derive(Clone, Debug)]
+pub enum Detail {
+    Null,
+    Bool(bool),
+    Number(f64),
+    BigInt(u64),
+    String(String),
+    Bytes(Vec<u8>),
+    Object(Vec<(&'static str, Detail)>),
+    CloseReason(Box<CloseReason>),
+}
+
+impl Detail {
+    pub fn to_js<'a, C: Context<'a>>(&self, cx: &mut C) -> JsResult<'a, JsValue> {
+        Ok(match self {
+            Detail::Null => cx.null().upcast(),
+            Detail::Bool(v) => cx.boolean(*v).upcast(),
+            Detail::Number(v) => cx.number(*v).upcast(),
+            Detail::BigInt(v) => JsBigInt::from_u64(cx, *v).upcast(),
+            Detail::String(v) => cx.string(v).upcast(),
+            Detail::Bytes(v) => JsUint8Array::from_slice(cx, v)?.upcast(),
+            Detail::Object(entries) => {
+                let object = cx.empty_object();
+
+                for (key, value) in entries {
+                    let value = value.to_js(cx)?;
+                    object.set(cx, *key, value)?;
+                }
+
+                object.upcast()
+            }
+            Detail::CloseReason(reason) => reason.to_object(cx)?.upcast(),
+        })
+    }
+}
+
+/// What was being attempted when an error happened. Attached to errors of exports so a rejection
+///  can be traced back to the operation, connection and stream it came from
+#[derive(Clone, Debug)]
+pub struct ErrorContext {
+    operation: &'static str,
+    tag: Option<ConnectionTag>,
+    stream_id: Option<u64>,
+}
+
+impl ErrorContext {
+    pub fn new(operation: &'static str) -> Self {
+        Self {
+            operation,
+            tag: None,
+            stream_id: None,
+        }
+    }
+
+    pub fn connection(mut self, tag: &ConnectionTag) -> Self {
+        self.tag = Some(tag.clone());
+        self
+    }
+
+    pub fn stream(mut self, id: u64) -> Self {
+        self.stream_id = Some(id);
+        self
+    }
+}
+
+/// An error which is turned into a JavaScript `Error` with a `code` and extra detail properties.
+///  All errors thrown or rejected by the native layer go through this type.
+#[derive(Clone, Debug)]
+pub struct NativeError {
+    pub code: ErrorCode,
+    pub kind: ErrorKind,
+    pub message: String,
+    pub details: Vec<(&'static str, Detail)>,
+}
+
+impl NativeError {
+    pub fn new(code: ErrorCode, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            kind: ErrorKind::Error,
+            message: message.into(),
+            details: Vec::new(),
+        }
+    }
+
+    pub fn type_error(message: impl Into<String>) -> Self {
+        Self {
+            kind: ErrorKind::TypeError,
+            ..Self::new(ErrorCode::InvalidArgument, message)
+        }
+    }
+
+    pub fn range_error(message: impl Into<String>) -> Self {
+        Self {
+            kind: ErrorKind::RangeError,
+            ..Self::new(ErrorCode::InvalidArgument, message)
+        }
+    }
+
+    pub fn with(mut self, key: &'static str, value: Detail) -> Self {
+        self.details.push((key, value));
+        self
+    }
+
+    pub fn to_error<'a, C: Context<'a>>(&self, cx: &mut C) -> JsResult<'a, JsError> {
+        let error = match self.kind {
+            ErrorKind::Error => cx.error(&self.message)?,
+            ErrorKind::TypeError => cx.type_error(&self.message)?,
+            ErrorKind::RangeError => cx.range_error(&self.message)?,
+        };
+
+        let code = cx.string(self.code.as_str());
+        error.set(cx, "code", code)?;
+
+        for (key, value) in &self.details {
+            let value = value.to_js(cx)?;
+            error.set(cx, *key, value)?;
+        }
+
+        Ok(error)
+    }
+
+    /// Sets `operation`, the connection and the `streamId` of the context
+    pub fn context(self, context: &ErrorContext) -> Self {
+        let mut result = self.with("operation", Detail::String(context.operation.to_owned()));
+
+        if let Some(tag) = &context.tag {
+            result = result.tagged(tag);
+        }
+
+        if let Some(stream_id) = context.stream_id {
+            result = result.with("streamId", Detail::BigInt(stream_id));
+        }
+
+        result
+    }
+
+    /// Sets `connectionId` and `label` of the connection the error belongs to
+    pub fn tagged(self, tag: &ConnectionTag) -> Self {
+        self.with("connectionId", Detail::Number(tag.id as f64))
+            .with("label", Detail::String(tag.label.to_string()))
+    }
+
+    /// Value to pass to a callback on the JavaScript thread. Never throws: if the error object
+    ///  can't be created, e.g. while the VM is shutting down, the message is passed as a plain
+    ///  string instead. Callbacks should always use this rather than `to_error`.
+    pub fn to_callback_value<'a, C: Context<'a>>(&self, cx: &mut C) -> Handle<'a, JsValue> {
+        match cx.try_catch(|cx| self.to_error(cx)) {
+            Ok(error) => error.upcast(),
+            Err(_) => cx.string(&self.message).upcast(),
+        }
+    }
+
+    pub fn throw<'a, C: Context<'a>, T>(&self, cx: &mut C) -> NeonResult<T> {
+        let error = self.to_error(cx)?;
+
+        cx.throw(error)
+    }
+}
+
+/// Machine readable variant of a `quinn::ConnectionError`. Exported to JavaScript as the
+///  `ConnectionErrorKind` constants and set as `kind` on connection errors and close details.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConnectionErrorKind {
+    TimedOut,
+    /// Includes the peer closing the connection with a transport error code
+    TransportError,
+    ApplicationClosed,
+    Reset,
+    VersionMismatch,
+    LocallyClosed,
+    CidsExhausted,
+    /// Closed locally by `autoCloseIdleMs` after the connection went without streams. Never
+    ///  derived from a `quinn::ConnectionError`, only set on close details
+    IdleAutoClose,
+}
+
+impl ConnectionErrorKind {
+    const ALL: [ConnectionErrorKind; 8] = [
+        ConnectionErrorKind::TimedOut,
+        ConnectionErrorKind::TransportError,
+        ConnectionErrorKind::ApplicationClosed,
+        ConnectionErrorKind::Reset,
+        ConnectionErrorKind::VersionMismatch,
+        ConnectionErrorKind::LocallyClosed,
+        ConnectionErrorKind::CidsExhausted,
+        ConnectionErrorKind::IdleAutoClose,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ConnectionErrorKind::TimedOut => "timedOut",
+            ConnectionErrorKind::TransportError => "transportError",
+            ConnectionErrorKind::ApplicationClosed => "applicationClosed",
+            ConnectionErrorKind::Reset => "reset",
+            ConnectionErrorKind::VersionMismatch => "versionMismatch",
+            ConnectionErrorKind::LocallyClosed => "locallyClosed",
+            ConnectionErrorKind::CidsExhausted => "cidsExhausted",
+            ConnectionErrorKind::IdleAutoClose => "idleAutoClose",
+        }
+    }
+
+    fn constant_name(&self) -> &'static str {
+        match self {
+            ConnectionErrorKind::TimedOut => "TIMED_OUT",
+            ConnectionErrorKind::TransportError => "TRANSPORT_ERROR",
+            ConnectionErrorKind::ApplicationClosed => "APPLICATION_CLOSED",
+            ConnectionErrorKind::Reset => "RESET",
+            ConnectionErrorKind::VersionMismatch => "VERSION_MISMATCH",
+            ConnectionErrorKind::LocallyClosed => "LOCALLY_CLOSED",
+            ConnectionErrorKind::CidsExhausted => "CIDS_EXHAUSTED",
+            ConnectionErrorKind::IdleAutoClose => "IDLE_AUTO_CLOSE",
+        }
+    }
+
+    /// Object mapping the constant names to their values
+    pub fn constants<'a, C: Context<'a>>(cx: &mut C) -> JsResult<'a, JsObject> {
+        let result = cx.empty_object();
+
+        for kind in Self::ALL {
+            let value = cx.string(kind.as_str());
+            result.set(cx, kind.constant_name(), value)?;
+        }
+
+        Ok(result)
+    }
+}
+
+impl From<&ConnectionError> for ConnectionErrorKind {
+    fn from(error: &ConnectionError) -> Self {
+        match error {
+            ConnectionError::TimedOut => ConnectionErrorKind::TimedOut,
+            ConnectionError::TransportError(_) | ConnectionError::ConnectionClosed(_) => {
+                ConnectionErrorKind::TransportError
+            }
+            ConnectionError::ApplicationClosed(_) => ConnectionErrorKind::ApplicationClosed,
+            ConnectionError::Reset => ConnectionErrorKind::Reset,
+            ConnectionError::VersionMismatch => ConnectionErrorKind::VersionMismatch,
+            ConnectionError::LocallyClosed => ConnectionErrorKind::LocallyClosed,
+            ConnectionError::CidsExhausted => ConnectionErrorKind::CidsExhausted,
+        }
+    }
+}
+
+impl From<&ConnectionError> for NativeError {
+    fn from(error: &ConnectionError) -> Self {
+        let code = match error {
+            ConnectionError::TransportError(e) if is_crypto_error(e.code) => ErrorCode::Tls,
+            ConnectionError::ConnectionClosed(e) if is_crypto_error(e.error_code) => ErrorCode::Tls,
+            _ => ErrorCode::ConnectionLost,
+        };
+
+        let mut result = Self::new(code, error.to_string()).with(
+            "kind",
+            Detail::String(ConnectionErrorKind::from(error).as_str().to_owned()),
+        );
+        result.details.extend(transport_error_details(error));
+
+        result
+    }
+}
+
+/// IETF name of a QUIC transport error code (RFC 9000 section 20.1)
+pub fn transport_error_name(code: u64) -> Option<&'static str> {
+    Some(match code {
+        0x00 => "NO_ERROR",
+        0x01 => "INTERNAL_ERROR",
+        0x02 => "CONNECTION_REFUSED",
+        0x03 => "FLOW_CONTROL_ERROR",
+        0x04 => "STREAM_LIMIT_ERROR",
+        0x05 => "STREAM_STATE_ERROR",
+        0x06 => "FINAL_SIZE_ERROR",
+        0x07 => "FRAME_ENCODING_ERROR",
+        0x08 => "TRANSPORT_PARAMETER_ERROR",
+        0x09 => "CONNECTION_ID_LIMIT_ERROR",
+        0x0a => "PROTOCOL_VIOLATION",
+        0x0b => "INVALID_TOKEN",
+        0x0c => "APPLICATION_ERROR",
+        0x0d => "CRYPTO_BUFFER_EXCEEDED",
+        0x0e => "KEY_UPDATE_ERROR",
+        0x0f => "AEAD_LIMIT_REACHED",
+        0x10 => "NO_VIABLE_PATH",
+        0x100..=0x1ff => "CRYPTO_ERROR",
+        _ => return None,
+    })
+}
+
+/// Fields describing a transport error, both for one detected locally and one sent by the peer.
+///  Empty for every other kind of connection error
+pub fn transport_error_details(error: &ConnectionError) -> Vec<(&'static str, Detail)> {
+    let (code, frame_type, reason, remote) = match error {
+        ConnectionError::TransportError(error) => {
+            (error.code, error.frame, error.reason.clone(), false)
+        }
+        ConnectionError::ConnectionClosed(close) => (
+            close.error_code,
+            close.frame_type,
+            String::from_utf8_lossy(&close.reason).into_owned(),
+            true,
+        ),
+        _ => return Vec::new(),
+    };
+
+    let code = u64::from(code);
+
+    vec![
+        ("transportErrorCode", Detail::BigInt(code)),
+        (
+            "transportErrorName",
+            transport_error_name(code).map_or(Detail::Null, |name| Detail::String(name.into())),
+        ),
+        (
+            "frameType",
+            frame_type.map_or(Detail::Null, |frame| Detail::String(frame.to_string())),
+        ),
+        ("transportReason", Detail::String(reason)),
+        ("remote", Detail::Bool(remote)),
+    ]
+}
+
+// TLS alerts are sent as transport errors in the 0x0100-0x01ff range
+fn is_crypto_error(code: TransportErrorCode) -> bool {
+    (0x100..0x200).contains(&u64::from(code))
+}
+
+impl From<&ClientError> for NativeError {
+    fn from(error: &ClientError) -> Self {
+        match error {
+            // Keeps the `kind` of the connection error
+            ClientError::QuinnConnection(e) if !matches!(e, ConnectionError::TimedOut) => e.into(),
+            ClientError::Proxy(e) => match e.reply_code() {
+                Some(code) => Self::new(error.code(), error.to_string())
+                    .with("replyCode", Detail::Number(code as f64)),
+                None => Self::new(error.code(), error.to_string()),
+            },
+            _ => Self::new(error.code(), error.to_string()),
+        }
+    }
+}
+
+impl From<&WriteError> for NativeError {
+    fn from(error: &WriteError) -> Self {
+        match error {
+            WriteError::Stopped(code) => Self::new(ErrorCode::StreamStopped, error.to_string())
+                .with("errorCode", Detail::BigInt(code.into_inner())),
+            WriteError::ConnectionLost(e) => e.into(),
+            WriteError::ClosedStream => Self::new(ErrorCode::StreamFinished, error.to_string()),
+            WriteError::ZeroRttRejected => Self::new(ErrorCode::ZeroRttRejected, error.to_string()),
+        }
+    }
+}
+
+impl From<&ReadError> for NativeError {
+    fn from(error: &ReadError) -> Self {
+        match error {
+            ReadError::Reset(code) => Self::new(ErrorCode::StreamReset, error.to_string())
+                .with("errorCode", Detail::BigInt(code.into_inner())),
+            ReadError::ConnectionLost(e) => e.into(),
+            ReadError::ClosedStream => Self::new(ErrorCode::StreamFinished, error.to_string()),
+            ReadError::IllegalOrderedRead => {
+                Self::new(ErrorCode::IllegalOrderedRead, error.to_string())
+            }
+            ReadError::ZeroRttRejected => Self::new(ErrorCode::ZeroRttRejected, error.to_string()),
+        }
+    }
+}
+
+impl From<&ClosedStream> for NativeError {
+    fn from(error: &ClosedStream) -> Self {
+        Self::new(ErrorCode::StreamFinished, error.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use quinn::VarInt;
+
+    use super::*;
+
+    fn detail<'e>(error: &'e NativeError, key: &str) -> Option<&'e Detail> {
+        error
+            .details
+            .iter()
+            .find(|(name, _)| *name == key)
+            .map(|(_, value)| value)
+    }
+
+    #[test]
+    fn reset_read_keeps_the_error_code() {
+        let error = NativeError::from(&ReadError::Reset(VarInt::from_u32(7)));
+
+        assert_eq!(error.code, ErrorCode::StreamReset);
+        assert!(matches!(
+            detail(&error, "errorCode"),
+            Some(Detail::BigInt(7))
+        ));
+    }
+
+    #[test]
+    fn lost_connection_read_nests_the_connection_error_kind() {
+        let error = NativeError::from(&ReadError::ConnectionLost(ConnectionError::TimedOut));
+
+        assert_eq!(error.code, ErrorCode::ConnectionLost);
+        assert!(matches!(
+            detail(&error, "kind"),
+            Some(Detail::String(kind)) if kind == "timedOut"
+        ));
+    }
+
+    #[test]
+    fn closed_stream_read_is_finished() {
+        let error = NativeError::from(&ReadError::ClosedStream);
+
+        assert_eq!(error.code, ErrorCode::StreamFinished);
+        assert!(error.details.is_empty());
+    }
+
+    #[test]
+    fn illegal_ordered_read_has_its_own_code() {
+        let error = NativeError::from(&ReadError::IllegalOrderedRead);
+
+        assert_eq!(error.code, ErrorCode::IllegalOrderedRead);
+        assert!(error.details.is_empty());
+    }
+
+    #[test]
+    fn rejected_zero_rtt_read_has_its_own_code() {
+        let error = NativeError::from(&ReadError::ZeroRttRejected);
+
+        assert_eq!(error.code, ErrorCode::ZeroRttRejected);
+        assert!(error.details.is_empty());
+    }
+}
@@ -0,0 +1,52 @@
+use std::{
+    collections::VecDeque,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+#[derive(Clone, Debug)]
+pub struct Event {
+    /// Since the log was created
+    pub elapsed: Duration,
+    pub name: &'static str,
+    pub stream_id: Option<u64>,
+    pub detail: Option<String>,
+}
+
+/// The last `capacity` events of a connection, kept for `debug_dump`. Older events are dropped
+///  as new ones are recorded
+#[derive(Debug)]
+pub struct EventLog {
+    started: Instant,
+    capacity: usize,
+    events: Mutex<VecDeque<Event>>,
+}
+
+impl EventLog {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            started: Instant::now(),
+            capacity,
+            events: Mutex::new(VecDeque::with_capacity(capacity)),
+        }
+    }
+
+    pub fn record(&self, name: &'static str, stream_id: Option<u64>, detail: Option<String>) {
+        let mut events = self.events.lock().unwrap();
+
+        if events.len() == self.capacity {
+            events.pop_front();
+        }
+        events.push_back(Event {
+            elapsed: self.started.elapsed(),
+            name,
+            stream_id,
+            detail,
+        });
+    }
+
+    /// Oldest first
+    pub fn snapshot(&self) -> Vec<Event> {
+        self.events.lock().unwrap().iter().cloned().collect()
+    }
+}
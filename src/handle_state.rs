@@ -0,0 +1,134 @@
+use std::sync::{
+    atomic::{AtomicU8, Ordering},
+    Arc,
+};
+
+use crate::errors::{Detail, ErrorCode, ErrorContext, NativeError};
+
+/// Where a handle passed to JavaScript is in its lifecycle. A handle only moves forward, so a
+///  stream which was destroyed stays consumed when its connection closes afterwards
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[repr(u8)]
+pub enum State {
+    Alive,
+    /// The connection the handle belongs to closed
+    Closed,
+    /// Used up by an export, like an initialized partial stream or a destroyed stream
+    Consumed,
+    /// The garbage collector finalized the handle, only reachable through clones of it
+    Finalized,
+}
+
+impl State {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            State::Alive => "alive",
+            State::Closed => "closed",
+            State::Consumed => "consumed",
+            State::Finalized => "finalized",
+        }
+    }
+
+    fn from_u8(value: u8) -> Self {
+        match value {
+            0 => State::Alive,
+            1 => State::Closed,
+            2 => State::Consumed,
+            _ => State::Finalized,
+        }
+    }
+}
+
+/// State of a handle, shared by all of its clones. Checked at the top of every export taking the
+///  handle
+#[derive(Clone, Debug, Default)]
+pub struct HandleState(Arc<AtomicU8>);
+
+impl HandleState {
+    pub fn get(&self) -> State {
+        State::from_u8(self.0.load(Ordering::Acquire))
+    }
+
+    /// Moves the handle to `state`, unless it is already further along. Returns the previous state
+    pub fn advance(&self, state: State) -> State {
+        State::from_u8(self.0.fetch_max(state as u8, Ordering::AcqRel))
+    }
+
+    /// Fails with `ERR_HANDLE_CONSUMED` once the handle was consumed or finalized. `handle` and
+    ///  `consumed` describe it, as in "the stream has already been destroyed". Closed handles
+    ///  pass, exports which need an open connection check that themselves
+    pub fn check(
+        &self,
+        handle: &str,
+        consumed: &str,
+        context: &ErrorContext,
+    ) -> Result<(), NativeError> {
+        let state = self.get();
+        let message = match state {
+            State::Alive | State::Closed => return Ok(()),
+            State::Consumed => format!("The {handle} has already been {consumed}"),
+            State::Finalized => format!("The {handle} has already been finalized"),
+        };
+
+        Err(NativeError::new(ErrorCode::HandleConsumed, message)
+            .with("handleState", Detail::String(state.as_str().to_owned()))
+            .context(context))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check(state: &HandleState) -> Result<(), NativeError> {
+        state.check("stream", "destroyed", &ErrorContext::new("write_stream"))
+    }
+
+    #[test]
+    fn only_moves_forward() {
+        let state = HandleState::default();
+
+        assert_eq!(state.advance(State::Consumed), State::Alive);
+        assert_eq!(state.advance(State::Closed), State::Consumed);
+        assert_eq!(state.get(), State::Consumed);
+        assert_eq!(state.advance(State::Finalized), State::Consumed);
+        assert_eq!(state.advance(State::Alive), State::Finalized);
+        assert_eq!(state.get(), State::Finalized);
+    }
+
+    #[test]
+    fn shared_by_clones() {
+        let state = HandleState::default();
+        state.clone().advance(State::Closed);
+
+        assert_eq!(state.get(), State::Closed);
+    }
+
+    #[test]
+    fn alive_and_closed_handles_pass() {
+        let state = HandleState::default();
+        assert!(check(&state).is_ok());
+
+        state.advance(State::Closed);
+        assert!(check(&state).is_ok());
+    }
+
+    #[test]
+    fn consumed_and_finalized_handles_fail() {
+        for (advance, message) in [
+            (State::Consumed, "The stream has already been destroyed"),
+            (State::Finalized, "The stream has already been finalized"),
+        ] {
+            let state = HandleState::default();
+            state.advance(advance);
+
+            let error = check(&state).unwrap_err();
+            assert_eq!(error.code, ErrorCode::HandleConsumed);
+            assert_eq!(error.message, message);
+            assert!(error.details.iter().any(|(key, value)| {
+                *key == "handleState"
+                    && matches!(value, Detail::String(state) if state == advance.as_str())
+            }));
+        }
+    }
+}
@@ -0,0 +1,118 @@
+use std::time::Duration;
+
+// Each power of two is split into this many linear sub buckets, giving a relative error of at
+//  most 1/8th. The layout is fixed, so memory use doesn't depend on the observed values.
+const SUB_BUCKETS: usize = 8;
+const SUB_BUCKET_BITS: usize = SUB_BUCKETS.trailing_zeros() as usize;
+// Covers 0 up to 2^29µs (~9 minutes), anything above is counted in the last bucket
+const MAGNITUDES: usize = 27;
+const BUCKETS: usize = SUB_BUCKETS * MAGNITUDES;
+
+fn bucket_index(micros: u64) -> usize {
+    if micros < SUB_BUCKETS as u64 {
+        return micros as usize;
+    }
+
+    let magnitude = 63 - micros.leading_zeros() as usize;
+    let sub_bucket = (micros >> (magnitude - SUB_BUCKET_BITS)) as usize & (SUB_BUCKETS - 1);
+    let index = (magnitude - SUB_BUCKET_BITS + 1) * SUB_BUCKETS + sub_bucket;
+
+    index.min(BUCKETS - 1)
+}
+
+fn bucket_lower_bound(index: usize) -> u64 {
+    if index < SUB_BUCKETS {
+        return index as u64;
+    }
+
+    let magnitude = index / SUB_BUCKETS + SUB_BUCKET_BITS - 1;
+    let sub_bucket = index % SUB_BUCKETS;
+
+    ((SUB_BUCKETS + sub_bucket) as u64) << (magnitude - SUB_BUCKET_BITS)
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Bucket {
+    pub lower: Duration,
+    pub upper: Duration,
+    pub count: u64,
+}
+
+#[derive(Clone, Debug)]
+pub struct Summary {
+    pub count: u64,
+    pub p50: Duration,
+    pub p90: Duration,
+    pub p99: Duration,
+    pub max: Duration,
+    /// Only the buckets containing at least one observation
+    pub buckets: Vec<Bucket>,
+}
+
+/// Log-linear histogram of durations with microsecond resolution
+#[derive(Clone)]
+pub struct Histogram {
+    counts: Box<[u64; BUCKETS]>,
+    count: u64,
+    max: Duration,
+}
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Self {
+            counts: Box::new([0; BUCKETS]),
+            count: 0,
+            max: Duration::ZERO,
+        }
+    }
+}
+
+impl Histogram {
+    pub fn record(&mut self, value: Duration) {
+        let micros = value.as_micros().min(u64::MAX as u128) as u64;
+
+        self.counts[bucket_index(micros)] += 1;
+        self.count += 1;
+        self.max = self.max.max(value);
+    }
+
+    // Upper bound of the bucket containing the given quantile, capped at the largest observation
+    fn quantile(&self, quantile: f64) -> Duration {
+        let target = ((self.count as f64 * quantile).ceil() as u64).max(1);
+        let mut seen = 0;
+
+        for (index, count) in self.counts.iter().enumerate() {
+            seen += count;
+
+            if seen >= target {
+                let upper = Duration::from_micros(bucket_lower_bound(index + 1));
+                return upper.min(self.max);
+            }
+        }
+
+        self.max
+    }
+
+    pub fn summary(&self) -> Summary {
+        let buckets = self
+            .counts
+            .iter()
+            .enumerate()
+            .filter(|(_, count)| **count > 0)
+            .map(|(index, count)| Bucket {
+                lower: Duration::from_micros(bucket_lower_bound(index)),
+                upper: Duration::from_micros(bucket_lower_bound(index + 1)),
+                count: *count,
+            })
+            .collect();
+
+        Summary {
+            count: self.count,
+            p50: self.quantile(0.5),
+            p90: self.quantile(0.9),
+            p99: self.quantile(0.99),
+            max: self.max,
+            buckets,
+        }
+    }
+}
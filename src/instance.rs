@@ -0,0 +1,48 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use neon::{prelude::*, thread::LocalKey};
+use tokio_util::sync::CancellationToken;
+
+// The addon is loaded once per JavaScript context, the main thread and every worker thread, but
+//  the statics (runtime, session cache, tracked connections) are shared by all of them. Anything
+//  calling back into JavaScript has to be tied to the context it was created in instead.
+static INSTANCE: LocalKey<Instance> = LocalKey::new();
+static NEXT_INSTANCE_ID: AtomicU64 = AtomicU64::new(1);
+
+struct Instance {
+    id: u64,
+    torn_down: CancellationToken,
+}
+
+// Instance data is dropped by Node.js once the context is gone, e.g. when a worker terminates
+impl Drop for Instance {
+    fn drop(&mut self) {
+        self.torn_down.cancel();
+    }
+}
+
+/// The JavaScript context a connection was created in
+#[derive(Clone, Debug)]
+pub struct InstanceHandle {
+    pub id: u64,
+    torn_down: CancellationToken,
+}
+
+impl InstanceHandle {
+    /// Resolves once the context has been torn down, none of its callbacks can be called anymore
+    pub async fn torn_down(&self) {
+        self.torn_down.cancelled().await
+    }
+}
+
+pub fn current<'a, C: Context<'a>>(cx: &mut C) -> InstanceHandle {
+    let instance = INSTANCE.get_or_init(cx, || Instance {
+        id: NEXT_INSTANCE_ID.fetch_add(1, Ordering::Relaxed),
+        torn_down: CancellationToken::new(),
+    });
+
+    InstanceHandle {
+        id: instance.id,
+        torn_down: instance.torn_down.clone(),
+    }
+}
@@ -1,472 +1,4252 @@
-use std::{net::SocketAddr, sync::Arc};
-
+use std::{
+    future::Future,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV6},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+        Arc, Weak,
+    },
+    time::{Duration, Instant},
+};
+
+use buffered::BufferedBytes;
+use bytes::Bytes;
 use cancel_with_value::CancelWithValue;
+use close_reason::{CloseReason, CloseWatch};
+use errors::{ConnectionErrorKind, Detail, ErrorCode, ErrorContext, NativeError};
+use event_log::EventLog;
+use handle_state::HandleState;
 use neon::{prelude::*, types::JsBigInt};
-use once_cell::sync::OnceCell;
 use quinn::{ClosedStream, ConnectionError, RecvStream, SendStream, StreamId, VarInt, WriteError};
+use readers::{ReadHandle, Readers};
+use ring::RingBuffer;
+use sampler::Sampler;
+use tag::ConnectionTag;
 use take_once::TakeOnce;
-use tokio::{runtime::Runtime, sync::Mutex, task::JoinHandle};
-
+use tokio::{
+    sync::{oneshot, watch},
+    task::JoinHandle,
+};
+use tracing::Instrument;
+use writer::Writer;
+
+mod args;
+mod buffered;
 mod cancel_with_value;
+mod close_reason;
+mod dscp;
+mod errors;
+mod event_log;
+mod handle_state;
+mod histogram;
+mod instance;
+mod logging;
+mod persistent;
+mod plain_socket;
+mod pool;
 mod quic;
+mod readers;
+mod ring;
+mod runtime;
+#[cfg(feature = "runtime-metrics")]
+mod runtime_metrics;
+mod sampler;
+mod socks;
+mod stats;
+mod tag;
 mod take_once;
+#[cfg(feature = "test-server")]
+mod test_server;
+mod writer;
+
+// Return a handle to the global tokio runtime or create one if it doesn't exist.
+// Throws a JavaScript exception if the `Runtime` fails to create or was shut down.
+fn runtime<'a, C: Context<'a>>(cx: &mut C) -> NeonResult<tokio::runtime::Handle> {
+    runtime::handle().or_else(|err| runtime_error(&err).throw(cx))
+}
+
+fn runtime_error(error: &runtime::RuntimeError) -> NativeError {
+    let code = match error {
+        runtime::RuntimeError::Build(_) => ErrorCode::Io,
+        runtime::RuntimeError::AlreadyStarted => ErrorCode::InvalidState,
+        runtime::RuntimeError::ShutDown => ErrorCode::ClientShutDown,
+    };
+
+    NativeError::new(code, error.to_string())
+}
+
+// Every endpoint created, so `shutdown` can close the connections which are still open
+static ENDPOINTS: std::sync::Mutex<Vec<Weak<quinn::Endpoint>>> = std::sync::Mutex::new(Vec::new());
 
-static RUNTIME: OnceCell<Runtime> = OnceCell::new();
+fn track_endpoint(endpoint: &Arc<quinn::Endpoint>) {
+    let mut endpoints = ENDPOINTS.lock().unwrap();
 
-// Return a global tokio runtime or create one if it doesn't exist.
-// Throws a JavaScript exception if the `Runtime` fails to create.
-fn runtime<'a, C: Context<'a>>(cx: &mut C) -> NeonResult<&'static Runtime> {
-    RUNTIME.get_or_try_init(|| Runtime::new().or_else(|err| cx.throw_error(err.to_string())))
+    endpoints.retain(|endpoint| endpoint.strong_count() > 0);
+    endpoints.push(Arc::downgrade(endpoint));
 }
 
+// Every connection which is still open, along with its streams, for `flush_all`. Shared by all
+//  contexts the addon is loaded in, `instance` tells which one a connection belongs to
+static CONNECTIONS: std::sync::Mutex<Vec<TrackedConnection>> = std::sync::Mutex::new(Vec::new());
+
 #[derive(Clone)]
-struct Connection {
-    connection: Arc<quinn::Connection>,
-    close_handle: Arc<JoinHandle<()>>,
-    stream_handle: Arc<JoinHandle<()>>,
+struct TrackedConnection {
+    tag: ConnectionTag,
+    instance: u64,
+    connection: Weak<quinn::Connection>,
+    streams: StreamRegistry,
 }
 
-impl Finalize for Connection {
-    fn finalize<'a, C: Context<'a>>(self, _: &mut C) {
-        self.close_handle.abort();
-        self.stream_handle.abort();
-        self.connection.close(0u8.into(), b"");
+impl TrackedConnection {
+    fn is_open(&self) -> bool {
+        self.connection
+            .upgrade()
+            .is_some_and(|connection| connection.close_reason().is_none())
     }
 }
 
-fn to_uint8_vec<'a, C>(
-    cx: &mut C,
-    value: Option<Handle<JsArray>>,
-) -> Result<Option<Vec<Vec<u8>>>, neon::result::Throw>
-where
-    C: Context<'a>,
-{
-    Ok(match value {
-        None => None,
-        Some(protocols) => {
-            let value: Result<Vec<_>, neon::result::Throw> = protocols
-                .to_vec(cx)?
-                .into_iter()
-                .map(|entry| {
-                    use neon::types::buffer::TypedArray;
+fn track_connection(connection: TrackedConnection) {
+    let mut connections = CONNECTIONS.lock().unwrap();
 
-                    entry
-                        .downcast_or_throw(cx)
-                        .map(|handle: Handle<JsUint8Array>| handle.as_slice(cx).to_vec())
-                })
-                .collect();
+    connections.retain(TrackedConnection::is_open);
+    connections.push(connection);
+}
 
-            Some(value?)
+// Waits until the peer acknowledged everything written to the finished streams. Streams which
+//  are not finished yet are only counted, without an end there's no way to tell whether their
+//  buffered data was sent. Returns the number still unacknowledged and the number not finished
+async fn flush_streams(
+    streams: Vec<RegisteredStream>,
+    deadline: tokio::time::Instant,
+) -> (usize, usize) {
+    let mut unfinished = 0;
+    let mut pending = tokio::task::JoinSet::new();
+
+    for stream in streams {
+        if stream.send.is_none() {
+            continue;
         }
+        if !stream.finished.load(Ordering::Acquire) {
+            unfinished += 1;
+            continue;
+        }
+
+        pending.spawn(async move {
+            if let Some(send) = stream.send.as_ref() {
+                send.stopped().await;
+            }
+        });
+    }
+
+    let _ = tokio::time::timeout_at(deadline, async {
+        while pending.join_next().await.is_some() {}
     })
+    .await;
+
+    (pending.len(), unfinished)
 }
 
-fn connect(mut cx: FunctionContext) -> JsResult<JsPromise> {
-    let port = cx.argument::<JsNumber>(0)?.value(&mut cx) as u16;
-    let ip = cx.argument::<JsString>(1)?.value(&mut cx);
-    let hostname = cx.argument::<JsString>(2)?.value(&mut cx);
-    let on_stream = cx.argument::<JsFunction>(3)?.root(&mut cx);
-    let on_close = cx.argument::<JsFunction>(4)?.root(&mut cx);
-    let on_error = cx.argument::<JsFunction>(5)?.root(&mut cx);
-    let alpn_protocols: Option<Handle<JsArray>> = cx.argument::<JsValue>(6)?.downcast(&mut cx).ok();
-    let certificate_authorities: Option<Handle<JsArray>> =
-        cx.argument::<JsValue>(7)?.downcast(&mut cx).ok();
+// Waits for the data written to the streams of every open connection to be acknowledged and
+//  resolves with what was left for each of them when the timeout expired
+fn flush_all(mut cx: FunctionContext) -> JsResult<JsPromise> {
+    let timeout = match args::optional_argument::<JsObject>(&mut cx, "flush_all", 0, "options")? {
+        Some(options) => optional_duration(&mut cx, "flush_all", options, "options", "timeoutMs")?,
+        None => None,
+    }
+    .unwrap_or(FLUSH_TIMEOUT);
+
+    let rt = runtime(&mut cx)?;
 
-    let client_auth = {
-        let args: Option<Handle<JsArray>> = cx.argument::<JsValue>(6)?.downcast(&mut cx).ok();
+    let channel = cx.channel();
+    let (deferred, promise) = cx.promise();
+
+    // Only the connections of the calling thread, a worker shouldn't wait on the main thread's
+    let instance = instance::current(&mut cx).id;
+    let connections: Vec<_> = CONNECTIONS
+        .lock()
+        .unwrap()
+        .iter()
+        .filter(|connection| connection.instance == instance && connection.is_open())
+        .cloned()
+        .collect();
+
+    rt.spawn(async move {
+        let deadline = tokio::time::Instant::now() + timeout;
+
+        let mut flushes = tokio::task::JoinSet::new();
+        for (index, connection) in connections.iter().enumerate() {
+            let streams = connection.streams.pending();
+            flushes.spawn(async move { (index, flush_streams(streams, deadline).await) });
+        }
 
-        to_uint8_vec(&mut cx, args)?.and_then(|args| {
-            if args.len() < 2 {
-                return None;
+        let mut reports = vec![(0, 0); connections.len()];
+        while let Some(result) = flushes.join_next().await {
+            if let Ok((index, report)) = result {
+                reports[index] = report;
             }
+        }
 
-            let mut args = args.into_iter();
-            let cert = args.next().unwrap();
-            let key = args.next().unwrap();
+        let _ = deferred.try_settle_with(&channel, move |mut cx| {
+            let result = cx.empty_array();
 
-            Some((cert, key))
-        })
-    };
+            for (i, (connection, (pending, unfinished))) in
+                connections.iter().zip(reports).enumerate()
+            {
+                let report = cx.empty_object();
+                let pending = cx.number(pending as f64);
+                let unfinished = cx.number(unfinished as f64);
 
-    let alpn_protocols = to_uint8_vec(&mut cx, alpn_protocols)?;
-    let certificate_authorities = to_uint8_vec(&mut cx, certificate_authorities)?;
+                connection.tag.apply(&mut cx, report)?;
+                report.set(&mut cx, "pendingStreams", pending)?;
+                report.set(&mut cx, "unfinishedStreams", unfinished)?;
 
-    let addr = SocketAddr::new(ip.parse().unwrap(), port);
-    let rt = runtime(&mut cx)?;
+                result.set(&mut cx, i as u32, report)?;
+            }
+
+            Ok(result)
+        });
+    });
 
-    let on_stream_channel = cx.channel();
-    let on_close_channel = cx.channel();
-    let on_error_channel = cx.channel();
+    Ok(promise)
+}
+
+// Default of `timeoutMs` for `flush_all`
+const FLUSH_TIMEOUT: Duration = Duration::from_secs(5);
+
+// How long `shutdown` waits for connections to drain, and then for the runtime's tasks to finish
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(3);
 
+// Closes every endpoint and shuts the runtime down once they are idle. Resolves immediately if
+//  the client was already shut down. The runtime is shared by all threads the addon is loaded in,
+//  so this also closes the connections of every worker
+fn shutdown(mut cx: FunctionContext) -> JsResult<JsPromise> {
     let channel = cx.channel();
     let (deferred, promise) = cx.promise();
 
-    rt.spawn(async move {
-        let result = quic::get_client(
-            addr,
-            &hostname,
-            alpn_protocols,
-            certificate_authorities,
-            client_auth,
-        )
-        .await;
+    let Some(rt) = runtime::take() else {
+        let _ = deferred.try_settle_with(&channel, |mut cx| Ok(cx.undefined()));
 
-        deferred.settle_with(&channel, move |mut cx| {
-            let (connection, endpoint) = result.or_else(|err| cx.throw_error(err.to_string()))?;
-            let connection = Arc::new(connection);
-            let endpoint = Arc::new(endpoint);
+        return Ok(promise);
+    };
 
-            let close_handle = {
-                let connection = connection.clone();
-                let endpoint = endpoint.clone();
-                rt.spawn(async move {
-                    let reason = connection.closed().await;
-                    endpoint.wait_idle().await;
+    let endpoints: Vec<_> = ENDPOINTS
+        .lock()
+        .unwrap()
+        .drain(..)
+        .filter_map(|endpoint| endpoint.upgrade())
+        .collect();
+
+    // `shutdown_timeout` blocks and can't be called from within the runtime itself
+    std::thread::spawn(move || {
+        rt.shutdown(
+            async {
+                for endpoint in &endpoints {
+                    endpoint.close(0u8.into(), b"");
+                }
 
-                    on_close_channel.send(move |mut cx| {
-                        let callback = on_close.into_inner(&mut cx);
-                        let this = cx.undefined();
+                let _ = tokio::time::timeout(SHUTDOWN_TIMEOUT, async {
+                    for endpoint in &endpoints {
+                        endpoint.wait_idle().await;
+                    }
+                })
+                .await;
 
-                        let args = vec![cx.string(reason.to_string()).upcast()];
+                drop(endpoints);
+            },
+            SHUTDOWN_TIMEOUT,
+        );
 
-                        callback.call(&mut cx, this, args)?;
+        let _ = deferred.try_settle_with(&channel, |mut cx| Ok(cx.undefined()));
+    });
 
-                        Ok(())
-                    });
-                })
-            };
+    Ok(promise)
+}
 
-            let stream_handle = {
-                let connection = connection.clone();
-                let on_error = Arc::new(on_error);
-                let on_stream = Arc::new(on_stream);
-
-                rt.spawn(async move {
-                    fn handle_bidi<E, S>(
-                        result: Result<(SendStream, RecvStream), ConnectionError>,
-                        error_handler: E,
-                        stream_handler: S,
-                    ) -> bool where
-                        E: FnOnce(ConnectionError) -> bool,
-                        S: FnOnce(PartialStream)
-                    {
-                        let (send, recv) = match result {
-                            Err(err) => return error_handler(err),
-                            Ok(v) => v,
-                        };
-
-                        let stream = PartialStream {
-                            send: Arc::new(TakeOnce::new(Some(send))),
-                            recv: Arc::new(TakeOnce::new(recv)),
-                        };
-
-                        stream_handler(stream);
-
-                        false
-                    }
+// Process-wide counter used to hand out connection ids.
+// `quinn::Connection::stable_id` is derived from an allocation address which may be reused once a
+//  connection is dropped, so we keep our own to guarantee ids never repeat within a process.
+static NEXT_CONNECTION_ID: AtomicU64 = AtomicU64::new(1);
 
-                    fn handle_uni<E, S>(
-                        result: Result<RecvStream, ConnectionError>,
-                        error_handler: E,
-                        stream_handler: S,
-                    )  -> bool where
-                        E: FnOnce(ConnectionError) -> bool,
-                        S: FnOnce(PartialStream)
-                    {
-                        let recv = match result {
-                            Err(err) => return error_handler(err),
-                            Ok(v) => v,
-                        };
-
-                        let stream = PartialStream {
-                            send: Arc::new(TakeOnce::new(None)),
-                            recv: Arc::new(TakeOnce::new(recv)),
-                        };
-
-                        stream_handler(stream);
-
-                        false
-                    }
+fn next_connection_id() -> u64 {
+    NEXT_CONNECTION_ID.fetch_add(1, Ordering::Relaxed)
+}
 
-                    loop {
-                        let on_error_channel = on_error_channel.clone();
-                        let on_error = on_error.clone();
-                        let handle_error = |error: ConnectionError| {
-                            match  error {
-                                ConnectionError::ConnectionClosed(_) |
-                                ConnectionError::ApplicationClosed(_) |
-                                ConnectionError::Reset |
-                                ConnectionError::LocallyClosed  => {},
-                                _ => {
-                                    on_error_channel.send(move |mut cx| {
-                                        let callback = on_error.as_ref().clone(&mut cx).into_inner(&mut cx);
-                                        let this = cx.undefined();
-
-                                        let args = &[cx.error(error.to_string()).unwrap().upcast()];
-
-                                        callback.call(&mut cx, this, args)?;
-
-                                        Ok(())
-                                    });
-                                }
-                            }
+// Endpoint created by `create_endpoint`, which can be passed to `connect` to share a single UDP
+//  socket between multiple connections
+struct Endpoint {
+    endpoint: Arc<quinn::Endpoint>,
+}
 
-                            true
-                        };
+impl Finalize for Endpoint {}
 
-                        let handle_stream = |stream: PartialStream| {
-                            let on_stream_channel = on_stream_channel.clone();
-                            let on_stream = on_stream.clone();
-                            rt.spawn(async move {
-                                on_stream_channel.send(move |mut cx| {
-                                    let callback = on_stream.as_ref().clone(&mut cx).into_inner(&mut cx);
-                                    let this = cx.undefined();
+impl args::Expected for JsBox<Endpoint> {
+    const DESCRIPTION: &'static str = "an endpoint handle";
+}
 
-                                    let is_uni = stream.send.peek(|v| v.is_none());
+fn create_endpoint(mut cx: FunctionContext) -> JsResult<JsBox<Endpoint>> {
+    let options = args::optional_argument::<JsObject>(&mut cx, "create_endpoint", 0, "options")?;
+
+    let ipv6 = match options {
+        Some(options) => args::optional_property::<_, JsBoolean>(
+            &mut cx,
+            "create_endpoint",
+            options,
+            "options",
+            "ipv6",
+        )?
+        .map(|value| value.value(&mut cx))
+        .unwrap_or(false),
+        None => false,
+    };
+    let ipv6_only = match options {
+        Some(options) => args::optional_property::<_, JsBoolean>(
+            &mut cx,
+            "create_endpoint",
+            options,
+            "options",
+            "ipv6Only",
+        )?
+        .map(|value| value.value(&mut cx)),
+        None => None,
+    };
+    // A descriptor on Unix, a `SOCKET` on Windows
+    let socket = match options {
+        Some(options) => args::optional_property::<_, JsNumber>(
+            &mut cx,
+            "create_endpoint",
+            options,
+            "options",
+            "fd",
+        )?
+        .map(|value| value.value(&mut cx)),
+        None => None,
+    };
+    let socket = match socket {
+        None => None,
+        Some(fd) if fd.fract() == 0.0 && (0.0..=quic::RawSocket::MAX as f64).contains(&fd) => {
+            Some(fd as quic::RawSocket)
+        }
+        Some(fd) => {
+            return NativeError::range_error(format!(
+                "create_endpoint: expected options.fd to be a socket handle, got {fd}"
+            ))
+            .throw(&mut cx)
+        }
+    };
 
-                                    let args: &[Handle<JsValue>] = &[cx.boxed(stream).upcast(), cx.boolean(is_uni).upcast()];
+    // An existing socket is used with the options it was created with
+    if socket.is_some() && ipv6_only.is_some() {
+        return NativeError::type_error(
+            "create_endpoint: options.ipv6Only can't be used together with options.fd",
+        )
+        .throw(&mut cx);
+    }
 
-                                    callback.call(&mut cx, this, args)?;
+    let rt = runtime(&mut cx)?;
 
-                                    Ok(())
-                                });
-                            });
-                        };
+    let endpoint = {
+        let _guard = rt.enter();
 
-                        tokio::select! {
-                            stream = connection.accept_bi() => if handle_bidi(stream, handle_error, handle_stream) { break; },
-                            stream = connection.accept_uni() => if handle_uni(stream, handle_error, handle_stream) { break; },
-                        }
-                    }
-                })
+        match socket {
+            None => quic::bind_endpoint(
+                ipv6,
+                &quic::SocketOptions {
+                    ipv6_only,
+                    ..Default::default()
+                },
+            )
+            .map(|(endpoint, _)| endpoint),
+            Some(socket) => quic::endpoint_from_socket(socket),
+        }
+        .or_else(|err| {
+            let code = match err.kind() {
+                std::io::ErrorKind::InvalidInput => ErrorCode::InvalidArgument,
+                _ => ErrorCode::Io,
             };
 
-            Ok(cx.boxed(Connection {
-                connection,
-                close_handle: Arc::new(close_handle),
-                stream_handle: Arc::new(stream_handle),
-            }))
-        });
+            NativeError::new(code, err.to_string())
+                .context(&ErrorContext::new("create_endpoint"))
+                .throw(&mut cx)
+        })?
+    };
+
+    let endpoint = Arc::new(endpoint);
+    track_endpoint(&endpoint);
+
+    Ok(cx.boxed(Endpoint { endpoint }))
+}
+
+// Token created by `create_cancel_token`. Operations it is passed to are rejected with
+//  `ERR_CANCELLED` once `cancel_token` is called, a single token can be used for any number of them
+#[derive(Clone)]
+struct CancelToken(CancelWithValue<Option<String>>);
+
+impl Finalize for CancelToken {}
+
+impl args::Expected for JsBox<CancelToken> {
+    const DESCRIPTION: &'static str = "a cancel token";
+}
+
+impl CancelToken {
+    fn error(reason: Option<String>) -> NativeError {
+        let message = reason
+            .clone()
+            .unwrap_or_else(|| String::from("The operation was cancelled"));
+        let reason = reason.map(Detail::String).unwrap_or(Detail::Null);
+
+        NativeError::new(ErrorCode::Cancelled, message).with("reason", reason)
+    }
+
+    // Runs `future` unless the token is cancelled first, in which case the future is dropped
+    async fn run<F: Future>(
+        token: Option<CancelToken>,
+        future: F,
+    ) -> Result<F::Output, NativeError> {
+        let Some(token) = token else {
+            return Ok(future.await);
+        };
+
+        tokio::select! {
+            reason = token.0.cancelled() => Err(Self::error(reason)),
+            output = future => Ok(output),
+        }
+    }
+}
+
+fn cancel_token_argument(
+    cx: &mut FunctionContext,
+    function: &str,
+    index: usize,
+) -> NeonResult<Option<CancelToken>> {
+    Ok(
+        args::optional_argument::<JsBox<CancelToken>>(cx, function, index, "cancelToken")?
+            .map(|token| (**token).clone()),
+    )
+}
+
+fn create_cancel_token(mut cx: FunctionContext) -> JsResult<JsBox<CancelToken>> {
+    Ok(cx.boxed(CancelToken(CancelWithValue::new())))
+}
+
+// Returns `false` if the token was already cancelled, the reason stays the first one given
+fn cancel_token(mut cx: FunctionContext) -> JsResult<JsBoolean> {
+    let token =
+        (**args::argument::<JsBox<CancelToken>>(&mut cx, "cancel_token", 0, "token")?).clone();
+    let reason = args::optional_argument::<JsString>(&mut cx, "cancel_token", 1, "reason")?
+        .map(|reason| reason.value(&mut cx));
+
+    Ok(cx.boolean(token.0.cancel(reason)))
+}
+
+// Controller created by `create_connect_controller`. `cancel_connect` aborts the `connect` it was
+//  passed to, or closes the connection once that resolved
+#[derive(Clone, Default)]
+struct ConnectController {
+    state: Arc<std::sync::Mutex<ConnectState>>,
+    aborted: tokio_util::sync::CancellationToken,
+}
+
+#[derive(Default)]
+enum ConnectState {
+    #[default]
+    Pending,
+    Connected(Weak<quinn::Connection>),
+    Cancelled,
+}
+
+impl Finalize for ConnectController {}
+
+impl args::Expected for JsBox<ConnectController> {
+    const DESCRIPTION: &'static str = "a connect controller";
+}
+
+impl ConnectController {
+    fn error() -> NativeError {
+        NativeError::new(ErrorCode::Cancelled, "The connection attempt was aborted")
+    }
+
+    // Runs `future` unless the attempt is aborted first, in which case the future is dropped
+    async fn run<F: Future>(
+        controller: Option<ConnectController>,
+        future: F,
+    ) -> Result<F::Output, NativeError> {
+        let Some(controller) = controller else {
+            return Ok(future.await);
+        };
+
+        tokio::select! {
+            _ = controller.aborted.cancelled() => Err(Self::error()),
+            output = future => Ok(output),
+        }
+    }
+
+    // Hands `connection` to the controller right before the promise resolves with it. Both this and
+    //  `cancel` run on the JavaScript thread, so an abort between the handshake completing and the
+    //  promise settling is seen here and the connection is closed instead
+    fn connected(&self, connection: &Arc<quinn::Connection>) -> Result<(), NativeError> {
+        let mut state = self.state.lock().unwrap();
+
+        match *state {
+            ConnectState::Cancelled => {
+                connection.close(0u32.into(), ABORTED_REASON);
+                Err(Self::error())
+            }
+            _ => {
+                *state = ConnectState::Connected(Arc::downgrade(connection));
+                Ok(())
+            }
+        }
+    }
+
+    // Returns `false` if it was already cancelled
+    fn cancel(&self) -> bool {
+        let mut state = self.state.lock().unwrap();
+
+        match std::mem::replace(&mut *state, ConnectState::Cancelled) {
+            ConnectState::Pending => {
+                self.aborted.cancel();
+                true
+            }
+            ConnectState::Connected(connection) => {
+                if let Some(connection) = connection.upgrade() {
+                    connection.close(0u32.into(), ABORTED_REASON);
+                }
+                true
+            }
+            ConnectState::Cancelled => false,
+        }
+    }
+}
+
+// Reason a connection closed by `cancel_connect` is closed with
+const ABORTED_REASON: &[u8] = b"aborted";
+
+fn create_connect_controller(mut cx: FunctionContext) -> JsResult<JsBox<ConnectController>> {
+    Ok(cx.boxed(ConnectController::default()))
+}
+
+fn cancel_connect(mut cx: FunctionContext) -> JsResult<JsBoolean> {
+    let controller =
+        args::argument::<JsBox<ConnectController>>(&mut cx, "cancel_connect", 0, "controller")?;
+
+    Ok(cx.boolean(controller.cancel()))
+}
+
+#[derive(Clone)]
+struct Connection {
+    tag: ConnectionTag,
+    version: u32,
+    timings: quic::HandshakeTimings,
+    handshake_kind: quic::HandshakeKind,
+    // Whether rustls considers the connection's TLS configuration FIPS compliant
+    fips: bool,
+    connection: Arc<quinn::Connection>,
+    endpoint: Arc<quinn::Endpoint>,
+    // Whether the endpoint was created for this connection alone, rather than passed to `connect`
+    owns_endpoint: bool,
+    // Whether the endpoint's datagrams are relayed through `options.proxy`
+    proxied: bool,
+    congestion_controller: quic::CongestionController,
+    // Options the endpoint's socket was bound with, `None` unless it was bound for this connection
+    socket_options: Option<quic::SocketOptions>,
+    // What the kernel applied of them when the connection was made
+    socket: Option<quic::SocketInfo>,
+    close_watch: CloseWatch,
+    // Closed once `on_close` is called, finalized along with the handle
+    handle_state: HandleState,
+    // Whether `close_connection` was called on this connection
+    closing: Arc<AtomicBool>,
+    streams: StreamRegistry,
+    keep_alive: KeepAlive,
+    callbacks: ConnectionCallbacks,
+    // Last value passed to `set_receive_window`, quinn doesn't expose it
+    receive_window: Arc<AtomicU64>,
+    sampler: Arc<Sampler>,
+    close_handle: Arc<JoinHandle<()>>,
+    stream_handle: Arc<JoinHandle<()>>,
+    sampler_handle: Arc<JoinHandle<()>>,
+}
+
+impl Connection {
+    fn close_reason(&self) -> Option<CloseReason> {
+        self.close_watch.reason()
+    }
+
+    fn error_context(&self, operation: &'static str) -> ErrorContext {
+        ErrorContext::new(operation).connection(&self.tag)
+    }
+
+    // Checked at the top of every export taking a connection. With `open` the connection must not
+    //  be closed either
+    fn check(&self, operation: &'static str, open: bool) -> Result<(), NativeError> {
+        let context = self.error_context(operation);
+
+        self.handle_state.check("connection", "closed", &context)?;
+        if open {
+            self.close_watch
+                .ensure_open()
+                .map_err(|err| err.context(&context))?;
+        }
+
+        Ok(())
+    }
+
+    fn state(&self) -> &'static str {
+        if self.close_watch.is_closed() {
+            "closed"
+        } else if self.closing.load(Ordering::Acquire) || self.connection.close_reason().is_some() {
+            "draining"
+        } else {
+            "connected"
+        }
+    }
+}
+
+impl args::Expected for JsBox<Connection> {
+    const DESCRIPTION: &'static str = "a connection handle";
+}
+
+// The connection passed as the first argument of `function`, after checking its handle
+fn connection_argument(
+    cx: &mut FunctionContext,
+    function: &'static str,
+    open: bool,
+) -> NeonResult<Connection> {
+    let connection =
+        (**args::argument::<JsBox<Connection>>(cx, function, 0, "connection")?).clone();
+    connection
+        .check(function, open)
+        .or_else(|err| err.throw(cx))?;
+
+    Ok(connection)
+}
+
+// How long a garbage collected connection gets to send its CONNECTION_CLOSE before its endpoint
+//  is dropped
+const FINALIZE_IDLE_TIMEOUT: Duration = Duration::from_secs(3);
+
+impl Finalize for Connection {
+    fn finalize<'a, C: Context<'a>>(self, _: &mut C) {
+        self.handle_state.advance(handle_state::State::Finalized);
+        self.close_handle.abort();
+        self.stream_handle.abort();
+        self.sampler_handle.abort();
+        self.connection.close(0u8.into(), b"");
+        self.streams.close_all();
+        self.keep_alive.release();
+
+        // Everything was already closed when the client was shut down
+        let Some(rt) = runtime::running() else {
+            return;
+        };
+
+        // A shared endpoint is kept alive by its `Endpoint` handle and the other connections on it
+        if self.owns_endpoint {
+            let endpoint = self.endpoint.clone();
+
+            rt.spawn(async move {
+                endpoint.close(0u8.into(), b"");
+                let _ = tokio::time::timeout(FINALIZE_IDLE_TIMEOUT, endpoint.wait_idle()).await;
+            });
+        }
+    }
+}
+
+// Holds the event loop open on behalf of a connection or stream. The channels their callbacks are
+//  called on are unreferenced, so `unref_connection` only has to toggle these. Released once the
+//  connection or stream is closed
+#[derive(Clone)]
+struct KeepAlive(Arc<std::sync::Mutex<Option<Channel>>>);
+
+impl KeepAlive {
+    // Clones of a channel share its libuv handle, which is referenced while any of them is
+    fn new<'a, C: Context<'a>>(cx: &mut C, channel: &Channel, referenced: bool) -> Self {
+        let mut channel = channel.clone();
+        if referenced {
+            channel.reference(cx);
+        } else {
+            channel.unref(cx);
+        }
+
+        Self(Arc::new(std::sync::Mutex::new(Some(channel))))
+    }
+
+    fn set_referenced<'a, C: Context<'a>>(&self, cx: &mut C, referenced: bool) {
+        if let Some(channel) = self.0.lock().unwrap().as_mut() {
+            if referenced {
+                channel.reference(cx);
+            } else {
+                channel.unref(cx);
+            }
+        }
+    }
+
+    fn release(&self) {
+        self.0.lock().unwrap().take();
+    }
+}
+
+// The `on_stream`, `on_close` and `on_error` callbacks of a connection. They are looked up on the
+//  JavaScript thread when an event is delivered, which is also where `set_connection_callbacks`
+//  replaces them, so every event goes to either the old or the new callback. `connect_persistent`
+//  shares them between all of its connections
+#[derive(Clone)]
+struct ConnectionCallbacks(Arc<std::sync::Mutex<CallbackRoots>>);
+
+struct CallbackRoots {
+    on_stream: Root<JsFunction>,
+    on_close: Root<JsFunction>,
+    on_error: Root<JsFunction>,
+}
+
+impl ConnectionCallbacks {
+    fn new(
+        on_stream: Root<JsFunction>,
+        on_close: Root<JsFunction>,
+        on_error: Root<JsFunction>,
+    ) -> Self {
+        Self(Arc::new(std::sync::Mutex::new(CallbackRoots {
+            on_stream,
+            on_close,
+            on_error,
+        })))
+    }
+
+    fn get<'a, C: Context<'a>>(
+        &self,
+        cx: &mut C,
+        callback: fn(&CallbackRoots) -> &Root<JsFunction>,
+    ) -> Handle<'a, JsFunction> {
+        callback(&self.0.lock().unwrap()).to_inner(cx)
+    }
+
+    // Replaces the given callbacks, the old ones are unrooted right away
+    fn replace<'a, C: Context<'a>>(
+        &self,
+        cx: &mut C,
+        on_stream: Option<Root<JsFunction>>,
+        on_close: Option<Root<JsFunction>>,
+        on_error: Option<Root<JsFunction>>,
+    ) {
+        let mut guard = self.0.lock().unwrap();
+        let roots = &mut *guard;
+
+        for (root, new) in [
+            (&mut roots.on_stream, on_stream),
+            (&mut roots.on_close, on_close),
+            (&mut roots.on_error, on_error),
+        ] {
+            if let Some(new) = new {
+                std::mem::replace(root, new).drop(cx);
+            }
+        }
+    }
+}
+
+// Channel which doesn't keep the event loop alive by itself, see `KeepAlive`.
+// Sending fails once the context is gone, during process exit or when a worker terminates. Events
+//  are dropped then, and the tasks sending them stop instead of calling into a dying VM.
+fn unreferenced_channel<'a, C: Context<'a>>(cx: &mut C) -> Channel {
+    let mut channel = cx.channel();
+    channel.unref(cx);
+
+    channel
+}
+
+// Streams of a connection which are still being read, so they can be stopped when the connection
+//  is garbage collected
+#[derive(Clone)]
+struct StreamRegistry {
+    streams: Arc<std::sync::Mutex<Vec<RegisteredStream>>>,
+    // Whether the connection keeps the event loop alive, new streams start out the same
+    referenced: Arc<AtomicBool>,
+    // Streams which were opened or accepted, but not initialized yet. Only changed while holding
+    //  the `streams` lock, so `close_if_idle` sees either the stream or its pending open
+    opening: Arc<AtomicUsize>,
+    // When the last stream was opened or seen open, used by `autoCloseIdleMs`
+    last_active: Arc<std::sync::Mutex<Instant>>,
+    // The connection's channel, shared by all of its streams instead of each creating their own
+    channel: Channel,
+    readers: Readers,
+    // Stream data queued on `channel`, reading pauses while it's above `maxBufferedBytes`
+    buffered: Arc<BufferedBytes>,
+    on_buffered_bytes_warning: Option<Arc<Root<JsFunction>>>,
+    // `writeChunkBytes`, writes are split into chunks of this size
+    write_chunk_size: usize,
+    // Only kept with `eventLogSize`
+    event_log: Option<Arc<EventLog>>,
+}
+
+// Counts a stream as open from the moment it is opened or accepted until it is initialized or the
+//  partial stream is garbage collected
+struct PendingOpen(StreamRegistry);
+
+impl Drop for PendingOpen {
+    fn drop(&mut self) {
+        let _streams = self.0.streams.lock().unwrap();
+
+        self.0.opening.fetch_sub(1, Ordering::AcqRel);
+        *self.0.last_active.lock().unwrap() = Instant::now();
+    }
+}
+
+#[derive(Clone)]
+struct RegisteredStream {
+    close_requested: CancelWithValue<StopRequest>,
+    handle: Arc<ReadHandle>,
+    details: StreamDetails,
+    keep_alive: KeepAlive,
+    send: Option<Writer>,
+    finished: Arc<AtomicBool>,
+}
+
+// How often a graceful close checks whether the streams it's waiting on are done
+const DRAIN_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+impl RegisteredStream {
+    // Reading ended and the send side, if any, was finished or reset by this side
+    fn is_done(&self) -> bool {
+        self.handle.is_finished() && (self.send.is_none() || self.finished.load(Ordering::Acquire))
+    }
+
+    // Resolves once the stream is done and the peer acknowledged everything written to it
+    async fn drained(self) {
+        while !self.is_done() {
+            tokio::time::sleep(DRAIN_POLL_INTERVAL).await;
+        }
+
+        if let Some(send) = self.send.as_ref() {
+            send.stopped().await;
+        }
+    }
+}
+
+impl StreamRegistry {
+    fn new(
+        channel: Channel,
+        readers: Readers,
+        buffered: BufferedBytes,
+        on_buffered_bytes_warning: Option<Arc<Root<JsFunction>>>,
+        write_chunk_size: usize,
+        event_log: Option<Arc<EventLog>>,
+    ) -> Self {
+        Self {
+            streams: Default::default(),
+            referenced: Arc::new(AtomicBool::new(true)),
+            opening: Default::default(),
+            last_active: Arc::new(std::sync::Mutex::new(Instant::now())),
+            channel,
+            readers,
+            buffered: Arc::new(buffered),
+            on_buffered_bytes_warning,
+            write_chunk_size,
+            event_log,
+        }
+    }
+
+    // Does nothing without `eventLogSize`
+    fn record(&self, name: &'static str, stream_id: Option<u64>, detail: Option<String>) {
+        if let Some(log) = &self.event_log {
+            log.record(name, stream_id, detail);
+        }
+    }
+
+    // Fails if the connection is already closing, checked under the same lock as `close_if_idle`
+    //  so a stream can't be opened while an idle connection is being closed
+    fn begin_open(&self, closing: &AtomicBool) -> Option<PendingOpen> {
+        let _streams = self.streams.lock().unwrap();
+
+        if closing.load(Ordering::Acquire) {
+            return None;
+        }
+
+        self.opening.fetch_add(1, Ordering::AcqRel);
+
+        Some(PendingOpen(self.clone()))
+    }
+
+    // Streams opened by the peer are counted regardless of the connection closing
+    fn accepted(&self) -> PendingOpen {
+        let _streams = self.streams.lock().unwrap();
+        self.opening.fetch_add(1, Ordering::AcqRel);
+
+        PendingOpen(self.clone())
+    }
+
+    // Marks the connection as closing if it went without streams for `idle`. Returns whether it
+    //  did, in which case the caller has to close the connection
+    fn close_if_idle(&self, idle: Duration, closing: &AtomicBool) -> bool {
+        let mut streams = self.streams.lock().unwrap();
+        let mut last_active = self.last_active.lock().unwrap();
+
+        streams.retain(|stream| !stream.is_done());
+        if !streams.is_empty() || self.opening.load(Ordering::Acquire) > 0 {
+            *last_active = Instant::now();
+            return false;
+        }
+
+        last_active.elapsed() >= idle && !closing.swap(true, Ordering::AcqRel)
+    }
+
+    // Keep alive for a new stream, only referenced if the connection is
+    fn keep_alive<'a, C: Context<'a>>(&self, cx: &mut C) -> KeepAlive {
+        KeepAlive::new(cx, &self.channel, self.referenced.load(Ordering::Acquire))
+    }
+
+    fn register(&self, stream: RegisteredStream) {
+        self.record("streamOpened", Some(stream.details.id.index()), None);
+
+        let mut streams = self.streams.lock().unwrap();
+
+        // Drop streams which already finished so the list doesn't grow with every stream opened
+        streams.retain(|stream| !stream.is_done());
+        streams.push(stream);
+    }
+
+    fn unregister(&self, handle: &Arc<ReadHandle>) {
+        self.streams
+            .lock()
+            .unwrap()
+            .retain(|stream| !Arc::ptr_eq(&stream.handle, handle));
+    }
+
+    // Streams which are still in use, including those which are not initialized yet
+    fn open(&self) -> usize {
+        let mut streams = self.streams.lock().unwrap();
+        streams.retain(|stream| !stream.is_done());
+
+        streams.len() + self.opening.load(Ordering::Acquire)
+    }
+
+    fn pending(&self) -> Vec<RegisteredStream> {
+        self.streams.lock().unwrap().clone()
+    }
+
+    // The registered streams for `debug_dump`, including those which are done but weren't
+    //  dropped from the list yet
+    fn snapshot<'a, C: Context<'a>>(&self, cx: &mut C) -> JsResult<'a, JsArray> {
+        let streams = self.pending();
+        let result = cx.empty_array();
+
+        for (i, stream) in streams.iter().enumerate() {
+            let entry = stream.details.to_object(cx)?;
+            let unidirectional = cx.boolean(stream.send.is_none());
+            let read_finished = cx.boolean(stream.handle.is_finished());
+            let write_finished: Handle<JsValue> = match &stream.send {
+                Some(_) => cx.boolean(stream.finished.load(Ordering::Acquire)).upcast(),
+                None => cx.null().upcast(),
+            };
+            let buffered_write_bytes =
+                cx.number(stream.send.as_ref().map_or(0, Writer::buffered) as f64);
+
+            entry.set(cx, "unidirectional", unidirectional)?;
+            entry.set(cx, "readFinished", read_finished)?;
+            entry.set(cx, "writeFinished", write_finished)?;
+            entry.set(cx, "bufferedWriteBytes", buffered_write_bytes)?;
+            result.set(cx, i as u32, entry)?;
+        }
+
+        Ok(result)
+    }
+
+    // Sizes of the queues between the QUIC stack and JavaScript, for `debug_dump`
+    fn queues<'a, C: Context<'a>>(&self, cx: &mut C) -> JsResult<'a, JsObject> {
+        let write_bytes: usize = self
+            .pending()
+            .iter()
+            .filter_map(|stream| stream.send.as_ref())
+            .map(Writer::buffered)
+            .sum();
+
+        let result = cx.empty_object();
+        let buffered_bytes = cx.number(self.buffered.level() as f64);
+        let max_buffered_bytes: Handle<JsValue> = match self.buffered.limit() {
+            Some(limit) => cx.number(limit as f64).upcast(),
+            None => cx.null().upcast(),
+        };
+        let buffered_write_bytes = cx.number(write_bytes as f64);
+        let opening_streams = cx.number(self.opening.load(Ordering::Acquire) as f64);
+
+        result.set(cx, "bufferedBytes", buffered_bytes)?;
+        result.set(cx, "maxBufferedBytes", max_buffered_bytes)?;
+        result.set(cx, "bufferedWriteBytes", buffered_write_bytes)?;
+        result.set(cx, "openingStreams", opening_streams)?;
+
+        Ok(result)
+    }
+
+    // `null` without `eventLogSize`
+    fn events<'a, C: Context<'a>>(&self, cx: &mut C) -> JsResult<'a, JsValue> {
+        let Some(log) = &self.event_log else {
+            return Ok(cx.null().upcast());
+        };
+
+        let result = cx.empty_array();
+        for (i, event) in log.snapshot().into_iter().enumerate() {
+            let entry = cx.empty_object();
+            let elapsed_ms = cx.number(event.elapsed.as_secs_f64() * 1000.0);
+            let name = cx.string(event.name);
+            let stream_id: Handle<JsValue> = match event.stream_id {
+                Some(id) => JsBigInt::from_u64(cx, id).upcast(),
+                None => cx.null().upcast(),
+            };
+            let detail: Handle<JsValue> = match event.detail {
+                Some(detail) => cx.string(detail).upcast(),
+                None => cx.null().upcast(),
+            };
+
+            entry.set(cx, "elapsedMs", elapsed_ms)?;
+            entry.set(cx, "name", name)?;
+            entry.set(cx, "streamId", stream_id)?;
+            entry.set(cx, "detail", detail)?;
+            result.set(cx, i as u32, entry)?;
+        }
+
+        Ok(result.upcast())
+    }
+
+    fn set_referenced<'a, C: Context<'a>>(&self, cx: &mut C, referenced: bool) {
+        self.referenced.store(referenced, Ordering::Release);
+
+        for stream in self.streams.lock().unwrap().iter() {
+            stream.keep_alive.set_referenced(cx, referenced);
+        }
+    }
+
+    fn close_all(&self) {
+        for stream in self.streams.lock().unwrap().drain(..) {
+            stream.close_requested.cancel(StopRequest::default());
+            stream.keep_alive.release();
+        }
+    }
+}
+
+// Every entry of the array has to be a `Uint8Array`, `name` is used for error messages
+fn to_uint8_vec<'a, C>(
+    cx: &mut C,
+    name: &str,
+    value: Option<Handle<JsArray>>,
+) -> Result<Option<Vec<Vec<u8>>>, neon::result::Throw>
+where
+    C: Context<'a>,
+{
+    Ok(match value {
+        None => None,
+        Some(protocols) => {
+            let value: Result<Vec<_>, neon::result::Throw> = protocols
+                .to_vec(cx)?
+                .into_iter()
+                .enumerate()
+                .map(|(i, entry)| {
+                    use neon::types::buffer::TypedArray;
+
+                    args::check::<_, JsUint8Array>(cx, "connect", &format!("{name}[{i}]"), entry)
+                        .map(|handle| handle.as_slice(cx).to_vec())
+                })
+                .collect();
+
+            Some(value?)
+        }
+    })
+}
+
+// Returns `None` for missing, `undefined` or `null` properties, throws if the value is not a
+//  number. Only used for the options of `connect`
+fn optional_number<'a, C: Context<'a>>(
+    cx: &mut C,
+    object: Handle<JsObject>,
+    object_name: &str,
+    key: &str,
+) -> NeonResult<Option<f64>> {
+    Ok(
+        args::optional_property::<_, JsNumber>(cx, "connect", object, object_name, key)?
+            .map(|value| value.value(cx)),
+    )
+}
+
+// Optional VarInt property of the `connect` options, a number or a BigInt
+fn optional_var_int<'a, C: Context<'a>>(
+    cx: &mut C,
+    object: Handle<JsObject>,
+    object_name: &str,
+    key: &str,
+) -> NeonResult<Option<VarInt>> {
+    let value: Handle<JsValue> = object.get(cx, key)?;
+
+    if value.is_a::<JsUndefined, _>(cx) || value.is_a::<JsNull, _>(cx) {
+        return Ok(None);
+    }
+
+    var_int(cx, "connect", &format!("{object_name}.{key}"), value).map(Some)
+}
+
+// UDP payload size of `options.mtu`, from the minimum QUIC requires up to the largest UDP payload
+fn mtu_size<'a, C: Context<'a>>(
+    cx: &mut C,
+    mtu: Handle<JsObject>,
+    key: &str,
+) -> NeonResult<Option<u16>> {
+    const MIN_MTU: f64 = 1200.0;
+    const MAX_MTU: f64 = 65527.0;
+
+    let Some(size) = optional_number(cx, mtu, "options.mtu", key)? else {
+        return Ok(None);
+    };
+
+    if size.fract() != 0.0 || !(MIN_MTU..=MAX_MTU).contains(&size) {
+        return NativeError::range_error(format!(
+            "connect: expected options.mtu.{key} to be an integer between {MIN_MTU} and {MAX_MTU}, got {size}"
+        ))
+        .throw(cx);
+    }
+
+    Ok(Some(size as u16))
+}
+
+// `options.dscp` of `connect`, the six bits of the differentiated services field
+fn dscp_option<'a, C: Context<'a>>(
+    cx: &mut C,
+    options: Handle<JsObject>,
+) -> NeonResult<Option<u8>> {
+    let Some(dscp) = optional_number(cx, options, "options", "dscp")? else {
+        return Ok(None);
+    };
+
+    if dscp.fract() != 0.0 || !(0.0..=63.0).contains(&dscp) {
+        return NativeError::range_error(format!(
+            "connect: expected options.dscp to be an integer between 0 and 63, got {dscp}"
+        ))
+        .throw(cx);
+    }
+
+    Ok(Some(dscp as u8))
+}
+
+// Size of a socket buffer of the `connect` options. The kernel takes it as an `int`
+fn buffer_size<'a, C: Context<'a>>(
+    cx: &mut C,
+    options: Handle<JsObject>,
+    key: &str,
+) -> NeonResult<Option<usize>> {
+    let Some(size) = optional_number(cx, options, "options", key)? else {
+        return Ok(None);
+    };
+
+    if size.fract() != 0.0 || !(1.0..=i32::MAX as f64).contains(&size) {
+        return NativeError::range_error(format!(
+            "connect: expected options.{key} to be a positive integer up to 2^31 - 1, got {size}"
+        ))
+        .throw(cx);
+    }
+
+    Ok(Some(size as usize))
+}
+
+// `localAddress` and `localPort` of the `connect` options, the port defaults to `0`
+fn local_address_option<'a, C: Context<'a>>(
+    cx: &mut C,
+    options: Handle<JsObject>,
+) -> NeonResult<(Option<SocketAddr>, u16)> {
+    let port = match optional_number(cx, options, "options", "localPort")? {
+        None => 0,
+        Some(port) => port_number(cx, "connect", "options.localPort", port)?,
+    };
+
+    let address =
+        args::optional_property::<_, JsString>(cx, "connect", options, "options", "localAddress")?
+            .map(|address| address.value(cx))
+            .map(|address| parse_ip(cx, "connect", "options.localAddress", &address, port))
+            .transpose()?;
+
+    Ok((address, port))
+}
+
+// Durations given by JavaScript are capped to this, which is as good as forever. Deadlines are
+//  computed by adding them to the current time, which would overflow for larger ones
+const MAX_DURATION: Duration = Duration::from_secs(100 * 365 * 24 * 60 * 60);
+
+// Duration given in milliseconds, `name` is the argument or property it came from. Throws if it
+//  is negative or can't be represented, like `Infinity` or 1e300
+fn duration_ms<'a, C: Context<'a>>(
+    cx: &mut C,
+    function: &str,
+    name: &str,
+    value: f64,
+) -> NeonResult<Duration> {
+    match Duration::try_from_secs_f64(value / 1000.0) {
+        Ok(duration) => Ok(duration.min(MAX_DURATION)),
+        Err(_) => NativeError::range_error(format!(
+            "{function}: expected {name} to be a non-negative number, got {value}"
+        ))
+        .throw(cx),
+    }
+}
+
+// Optional duration given in milliseconds, like `timeoutMs`, see `duration_ms`
+fn optional_duration<'a, C: Context<'a>>(
+    cx: &mut C,
+    function: &str,
+    object: Handle<JsObject>,
+    object_name: &str,
+    key: &str,
+) -> NeonResult<Option<Duration>> {
+    let Some(value) =
+        args::optional_property::<_, JsNumber>(cx, function, object, object_name, key)?
+    else {
+        return Ok(None);
+    };
+    let value = value.value(cx);
+
+    duration_ms(cx, function, &format!("{object_name}.{key}"), value).map(Some)
+}
+
+// Application error codes and other VarInts are 62-bit, so they are accepted as a BigInt as well
+//  as a number. Numbers have to be integers within the safe integer range so no precision is lost
+fn var_int<'a, C: Context<'a>>(
+    cx: &mut C,
+    function: &str,
+    name: &str,
+    value: Handle<JsValue>,
+) -> NeonResult<VarInt> {
+    const MAX_SAFE_INTEGER: f64 = ((1u64 << 53) - 1) as f64;
+
+    let value = if let Ok(value) = value.downcast::<JsBigInt, _>(cx) {
+        match value.to_u64(cx) {
+            Ok(value) => value,
+            Err(_) => {
+                return NativeError::range_error(format!(
+                    "{function}: expected {name} to be a non-negative 62-bit integer"
+                ))
+                .throw(cx)
+            }
+        }
+    } else {
+        let Ok(value) = value.downcast::<JsNumber, _>(cx) else {
+            let actual = args::type_of(cx, value);
+
+            return NativeError::type_error(format!(
+                "{function}: expected {name} to be a number or a bigint, got {actual}"
+            ))
+            .throw(cx);
+        };
+        let value = value.value(cx);
+
+        if value.is_nan() || value.fract() != 0.0 || !(0.0..=MAX_SAFE_INTEGER).contains(&value) {
+            return NativeError::range_error(format!(
+                "{function}: expected {name} to be a non-negative safe integer, use a BigInt for larger values"
+            ))
+            .throw(cx);
+        }
+
+        value as u64
+    };
+
+    VarInt::from_u64(value).or_else(|e| NativeError::range_error(e.to_string()).throw(cx))
+}
+
+fn port_number<'a, C: Context<'a>>(
+    cx: &mut C,
+    function: &str,
+    name: &str,
+    port: f64,
+) -> NeonResult<u16> {
+    if port.fract() != 0.0 || !(0.0..=u16::MAX as f64).contains(&port) {
+        return NativeError::range_error(format!(
+            "{function}: expected {name} to be an integer between 0 and 65535, got {port}"
+        ))
+        .throw(cx);
+    }
+
+    Ok(port as u16)
+}
+
+// Parses an IP address given to `function` as `name`. IPv6 addresses may have a zone, either an
+//  interface name like `fe80::1%eth0` or its index like `fe80::1%2`, which link-local addresses
+//  need to tell which link they're on
+fn parse_ip<'a, C: Context<'a>>(
+    cx: &mut C,
+    function: &str,
+    name: &str,
+    ip: &str,
+    port: u16,
+) -> NeonResult<SocketAddr> {
+    let Some((address, zone)) = ip.split_once('%') else {
+        return match ip.parse::<IpAddr>() {
+            Ok(address) => Ok(SocketAddr::new(address, port)),
+            Err(_) => NativeError::type_error(format!(
+                "{function}: expected {name} to be an IPv4 or IPv6 address, got {ip:?}"
+            ))
+            .throw(cx),
+        };
+    };
+
+    let Ok(address) = address.parse::<Ipv6Addr>() else {
+        return NativeError::type_error(format!(
+            "{function}: expected {name} to be an IPv6 address with a zone, got {ip:?}"
+        ))
+        .throw(cx);
+    };
+
+    let scope_id = match zone.parse::<u32>() {
+        Ok(index) => index,
+        Err(_) => match quic::interface_index(zone) {
+            Ok(index) => index.get(),
+            Err(err) => {
+                return NativeError::type_error(format!(
+                    "{function}: unable to look up the interface {zone:?} of {name} {ip:?}: {err}"
+                ))
+                .throw(cx)
+            }
+        },
+    };
+
+    Ok(SocketAddrV6::new(address, port, 0, scope_id).into())
+}
+
+// Validates a window over the sampled statistics given in milliseconds
+fn sample_window<'a, C: Context<'a>>(
+    cx: &mut C,
+    function: &str,
+    name: &str,
+    window_ms: f64,
+) -> NeonResult<std::time::Duration> {
+    if window_ms.is_nan() || window_ms <= 0.0 || window_ms > sampler::HISTORY.as_millis() as f64 {
+        return NativeError::range_error(format!(
+            "{function}: expected {name} to be above 0 and at most {} milliseconds, got {window_ms}",
+            sampler::HISTORY.as_millis()
+        ))
+        .throw(cx);
+    }
+
+    Ok(std::time::Duration::from_secs_f64(window_ms / 1000.0))
+}
+
+// Optional settings passed to `connect` as a single object. Cloned by `connect_persistent` for
+//  every connection it makes, the callbacks are shared by all of them
+#[derive(Clone)]
+struct ConnectOptions {
+    label: Arc<str>,
+    on_congestion_event: Option<Arc<Root<JsFunction>>>,
+    throughput_window: std::time::Duration,
+    rtt_histogram: bool,
+    cwnd_warning: Option<sampler::CwndWarning>,
+    on_cwnd_warning: Option<Arc<Root<JsFunction>>>,
+    endpoint: quic::ClientEndpoint,
+    drain_timeout: Duration,
+    cancel_token: Option<CancelToken>,
+    controller: Option<ConnectController>,
+    // Of the handshake, `None` leaves it to quinn's idle timeout
+    connect_timeout: Option<Duration>,
+    auto_close_idle: Option<Duration>,
+    transport: quic::TransportOptions,
+    // Interval of the keep-alive sent by `keep_alive_while_active` instead of quinn's
+    active_keep_alive: Option<Duration>,
+    stream_batch: Option<StreamBatch>,
+    max_buffered_bytes: Option<usize>,
+    on_buffered_bytes_warning: Option<Arc<Root<JsFunction>>>,
+    write_chunk_size: usize,
+    // Capacity of the event log included in `debug_dump`, `None` doesn't keep one
+    event_log_size: Option<usize>,
+}
+
+// Accepted streams are passed to `on_stream` together, as an array, while the peer keeps opening
+//  them. A batch is delivered once it has `max_streams` streams, it was started `max_delay` ago, or
+//  no other stream is waiting to be accepted
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+struct StreamBatch {
+    max_streams: usize,
+    max_delay: Duration,
+}
+
+// Defaults of `streamBatch.maxStreams` and `streamBatch.maxDelayMs`
+const STREAM_BATCH_SIZE: usize = 64;
+const STREAM_BATCH_DELAY: Duration = Duration::from_millis(10);
+
+// Default of `writeChunkBytes`
+const WRITE_CHUNK_SIZE: usize = 256 * 1024;
+
+// Upper bound of `eventLogSize`
+const MAX_EVENT_LOG_SIZE: usize = 65_536;
+
+// Bounds of `initialRttMs`
+const MIN_INITIAL_RTT_MS: f64 = 1.0;
+const MAX_INITIAL_RTT_MS: f64 = 60_000.0;
+
+// Exclusive upper bound of `ackFrequency.maxAckDelayMs`
+const MAX_ACK_DELAY_MS: f64 = (1 << 14) as f64;
+
+// Default of `drainTimeoutMs`, how long `on_close` may be delayed waiting for the endpoint to drain
+const DRAIN_TIMEOUT: Duration = Duration::from_secs(3);
+
+impl ConnectOptions {
+    fn from_object<'a, C: Context<'a>>(cx: &mut C, options: Handle<JsObject>) -> NeonResult<Self> {
+        let label =
+            args::optional_property::<_, JsString>(cx, "connect", options, "options", "label")?
+                .map(|label| label.value(cx))
+                .unwrap_or_default()
+                .into();
+
+        let on_congestion_event = args::optional_property::<_, JsFunction>(
+            cx,
+            "connect",
+            options,
+            "options",
+            "onCongestionEvent",
+        )?
+        .map(|callback| Arc::new(callback.root(cx)));
+
+        let throughput_window = match optional_number(cx, options, "options", "throughputWindowMs")?
+        {
+            None => sampler::DEFAULT_THROUGHPUT_WINDOW,
+            Some(window_ms) => {
+                sample_window(cx, "connect", "options.throughputWindowMs", window_ms)?
+            }
+        };
+
+        let rtt_histogram = args::optional_property::<_, JsBoolean>(
+            cx,
+            "connect",
+            options,
+            "options",
+            "rttHistogram",
+        )?
+        .map(|value| value.value(cx))
+        .unwrap_or(false);
+
+        let on_cwnd_warning = args::optional_property::<_, JsFunction>(
+            cx,
+            "connect",
+            options,
+            "options",
+            "onCwndWarning",
+        )?
+        .map(|callback| Arc::new(callback.root(cx)));
+
+        let cwnd_warning = match args::optional_property::<_, JsObject>(
+            cx,
+            "connect",
+            options,
+            "options",
+            "cwndWarning",
+        )? {
+            None => None,
+            Some(config) => {
+                let threshold =
+                    optional_number(cx, config, "options.cwndWarning", "thresholdBytes")?;
+                let sustain =
+                    optional_duration(cx, "connect", config, "options.cwndWarning", "sustainMs")?
+                        .unwrap_or_default();
+
+                let Some(threshold) = threshold.filter(|v| v.is_finite() && *v >= 0.0) else {
+                    return NativeError::range_error(format!(
+                        "connect: expected options.cwndWarning.thresholdBytes to be a non-negative number, got {}",
+                        threshold.map_or(String::from("undefined"), |v| v.to_string())
+                    ))
+                    .throw(cx);
+                };
+
+                Some(sampler::CwndWarning {
+                    threshold: threshold as u64,
+                    sustain,
+                })
+            }
+        };
+
+        let endpoint = args::optional_property::<_, JsBox<Endpoint>>(
+            cx, "connect", options, "options", "endpoint",
+        )?
+        .map(|endpoint| endpoint.endpoint.as_ref().clone());
+
+        let proxy =
+            args::optional_property::<_, JsObject>(cx, "connect", options, "options", "proxy")?
+                .map(|proxy| proxy_options(cx, proxy))
+                .transpose()?;
+
+        let (local_address, local_port) = local_address_option(cx, options)?;
+        let socket = quic::SocketOptions {
+            local_address: local_address.map(|addr| addr.ip()),
+            local_port,
+            ipv6: args::optional_property::<_, JsBoolean>(
+                cx, "connect", options, "options", "ipv6",
+            )?
+            .map(|ipv6| ipv6.value(cx)),
+            ipv6_only: args::optional_property::<_, JsBoolean>(
+                cx, "connect", options, "options", "ipv6Only",
+            )?
+            .map(|ipv6_only| ipv6_only.value(cx)),
+            local_scope_id: match local_address {
+                Some(SocketAddr::V6(addr)) => addr.scope_id(),
+                _ => 0,
+            },
+            interface: args::optional_property::<_, JsString>(
+                cx,
+                "connect",
+                options,
+                "options",
+                "interface",
+            )?
+            .map(|interface| interface.value(cx)),
+            send_buffer_size: buffer_size(cx, options, "udpSendBufferSize")?,
+            recv_buffer_size: buffer_size(cx, options, "udpRecvBufferSize")?,
+            dscp: dscp_option(cx, options)?,
+            disable_offload: args::optional_property::<_, JsBoolean>(
+                cx,
+                "connect",
+                options,
+                "options",
+                "udpOffload",
+            )?
+            .is_some_and(|offload| !offload.value(cx)),
+        };
+
+        let endpoint = match (endpoint, proxy) {
+            (None, None) => quic::ClientEndpoint::Bind(socket),
+            // The socket options only apply to an endpoint bound for the connection
+            _ if socket != quic::SocketOptions::default() => {
+                return NativeError::type_error(
+                    "connect: options.localAddress, options.localPort, options.ipv6, options.ipv6Only, options.interface, options.udpSendBufferSize, options.udpRecvBufferSize, options.dscp and options.udpOffload can't be used together with options.endpoint or options.proxy",
+                )
+                .throw(cx);
+            }
+            (Some(endpoint), None) => quic::ClientEndpoint::Shared(endpoint),
+            (None, Some(proxy)) => quic::ClientEndpoint::Proxy(proxy),
+            // The endpoint's socket is already bound, its datagrams can't be relayed anymore
+            (Some(_), Some(_)) => {
+                return NativeError::type_error(
+                    "connect: options.endpoint and options.proxy can't be used together",
+                )
+                .throw(cx);
+            }
+        };
+
+        let drain_timeout = optional_duration(cx, "connect", options, "options", "drainTimeoutMs")?
+            .unwrap_or(DRAIN_TIMEOUT);
+
+        let cancel_token = args::optional_property::<_, JsBox<CancelToken>>(
+            cx,
+            "connect",
+            options,
+            "options",
+            "cancelToken",
+        )?
+        .map(|token| (**token).clone());
+
+        let controller = args::optional_property::<_, JsBox<ConnectController>>(
+            cx,
+            "connect",
+            options,
+            "options",
+            "controller",
+        )?
+        .map(|controller| (**controller).clone());
+
+        // `0` is the same as not giving one
+        let connect_timeout = optional_duration(cx, "connect", options, "options", "timeoutMs")?
+            .filter(|timeout| !timeout.is_zero());
+
+        let auto_close_idle =
+            optional_duration(cx, "connect", options, "options", "autoCloseIdleMs")?;
+
+        let mut transport = quic::TransportOptions::default();
+        let mut active_keep_alive = None;
+        if let Some(keep_alive) =
+            args::optional_property::<_, JsObject>(cx, "connect", options, "options", "keepAlive")?
+        {
+            // `null` turns the keep-alive off, for applications which send heartbeats of their own
+            let interval_ms: Handle<JsValue> = keep_alive.get(cx, "intervalMs")?;
+            let disabled = interval_ms.is_a::<JsNull, _>(cx);
+
+            let interval =
+                optional_duration(cx, "connect", keep_alive, "options.keepAlive", "intervalMs")?
+                    .unwrap_or(quic::DEFAULT_KEEP_ALIVE_INTERVAL);
+            let only_when_active = args::optional_property::<_, JsBoolean>(
+                cx,
+                "connect",
+                keep_alive,
+                "options.keepAlive",
+                "onlyWhenActive",
+            )?
+            .is_some_and(|value| value.value(cx));
+
+            if interval.is_zero() {
+                return NativeError::range_error(
+                    "connect: expected options.keepAlive.intervalMs to be greater than 0",
+                )
+                .throw(cx);
+            }
+
+            if disabled {
+                transport.keep_alive_interval = None;
+            } else if only_when_active {
+                transport.keep_alive_interval = None;
+                active_keep_alive = Some(interval);
+            } else {
+                transport.keep_alive_interval = Some(interval);
+            }
+        }
+
+        // `null` turns the idle timeout off, unlike leaving it out which keeps quinn's default
+        let idle_timeout_ms: Handle<JsValue> = options.get(cx, "idleTimeoutMs")?;
+        if idle_timeout_ms.is_a::<JsNull, _>(cx) {
+            transport.max_idle_timeout = Some(None);
+        } else if let Some(idle_timeout_ms) =
+            optional_number(cx, options, "options", "idleTimeoutMs")?
+        {
+            // The timeout is sent to the server as a VarInt of milliseconds
+            let idle_timeout = if idle_timeout_ms.fract() == 0.0 && idle_timeout_ms > 0.0 {
+                quinn::IdleTimeout::try_from(Duration::from_millis(idle_timeout_ms as u64)).ok()
+            } else {
+                None
+            };
+
+            let Some(idle_timeout) = idle_timeout else {
+                return NativeError::range_error(format!(
+                    "connect: expected options.idleTimeoutMs to be a positive integer below 2^62, or null to disable it, got {idle_timeout_ms}"
+                ))
+                .throw(cx);
+            };
+
+            transport.max_idle_timeout = Some(Some(idle_timeout));
+        }
+
+        if let Some(name) = args::optional_property::<_, JsString>(
+            cx,
+            "connect",
+            options,
+            "options",
+            "congestionControl",
+        )? {
+            let name = name.value(cx);
+
+            let Some(controller) = quic::CongestionController::from_name(&name) else {
+                let supported =
+                    quic::CongestionController::ALL.map(|controller| controller.as_str());
+
+                return NativeError::range_error(format!(
+                    "connect: expected options.congestionControl to be one of {}, got {name:?}",
+                    supported.join(", ")
+                ))
+                .throw(cx);
+            };
+
+            transport.congestion_controller = controller;
+        }
+
+        if let Some(initial_window) = optional_number(cx, options, "options", "initialWindowBytes")?
+        {
+            if initial_window.fract() != 0.0
+                || !(quic::MIN_INITIAL_WINDOW as f64..=u32::MAX as f64).contains(&initial_window)
+            {
+                return NativeError::range_error(format!(
+                    "connect: expected options.initialWindowBytes to be an integer between {} and {}, got {initial_window}",
+                    quic::MIN_INITIAL_WINDOW,
+                    u32::MAX
+                ))
+                .throw(cx);
+            }
+
+            transport.initial_window = Some(initial_window as u64);
+        }
+
+        // Used until the handshake measured the path, a value far below the actual RTT makes
+        //  loss detection resend the handshake packets spuriously
+        if let Some(initial_rtt_ms) = optional_number(cx, options, "options", "initialRttMs")? {
+            // Checked as a number, a huge value doesn't fit into a `Duration`
+            if !(MIN_INITIAL_RTT_MS..=MAX_INITIAL_RTT_MS).contains(&initial_rtt_ms) {
+                return NativeError::range_error(format!(
+                    "connect: expected options.initialRttMs to be between {MIN_INITIAL_RTT_MS} and {MAX_INITIAL_RTT_MS}, got {initial_rtt_ms}"
+                ))
+                .throw(cx);
+            }
+
+            let initial_rtt = Duration::from_secs_f64(initial_rtt_ms / 1000.0);
+            transport.initial_rtt = Some(initial_rtt);
+        }
+
+        // Asks the server to acknowledge less often, if it supports the ACK frequency extension
+        if let Some(ack_frequency) = args::optional_property::<_, JsObject>(
+            cx,
+            "connect",
+            options,
+            "options",
+            "ackFrequency",
+        )? {
+            let mut config = quinn::AckFrequencyConfig::default();
+
+            if let Some(threshold) = optional_var_int(
+                cx,
+                ack_frequency,
+                "options.ackFrequency",
+                "ackElicitingThreshold",
+            )? {
+                config.ack_eliciting_threshold(threshold);
+            }
+            if let Some(threshold) = optional_var_int(
+                cx,
+                ack_frequency,
+                "options.ackFrequency",
+                "reorderingThreshold",
+            )? {
+                config.reordering_threshold(threshold);
+            }
+            if let Some(max_ack_delay_ms) =
+                optional_number(cx, ack_frequency, "options.ackFrequency", "maxAckDelayMs")?
+            {
+                // RFC 9000 doesn't allow a max_ack_delay of 2^14 ms or more
+                if max_ack_delay_ms.fract() != 0.0
+                    || !(0.0..MAX_ACK_DELAY_MS).contains(&max_ack_delay_ms)
+                {
+                    return NativeError::range_error(format!(
+                        "connect: expected options.ackFrequency.maxAckDelayMs to be an integer below {MAX_ACK_DELAY_MS}, got {max_ack_delay_ms}"
+                    ))
+                    .throw(cx);
+                }
+
+                config.max_ack_delay(Some(Duration::from_millis(max_ack_delay_ms as u64)));
+            }
+
+            transport.ack_frequency = Some(config);
+        }
+
+        if let Some(mtu) =
+            args::optional_property::<_, JsObject>(cx, "connect", options, "options", "mtu")?
+        {
+            transport.initial_mtu = mtu_size(cx, mtu, "initial")?;
+            transport.min_mtu = mtu_size(cx, mtu, "min")?;
+            let upper_bound = mtu_size(cx, mtu, "upperBound")?;
+            let interval = optional_duration(cx, "connect", mtu, "options.mtu", "intervalMs")?;
+
+            if let (Some(initial), Some(min)) = (transport.initial_mtu, transport.min_mtu) {
+                if initial < min {
+                    return NativeError::range_error(format!(
+                        "connect: expected options.mtu.initial to be at least options.mtu.min, got {initial} and {min}"
+                    ))
+                    .throw(cx);
+                }
+            }
+            // Discovery only ever probes above the current MTU, quinn starts at the larger of
+            //  `initial` and `min`
+            let start = transport.initial_mtu.max(transport.min_mtu);
+            if let (Some(upper_bound), Some(start)) = (upper_bound, start) {
+                if upper_bound < start {
+                    return NativeError::range_error(format!(
+                        "connect: expected options.mtu.upperBound to be at least options.mtu.initial and options.mtu.min, got {upper_bound} and {start}"
+                    ))
+                    .throw(cx);
+                }
+            }
+
+            let discovery = args::optional_property::<_, JsBoolean>(
+                cx,
+                "connect",
+                mtu,
+                "options.mtu",
+                "discovery",
+            )?
+            .map(|discovery| discovery.value(cx))
+            .unwrap_or(true);
+
+            if !discovery {
+                // Without discovery the size given as `initial` is the only one ever sent, a black
+                //  hole can't lower it either
+                let Some(initial) = transport.initial_mtu else {
+                    return NativeError::type_error(
+                        "connect: expected options.mtu.initial to be set when options.mtu.discovery is false",
+                    )
+                    .throw(cx);
+                };
+                if transport.min_mtu.is_some_and(|min| min != initial)
+                    || upper_bound.is_some()
+                    || interval.is_some()
+                {
+                    return NativeError::type_error(
+                        "connect: options.mtu.min, options.mtu.upperBound and options.mtu.intervalMs can't be used when options.mtu.discovery is false",
+                    )
+                    .throw(cx);
+                }
+
+                transport.min_mtu = Some(initial);
+                transport.mtu_discovery = Some(None);
+            } else if upper_bound.is_some() || interval.is_some() {
+                let mut config = quinn::MtuDiscoveryConfig::default();
+                if let Some(upper_bound) = upper_bound {
+                    config.upper_bound(upper_bound);
+                }
+                if let Some(interval) = interval {
+                    config.interval(interval);
+                }
+
+                transport.mtu_discovery = Some(Some(config));
+                transport.mtu_upper_bound = upper_bound;
+            }
+        }
+        transport.proxied = matches!(endpoint, quic::ClientEndpoint::Proxy(_));
+
+        // Flow control of the connection as a whole, on top of the limits of every stream
+        transport.receive_window = optional_var_int(cx, options, "options", "receiveWindowBytes")?;
+        transport.send_window =
+            optional_var_int(cx, options, "options", "sendWindowBytes")?.map(VarInt::into_inner);
+
+        // 0 is accepted, the server can't open any streams of the kind then
+        transport.max_concurrent_bidi_streams =
+            optional_var_int(cx, options, "options", "maxConcurrentBidiStreams")?;
+        transport.max_concurrent_uni_streams =
+            optional_var_int(cx, options, "options", "maxConcurrentUniStreams")?;
+
+        if let Some(reuse_tokens) = args::optional_property::<_, JsBoolean>(
+            cx,
+            "connect",
+            options,
+            "options",
+            "reuseTokens",
+        )? {
+            transport.reuse_tokens = reuse_tokens.value(cx);
+        }
+
+        let stream_batch = match args::optional_property::<_, JsObject>(
+            cx,
+            "connect",
+            options,
+            "options",
+            "streamBatch",
+        )? {
+            None => None,
+            Some(batch) => {
+                let max_streams = optional_number(cx, batch, "options.streamBatch", "maxStreams")?
+                    .unwrap_or(STREAM_BATCH_SIZE as f64);
+                let max_delay =
+                    optional_duration(cx, "connect", batch, "options.streamBatch", "maxDelayMs")?
+                        .unwrap_or(STREAM_BATCH_DELAY);
+
+                if max_streams.fract() != 0.0 || max_streams < 1.0 {
+                    return NativeError::range_error(format!(
+                        "connect: expected options.streamBatch.maxStreams to be a positive integer, got {max_streams}"
+                    ))
+                    .throw(cx);
+                }
+
+                Some(StreamBatch {
+                    max_streams: max_streams as usize,
+                    max_delay,
+                })
+            }
+        };
+
+        let max_buffered_bytes = optional_number(cx, options, "options", "maxBufferedBytes")?;
+        if let Some(bytes) = max_buffered_bytes {
+            if bytes.fract() != 0.0 || bytes < 1.0 {
+                return NativeError::range_error(format!(
+                    "connect: expected options.maxBufferedBytes to be a positive integer, got {bytes}"
+                ))
+                .throw(cx);
+            }
+        }
+
+        let on_buffered_bytes_warning = args::optional_property::<_, JsFunction>(
+            cx,
+            "connect",
+            options,
+            "options",
+            "onBufferedBytesWarning",
+        )?
+        .map(|callback| Arc::new(callback.root(cx)));
+
+        let write_chunk_size = optional_number(cx, options, "options", "writeChunkBytes")?
+            .unwrap_or(WRITE_CHUNK_SIZE as f64);
+        if write_chunk_size.fract() != 0.0 || write_chunk_size < 1.0 {
+            return NativeError::range_error(format!(
+                "connect: expected options.writeChunkBytes to be a positive integer, got {write_chunk_size}"
+            ))
+            .throw(cx);
+        }
+
+        let event_log_size = optional_number(cx, options, "options", "eventLogSize")?;
+        if let Some(size) = event_log_size {
+            if size.fract() != 0.0 || !(1.0..=MAX_EVENT_LOG_SIZE as f64).contains(&size) {
+                return NativeError::range_error(format!(
+                    "connect: expected options.eventLogSize to be an integer between 1 and {MAX_EVENT_LOG_SIZE}, got {size}"
+                ))
+                .throw(cx);
+            }
+        }
+
+        Ok(Self {
+            label,
+            on_congestion_event,
+            throughput_window,
+            rtt_histogram,
+            cwnd_warning,
+            on_cwnd_warning,
+            endpoint,
+            drain_timeout,
+            cancel_token,
+            controller,
+            connect_timeout,
+            auto_close_idle,
+            transport,
+            active_keep_alive,
+            stream_batch,
+            max_buffered_bytes: max_buffered_bytes.map(|bytes| bytes as usize),
+            on_buffered_bytes_warning,
+            write_chunk_size: write_chunk_size as usize,
+            event_log_size: event_log_size.map(|size| size as usize),
+        })
+    }
+}
+
+// Parses `options.proxy` of `connect`
+fn proxy_options<'a, C: Context<'a>>(
+    cx: &mut C,
+    proxy: Handle<JsObject>,
+) -> NeonResult<socks::Proxy> {
+    let host: Handle<JsValue> = proxy.get(cx, "host")?;
+    let host = args::check::<_, JsString>(cx, "connect", "options.proxy.host", host)?.value(cx);
+
+    let port: Handle<JsValue> = proxy.get(cx, "port")?;
+    let port = args::check::<_, JsNumber>(cx, "connect", "options.proxy.port", port)?.value(cx);
+    let port = port_number(cx, "connect", "options.proxy.port", port)?;
+
+    let username =
+        args::optional_property::<_, JsString>(cx, "connect", proxy, "options.proxy", "username")?
+            .map(|username| username.value(cx));
+    let password =
+        args::optional_property::<_, JsString>(cx, "connect", proxy, "options.proxy", "password")?
+            .map(|password| password.value(cx));
+
+    let credentials = match (username, password) {
+        (None, None) => None,
+        (Some(username), Some(password)) => Some((username, password)),
+        _ => {
+            return NativeError::type_error(
+                "connect: expected both or neither of options.proxy.username and options.proxy.password",
+            )
+            .throw(cx);
+        }
+    };
+
+    // RFC 1929 sends both with a single byte length
+    if let Some((username, password)) = &credentials {
+        if !(1..=255).contains(&username.len()) || !(1..=255).contains(&password.len()) {
+            return NativeError::range_error(
+                "connect: expected options.proxy.username and options.proxy.password to be between 1 and 255 bytes",
+            )
+            .throw(cx);
+        }
+    }
+
+    Ok(socks::Proxy {
+        host,
+        port,
+        credentials,
+    })
+}
+
+// JavaScript callbacks for the events derived from the sampled statistics. The roots live as long
+//  as the sampler, every event only takes a handle to them
+struct SamplerCallbacks {
+    on_congestion_event: Option<Arc<Root<JsFunction>>>,
+    on_cwnd_warning: Option<Arc<Root<JsFunction>>>,
+    // The events are recorded in the connection's log as well
+    event_log: Option<Arc<EventLog>>,
+}
+
+// Periodically samples the connection statistics until the connection closes, forwarding the
+//  events derived from them to JavaScript.
+async fn run_sampler(
+    connection: Arc<quinn::Connection>,
+    tag: ConnectionTag,
+    sampler: Arc<Sampler>,
+    callbacks: SamplerCallbacks,
+    channel: Channel,
+) {
+    let mut interval = tokio::time::interval(sampler::SAMPLE_INTERVAL);
+
+    loop {
+        tokio::select! {
+            _ = connection.closed() => break,
+            _ = interval.tick() => {},
+        }
+
+        for event in sampler.record(&connection.stats()) {
+            let (callback, kind, sample) = match event {
+                sampler::Event::Congestion(kind, sample) => {
+                    (&callbacks.on_congestion_event, Some(kind), sample)
+                }
+                sampler::Event::CwndWarning(sample) => (&callbacks.on_cwnd_warning, None, sample),
+            };
+
+            if let Some(log) = &callbacks.event_log {
+                let name = kind.as_ref().map_or("cwndWarning", |kind| kind.as_str());
+                log.record(name, None, None);
+            }
+
+            let Some(callback) = callback.clone() else {
+                continue;
+            };
+
+            let tag = tag.clone();
+
+            let sent = channel.try_send(move |mut cx| {
+                let callback = callback.to_inner(&mut cx);
+                let this = cx.undefined();
+
+                let snapshot = cx.empty_object();
+                let cwnd = cx.number(sample.cwnd as f64);
+                let rtt_ms = cx.number(sample.rtt.as_secs_f64() * 1000.0);
+                let congestion_events = cx.number(sample.congestion_events as f64);
+                // Quinn doesn't expose the bytes in flight, the field is kept for a stable shape
+                let bytes_in_flight = cx.null();
+
+                tag.apply(&mut cx, snapshot)?;
+                snapshot.set(&mut cx, "cwnd", cwnd)?;
+                snapshot.set(&mut cx, "rttMs", rtt_ms)?;
+                snapshot.set(&mut cx, "congestionEvents", congestion_events)?;
+                snapshot.set(&mut cx, "bytesInFlight", bytes_in_flight)?;
+
+                let args: Vec<Handle<JsValue>> = match kind {
+                    Some(kind) => vec![cx.string(kind.as_str()).upcast(), snapshot.upcast()],
+                    None => vec![snapshot.upcast()],
+                };
+
+                callback.call(&mut cx, this, args)?;
+
+                Ok(())
+            });
+
+            if sent.is_err() {
+                return;
+            }
+        }
+    }
+}
+
+// Replaces quinn's keep-alive, which pings regardless of whether the connection is in use. Pings
+//  every `interval` while streams are open, idle connections are left to the idle timeout. PING
+//  frames are handled by the peer's QUIC stack, its application never sees them
+async fn keep_alive_while_active(
+    connection: Arc<quinn::Connection>,
+    streams: StreamRegistry,
+    interval: Duration,
+) {
+    let mut ticker = tokio::time::interval(interval);
+    ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+    loop {
+        tokio::select! {
+            _ = connection.closed() => return,
+            _ = ticker.tick() => {},
+        }
+
+        if streams.open() > 0 {
+            connection.ping();
+        }
+    }
+}
+
+// Application error code and reason a connection is closed with by `autoCloseIdleMs`
+const IDLE_AUTO_CLOSE_CODE: u32 = 0;
+const IDLE_AUTO_CLOSE_REASON: &[u8] = b"idle";
+
+// Closes the connection once it went without streams for `idle`. Sets `idle_closed` before closing
+//  so the close task reports the `idleAutoClose` kind
+async fn auto_close_idle(
+    connection: Arc<quinn::Connection>,
+    streams: StreamRegistry,
+    closing: Arc<AtomicBool>,
+    idle_closed: Arc<AtomicBool>,
+    idle: Duration,
+) {
+    let mut interval =
+        tokio::time::interval((idle / 4).clamp(Duration::from_millis(10), Duration::from_secs(1)));
+
+    loop {
+        tokio::select! {
+            _ = connection.closed() => return,
+            _ = interval.tick() => {},
+        }
+
+        if streams.close_if_idle(idle, &closing) {
+            tracing::debug!(idle_ms = idle.as_millis() as u64, "closing idle connection");
+            idle_closed.store(true, Ordering::Release);
+            connection.close(IDLE_AUTO_CLOSE_CODE.into(), IDLE_AUTO_CLOSE_REASON);
+
+            return;
+        }
+    }
+}
+
+// Arguments `connect` and `connect_persistent` take in the same positions
+struct ConnectArguments {
+    addr: SocketAddr,
+    hostname: String,
+    alpn_protocols: Option<Vec<Vec<u8>>>,
+    certificate_authorities: Option<Vec<Vec<u8>>>,
+    client_auth: Option<(Vec<u8>, Vec<u8>)>,
+    callbacks: ConnectionCallbacks,
+    options: ConnectOptions,
+}
+
+impl ConnectArguments {
+    // `first` is the position of the port, exports taking a handle before it pass 1
+    fn from_arguments(cx: &mut FunctionContext, function: &str, first: usize) -> NeonResult<Self> {
+        let port = args::argument::<JsNumber>(cx, function, first, "options.port")?.value(cx);
+        let port = port_number(cx, function, "options.port", port)?;
+
+        let ip =
+            args::argument::<JsString>(cx, function, first + 1, "options.ipAddress")?.value(cx);
+        let addr = parse_ip(cx, function, "options.ipAddress", &ip, port)?;
+        let hostname =
+            args::argument::<JsString>(cx, function, first + 2, "options.hostname")?.value(cx);
+        let on_stream =
+            args::argument::<JsFunction>(cx, function, first + 3, "options.onStream")?.root(cx);
+        let on_close =
+            args::argument::<JsFunction>(cx, function, first + 4, "options.onClose")?.root(cx);
+        let on_error =
+            args::argument::<JsFunction>(cx, function, first + 5, "options.onError")?.root(cx);
+        let callbacks = ConnectionCallbacks::new(on_stream, on_close, on_error);
+        let alpn_protocols =
+            args::optional_argument::<JsArray>(cx, function, first + 6, "options.alpnProtocols")?;
+        let certificate_authorities = args::optional_argument::<JsArray>(
+            cx,
+            function,
+            first + 7,
+            "options.certificateAuthorities",
+        )?;
+
+        let client_auth = {
+            let args = args::optional_argument::<JsArray>(
+                cx,
+                function,
+                first + 8,
+                "options.clientAuthentication",
+            )?;
+
+            to_uint8_vec(cx, "options.clientAuthentication", args)?.and_then(|args| {
+                let mut args = args.into_iter();
+
+                match (args.next(), args.next()) {
+                    (Some(cert), Some(key)) => Some((cert, key)),
+                    _ => None,
+                }
+            })
+        };
+
+        let alpn_protocols = to_uint8_vec(cx, "options.alpnProtocols", alpn_protocols)?;
+        let certificate_authorities = to_uint8_vec(
+            cx,
+            "options.certificateAuthorities",
+            certificate_authorities,
+        )?;
+
+        let options = {
+            let options = args::optional_argument::<JsObject>(cx, function, first + 9, "options")?
+                .unwrap_or_else(|| cx.empty_object());
+
+            ConnectOptions::from_object(cx, options)?
+        };
+
+        Ok(Self {
+            addr,
+            hostname,
+            alpn_protocols,
+            certificate_authorities,
+            client_auth,
+            callbacks,
+            options,
+        })
+    }
+}
+
+// What the close task of a connection does once the close details are known
+#[derive(Clone)]
+enum OnClose {
+    // Calls `on_close`
+    Report,
+    // Leaves it to `connect_persistent`, which only calls `on_close` once it stops reconnecting.
+    //  Its keep alive takes over from the connection's, so the event loop stays open in between
+    HandOver(KeepAlive),
+}
+
+// Everything besides the client a `Connection` is made from, see `connection_handle`
+struct ConnectionSetup {
+    rt: tokio::runtime::Handle,
+    // Every event of the connection and its streams is sent over this one channel, each channel
+    //  is a libuv handle of its own
+    events: Channel,
+    tag: ConnectionTag,
+    span: tracing::Span,
+    instance: instance::InstanceHandle,
+    callbacks: ConnectionCallbacks,
+    options: ConnectOptions,
+    // Handed the connection before any of its tasks are spawned, fails if the attempt was aborted
+    controller: Option<ConnectController>,
+    on_closed: OnClose,
+}
+
+// Spawns the tasks of a connection which just finished its handshake. Has to be called on the
+//  JavaScript thread, right before the connection is handed to it
+fn connection_handle<'a, C: Context<'a>>(
+    cx: &mut C,
+    client: quic::Client,
+    setup: ConnectionSetup,
+) -> Result<Connection, NativeError> {
+    let quic::Client {
+        connection,
+        endpoint,
+        owns_endpoint,
+        timings,
+        handshake_kind,
+        fips,
+        socket,
+    } = client;
+    let ConnectionSetup {
+        rt,
+        events,
+        tag,
+        span,
+        instance,
+        callbacks,
+        options,
+        controller,
+        on_closed,
+    } = setup;
+
+    let connection = Arc::new(connection);
+    if let Some(controller) = &controller {
+        controller.connected(&connection)?;
+    }
+    let endpoint = Arc::new(endpoint);
+    track_endpoint(&endpoint);
+
+    let (closed_sender, closed) = watch::channel(None);
+    let close_watch = CloseWatch::new(tag.clone(), connection.clone(), closed);
+    let event_log = options
+        .event_log_size
+        .map(|size| Arc::new(EventLog::new(size)));
+    let streams = StreamRegistry::new(
+        events.clone(),
+        Readers::new(&rt),
+        BufferedBytes::new(options.max_buffered_bytes),
+        options.on_buffered_bytes_warning.clone(),
+        options.write_chunk_size,
+        event_log.clone(),
+    );
+    streams.record("connected", None, Some(handshake_kind.as_str().to_string()));
+    let handle_state = HandleState::default();
+    let closing = Arc::new(AtomicBool::new(false));
+    let idle_closed = Arc::new(AtomicBool::new(false));
+    let keep_alive = KeepAlive::new(cx, &events, true);
+    track_connection(TrackedConnection {
+        tag: tag.clone(),
+        instance: instance.id,
+        connection: Arc::downgrade(&connection),
+        streams: streams.clone(),
+    });
+
+    let sampler = Arc::new(Sampler::new(
+        options.throughput_window,
+        options.rtt_histogram,
+        options.cwnd_warning,
+    ));
+    let sampler_handle = rt.spawn(
+        run_sampler(
+            connection.clone(),
+            tag.clone(),
+            sampler.clone(),
+            SamplerCallbacks {
+                on_congestion_event: options.on_congestion_event.clone(),
+                on_cwnd_warning: options.on_cwnd_warning.clone(),
+                event_log: event_log.clone(),
+            },
+            events.clone(),
+        )
+        .instrument(span.clone()),
+    );
+
+    // Nothing is left to deliver the events to once the context is gone, e.g. a
+    //  terminated worker. Closing lets the other tasks of the connection end by themselves
+    rt.spawn({
+        let connection = connection.clone();
+        let endpoint = endpoint.clone();
+
+        async move {
+            tokio::select! {
+                _ = connection.closed() => {},
+                _ = instance.torn_down() => {
+                    tracing::debug!("context torn down, closing");
+                    connection.close(0u8.into(), b"");
+                    if owns_endpoint {
+                        endpoint.close(0u8.into(), b"");
+                    }
+                },
+            }
+        }
+        .instrument(span.clone())
+    });
+
+    if let Some(interval) = options.active_keep_alive {
+        rt.spawn(
+            keep_alive_while_active(connection.clone(), streams.clone(), interval)
+                .instrument(span.clone()),
+        );
+    }
+
+    if let Some(idle) = options.auto_close_idle {
+        // Ends by itself once the connection closes
+        rt.spawn(
+            auto_close_idle(
+                connection.clone(),
+                streams.clone(),
+                closing.clone(),
+                idle_closed.clone(),
+                idle,
+            )
+            .instrument(span.clone()),
+        );
+    }
+
+    let close_handle = {
+        let connection = connection.clone();
+        let endpoint = endpoint.clone();
+        let tag = tag.clone();
+        let keep_alive = keep_alive.clone();
+        let callbacks = callbacks.clone();
+        let events = events.clone();
+        let drain_timeout = options.drain_timeout;
+        let streams = streams.clone();
+        let handle_state = handle_state.clone();
+        rt.spawn(
+            async move {
+                let mut reason = CloseReason::new(tag, connection.closed().await);
+                reason.idle_auto_close = idle_closed.load(Ordering::Acquire);
+                tracing::debug!(reason = %reason.error, "closed");
+                streams.record("closed", None, Some(reason.error.to_string()));
+                // A shared endpoint only becomes idle once all of its connections are, which
+                //  would delay `on_close` by the traffic of unrelated connections
+                if owns_endpoint {
+                    reason.drained = tokio::time::timeout(drain_timeout, endpoint.wait_idle())
+                        .await
+                        .is_ok();
+                }
+
+                closed_sender.send_replace(Some(reason.clone()));
+                handle_state.advance(handle_state::State::Closed);
+
+                let sent = events.try_send({
+                    let keep_alive = keep_alive.clone();
+
+                    move |mut cx| {
+                        // The persistent connection's keep alive is referenced before this one is
+                        //  released, so the event loop can't exit in between
+                        if let OnClose::HandOver(persistent) = &on_closed {
+                            persistent.set_referenced(&mut cx, true);
+                            keep_alive.release();
+                            return Ok(());
+                        }
+
+                        keep_alive.release();
+
+                        let callback = callbacks.get(&mut cx, |roots| &roots.on_close);
+                        let this = cx.undefined();
+
+                        let details = reason.to_object(&mut cx)?;
+
+                        let args = vec![
+                            cx.string(reason.error.to_string()).upcast(),
+                            details.upcast(),
+                        ];
+
+                        callback.call(&mut cx, this, args)?;
+
+                        Ok(())
+                    }
+                });
+
+                if sent.is_err() {
+                    keep_alive.release();
+                }
+            }
+            .instrument(span.clone()),
+        )
+    };
+
+    let stream_handle = {
+        let connection = connection.clone();
+        let close_watch = close_watch.clone();
+        let streams = streams.clone();
+        let tag = tag.clone();
+        let callbacks = callbacks.clone();
+        let events = events.clone();
+        let stream_batch = options.stream_batch;
+
+        rt.spawn(
+            async move {
+                fn handle_bidi<E, S>(
+                    result: Result<(SendStream, RecvStream), ConnectionError>,
+                    close_watch: CloseWatch,
+                    streams: StreamRegistry,
+                    error_handler: E,
+                    stream_handler: S,
+                ) -> bool
+                where
+                    E: FnOnce(ConnectionError) -> bool,
+                    S: FnOnce(PartialStream) -> bool,
+                {
+                    let (send, recv) = match result {
+                        Err(err) => return error_handler(err),
+                        Ok(v) => v,
+                    };
+
+                    let stream = PartialStream {
+                        send: Arc::new(TakeOnce::new(Some(send))),
+                        details: StreamDetails::new(&recv),
+                        recv: Arc::new(TakeOnce::new(PendingRecv::accepted(recv))),
+                        close_watch,
+                        pending: Arc::new(TakeOnce::new(streams.accepted())),
+                        streams,
+                        handle_state: HandleState::default(),
+                    };
+
+                    stream_handler(stream)
+                }
+
+                fn handle_uni<E, S>(
+                    result: Result<RecvStream, ConnectionError>,
+                    close_watch: CloseWatch,
+                    streams: StreamRegistry,
+                    error_handler: E,
+                    stream_handler: S,
+                ) -> bool
+                where
+                    E: FnOnce(ConnectionError) -> bool,
+                    S: FnOnce(PartialStream) -> bool,
+                {
+                    let recv = match result {
+                        Err(err) => return error_handler(err),
+                        Ok(v) => v,
+                    };
+
+                    let stream = PartialStream {
+                        send: Arc::new(TakeOnce::new(None)),
+                        details: StreamDetails::new(&recv),
+                        recv: Arc::new(TakeOnce::new(PendingRecv::accepted(recv))),
+                        close_watch,
+                        pending: Arc::new(TakeOnce::new(streams.accepted())),
+                        streams,
+                        handle_state: HandleState::default(),
+                    };
+
+                    stream_handler(stream)
+                }
+
+                enum Accepted {
+                    Bi(Result<(SendStream, RecvStream), ConnectionError>),
+                    Uni(Result<RecvStream, ConnectionError>),
+                }
+
+                let accept = || async {
+                    tokio::select! {
+                        stream = connection.accept_bi() => Accepted::Bi(stream),
+                        stream = connection.accept_uni() => Accepted::Uni(stream),
+                    }
+                };
+
+                // Stops accepting once nobody is left to hand the streams to, dropping the
+                //  stream stops it
+                let deliver_stream = |stream: PartialStream| {
+                    let on_stream = callbacks.clone();
+                    events
+                        .try_send(move |mut cx| {
+                            let callback = on_stream.get(&mut cx, |roots| &roots.on_stream);
+                            let this = cx.undefined();
+
+                            let is_uni = stream.send.peek(|v| v.is_none()).unwrap_or(true);
+
+                            let args: &[Handle<JsValue>] =
+                                &[cx.boxed(stream).upcast(), cx.boolean(is_uni).upcast()];
+
+                            callback.call(&mut cx, this, args)?;
+
+                            Ok(())
+                        })
+                        .is_err()
+                };
+
+                // Passes `[{ stream, details }]` in the order the streams were accepted in
+                let deliver_batch = |batch: Vec<PartialStream>| {
+                    let on_stream = callbacks.clone();
+                    events
+                        .try_send(move |mut cx| {
+                            let callback = on_stream.get(&mut cx, |roots| &roots.on_stream);
+                            let this = cx.undefined();
+
+                            let array = cx.empty_array();
+                            for (i, stream) in batch.into_iter().enumerate() {
+                                let entry = cx.empty_object();
+                                let details = stream.details.to_object(&mut cx)?;
+                                let is_uni =
+                                    cx.boolean(stream.send.peek(|v| v.is_none()).unwrap_or(true));
+                                details.set(&mut cx, "isUnidirectional", is_uni)?;
+
+                                let stream = cx.boxed(stream);
+                                entry.set(&mut cx, "stream", stream)?;
+                                entry.set(&mut cx, "details", details)?;
+                                array.set(&mut cx, i as u32, entry)?;
+                            }
+
+                            let args: &[Handle<JsValue>] = &[array.upcast()];
+
+                            callback.call(&mut cx, this, args)?;
+
+                            Ok(())
+                        })
+                        .is_err()
+                };
+
+                let mut batch = Vec::new();
+                let mut batch_started = Instant::now();
+
+                loop {
+                    let accepted = if batch.is_empty() {
+                        accept().await
+                    } else {
+                        // Give the connection a chance to process packets which already arrived,
+                        //  only streams which are waiting by then are added to the batch
+                        tokio::task::yield_now().await;
+
+                        match tokio::time::timeout(Duration::ZERO, accept()).await {
+                            Ok(accepted) => accepted,
+                            Err(_) => {
+                                if deliver_batch(std::mem::take(&mut batch)) {
+                                    break;
+                                }
+                                continue;
+                            }
+                        }
+                    };
+
+                    let on_error = callbacks.clone();
+                    let tag = tag.clone();
+                    // Closes initiated by either side are only reported through `on_close`. Everything
+                    //  else (timeouts, local transport errors, version mismatches, exhausted CIDs) is
+                    //  also passed to `on_error`, with the same `kind` as the close details
+                    let handle_error = |error: ConnectionError| {
+                        match error {
+                            ConnectionError::ConnectionClosed(_)
+                            | ConnectionError::ApplicationClosed(_)
+                            | ConnectionError::Reset
+                            | ConnectionError::LocallyClosed => {}
+                            _ => {
+                                let _ = events.try_send(move |mut cx| {
+                                    let callback = on_error.get(&mut cx, |roots| &roots.on_error);
+                                    let this = cx.undefined();
+
+                                    let args = &[NativeError::from(&error)
+                                        .context(
+                                            &ErrorContext::new("accept_stream").connection(&tag),
+                                        )
+                                        .to_callback_value(&mut cx)];
+
+                                    callback.call(&mut cx, this, args)?;
+
+                                    Ok(())
+                                });
+                            }
+                        }
+
+                        true
+                    };
+
+                    let handle_stream = |stream: PartialStream| match stream_batch {
+                        None => deliver_stream(stream),
+                        Some(_) => {
+                            if batch.is_empty() {
+                                batch_started = Instant::now();
+                            }
+                            batch.push(stream);
+
+                            false
+                        }
+                    };
+
+                    let stop = match accepted {
+                        Accepted::Bi(stream) => handle_bidi(
+                            stream,
+                            close_watch.clone(),
+                            streams.clone(),
+                            handle_error,
+                            handle_stream,
+                        ),
+                        Accepted::Uni(stream) => handle_uni(
+                            stream,
+                            close_watch.clone(),
+                            streams.clone(),
+                            handle_error,
+                            handle_stream,
+                        ),
+                    };
+                    if stop {
+                        break;
+                    }
+
+                    if let Some(stream_batch) = stream_batch {
+                        let full = batch.len() >= stream_batch.max_streams
+                            || batch_started.elapsed() >= stream_batch.max_delay;
+                        if full && deliver_batch(std::mem::take(&mut batch)) {
+                            break;
+                        }
+                    }
+                }
+
+                // Streams accepted before the connection closed are handed over all the same
+                if !batch.is_empty() {
+                    deliver_batch(batch);
+                }
+            }
+            .instrument(span),
+        )
+    };
+
+    Ok(Connection {
+        tag,
+        version: quic::QUIC_VERSION,
+        timings,
+        handshake_kind,
+        fips,
+        connection,
+        endpoint,
+        owns_endpoint,
+        proxied: matches!(options.endpoint, quic::ClientEndpoint::Proxy(_)),
+        congestion_controller: options.transport.congestion_controller,
+        socket_options: match &options.endpoint {
+            quic::ClientEndpoint::Bind(socket_options) => Some(socket_options.clone()),
+            _ => None,
+        },
+        socket,
+        close_watch,
+        handle_state,
+        closing,
+        streams,
+        keep_alive,
+        callbacks,
+        // Quinn's default unless `receiveWindowBytes` was given
+        receive_window: Arc::new(AtomicU64::new(
+            options
+                .transport
+                .receive_window
+                .unwrap_or(VarInt::MAX)
+                .into_inner(),
+        )),
+        sampler,
+        close_handle: Arc::new(close_handle),
+        stream_handle: Arc::new(stream_handle),
+        sampler_handle: Arc::new(sampler_handle),
+    })
+}
+
+fn connect(mut cx: FunctionContext) -> JsResult<JsPromise> {
+    let ConnectArguments {
+        addr,
+        hostname,
+        alpn_protocols,
+        certificate_authorities,
+        client_auth,
+        callbacks,
+        options,
+    } = ConnectArguments::from_arguments(&mut cx, "connect", 0)?;
+
+    let rt = runtime(&mut cx)?;
+    let instance = instance::current(&mut cx);
+
+    let events = unreferenced_channel(&mut cx);
+
+    let tag = ConnectionTag {
+        id: next_connection_id(),
+        label: options.label.clone(),
+    };
+    // Quinn instruments the tasks it spawns with the current span, so creating the connection
+    //  inside of it also tags quinn's own events
+    let span = tag.span();
+    let connect_span = span.clone();
+
+    let channel = cx.channel();
+    let (deferred, promise) = cx.promise();
+
+    // The handle is moved into the task as well, the connection's own tasks are spawned on it
+    rt.clone().spawn(
+        async move {
+            let result = ConnectController::run(
+                options.controller.clone(),
+                CancelToken::run(
+                    options.cancel_token.clone(),
+                    quic::get_client(
+                        addr,
+                        &hostname,
+                        alpn_protocols,
+                        certificate_authorities,
+                        client_auth,
+                        options.endpoint.clone(),
+                        &options.transport,
+                        options.connect_timeout,
+                    ),
+                ),
+            )
+            .await
+            .and_then(|result| result)
+            .and_then(|result| result.map_err(|err| NativeError::from(&err)));
+
+            match &result {
+                Ok(_) => tracing::debug!("connected"),
+                Err(err) => tracing::debug!(error = %err.message, "unable to connect"),
+            }
+
+            let _ = deferred.try_settle_with(&channel, move |mut cx| {
+                let context = ErrorContext::new("connect").connection(&tag);
+                let client = result.or_else(|err| err.context(&context).throw(&mut cx))?;
+
+                let setup = ConnectionSetup {
+                    rt,
+                    events,
+                    tag,
+                    span,
+                    instance,
+                    callbacks,
+                    controller: options.controller.clone(),
+                    options,
+                    on_closed: OnClose::Report,
+                };
+
+                match connection_handle(&mut cx, client, setup) {
+                    Ok(connection) => Ok(cx.boxed(connection)),
+                    Err(err) => err.context(&context).throw(&mut cx),
+                }
+            });
+        }
+        .instrument(connect_span),
+    );
+
+    Ok(promise)
+}
+
+struct PartialStream {
+    send: Arc<TakeOnce<Option<SendStream>>>,
+    recv: Arc<TakeOnce<PendingRecv>>,
+    details: StreamDetails,
+    close_watch: CloseWatch,
+    streams: StreamRegistry,
+    // Counts the stream as open for `autoCloseIdleMs` until it is initialized
+    pending: Arc<TakeOnce<PendingOpen>>,
+    // Consumed by `initialize_stream` and `discard_stream`
+    handle_state: HandleState,
+}
+
+impl PartialStream {
+    // Checked at the top of every export taking a partial stream
+    fn check(&self, operation: &'static str) -> Result<(), NativeError> {
+        let context = ErrorContext::new(operation)
+            .connection(self.close_watch.tag())
+            .stream(self.details.id.index());
+
+        self.handle_state
+            .check("partial stream", "initialized or discarded", &context)
+    }
+}
+
+impl args::Expected for JsBox<PartialStream> {
+    const DESCRIPTION: &'static str = "a partial stream handle";
+}
+
+impl Finalize for PartialStream {
+    // Do nothing since `initialize_stream` must be called immediately after
+    fn finalize<'a, C: Context<'a>>(self, _: &mut C) {}
+}
+
+#[derive(Clone, Debug)]
+struct StreamDetails {
+    id: StreamId,
+    is_0rtt: bool,
+}
+
+impl StreamDetails {
+    fn new(recv: &RecvStream) -> Self {
+        Self {
+            id: recv.id(),
+            is_0rtt: recv.is_0rtt(),
+        }
+    }
+
+    fn to_object<'a, C: Context<'a>>(&self, cx: &mut C) -> JsResult<'a, JsObject> {
+        let result = cx.empty_object();
+        let id = JsBigInt::from_u64(cx, self.id.index());
+        let is_0rtt = cx.boolean(self.is_0rtt);
+
+        result.set(cx, "id", id)?;
+        result.set(cx, "is0rtt", is_0rtt)?;
+
+        Ok(result)
+    }
+}
+
+#[derive(Clone)]
+struct Stream {
+    send: Option<Writer>,
+    handle: Arc<ReadHandle>,
+    details: StreamDetails,
+    close_requested: CancelWithValue<StopRequest>,
+    close_watch: CloseWatch,
+    keep_alive: KeepAlive,
+    // Set as soon as `close_write`, `close_stream` or `reset_stream` is called, so later writes can
+    //  be told apart from the peer stopping the stream
+    finished: Arc<AtomicBool>,
+    streams: StreamRegistry,
+    // Consumed by `destroy_stream`, every later call on the handle fails
+    handle_state: HandleState,
+    pipelined: Arc<PipelinedWrites>,
+}
+
+impl Stream {
+    fn error_context(&self, operation: &'static str) -> ErrorContext {
+        ErrorContext::new(operation)
+            .connection(self.close_watch.tag())
+            .stream(self.details.id.index())
+    }
+
+    // Checked at the top of every export taking a stream. With `open` the stream's connection must
+    //  not be closed either
+    fn check(&self, operation: &'static str, open: bool) -> Result<(), NativeError> {
+        let context = self.error_context(operation);
+
+        self.handle_state.check("stream", "destroyed", &context)?;
+        if open {
+            self.close_watch
+                .ensure_open()
+                .map_err(|err| err.context(&context))?;
+        }
+
+        Ok(())
+    }
+}
+
+impl args::Expected for JsBox<Stream> {
+    const DESCRIPTION: &'static str = "a stream handle";
+}
+
+// The stream passed as the first argument of `function`, after checking its handle
+fn stream_argument(
+    cx: &mut FunctionContext,
+    function: &'static str,
+    open: bool,
+) -> NeonResult<Stream> {
+    let stream = (**args::argument::<JsBox<Stream>>(cx, function, 0, "stream")?).clone();
+    stream.check(function, open).or_else(|err| err.throw(cx))?;
+
+    Ok(stream)
+}
+
+impl Finalize for Stream {
+    fn finalize<'a, C: Context<'a>>(self, _: &mut C) {
+        self.handle_state.advance(handle_state::State::Finalized);
+        self.handle.abort();
+        self.keep_alive.release();
+
+        // Queued behind any write which is still in progress
+        if let Some(send) = self.send.as_ref() {
+            send.finish_later();
+        }
+    }
+}
+
+// Bytes queued on a stream after which `write_stream_nowait` returns `false`
+const WRITE_HIGH_WATER_MARK: usize = 64 * 1024;
+
+// Callbacks of the writes made with `write_stream_nowait`, which don't have a promise to settle
+struct PipelinedWrites {
+    on_error: Root<JsFunction>,
+    on_drain: Option<Root<JsFunction>>,
+    // Set when a write returned `false`, cleared when `on_drain` is called
+    draining: AtomicBool,
+    // Only the first failed write is reported, the ones queued after it fail as well
+    failed: AtomicBool,
+}
+
+// Chunks taken from a stream per read, each is delivered to `on_data` separately
+const READ_CHUNKS: usize = 16;
+
+// At most this much is read from an accepted stream before it is initialized, afterwards flow
+//  control holds the peer back
+const PRE_READ_LIMIT: usize = 64 * 1024;
+
+// What was read from a stream before `initialize_stream`, delivered before anything else
+struct PreRead {
+    recv: RecvStream,
+    chunks: Vec<Bytes>,
+    // Set if the stream already ended, cleanly or not
+    end: Option<Result<(), quinn::ReadError>>,
+}
+
+// Receive side of a partial stream
+enum PendingRecv {
+    Ready(RecvStream),
+    // Streams opened by the peer are read until they are initialized, so data, a FIN or a reset
+    //  which arrive while the `on_stream` callback is queued are delivered in order afterwards
+    PreReading {
+        initialized: oneshot::Sender<()>,
+        task: JoinHandle<PreRead>,
+    },
+}
+
+impl PendingRecv {
+    // Has to be called from within the runtime
+    fn accepted(recv: RecvStream) -> Self {
+        let (initialized, wait) = oneshot::channel();
+
+        Self::PreReading {
+            initialized,
+            task: tokio::spawn(pre_read(recv, wait)),
+        }
+    }
+
+    // `None` if the pre-read task was aborted, i.e. the runtime is shutting down
+    async fn into_pre_read(self) -> Option<PreRead> {
+        match self {
+            PendingRecv::Ready(recv) => Some(PreRead {
+                recv,
+                chunks: Vec::new(),
+                end: None,
+            }),
+            PendingRecv::PreReading { initialized, task } => {
+                let _ = initialized.send(());
+                task.await.ok()
+            }
+        }
+    }
+}
+
+// Reads until the stream is initialized, ends, or `PRE_READ_LIMIT` is buffered. Also stops when
+//  the partial stream is dropped without being initialized
+async fn pre_read(mut recv: RecvStream, mut initialized: oneshot::Receiver<()>) -> PreRead {
+    let mut chunks = Vec::new();
+    let mut buffered = 0;
+
+    while buffered < PRE_READ_LIMIT {
+        let result = tokio::select! {
+            biased;
+            _ = &mut initialized => break,
+            result = recv.read_chunk(PRE_READ_LIMIT - buffered, true) => result,
+        };
+
+        let end = match result {
+            Ok(Some(chunk)) => {
+                buffered += chunk.bytes.len();
+                chunks.push(chunk.bytes);
+                continue;
+            }
+            Ok(None) => Ok(()),
+            Err(e) => Err(e),
+        };
+
+        return PreRead {
+            recv,
+            chunks,
+            end: Some(end),
+        };
+    }
+
+    PreRead {
+        recv,
+        chunks,
+        end: None,
+    }
+}
+
+// Why the read loop of a stream is asked to stop. `error_code` is sent to the peer
+#[derive(Clone, Copy, Default)]
+struct StopRequest {
+    error_code: VarInt,
+    // Set by `destroy_stream`, the close callback gets "destroyed" as the reason
+    destroyed: bool,
+}
+
+impl From<VarInt> for StopRequest {
+    fn from(error_code: VarInt) -> Self {
+        Self {
+            error_code,
+            destroyed: false,
+        }
+    }
+}
+
+// The callbacks passed to `initialize_stream`
+struct StreamCallbacks {
+    on_data: Root<JsFunction>,
+    on_close: Root<JsFunction>,
+    on_error: Root<JsFunction>,
+}
+
+// Events of a stream. They are all sent over the connection's channel, so they also arrive in the
+//  order they happened in, and are dispatched to the matching callback on the JavaScript thread
+enum StreamEvent {
+    Data(Vec<u8>),
+    // Data was written to the ring buffer JavaScript is waiting on, `on_data` is called without
+    //  arguments
+    Wake,
+    // The error is passed as the second argument, it is `undefined` when the stream finished
+    //  cleanly
+    Close(String, Option<NativeError>),
+    Error(NativeError),
+}
+
+impl StreamEvent {
+    fn deliver<'a, C: Context<'a>>(
+        self,
+        cx: &mut C,
+        callbacks: &StreamCallbacks,
+    ) -> NeonResult<()> {
+        let (callback, args): (_, Vec<Handle<JsValue>>) = match self {
+            StreamEvent::Data(packet) => {
+                let array = {
+                    let a = JsUint8Array::new(cx, packet.len())?;
+                    for (i, n) in packet.iter().enumerate() {
+                        let v = cx.number(*n);
+                        a.set(cx, i as u32, v)?;
+                    }
+                    a
+                };
+
+                (&callbacks.on_data, vec![array.upcast()])
+            }
+            StreamEvent::Wake => (&callbacks.on_data, Vec::new()),
+            StreamEvent::Close(reason, error) => {
+                let error = match error {
+                    None => cx.undefined().upcast(),
+                    Some(error) => error.to_callback_value(cx),
+                };
+
+                (&callbacks.on_close, vec![cx.string(reason).upcast(), error])
+            }
+            StreamEvent::Error(details) => {
+                (&callbacks.on_error, vec![details.to_callback_value(cx)])
+            }
+        };
+
+        let callback = callback.to_inner(cx);
+        let this = cx.undefined();
+
+        callback.call(cx, this, args)?;
+
+        Ok(())
+    }
+}
+
+// Tells JavaScript that `maxBufferedBytes` was exceeded, only the first time it happens
+fn warn_buffered_bytes(streams: &StreamRegistry, tag: &ConnectionTag) {
+    let Some(callback) = streams.on_buffered_bytes_warning.clone() else {
+        return;
+    };
+
+    let tag = tag.clone();
+    let buffered = streams.buffered.clone();
+
+    let _ = streams.channel.try_send(move |mut cx| {
+        let callback = callback.to_inner(&mut cx);
+        let this = cx.undefined();
+
+        let details = cx.empty_object();
+        let buffered_bytes = cx.number(buffered.level() as f64);
+        let max_buffered_bytes = cx.number(buffered.limit().unwrap_or_default() as f64);
+
+        tag.apply(&mut cx, details)?;
+        details.set(&mut cx, "bufferedBytes", buffered_bytes)?;
+        details.set(&mut cx, "maxBufferedBytes", max_buffered_bytes)?;
+
+        callback.call(&mut cx, this, [details.upcast()])?;
+
+        Ok(())
+    });
+}
+
+async fn handle_read(
+    recv: PendingRecv,
+    close_requested: CancelWithValue<StopRequest>,
+    close_watch: CloseWatch,
+    keep_alive: KeepAlive,
+    callbacks: StreamCallbacks,
+    streams: StreamRegistry,
+    ring: Option<RingBuffer>,
+) {
+    let Some(PreRead {
+        mut recv,
+        chunks,
+        end,
+    }) = recv.into_pre_read().await
+    else {
+        keep_alive.release();
+        return;
+    };
+
+    let context = ErrorContext::new("read_stream")
+        .connection(close_watch.tag())
+        .stream(recv.id().index());
+
+    let channel = &streams.channel;
+    let buffered = &streams.buffered;
+
+    let callbacks = Arc::new(callbacks);
+    let send_event = |event: StreamEvent| {
+        let callbacks = callbacks.clone();
+        // Data counts towards `maxBufferedBytes` until the closure ran
+        let bytes = match &event {
+            StreamEvent::Data(packet) => packet.len(),
+            _ => 0,
+        };
+        let released = buffered.clone();
+
+        if buffered.add(bytes) {
+            warn_buffered_bytes(&streams, close_watch.tag());
+        }
+
+        let sent = channel.try_send(move |mut cx| {
+            released.remove(bytes);
+
+            event.deliver(&mut cx, &callbacks)
+        });
+
+        if sent.is_err() {
+            buffered.remove(bytes);
+        }
+
+        sent
+    };
+
+    let handle_close = |reason: String, error: Option<NativeError>| {
+        // What's left in the ring buffer can still be read, the state tells JavaScript not to wait
+        //  for more
+        if let Some(ring) = &ring {
+            ring.close(match error {
+                None => ring::STATE_ENDED,
+                Some(_) => ring::STATE_FAILED,
+            });
+        }
+
+        let callbacks = callbacks.clone();
+        let released = keep_alive.clone();
+        let sent = channel.try_send(move |mut cx| {
+            released.release();
+
+            StreamEvent::Close(reason, error).deliver(&mut cx, &callbacks)
+        });
+
+        if sent.is_err() {
+            keep_alive.release();
+        }
+    };
+
+    // Errors which are the expected end of a stream (reset by the peer, the connection going
+    //  away, the stream being closed locally) only close the stream. Errors which indicate
+    //  misuse or rejected data are passed to the error callback first, reading again would
+    //  fail in the same way, so the stream is closed afterwards as well
+    let handle_error = |e: quinn::ReadError| {
+        let details = match &e {
+            quinn::ReadError::ConnectionLost(error) => close_watch.error(error),
+            e => NativeError::from(e),
+        }
+        .context(&context);
+
+        match e {
+            quinn::ReadError::ConnectionLost(_)
+            | quinn::ReadError::ClosedStream
+            | quinn::ReadError::Reset(_) => {}
+            quinn::ReadError::IllegalOrderedRead | quinn::ReadError::ZeroRttRejected => {
+                let _ = send_event(StreamEvent::Error(details.clone()));
+            }
+        }
+
+        handle_close(e.to_string(), Some(details));
+    };
+
+    // Returns `false` when nobody is left to read the data, or to be told about the close
+    let handle_data = |packet: Vec<u8>| send_event(StreamEvent::Data(packet)).is_ok();
+
+    // Like `handle_data`, or writes to the ring buffer. Writing waits while the ring buffer is
+    //  full, a stop request is returned if the stream is asked to stop meanwhile
+    let deliver = |chunk: Bytes| {
+        let ring = ring.as_ref();
+        let handle_data = &handle_data;
+        let send_event = &send_event;
+        let close_requested = &close_requested;
+
+        async move {
+            let Some(ring) = ring else {
+                return Ok(handle_data(chunk.to_vec()));
+            };
+
+            tokio::select! {
+                delivered = ring.write(&chunk, || send_event(StreamEvent::Wake).is_ok()) => Ok(delivered),
+                request = close_requested.cancelled() => Err(request),
+            }
+        }
+    };
+
+    for chunk in chunks {
+        match deliver(chunk).await {
+            Ok(true) => {}
+            Ok(false) => {
+                let _ = recv.stop(0u8.into());
+                keep_alive.release();
+
+                return;
+            }
+            Err(request) => {
+                let _ = recv.stop(request.error_code);
+                let reason = if request.destroyed {
+                    "destroyed"
+                } else {
+                    "closed"
+                };
+                handle_close(String::from(reason), None);
+
+                return;
+            }
+        }
+    }
+
+    match end {
+        Some(Ok(())) => {
+            handle_close(String::from("closed"), None);
+            return;
+        }
+        Some(Err(e)) => {
+            handle_error(e);
+            return;
+        }
+        None => {}
+    }
+
+    // Everything quinn has buffered is taken at once, instead of waking up for every chunk
+    let mut chunks: [Bytes; READ_CHUNKS] = Default::default();
+
+    let mut destroyed = false;
+    'read: loop {
+        let read_result = tokio::select! {
+            // Nothing is read while too much data of the connection is waiting for JavaScript, so
+            //  flow control holds the peer back
+            result = async {
+                buffered.wait().await;
+                recv.read_chunks(&mut chunks).await
+            } => result,
+            request = close_requested.cancelled() => {
+                let _ = recv.stop(request.error_code);
+                destroyed = request.destroyed;
+
+                break;
+            },
+        };
+
+        match read_result {
+            Err(e) => {
+                handle_error(e);
+                return;
+            }
+            Ok(None) => break,
+            Ok(Some(n)) => {
+                for chunk in &mut chunks[..n] {
+                    match deliver(std::mem::take(chunk)).await {
+                        Ok(true) => {}
+                        Ok(false) => {
+                            let _ = recv.stop(0u8.into());
+                            keep_alive.release();
+
+                            return;
+                        }
+                        Err(request) => {
+                            let _ = recv.stop(request.error_code);
+                            destroyed = request.destroyed;
+
+                            break 'read;
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    let reason = if destroyed { "destroyed" } else { "closed" };
+    handle_close(String::from(reason), None);
+}
+
+fn create_stream(mut cx: FunctionContext) -> JsResult<JsPromise> {
+    let connection = connection_argument(&mut cx, "create_stream", true)?;
+
+    let cancel_token = cancel_token_argument(&mut cx, "create_stream", 1)?;
+
+    let context = connection.error_context("create_stream");
+
+    // Also covers a graceful close waiting for the existing streams and an idle connection being
+    //  closed by `autoCloseIdleMs`
+    let Some(pending) = connection.streams.begin_open(&connection.closing) else {
+        return NativeError::new(ErrorCode::ConnectionClosed, "The connection is closing")
+            .context(&context)
+            .throw(&mut cx);
+    };
+
+    let rt = runtime(&mut cx)?;
+
+    let channel = cx.channel();
+    let (deferred, promise) = cx.promise();
+
+    rt.spawn(async move {
+        let result = CancelToken::run(cancel_token, connection.connection.open_bi())
+            .await
+            .and_then(|result| result.map_err(|err| connection.close_watch.error(&err)));
+
+        let _ = deferred.try_settle_with(&channel, move |mut cx| {
+            let (send, recv) = result.or_else(|err| err.context(&context).throw(&mut cx))?;
+
+            let partial_stream = PartialStream {
+                send: Arc::new(TakeOnce::new(Some(send))),
+                details: StreamDetails::new(&recv),
+                recv: Arc::new(TakeOnce::new(PendingRecv::Ready(recv))),
+                close_watch: connection.close_watch.clone(),
+                streams: connection.streams.clone(),
+                pending: Arc::new(TakeOnce::new(pending)),
+                handle_state: HandleState::default(),
+            };
+
+            Ok(cx.boxed(partial_stream))
+        });
+    });
+
+    Ok(promise)
+}
+
+fn initialize_stream(mut cx: FunctionContext) -> JsResult<JsBox<Stream>> {
+    let partial_stream =
+        args::argument::<JsBox<PartialStream>>(&mut cx, "initialize_stream", 0, "partialStream")?;
+    partial_stream
+        .check("initialize_stream")
+        .or_else(|err| err.throw(&mut cx))?;
+    let on_data = args::argument::<JsFunction>(&mut cx, "initialize_stream", 1, "options.onData")?
+        .root(&mut cx);
+    let on_close =
+        args::argument::<JsFunction>(&mut cx, "initialize_stream", 2, "options.onClose")?
+            .root(&mut cx);
+    let on_error =
+        args::argument::<JsFunction>(&mut cx, "initialize_stream", 3, "options.onError")?
+            .root(&mut cx);
+    let ring = match args::optional_argument::<JsUint8Array>(
+        &mut cx,
+        "initialize_stream",
+        4,
+        "options.ringBuffer",
+    )? {
+        Some(view) => Some(RingBuffer::new(&mut cx, view)?),
+        None => None,
+    };
+    let on_drain =
+        args::optional_argument::<JsFunction>(&mut cx, "initialize_stream", 5, "options.onDrain")?
+            .map(|on_drain| on_drain.root(&mut cx));
+    // `write_stream_nowait` reports its errors to `on_error` as well
+    let pipelined = Arc::new(PipelinedWrites {
+        on_error: on_error.clone(&mut cx),
+        on_drain,
+        draining: AtomicBool::new(false),
+        failed: AtomicBool::new(false),
+    });
+
+    let rt = runtime(&mut cx)?;
+
+    let (Some(send), Some(recv)) = (
+        partial_stream.send.clone().take(),
+        partial_stream.recv.clone().take(),
+    ) else {
+        return NativeError::new(
+            ErrorCode::HandleConsumed,
+            "The partial stream has already been initialized",
+        )
+        .throw(&mut cx);
+    };
+    partial_stream
+        .handle_state
+        .advance(handle_state::State::Consumed);
+
+    let details = partial_stream.details.clone();
+    let close_requested = CancelWithValue::new();
+
+    let close_watch = partial_stream.close_watch.clone();
+
+    let keep_alive = partial_stream.streams.keep_alive(&mut cx);
+    let streams = partial_stream.streams.clone();
+
+    let handle = partial_stream.streams.readers.spawn({
+        let close_requested = close_requested.clone();
+        let close_watch = close_watch.clone();
+        let keep_alive = keep_alive.clone();
+
+        async move {
+            handle_read(
+                recv,
+                close_requested,
+                close_watch,
+                keep_alive,
+                StreamCallbacks {
+                    on_data,
+                    on_close,
+                    on_error,
+                },
+                streams,
+                ring,
+            )
+            .await
+        }
+    });
+
+    let stream = Stream {
+        send: send.map(|send| Writer::spawn(&rt, send, partial_stream.streams.write_chunk_size)),
+        handle,
+        close_requested,
+        details,
+        close_watch,
+        keep_alive,
+        finished: Arc::new(AtomicBool::new(false)),
+        streams: partial_stream.streams.clone(),
+        handle_state: HandleState::default(),
+        pipelined,
+    };
+
+    partial_stream.streams.register(RegisteredStream {
+        close_requested: stream.close_requested.clone(),
+        handle: stream.handle.clone(),
+        details: stream.details.clone(),
+        keep_alive: stream.keep_alive.clone(),
+        send: stream.send.clone(),
+        finished: stream.finished.clone(),
+    });
+    // Registered now, so it no longer has to be counted separately
+    drop(partial_stream.pending.take());
+
+    Ok(cx.boxed(stream))
+}
+
+// Rejects a stream opened by the peer without initializing it: the receive side is stopped and the
+//  send side reset with the given code. Nothing is rooted or spawned for JavaScript, afterwards the
+//  partial stream is consumed like after `initialize_stream`
+fn discard_stream(mut cx: FunctionContext) -> JsResult<JsUndefined> {
+    let partial_stream =
+        args::argument::<JsBox<PartialStream>>(&mut cx, "discard_stream", 0, "partialStream")?;
+    partial_stream
+        .check("discard_stream")
+        .or_else(|err| err.throw(&mut cx))?;
+    let error_code = {
+        let arg = cx
+            .argument_opt(1)
+            .unwrap_or_else(|| cx.undefined().upcast());
+
+        var_int(&mut cx, "discard_stream", "errorCode", arg)?
+    };
+
+    let rt = runtime(&mut cx)?;
+
+    let (Some(send), Some(recv)) = (
+        partial_stream.send.clone().take(),
+        partial_stream.recv.clone().take(),
+    ) else {
+        return NativeError::new(
+            ErrorCode::HandleConsumed,
+            "The partial stream has already been initialized",
+        )
+        .throw(&mut cx);
+    };
+    partial_stream
+        .handle_state
+        .advance(handle_state::State::Consumed);
+
+    if let Some(mut send) = send {
+        let _ = send.reset(error_code);
+    }
+
+    match recv {
+        PendingRecv::Ready(mut recv) => {
+            let _ = recv.stop(error_code);
+        }
+        // Whatever was read already is dropped along with the stream
+        recv @ PendingRecv::PreReading { .. } => {
+            rt.spawn(async move {
+                if let Some(PreRead { mut recv, .. }) = recv.into_pre_read().await {
+                    let _ = recv.stop(error_code);
+                }
+            });
+        }
+    }
+
+    drop(partial_stream.pending.take());
+
+    Ok(cx.undefined())
+}
+
+// Attaches the close details when the write failed because the connection closed
+fn write_error(close_watch: &CloseWatch, error: &WriteError) -> NativeError {
+    match error {
+        WriteError::ConnectionLost(error) => close_watch.error(error),
+        error => NativeError::from(error),
+    }
+}
+
+enum WriteData<'a> {
+    View(Handle<'a, JsUint8Array>),
+    Buffer(Handle<'a, JsArrayBuffer>),
+}
+
+impl<'a> WriteData<'a> {
+    fn as_slice<'b>(&self, cx: &'b FunctionContext<'a>) -> &'b [u8] {
+        use neon::types::buffer::TypedArray;
+
+        match self {
+            WriteData::View(view) => view.as_slice(cx),
+            WriteData::Buffer(buffer) => buffer.as_slice(cx),
+        }
+    }
+}
+
+// The data of a write, a `Uint8Array` or an `ArrayBuffer` at `index`. The optional `byteOffset` and
+//  `byteLength` arguments at `range_index` select part of it, only that part is copied
+fn write_data(
+    cx: &mut FunctionContext,
+    function: &str,
+    index: usize,
+    range_index: usize,
+) -> NeonResult<Vec<u8>> {
+    let offset = args::optional_argument::<JsNumber>(cx, function, range_index, "byteOffset")?
+        .map(|offset| offset.value(cx));
+    let length = args::optional_argument::<JsNumber>(cx, function, range_index + 1, "byteLength")?
+        .map(|length| length.value(cx));
+
+    let value = cx
+        .argument_opt(index)
+        .unwrap_or_else(|| cx.undefined().upcast());
+
+    let data = if let Ok(view) = value.downcast::<JsUint8Array, _>(cx) {
+        WriteData::View(view)
+    } else if let Ok(buffer) = value.downcast::<JsArrayBuffer, _>(cx) {
+        WriteData::Buffer(buffer)
+    } else {
+        let actual = args::type_of(cx, value);
+
+        return NativeError::type_error(format!(
+            "{function}: expected packet to be a Uint8Array or an ArrayBuffer, got {actual}"
+        ))
+        .throw(cx);
+    };
+
+    let total = data.as_slice(cx).len();
+
+    let start = offset.unwrap_or(0.0);
+    if start.fract() != 0.0 || start < 0.0 || start > total as f64 {
+        return NativeError::range_error(format!(
+            "{function}: expected byteOffset to be an integer between 0 and {total}, got {start}"
+        ))
+        .throw(cx);
+    }
+
+    let start = start as usize;
+    let length = length.unwrap_or((total - start) as f64);
+    if length.fract() != 0.0 || length < 0.0 || length > (total - start) as f64 {
+        return NativeError::range_error(format!(
+            "{function}: expected byteLength to be an integer between 0 and {}, got {length}",
+            total - start
+        ))
+        .throw(cx);
+    }
+
+    let end = start + length as usize;
+
+    Ok(data.as_slice(cx)[start..end].to_vec())
+}
+
+fn write_stream(mut cx: FunctionContext) -> JsResult<JsPromise> {
+    let stream = stream_argument(&mut cx, "write_stream", false)?;
+    let packet = write_data(&mut cx, "write_stream", 1, 3)?;
+    let cancel_token = cancel_token_argument(&mut cx, "write_stream", 2)?;
+
+    let context = stream.error_context("write_stream");
+
+    if stream.finished.load(Ordering::Acquire) {
+        return NativeError::new(
+            ErrorCode::WriteAfterFinish,
+            "Cannot write to the stream after close_write, close_stream or reset_stream was called",
+        )
+        .with("recoverable", Detail::Bool(false))
+        .context(&context)
+        .throw(&mut cx);
+    }
+
+    stream
+        .close_watch
+        .ensure_open()
+        .or_else(|err| err.context(&context).throw(&mut cx))?;
+
+    let rt = runtime(&mut cx)?;
+
+    let channel = cx.channel();
+    let (deferred, promise) = cx.promise();
+
+    // Queued right away, so writes end up on the stream in the order they were made
+    let written = stream
+        .send
+        .as_ref()
+        .map(|send| send.write(packet, cancel_token.clone()));
+
+    rt.spawn(async move {
+        // A cancelled write may have been partially written already
+        let result = CancelToken::run(cancel_token, async {
+            match written {
+                None => Ok(Err(WriteError::ClosedStream)),
+                Some(written) => written.await,
+            }
+        })
+        .await
+        .and_then(|result| result)
+        .and_then(|result| result.map_err(|err| write_error(&stream.close_watch, &err)));
+
+        let _ = deferred.try_settle_with(&channel, move |mut cx| {
+            result.or_else(|err| err.context(&context).throw(&mut cx))?;
+
+            Ok(cx.undefined())
+        });
+    });
+
+    Ok(promise)
+}
+
+// Like `write_stream` without the promise. Returns whether less than `WRITE_HIGH_WATER_MARK` bytes
+//  are queued on the stream, if not `on_drain` is called once that's the case again. A failed
+//  write is reported to `on_error`, with the range of the stream it would have been written to
+fn write_stream_nowait(mut cx: FunctionContext) -> JsResult<JsBoolean> {
+    let stream = stream_argument(&mut cx, "write_stream_nowait", false)?;
+    let packet = write_data(&mut cx, "write_stream_nowait", 1, 2)?;
+
+    let context = stream.error_context("write_stream_nowait");
+
+    if stream.finished.load(Ordering::Acquire) {
+        return NativeError::new(
+            ErrorCode::WriteAfterFinish,
+            "Cannot write to the stream after close_write, close_stream or reset_stream was called",
+        )
+        .with("recoverable", Detail::Bool(false))
+        .context(&context)
+        .throw(&mut cx);
+    }
+
+    stream
+        .close_watch
+        .ensure_open()
+        .or_else(|err| err.context(&context).throw(&mut cx))?;
+
+    let Some(send) = stream.send.clone() else {
+        return NativeError::from(&WriteError::ClosedStream)
+            .context(&context)
+            .throw(&mut cx);
+    };
+
+    let below = send.buffered() + packet.len() < WRITE_HIGH_WATER_MARK;
+    // Set before the write is queued, so it can't complete before anyone waits for the drain
+    if !below {
+        stream.pipelined.draining.store(true, Ordering::Release);
+    }
+
+    let channel = stream.streams.channel.clone();
+    let pipelined = stream.pipelined.clone();
+    let close_watch = stream.close_watch.clone();
+    let writer = send.clone();
+
+    send.write_with(packet, None, move |range, result| {
+        match result.and_then(|result| result.map_err(|err| write_error(&close_watch, &err))) {
+            Ok(()) => {
+                if writer.buffered() >= WRITE_HIGH_WATER_MARK
+                    || !pipelined.draining.swap(false, Ordering::AcqRel)
+                    || pipelined.on_drain.is_none()
+                {
+                    return;
+                }
+
+                let _ = channel.try_send(move |mut cx| {
+                    if let Some(on_drain) = pipelined.on_drain.as_ref() {
+                        let callback = on_drain.to_inner(&mut cx);
+                        let this = cx.undefined();
+
+                        callback.call(&mut cx, this, Vec::<Handle<JsValue>>::new())?;
+                    }
+
+                    Ok(())
+                });
+            }
+            Err(err) => {
+                if pipelined.failed.swap(true, Ordering::AcqRel) {
+                    return;
+                }
+
+                let err = err
+                    .with("byteStart", Detail::BigInt(range.start))
+                    .with("byteEnd", Detail::BigInt(range.end))
+                    .context(&context);
+
+                let _ = channel.try_send(move |mut cx| {
+                    let callback = pipelined.on_error.to_inner(&mut cx);
+                    let this = cx.undefined();
+                    let error = err.to_callback_value(&mut cx);
+
+                    callback.call(&mut cx, this, [error])?;
+
+                    Ok(())
+                });
+            }
+        }
+    });
+
+    Ok(cx.boolean(below))
+}
+
+// What a call to `close_stream`, `close_write` or `close_connection` actually did. Calling them again
+//  is a no-op which resolves with `alreadyClosed: true`
+#[derive(Clone, Copy, Default)]
+struct CloseActions {
+    send_finished: bool,
+    receive_stopped: bool,
+}
+
+impl CloseActions {
+    fn to_object<'a, C: Context<'a>>(self, cx: &mut C) -> JsResult<'a, JsObject> {
+        let result = cx.empty_object();
+
+        let send_finished = cx.boolean(self.send_finished);
+        let already_closed = cx.boolean(!self.send_finished && !self.receive_stopped);
+        let receive_stopped = cx.boolean(self.receive_stopped);
+
+        result.set(cx, "sendFinished", send_finished)?;
+        result.set(cx, "alreadyClosed", already_closed)?;
+        result.set(cx, "receiveStopped", receive_stopped)?;
+
+        Ok(result)
+    }
+}
+
+fn close_stream(mut cx: FunctionContext) -> JsResult<JsPromise> {
+    let stream = stream_argument(&mut cx, "close_stream", false)?;
+
+    let error_code = {
+        let arg = cx
+            .argument_opt(1)
+            .unwrap_or_else(|| cx.undefined().upcast());
+
+        var_int(&mut cx, "close_stream", "errorCode", arg)?
+    };
+
+    stream.finished.store(true, Ordering::Release);
+
+    let rt = runtime(&mut cx)?;
+
+    let channel = cx.channel();
+    let (deferred, promise) = cx.promise();
+
+    let finished = stream.send.as_ref().map(|send| send.finish());
+
+    rt.spawn(async move {
+        // Fails when the stream was already finished or reset, which makes this part a no-op
+        let send_finished = match finished {
+            Some(finished) => finished.await.is_ok(),
+            None => false,
+        };
+
+        // The read loop may also have ended by itself because the peer finished the stream
+        let receive_stopped =
+            !stream.handle.is_finished() && stream.close_requested.cancel(error_code.into());
+
+        let actions = CloseActions {
+            send_finished,
+            receive_stopped,
+        };
+
+        let _ = deferred.try_settle_with(&channel, move |mut cx| actions.to_object(&mut cx));
+    });
+
+    Ok(promise)
+}
+
+fn reset_stream(mut cx: FunctionContext) -> JsResult<JsPromise> {
+    let stream = stream_argument(&mut cx, "reset_stream", false)?;
+
+    let error_code = {
+        let arg = cx
+            .argument_opt(1)
+            .unwrap_or_else(|| cx.undefined().upcast());
+
+        var_int(&mut cx, "reset_stream", "errorCode", arg)?
+    };
+
+    stream.finished.store(true, Ordering::Release);
+
+    let context = stream.error_context("reset_stream");
+
+    stream
+        .close_watch
+        .ensure_open()
+        .or_else(|err| err.context(&context).throw(&mut cx))?;
+
+    let rt = runtime(&mut cx)?;
+
+    let channel = cx.channel();
+    let (deferred, promise) = cx.promise();
+
+    let reset = stream.send.as_ref().map(|send| send.reset(error_code));
+
+    rt.spawn(async move {
+        let result = match reset {
+            None => Err(ClosedStream::default()),
+            Some(reset) => reset.await,
+        };
+
+        let _ = deferred.try_settle_with(&channel, move |mut cx| {
+            result.or_else(|err| NativeError::from(&err).context(&context).throw(&mut cx))?;
+
+            Ok(cx.undefined())
+        });
+    });
+
+    Ok(promise)
+}
+
+// How long `destroy_stream` waits for the read loop to stop the receive side before aborting it
+const DESTROY_TIMEOUT: Duration = Duration::from_millis(100);
+
+// Stops the receive side and resets the send side with the same code, and releases everything
+//  held for the stream without waiting for the garbage collector. The close callback is called
+//  with "destroyed", unless the stream was already closed
+fn destroy_stream(mut cx: FunctionContext) -> JsResult<JsPromise> {
+    let stream = stream_argument(&mut cx, "destroy_stream", false)?;
+
+    let error_code =
+        match args::optional_argument::<JsObject>(&mut cx, "destroy_stream", 1, "options")? {
+            None => VarInt::from(0u8),
+            Some(options) => {
+                let value: Handle<JsValue> = options.get(&mut cx, "errorCode")?;
+
+                if value.is_a::<JsUndefined, _>(&mut cx) {
+                    VarInt::from(0u8)
+                } else {
+                    var_int(&mut cx, "destroy_stream", "options.errorCode", value)?
+                }
+            }
+        };
+
+    // Checked again, a getter on `options` may have destroyed the stream meanwhile
+    if stream.handle_state.advance(handle_state::State::Consumed) >= handle_state::State::Consumed {
+        stream
+            .check("destroy_stream", false)
+            .or_else(|err| err.throw(&mut cx))?;
+    }
+    stream.finished.store(true, Ordering::Release);
+
+    let rt = runtime(&mut cx)?;
+
+    let channel = cx.channel();
+    let (deferred, promise) = cx.promise();
+
+    // Neither side waits for the other, the reset doesn't wait for the writes queued before it
+    stream.close_requested.cancel(StopRequest {
+        error_code,
+        destroyed: true,
+    });
+    let reset = stream.send.as_ref().map(|send| send.reset(error_code));
+
+    rt.spawn(async move {
+        if let Some(reset) = reset {
+            let _ = reset.await;
+        }
+
+        let _ = tokio::time::timeout(DESTROY_TIMEOUT, async {
+            while !stream.handle.is_finished() {
+                tokio::time::sleep(DRAIN_POLL_INTERVAL).await;
+            }
+        })
+        .await;
+        stream.handle.abort();
+
+        stream.streams.unregister(&stream.handle);
+        stream.keep_alive.release();
+
+        let _ = deferred.try_settle_with(&channel, |mut cx| Ok(cx.undefined()));
+    });
+
+    Ok(promise)
+}
+
+// Stops the read loop, which asks the peer to stop sending with the given code
+fn stop_read(mut cx: FunctionContext) -> JsResult<JsUndefined> {
+    let stream = stream_argument(&mut cx, "stop_read", false)?;
+
+    let error_code = {
+        let arg = cx
+            .argument_opt(1)
+            .unwrap_or_else(|| cx.undefined().upcast());
+
+        var_int(&mut cx, "stop_read", "errorCode", arg)?
+    };
+
+    stream.close_requested.cancel(error_code.into());
+
+    Ok(cx.undefined())
+}
+
+fn close_write(mut cx: FunctionContext) -> JsResult<JsPromise> {
+    let stream = stream_argument(&mut cx, "close_write", false)?;
+
+    stream.finished.store(true, Ordering::Release);
+
+    let rt = runtime(&mut cx)?;
+
+    let channel = cx.channel();
+    let (deferred, promise) = cx.promise();
+
+    let finished = stream.send.as_ref().map(|send| send.finish());
+
+    rt.spawn(async move {
+        // Unidirectional streams don't have a write-end, so there is nothing to close
+        let send_finished = match finished {
+            Some(finished) => finished.await.is_ok(),
+            None => false,
+        };
+
+        let actions = CloseActions {
+            send_finished,
+            ..Default::default()
+        };
+
+        let _ = deferred.try_settle_with(&channel, move |mut cx| actions.to_object(&mut cx));
+    });
+
+    Ok(promise)
+}
+
+// Error code and reason given as the second and third argument to close a connection or endpoint
+fn close_arguments(cx: &mut FunctionContext, function: &str) -> NeonResult<(VarInt, Vec<u8>)> {
+    use neon::types::buffer::TypedArray;
+
+    let code = {
+        let arg = cx
+            .argument_opt(1)
+            .unwrap_or_else(|| cx.undefined().upcast());
+
+        var_int(cx, function, "errorCode", arg)?
+    };
+    let reason = match args::optional_argument::<JsUint8Array>(cx, function, 2, "reason")? {
+        Some(arr) => arr.as_slice(cx).to_vec(),
+        None => Vec::new(),
+    };
+
+    Ok((code, reason))
+}
+
+// The endpoint of either an endpoint handle or a connection handle
+fn endpoint_argument(
+    cx: &mut FunctionContext,
+    function: &'static str,
+) -> NeonResult<Arc<quinn::Endpoint>> {
+    let value = cx
+        .argument_opt(0)
+        .unwrap_or_else(|| cx.undefined().upcast());
+
+    if let Ok(endpoint) = value.downcast::<JsBox<Endpoint>, _>(cx) {
+        return Ok(endpoint.endpoint.clone());
+    }
+    if let Ok(connection) = value.downcast::<JsBox<Connection>, _>(cx) {
+        connection
+            .check(function, false)
+            .or_else(|err| err.throw(cx))?;
+
+        return Ok(connection.endpoint.clone());
+    }
+
+    let actual = args::type_of(cx, value);
+
+    NativeError::type_error(format!(
+        "{function}: expected endpoint to be an endpoint or connection handle, got {actual}"
+    ))
+    .with("operation", Detail::String(function.to_owned()))
+    .throw(cx)
+}
+
+// Closes every connection on the endpoint immediately and stops it from accepting new ones.
+//  Closing an endpoint again has no effect
+fn close_endpoint(mut cx: FunctionContext) -> JsResult<JsUndefined> {
+    let endpoint = endpoint_argument(&mut cx, "close_endpoint")?;
+    let (code, reason) = close_arguments(&mut cx, "close_endpoint")?;
+
+    endpoint.close(code, &reason);
+
+    Ok(cx.undefined())
+}
+
+// Resolves once all connections on the endpoint are drained, after which the UDP socket is
+//  released as soon as the last handle to it is dropped
+fn wait_endpoint_idle(mut cx: FunctionContext) -> JsResult<JsPromise> {
+    let endpoint = endpoint_argument(&mut cx, "wait_endpoint_idle")?;
+
+    let rt = runtime(&mut cx)?;
+
+    let channel = cx.channel();
+    let (deferred, promise) = cx.promise();
+
+    rt.spawn(async move {
+        endpoint.wait_idle().await;
+
+        let _ = deferred.try_settle_with(&channel, |mut cx| Ok(cx.undefined()));
     });
 
     Ok(promise)
 }
 
-struct PartialStream {
-    send: Arc<TakeOnce<Option<SendStream>>>,
-    recv: Arc<TakeOnce<RecvStream>>,
-}
+// The connection and its streams stop keeping the event loop alive, like `net.Socket.unref`
+fn unref_connection(mut cx: FunctionContext) -> JsResult<JsUndefined> {
+    let connection = connection_argument(&mut cx, "unref_connection", false)?;
 
-impl Finalize for PartialStream {
-    // Do nothing since `initialize_stream` must be called immediately after
-    fn finalize<'a, C: Context<'a>>(self, _: &mut C) {}
-}
+    connection.keep_alive.set_referenced(&mut cx, false);
+    connection.streams.set_referenced(&mut cx, false);
 
-#[derive(Clone, Debug)]
-struct StreamDetails {
-    id: StreamId,
-    is_0rtt: bool,
+    Ok(cx.undefined())
 }
 
-impl StreamDetails {
-    fn new(recv: &RecvStream) -> Self {
-        Self {
-            id: recv.id(),
-            is_0rtt: recv.is_0rtt(),
-        }
-    }
-}
+fn ref_connection(mut cx: FunctionContext) -> JsResult<JsUndefined> {
+    let connection = connection_argument(&mut cx, "ref_connection", false)?;
 
-#[derive(Clone)]
-struct Stream {
-    send: Arc<Option<Mutex<SendStream>>>,
-    handle: Arc<JoinHandle<()>>,
-    details: StreamDetails,
-    close_requested: CancelWithValue<VarInt>,
+    connection.keep_alive.set_referenced(&mut cx, true);
+    connection.streams.set_referenced(&mut cx, true);
+
+    Ok(cx.undefined())
 }
 
-impl Finalize for Stream {
-    fn finalize<'a, C: Context<'a>>(self, _: &mut C) {
-        let rt = RUNTIME.get().unwrap();
+// Changes how many streams of each kind the peer may have open at once. Lowering a limit doesn't
+//  close streams which are already open, it only withholds further credit until enough of them
+//  are done
+fn set_stream_limits(mut cx: FunctionContext) -> JsResult<JsUndefined> {
+    let connection = connection_argument(&mut cx, "set_stream_limits", true)?;
+    let limits = args::argument::<JsObject>(&mut cx, "set_stream_limits", 1, "limits")?;
 
-        self.handle.clone().abort();
+    let limit = |cx: &mut FunctionContext, key: &str| -> NeonResult<Option<VarInt>> {
+        let value: Handle<JsValue> = limits.get(cx, key)?;
 
-        rt.spawn(async move {
-            if let Some(send) = self.send.clone().as_ref() {
-                let _ = send.lock().await.finish();
-            }
-        });
-    }
-}
+        if value.is_a::<JsUndefined, _>(cx) || value.is_a::<JsNull, _>(cx) {
+            return Ok(None);
+        }
 
-async fn handle_read(
-    mut recv: quinn::RecvStream,
-    close_requested: CancelWithValue<VarInt>,
-    data: (Root<JsFunction>, Channel),
-    close: (Root<JsFunction>, Channel),
-    error: (Root<JsFunction>, Channel),
-) {
-    let mut buf = [0u8; 2048];
+        var_int(cx, "set_stream_limits", &format!("limits.{key}"), value).map(Some)
+    };
 
-    let data_callback = Arc::new(data.0);
-    let close_callback = Arc::new(close.0);
-    let error_callback = Arc::new(error.0);
+    let bidi = limit(&mut cx, "bidi")?;
+    let uni = limit(&mut cx, "uni")?;
 
-    let handle_close = |reason: String| {
-        let callback = close_callback.clone();
-        close.1.send(move |mut cx| {
-            let callback = callback.as_ref().clone(&mut cx).into_inner(&mut cx);
-            let this = cx.undefined();
+    // A graceful close has set both limits to 0, raising them again would let the peer open
+    //  streams the close then has to wait for
+    if connection.closing.load(Ordering::Acquire) {
+        return NativeError::new(ErrorCode::ConnectionClosed, "The connection is closing")
+            .context(&connection.error_context("set_stream_limits"))
+            .throw(&mut cx);
+    }
 
-            let args = vec![cx.string(reason).upcast()];
+    if let Some(bidi) = bidi {
+        connection.connection.set_max_concurrent_bi_streams(bidi);
+    }
+    if let Some(uni) = uni {
+        connection.connection.set_max_concurrent_uni_streams(uni);
+    }
 
-            callback.call(&mut cx, this, args)?;
+    Ok(cx.undefined())
+}
 
-            Ok(())
-        });
-    };
+// Changes how much data the peer may send ahead of what was read, across all streams. Applies
+//  with the next flow control update, shrinking it below what was already granted takes until
+//  the peer used up that credit
+fn set_receive_window(mut cx: FunctionContext) -> JsResult<JsUndefined> {
+    let connection = connection_argument(&mut cx, "set_receive_window", true)?;
+    let bytes = cx
+        .argument_opt(1)
+        .unwrap_or_else(|| cx.undefined().upcast());
+    let bytes = var_int(&mut cx, "set_receive_window", "bytes", bytes)?;
+
+    connection.connection.set_receive_window(bytes);
+    connection
+        .receive_window
+        .store(bytes.into_inner(), Ordering::Release);
+
+    Ok(cx.undefined())
+}
 
-    loop {
-        let read_result = tokio::select! {
-            result = recv.read(&mut buf) => result,
-            error_code = close_requested.cancelled() => {
-                let _ = recv.stop(error_code);
+// Default of `timeoutMs` for `close_connection`
+const CLOSE_DRAIN_TIMEOUT: Duration = Duration::from_secs(1);
 
-                break;
-            },
+fn close_connection(mut cx: FunctionContext) -> JsResult<JsPromise> {
+    let connection = connection_argument(&mut cx, "close_connection", false)?;
+    let (code, reason) = close_arguments(&mut cx, "close_connection")?;
+    let timeout =
+        match args::optional_argument::<JsNumber>(&mut cx, "close_connection", 3, "timeoutMs")? {
+            None => CLOSE_DRAIN_TIMEOUT,
+            Some(timeout) => {
+                let timeout = timeout.value(&mut cx);
+                duration_ms(&mut cx, "close_connection", "timeoutMs", timeout)?
+            }
         };
 
-        match read_result {
-            Err(e) => match e {
-                quinn::ReadError::ConnectionLost(e) => {
-                    handle_close(e.to_string());
-                    return;
-                }
-                quinn::ReadError::ClosedStream | quinn::ReadError::Reset(_) => {
-                    handle_close(e.to_string());
-                    return;
-                }
-                quinn::ReadError::IllegalOrderedRead | quinn::ReadError::ZeroRttRejected => {
-                    let callback = error_callback.clone();
-                    error.1.send(move |mut cx| {
-                        let callback = callback.as_ref().clone(&mut cx).into_inner(&mut cx);
-                        let this = cx.undefined();
+    let rt = runtime(&mut cx)?;
 
-                        let args = &[cx.error(e.to_string()).unwrap().upcast()];
+    let channel = cx.channel();
+    let (deferred, promise) = cx.promise();
 
-                        callback.call(&mut cx, this, args)?;
+    let already_closed = connection.closing.swap(true, Ordering::AcqRel)
+        || connection.connection.close_reason().is_some();
 
-                        Ok(())
-                    });
-                }
-            },
-            Ok(option) => match option {
-                None => break,
-                Some(n) => {
-                    let packet = buf[..n].to_vec();
-
-                    let callback = data_callback.clone();
-                    data.1.send(move |mut cx| {
-                        let callback = callback.as_ref().clone(&mut cx).into_inner(&mut cx);
-                        let this = cx.undefined();
+    rt.spawn(async move {
+        if !already_closed {
+            connection.connection.close(code, &reason);
+        }
 
-                        let array = {
-                            let a = JsUint8Array::new(&mut cx, packet.len())?;
-                            for (i, n) in packet.iter().enumerate() {
-                                let v = cx.number(*n);
-                                a.set(&mut cx, i as u32, v)?;
-                            }
-                            a
-                        };
+        // The CONNECTION_CLOSE frame is sent by the endpoint, exiting right after `close` can
+        //  lose it. A shared endpoint stays busy with its other connections, so it isn't waited on
+        let drained = connection.owns_endpoint
+            && tokio::time::timeout(timeout, connection.endpoint.wait_idle())
+                .await
+                .is_ok();
 
-                        let args = vec![array.upcast()];
+        let _ = deferred.try_settle_with(&channel, move |mut cx| {
+            let result = cx.empty_object();
+            let already_closed = cx.boolean(already_closed);
+            let drained = cx.boolean(drained);
 
-                        callback.call(&mut cx, this, args)?;
+            result.set(&mut cx, "alreadyClosed", already_closed)?;
+            result.set(&mut cx, "drained", drained)?;
 
-                        Ok(())
-                    });
-                }
-            },
-        }
-    }
+            Ok(result)
+        });
+    });
 
-    handle_close(String::from("closed"));
+    Ok(promise)
 }
 
-fn create_stream(mut cx: FunctionContext) -> JsResult<JsPromise> {
-    let connection = (**cx.argument::<JsBox<Connection>>(0)?).clone();
+// Default of `timeoutMs` for `close_connection_graceful`
+const GRACEFUL_CLOSE_TIMEOUT: Duration = Duration::from_secs(10);
+
+// Stops new streams from being opened or accepted and waits for the existing ones to finish before
+//  closing the connection. Closes right away when the timeout expires
+fn close_connection_graceful(mut cx: FunctionContext) -> JsResult<JsPromise> {
+    let connection = connection_argument(&mut cx, "close_connection_graceful", false)?;
+    let (code, reason) = close_arguments(&mut cx, "close_connection_graceful")?;
+    let timeout = match args::optional_argument::<JsObject>(
+        &mut cx,
+        "close_connection_graceful",
+        3,
+        "options",
+    )? {
+        Some(options) => optional_duration(
+            &mut cx,
+            "close_connection_graceful",
+            options,
+            "options",
+            "timeoutMs",
+        )?,
+        None => None,
+    }
+    .unwrap_or(GRACEFUL_CLOSE_TIMEOUT);
 
     let rt = runtime(&mut cx)?;
 
     let channel = cx.channel();
     let (deferred, promise) = cx.promise();
 
+    let already_closed = connection.closing.swap(true, Ordering::AcqRel)
+        || connection.connection.close_reason().is_some();
+
     rt.spawn(async move {
-        let result = connection.connection.open_bi().await;
+        let mut remaining = 0;
+
+        if !already_closed {
+            // Streams the peer already opened are still accepted, only new ones are refused
+            connection
+                .connection
+                .set_max_concurrent_bi_streams(0u8.into());
+            connection
+                .connection
+                .set_max_concurrent_uni_streams(0u8.into());
+
+            let mut pending = tokio::task::JoinSet::new();
+            for stream in connection.streams.pending() {
+                pending.spawn(stream.drained());
+            }
 
-        deferred.settle_with(&channel, move |mut cx| {
-            let (send, recv) = result.or_else(|err| cx.throw_error(err.to_string()))?;
+            let _ = tokio::time::timeout(timeout, async {
+                while pending.join_next().await.is_some() {}
+            })
+            .await;
 
-            let partial_stream = PartialStream {
-                send: Arc::new(TakeOnce::new(Some(send))),
-                recv: Arc::new(TakeOnce::new(recv)),
-            };
+            remaining = pending.len();
+            connection.connection.close(code, &reason);
+        }
 
-            Ok(cx.boxed(partial_stream))
+        let _ = deferred.try_settle_with(&channel, move |mut cx| {
+            let result = cx.empty_object();
+            let graceful = cx.boolean(!already_closed && remaining == 0);
+            let remaining_streams = cx.number(remaining as f64);
+            let already_closed = cx.boolean(already_closed);
+
+            result.set(&mut cx, "graceful", graceful)?;
+            result.set(&mut cx, "remainingStreams", remaining_streams)?;
+            result.set(&mut cx, "alreadyClosed", already_closed)?;
+
+            Ok(result)
         });
     });
 
     Ok(promise)
 }
 
-fn initialize_stream(mut cx: FunctionContext) -> JsResult<JsBox<Stream>> {
-    let partial_stream = cx.argument::<JsBox<PartialStream>>(0)?;
-    let on_data = cx.argument::<JsFunction>(1)?.root(&mut cx);
-    let on_close = cx.argument::<JsFunction>(2)?.root(&mut cx);
-    let on_error = cx.argument::<JsFunction>(3)?.root(&mut cx);
-
-    let rt = runtime(&mut cx)?;
-
-    let data_channel = cx.channel();
-    let close_channel = cx.channel();
-    let error_channel = cx.channel();
+fn stream_details(mut cx: FunctionContext) -> JsResult<JsObject> {
+    let stream = stream_argument(&mut cx, "stream_details", false)?;
 
-    let send = partial_stream.send.clone().take();
-    let recv = partial_stream.recv.clone().take();
+    stream.details.to_object(&mut cx)
+}
 
-    let details = StreamDetails::new(&recv);
-    let close_requested = CancelWithValue::new();
+fn connection_id(mut cx: FunctionContext) -> JsResult<JsNumber> {
+    let connection = connection_argument(&mut cx, "connection_id", false)?;
 
-    let handle = rt.spawn({
-        let close_requested = close_requested.clone();
+    Ok(cx.number(connection.tag.id as f64))
+}
 
-        async move {
-            handle_read(
-                recv,
-                close_requested,
-                (on_data, data_channel),
-                (on_close, close_channel),
-                (on_error, error_channel),
-            )
-            .await
-        }
-    });
+fn negotiated_version(mut cx: FunctionContext) -> JsResult<JsObject> {
+    let connection = connection_argument(&mut cx, "negotiated_version", false)?;
 
-    let stream = Stream {
-        send: Arc::new(send.map(Mutex::new)),
-        handle: Arc::new(handle),
-        close_requested,
-        details,
+    let result = cx.empty_object();
+    let version = cx.number(connection.version);
+    let name: Handle<JsValue> = match quic::version_name(connection.version) {
+        Some(name) => cx.string(name).upcast(),
+        None => cx.null().upcast(),
     };
 
-    Ok(cx.boxed(stream))
+    result.set(&mut cx, "version", version)?;
+    result.set(&mut cx, "name", name)?;
+
+    Ok(result)
 }
 
-fn write_stream(mut cx: FunctionContext) -> JsResult<JsPromise> {
-    use neon::types::buffer::TypedArray;
+fn handshake_timings(mut cx: FunctionContext) -> JsResult<JsObject> {
+    let connection = connection_argument(&mut cx, "handshake_timings", false)?;
+
+    let result = cx.empty_object();
+    let handshake_ms = cx.number(connection.timings.handshake.as_secs_f64() * 1000.0);
+    let total_ms = cx.number(connection.timings.total.as_secs_f64() * 1000.0);
+
+    result.set(&mut cx, "handshakeMs", handshake_ms)?;
+    result.set(&mut cx, "totalMs", total_ms)?;
+
+    Ok(result)
+}
 
-    let stream = (**cx.argument::<JsBox<Stream>>(0)?).clone();
-    let packet = cx.argument::<JsTypedArray<u8>>(1)?.as_slice(&cx).to_vec();
+fn await_close(mut cx: FunctionContext) -> JsResult<JsPromise> {
+    let connection = connection_argument(&mut cx, "await_close", false)?;
 
     let rt = runtime(&mut cx)?;
 
@@ -474,114 +4254,419 @@ fn write_stream(mut cx: FunctionContext) -> JsResult<JsPromise> {
     let (deferred, promise) = cx.promise();
 
     rt.spawn(async move {
-        let result = {
-            match stream
-                .send
-                .clone()
-                .as_ref()
-                .as_ref()
-                .ok_or(WriteError::ClosedStream)
-            {
-                Err(e) => Err(e),
-                Ok(send) => {
-                    let mut send = send.lock().await;
-
-                    send.write_all(&packet).await
-                }
-            }
-        };
+        let mut closed = connection.close_watch.closed.clone();
 
-        deferred.settle_with(&channel, move |mut cx| {
-            result.or_else(|err| cx.throw_error(err.to_string()))?;
+        // Resolves immediately if the value has already been set.
+        //  Only fails when the close task was aborted, which happens when the connection gets finalized
+        let reason = closed
+            .wait_for(Option::is_some)
+            .await
+            .ok()
+            .and_then(|reason| reason.clone());
 
-            Ok(cx.undefined())
+        let _ = deferred.try_settle_with(&channel, move |mut cx| match reason {
+            None => NativeError::new(
+                ErrorCode::InvalidState,
+                "Connection was finalized before it closed",
+            )
+            .context(&connection.error_context("await_close"))
+            .throw(&mut cx),
+            Some(reason) => reason.to_object(&mut cx),
         });
     });
 
     Ok(promise)
 }
 
-fn close_stream(mut cx: FunctionContext) -> JsResult<JsPromise> {
-    let stream = (**cx.argument::<JsBox<Stream>>(0)?).clone();
+fn close_reason(mut cx: FunctionContext) -> JsResult<JsValue> {
+    let connection = connection_argument(&mut cx, "close_reason", false)?;
 
-    let error_code = {
-        let arg = cx.argument::<JsNumber>(1)?;
-        let value = arg.value(&mut cx) as u64;
+    match connection.close_reason() {
+        None => Ok(cx.null().upcast()),
+        Some(reason) => Ok(reason.to_object(&mut cx)?.upcast()),
+    }
+}
+
+fn connection_state(mut cx: FunctionContext) -> JsResult<JsString> {
+    let connection = connection_argument(&mut cx, "connection_state", false)?;
+
+    Ok(cx.string(connection.state()))
+}
+
+// Replaces the callbacks passed to `connect`. Each of `onStream`, `onClose` and `onError` is
+//  optional, callbacks which aren't given are kept
+fn set_connection_callbacks(mut cx: FunctionContext) -> JsResult<JsUndefined> {
+    let connection = connection_argument(&mut cx, "set_connection_callbacks", false)?;
+    let options = args::argument::<JsObject>(&mut cx, "set_connection_callbacks", 1, "callbacks")?;
+
+    fn callback(
+        cx: &mut FunctionContext,
+        options: Handle<JsObject>,
+        key: &str,
+    ) -> NeonResult<Option<Root<JsFunction>>> {
+        Ok(args::optional_property::<_, JsFunction>(
+            cx,
+            "set_connection_callbacks",
+            options,
+            "callbacks",
+            key,
+        )?
+        .map(|callback| callback.root(cx)))
+    }
+
+    let on_stream = callback(&mut cx, options, "onStream")?;
+    let on_close = callback(&mut cx, options, "onClose")?;
+    let on_error = callback(&mut cx, options, "onError")?;
+
+    connection
+        .callbacks
+        .replace(&mut cx, on_stream, on_close, on_error);
+
+    Ok(cx.undefined())
+}
+
+fn open_streams(mut cx: FunctionContext) -> JsResult<JsNumber> {
+    let connection = connection_argument(&mut cx, "open_streams", false)?;
+
+    Ok(cx.number(connection.streams.open() as f64))
+}
+
+// Quinn does not expose the transport parameters received from the peer, only values derived
+//  from them. Parameters which can't be observed are reported as `null`, derived values are listed
+//  in the `derived` array so they aren't mistaken for the raw values.
+fn peer_parameters(mut cx: FunctionContext) -> JsResult<JsObject> {
+    let connection = connection_argument(&mut cx, "peer_parameters", false)?;
+
+    let max_datagram_size = connection.connection.max_datagram_size();
+
+    let result = cx.empty_object();
 
-        VarInt::from_u64(value).or_else(|e| cx.throw_error(e.to_string()))?
+    let max_datagram_frame_size: Handle<JsValue> = match max_datagram_size {
+        Some(size) => cx.number(size as f64).upcast(),
+        None => cx.null().upcast(),
     };
+    let datagrams_supported = cx.boolean(max_datagram_size.is_some());
+
+    result.set(&mut cx, "maxDatagramFrameSize", max_datagram_frame_size)?;
+    result.set(&mut cx, "datagramsSupported", datagrams_supported)?;
+
+    for key in [
+        "maxConcurrentBidiStreams",
+        "maxConcurrentUniStreams",
+        "initialMaxData",
+        "maxIdleTimeout",
+        "activeMigrationDisabled",
+    ] {
+        let value = cx.null();
+        result.set(&mut cx, key, value)?;
+    }
 
-    let rt = runtime(&mut cx)?;
+    let derived = cx.empty_array();
+    for (i, key) in ["maxDatagramFrameSize", "datagramsSupported"]
+        .iter()
+        .enumerate()
+    {
+        let key = cx.string(key);
+        derived.set(&mut cx, i as u32, key)?;
+    }
+    result.set(&mut cx, "derived", derived)?;
 
-    let channel = cx.channel();
-    let (deferred, promise) = cx.promise();
+    Ok(result)
+}
 
-    rt.spawn(async move {
-        if let Some(send) = stream.send.clone().as_ref() {
-            let mut send = send.lock().await;
+fn loss_rate(mut cx: FunctionContext) -> JsResult<JsValue> {
+    let connection = connection_argument(&mut cx, "loss_rate", false)?;
+
+    let window = match args::optional_argument::<JsNumber>(&mut cx, "loss_rate", 1, "windowMs")? {
+        Some(window_ms) => {
+            let window_ms = window_ms.value(&mut cx);
 
-            // Returns an error if the request gets closed multiple times, but we allow that to happen
-            //  So we can just ignore it
-            let _ = send.finish();
+            Some(sample_window(&mut cx, "loss_rate", "windowMs", window_ms)?)
         }
+        None => None,
+    };
 
-        stream.close_requested.cancel(error_code);
+    match connection.sampler.loss_rate(window) {
+        None => Ok(cx.null().upcast()),
+        Some(rate) => Ok(cx.number(rate).upcast()),
+    }
+}
 
-        deferred.settle_with(&channel, move |mut cx| Ok(cx.undefined()));
-    });
+fn throughput(mut cx: FunctionContext) -> JsResult<JsObject> {
+    let connection = connection_argument(&mut cx, "throughput", false)?;
 
-    Ok(promise)
+    let throughput = connection.sampler.throughput();
+
+    let result = cx.empty_object();
+    let send_bps = cx.number(throughput.send);
+    let recv_bps = cx.number(throughput.recv);
+
+    result.set(&mut cx, "sendBps", send_bps)?;
+    result.set(&mut cx, "recvBps", recv_bps)?;
+
+    Ok(result)
 }
 
-fn close_write(mut cx: FunctionContext) -> JsResult<JsPromise> {
-    let stream = (**cx.argument::<JsBox<Stream>>(0)?).clone();
+fn rtt_histogram(mut cx: FunctionContext) -> JsResult<JsObject> {
+    let connection = connection_argument(&mut cx, "rtt_histogram", false)?;
+
+    let reset = match args::optional_argument::<JsObject>(&mut cx, "rtt_histogram", 1, "options")? {
+        Some(options) => args::optional_property::<_, JsBoolean>(
+            &mut cx,
+            "rtt_histogram",
+            options,
+            "options",
+            "reset",
+        )?
+        .map(|value| value.value(&mut cx))
+        .unwrap_or(false),
+        None => false,
+    };
 
-    let rt = runtime(&mut cx)?;
+    let Some(summary) = connection.sampler.rtt_histogram(reset) else {
+        return NativeError::new(
+            ErrorCode::InvalidState,
+            "The RTT histogram was not enabled for this connection",
+        )
+        .context(&connection.error_context("rtt_histogram"))
+        .throw(&mut cx);
+    };
 
-    let channel = cx.channel();
-    let (deferred, promise) = cx.promise();
+    let as_ms = |duration: std::time::Duration| duration.as_secs_f64() * 1000.0;
 
-    rt.spawn(async move {
-        let result = match stream
-            .send
-            .clone()
-            .as_ref()
-            .as_ref()
-            .ok_or(ClosedStream::new())
-        {
-            Err(e) => Err(e),
-            Ok(send) => {
-                let mut send = send.lock().await;
+    let result = cx.empty_object();
+    let count = cx.number(summary.count as f64);
+    let p50 = cx.number(as_ms(summary.p50));
+    let p90 = cx.number(as_ms(summary.p90));
+    let p99 = cx.number(as_ms(summary.p99));
+    let max = cx.number(as_ms(summary.max));
+
+    result.set(&mut cx, "count", count)?;
+    result.set(&mut cx, "p50Ms", p50)?;
+    result.set(&mut cx, "p90Ms", p90)?;
+    result.set(&mut cx, "p99Ms", p99)?;
+    result.set(&mut cx, "maxMs", max)?;
+
+    let buckets = cx.empty_array();
+    for (i, bucket) in summary.buckets.iter().enumerate() {
+        let entry = cx.empty_object();
+        let lower = cx.number(as_ms(bucket.lower));
+        let upper = cx.number(as_ms(bucket.upper));
+        let count = cx.number(bucket.count as f64);
+
+        entry.set(&mut cx, "lowerMs", lower)?;
+        entry.set(&mut cx, "upperMs", upper)?;
+        entry.set(&mut cx, "count", count)?;
+
+        buckets.set(&mut cx, i as u32, entry)?;
+    }
+    result.set(&mut cx, "buckets", buckets)?;
+
+    Ok(result)
+}
+
+fn details_object<'a, C: Context<'a>>(
+    cx: &mut C,
+    connection: &Connection,
+) -> JsResult<'a, JsObject> {
+    let handshake_data = connection
+        .connection
+        .handshake_data()
+        .and_then(|data| data.downcast::<quinn::crypto::rustls::HandshakeData>().ok());
+
+    let result = cx.empty_object();
 
-                send.finish()
+    let remote_address = cx.string(connection.connection.remote_address().to_string());
+    let local_address: Handle<JsValue> = match connection.endpoint.local_addr() {
+        Ok(addr) => cx.string(addr.to_string()).upcast(),
+        Err(_) => cx.null().upcast(),
+    };
+    let local_ip: Handle<JsValue> = match connection.connection.local_ip() {
+        Some(ip) => cx.string(ip.to_string()).upcast(),
+        None => cx.null().upcast(),
+    };
+    let alpn_protocol: Handle<JsValue> = match handshake_data
+        .as_ref()
+        .and_then(|data| data.protocol.as_ref())
+    {
+        Some(protocol) => cx.string(String::from_utf8_lossy(protocol)).upcast(),
+        None => cx.null().upcast(),
+    };
+    let server_name: Handle<JsValue> = match handshake_data
+        .as_ref()
+        .and_then(|data| data.server_name.as_ref())
+    {
+        Some(name) => cx.string(name).upcast(),
+        None => cx.null().upcast(),
+    };
+    let version = cx.number(connection.version);
+    let handshake_kind = cx.string(connection.handshake_kind.as_str());
+    let fips = cx.boolean(connection.fips);
+    let congestion_controller = cx.string(connection.congestion_controller.as_str());
+    let socket: Handle<JsValue> = match connection.socket {
+        Some(info) => {
+            let socket = cx.empty_object();
+            let send_buffer_size = cx.number(info.send_buffer_size as f64);
+            let recv_buffer_size = cx.number(info.recv_buffer_size as f64);
+            socket.set(cx, "sendBufferSize", send_buffer_size)?;
+            socket.set(cx, "recvBufferSize", recv_buffer_size)?;
+            if let Some(dscp) = info.dscp {
+                let dscp = cx.number(dscp);
+                socket.set(cx, "dscp", dscp)?;
             }
-        };
+            let gso_segments = cx.number(info.max_transmit_segments as f64);
+            let gro_segments = cx.number(info.max_receive_segments as f64);
+            socket.set(cx, "gsoSegments", gso_segments)?;
+            socket.set(cx, "groSegments", gro_segments)?;
 
-        deferred.settle_with(&channel, move |mut cx| {
-            result.or_else(|err| cx.throw_error(err.to_string()))?;
+            socket.upcast()
+        }
+        None => cx.null().upcast(),
+    };
+    let receive_window = JsBigInt::from_u64(cx, connection.receive_window.load(Ordering::Acquire));
+    let buffered_bytes = cx.number(connection.streams.buffered.level() as f64);
+
+    connection.tag.apply(cx, result)?;
+    result.set(cx, "remoteAddress", remote_address)?;
+    result.set(cx, "localAddress", local_address)?;
+    result.set(cx, "localIp", local_ip)?;
+    result.set(cx, "alpnProtocol", alpn_protocol)?;
+    result.set(cx, "serverName", server_name)?;
+    result.set(cx, "version", version)?;
+    result.set(cx, "handshakeKind", handshake_kind)?;
+    result.set(cx, "fips", fips)?;
+    result.set(cx, "congestionControl", congestion_controller)?;
+    result.set(cx, "socket", socket)?;
+    result.set(cx, "receiveWindow", receive_window)?;
+    result.set(cx, "bufferedBytes", buffered_bytes)?;
 
-            Ok(cx.undefined())
-        });
-    });
+    Ok(result)
+}
 
-    Ok(promise)
+fn connection_details(mut cx: FunctionContext) -> JsResult<JsObject> {
+    let connection = connection_argument(&mut cx, "connection_details", false)?;
+
+    details_object(&mut cx, &connection)
 }
 
-fn close_connection(mut cx: FunctionContext) -> JsResult<JsPromise> {
-    let connection = (**cx.argument::<JsBox<Connection>>(0)?).clone();
-    let code = cx.argument::<JsNumber>(1)?.value(&mut cx);
-    let reason = {
-        let arg = cx.argument::<JsValue>(2)?;
+// Everything we know about a connection in a single JSON serializable object, meant to be
+//  attached to bug reports. Never contains key material.
+fn debug_dump(mut cx: FunctionContext) -> JsResult<JsObject> {
+    let connection = connection_argument(&mut cx, "debug_dump", false)?;
 
-        if arg.is_a::<JsUint8Array, _>(&mut cx) {
-            use neon::types::buffer::TypedArray;
+    let result = cx.empty_object();
 
-            let arr: Handle<JsUint8Array> = arg.downcast_or_throw(&mut cx)?;
+    let details = details_object(&mut cx, &connection)?;
+    let state = cx.string(connection.state());
+    let close_reason: Handle<JsValue> = match connection.close_reason() {
+        Some(reason) => reason.to_object(&mut cx)?.upcast(),
+        None => cx.null().upcast(),
+    };
+    let stats = stats::connection_stats(&mut cx, &connection.connection.stats())?;
+
+    let timings = cx.empty_object();
+    let handshake_ms = cx.number(connection.timings.handshake.as_secs_f64() * 1000.0);
+    let total_ms = cx.number(connection.timings.total.as_secs_f64() * 1000.0);
+    timings.set(&mut cx, "handshakeMs", handshake_ms)?;
+    timings.set(&mut cx, "totalMs", total_ms)?;
+
+    let sampler = cx.empty_object();
+    let loss_rate: Handle<JsValue> = match connection.sampler.loss_rate(None) {
+        Some(rate) => cx.number(rate).upcast(),
+        None => cx.null().upcast(),
+    };
+    let throughput = connection.sampler.throughput();
+    let send_bps = cx.number(throughput.send);
+    let recv_bps = cx.number(throughput.recv);
+    sampler.set(&mut cx, "lossRate", loss_rate)?;
+    sampler.set(&mut cx, "sendBps", send_bps)?;
+    sampler.set(&mut cx, "recvBps", recv_bps)?;
+
+    let streams = connection.streams.snapshot(&mut cx)?;
+    let queues = connection.streams.queues(&mut cx)?;
+    let events = connection.streams.events(&mut cx)?;
+
+    result.set(&mut cx, "details", details)?;
+    result.set(&mut cx, "state", state)?;
+    result.set(&mut cx, "closeReason", close_reason)?;
+    result.set(&mut cx, "handshakeTimings", timings)?;
+    result.set(&mut cx, "stats", stats)?;
+    result.set(&mut cx, "sampler", sampler)?;
+    result.set(&mut cx, "streams", streams)?;
+    result.set(&mut cx, "queues", queues)?;
+    result.set(&mut cx, "events", events)?;
 
-            arr.as_slice(&cx).to_vec()
-        } else {
-            Vec::new()
+    Ok(result)
+}
+
+// Moves the endpoint to a newly bound UDP socket, after which connections migrate to the new path.
+//  Without a `localAddress` the unspecified address of the current socket's family is used
+fn rebind(mut cx: FunctionContext) -> JsResult<JsPromise> {
+    let connection = cx
+        .argument_opt(0)
+        .and_then(|value| value.downcast::<JsBox<Connection>, _>(&mut cx).ok());
+
+    // A plain socket would send past the proxy, and the association is bound to the current one
+    if let Some(connection) = &connection {
+        connection
+            .check("rebind", true)
+            .or_else(|err| err.throw(&mut cx))?;
+
+        if connection.proxied {
+            return NativeError::new(
+                ErrorCode::InvalidState,
+                "rebind: a connection made through a proxy can't be rebound",
+            )
+            .context(&ErrorContext::new("rebind").connection(&connection.tag))
+            .throw(&mut cx);
         }
+    }
+    // The new socket gets the same options, e.g. stays on the same interface
+    let socket_options = connection
+        .and_then(|connection| connection.socket_options.clone())
+        .unwrap_or_default();
+
+    let endpoint = endpoint_argument(&mut cx, "rebind")?;
+    let current = endpoint.local_addr().or_else(|err| {
+        NativeError::new(ErrorCode::Io, err.to_string())
+            .context(&ErrorContext::new("rebind"))
+            .throw(&mut cx)
+    })?;
+
+    let (address, port) =
+        match args::optional_argument::<JsObject>(&mut cx, "rebind", 1, "options")? {
+            None => (None, 0),
+            Some(options) => {
+                let address = args::optional_property::<_, JsString>(
+                    &mut cx,
+                    "rebind",
+                    options,
+                    "options",
+                    "localAddress",
+                )?
+                .map(|address| address.value(&mut cx));
+                let port = match args::optional_property::<_, JsNumber>(
+                    &mut cx,
+                    "rebind",
+                    options,
+                    "options",
+                    "localPort",
+                )? {
+                    None => 0,
+                    Some(port) => {
+                        let port = port.value(&mut cx);
+                        port_number(&mut cx, "rebind", "options.localPort", port)?
+                    }
+                };
+
+                (address, port)
+            }
+        };
+
+    let addr = match address {
+        None if current.is_ipv6() => SocketAddr::new(IpAddr::V6(Ipv6Addr::UNSPECIFIED), port),
+        None => SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), port),
+        Some(address) => parse_ip(&mut cx, "rebind", "options.localAddress", &address, port)?,
     };
 
     let rt = runtime(&mut cx)?;
@@ -590,44 +4675,217 @@ fn close_connection(mut cx: FunctionContext) -> JsResult<JsPromise> {
     let (deferred, promise) = cx.promise();
 
     rt.spawn(async move {
-        connection.connection.close((code as u32).into(), &reason);
-
-        deferred.settle_with(&channel, move |mut cx| Ok(cx.undefined()));
+        // The old socket is only replaced once the new one is bound
+        let result = quic::bind_socket(addr, &socket_options)
+            .and_then(|(socket, _)| quic::wrap_socket(socket, &socket_options))
+            .and_then(|socket| endpoint.rebind_abstract(socket));
+
+        let _ = deferred.try_settle_with(&channel, move |mut cx| {
+            result.or_else(|err| {
+                NativeError::new(ErrorCode::Io, err.to_string())
+                    .context(&ErrorContext::new("rebind"))
+                    .with("localAddress", Detail::String(addr.to_string()))
+                    .throw(&mut cx)
+            })?;
+
+            local_address_object(&mut cx, &endpoint, socket_options.interface.as_deref())
+        });
     });
 
     Ok(promise)
 }
 
-fn stream_details(mut cx: FunctionContext) -> JsResult<JsObject> {
-    let stream = (**cx.argument::<JsBox<Stream>>(0)?).clone();
+fn local_address_object<'a, C: Context<'a>>(
+    cx: &mut C,
+    endpoint: &quinn::Endpoint,
+    interface: Option<&str>,
+) -> JsResult<'a, JsObject> {
+    let addr = endpoint.local_addr().or_else(|err| {
+        NativeError::new(ErrorCode::Io, err.to_string())
+            .context(&ErrorContext::new("local_address"))
+            .throw(cx)
+    })?;
 
     let result = cx.empty_object();
-    let id = JsBigInt::from_u64(&mut cx, stream.details.id.index());
-    let is_0rtt = cx.boolean(stream.details.is_0rtt);
+    let address = cx.string(addr.ip().to_string());
+    let port = cx.number(addr.port());
 
-    result.set(&mut cx, "id", id)?;
-    result.set(&mut cx, "is0rtt", is_0rtt)?;
+    result.set(cx, "address", address)?;
+    result.set(cx, "port", port)?;
+
+    if let Some(interface) = interface {
+        let interface = cx.string(interface);
+        result.set(cx, "interface", interface)?;
+    }
 
     Ok(result)
 }
 
+// Address of the UDP socket of an endpoint or a connection's endpoint
+fn local_address(mut cx: FunctionContext) -> JsResult<JsObject> {
+    let endpoint = endpoint_argument(&mut cx, "local_address")?;
+    let interface = cx
+        .argument_opt(0)
+        .and_then(|value| value.downcast::<JsBox<Connection>, _>(&mut cx).ok())
+        .and_then(|connection| connection.socket_options.clone())
+        .and_then(|socket_options| socket_options.interface);
+
+    local_address_object(&mut cx, &endpoint, interface.as_deref())
+}
+
 fn get_remote(mut cx: FunctionContext) -> JsResult<JsString> {
-    let connection = (**cx.argument::<JsBox<Connection>>(0)?).clone();
+    let connection = connection_argument(&mut cx, "get_remote", false)?;
 
     Ok(cx.string(connection.connection.remote_address().to_string()))
 }
 
+fn logging_error(error: &logging::LoggingError) -> NativeError {
+    match error {
+        logging::LoggingError::InvalidFilter(_) => NativeError::type_error(error.to_string()),
+        _ => NativeError::new(ErrorCode::InvalidState, error.to_string()),
+    }
+}
+
+fn init_logging(mut cx: FunctionContext) -> JsResult<JsUndefined> {
+    let filter = args::argument::<JsString>(&mut cx, "init_logging", 0, "filter")?.value(&mut cx);
+
+    logging::init(&filter).or_else(|err| logging_error(&err).throw(&mut cx))?;
+
+    Ok(cx.undefined())
+}
+
+fn set_log_level(mut cx: FunctionContext) -> JsResult<JsUndefined> {
+    let filter = args::argument::<JsString>(&mut cx, "set_log_level", 0, "filter")?.value(&mut cx);
+
+    logging::set_level(&filter).or_else(|err| logging_error(&err).throw(&mut cx))?;
+
+    Ok(cx.undefined())
+}
+
+// Non-negative integer option of `init`, `minimum` is the smallest value accepted
+fn init_size(
+    cx: &mut FunctionContext,
+    options: Handle<JsObject>,
+    key: &str,
+    minimum: usize,
+) -> NeonResult<Option<usize>> {
+    let Some(value) = args::optional_property::<_, JsNumber>(cx, "init", options, "options", key)?
+    else {
+        return Ok(None);
+    };
+    let value = value.value(cx);
+
+    if value.fract() != 0.0 || !(minimum as f64..=u32::MAX as f64).contains(&value) {
+        return NativeError::range_error(format!(
+            "init: expected options.{key} to be an integer of at least {minimum}, got {value}"
+        ))
+        .throw(cx);
+    }
+
+    Ok(Some(value as usize))
+}
+
+// How the addon was built, so JavaScript can check it loaded the build it expects
+fn build_info(mut cx: FunctionContext) -> JsResult<JsObject> {
+    let result = cx.empty_object();
+
+    let version = cx.string(env!("CARGO_PKG_VERSION"));
+    let crypto_provider = cx.string(quic::CRYPTO_PROVIDER);
+    let fips = cx.boolean(quic::FIPS);
+
+    result.set(&mut cx, "version", version)?;
+    result.set(&mut cx, "cryptoProvider", crypto_provider)?;
+    result.set(&mut cx, "fips", fips)?;
+
+    Ok(result)
+}
+
+fn init(mut cx: FunctionContext) -> JsResult<JsUndefined> {
+    let config = match args::optional_argument::<JsObject>(&mut cx, "init", 0, "options")? {
+        None => runtime::RuntimeConfig::default(),
+        Some(options) => runtime::RuntimeConfig {
+            worker_threads: init_size(&mut cx, options, "workerThreads", 0)?,
+            thread_name: args::optional_property::<_, JsString>(
+                &mut cx,
+                "init",
+                options,
+                "options",
+                "threadName",
+            )?
+            .map(|value| value.value(&mut cx)),
+            thread_stack_size: init_size(&mut cx, options, "threadStackSize", 1)?,
+        },
+    };
+
+    runtime::configure(config).or_else(|err| runtime_error(&err).throw(&mut cx))?;
+
+    Ok(cx.undefined())
+}
+
 #[neon::main]
 fn main(mut cx: ModuleContext) -> NeonResult<()> {
+    let connection_error_kinds = ConnectionErrorKind::constants(&mut cx)?;
+    cx.export_value("ConnectionErrorKind", connection_error_kinds)?;
+
+    let error_codes = ErrorCode::constants(&mut cx)?;
+    cx.export_value("errorCodes", error_codes)?;
+
+    cx.export_function("build_info", build_info)?;
+    cx.export_function("init", init)?;
+    cx.export_function("create_endpoint", create_endpoint)?;
+    cx.export_function("create_cancel_token", create_cancel_token)?;
+    cx.export_function("cancel_token", cancel_token)?;
+    cx.export_function("create_connect_controller", create_connect_controller)?;
+    cx.export_function("cancel_connect", cancel_connect)?;
     cx.export_function("connect", connect)?;
+    persistent::export(&mut cx)?;
+    pool::export(&mut cx)?;
     cx.export_function("create_stream", create_stream)?;
     cx.export_function("initialize_stream", initialize_stream)?;
+    ring::export(&mut cx)?;
+    cx.export_function("discard_stream", discard_stream)?;
     cx.export_function("write_stream", write_stream)?;
+    cx.export_function("write_stream_nowait", write_stream_nowait)?;
     cx.export_function("close_write", close_write)?;
     cx.export_function("close_stream", close_stream)?;
+    cx.export_function("reset_stream", reset_stream)?;
+    cx.export_function("destroy_stream", destroy_stream)?;
+    cx.export_function("stop_read", stop_read)?;
     cx.export_function("stream_details", stream_details)?;
     cx.export_function("get_remote", get_remote)?;
+    cx.export_function("rebind", rebind)?;
+    cx.export_function("local_address", local_address)?;
+    cx.export_function("connection_id", connection_id)?;
+    cx.export_function("negotiated_version", negotiated_version)?;
+    cx.export_function("handshake_timings", handshake_timings)?;
+    cx.export_function("await_close", await_close)?;
+    cx.export_function("close_reason", close_reason)?;
+    cx.export_function("connection_state", connection_state)?;
+    cx.export_function("open_streams", open_streams)?;
+    cx.export_function("set_connection_callbacks", set_connection_callbacks)?;
+    cx.export_function("peer_parameters", peer_parameters)?;
+    cx.export_function("loss_rate", loss_rate)?;
+    cx.export_function("throughput", throughput)?;
+    cx.export_function("rtt_histogram", rtt_histogram)?;
+    cx.export_function("init_logging", init_logging)?;
+    cx.export_function("set_log_level", set_log_level)?;
+    cx.export_function("connection_details", connection_details)?;
+    cx.export_function("debug_dump", debug_dump)?;
     cx.export_function("close_connection", close_connection)?;
+    cx.export_function("close_connection_graceful", close_connection_graceful)?;
+    cx.export_function("unref_connection", unref_connection)?;
+    cx.export_function("ref_connection", ref_connection)?;
+    cx.export_function("set_stream_limits", set_stream_limits)?;
+    cx.export_function("set_receive_window", set_receive_window)?;
+    cx.export_function("close_endpoint", close_endpoint)?;
+    cx.export_function("wait_endpoint_idle", wait_endpoint_idle)?;
+    cx.export_function("shutdown", shutdown)?;
+    cx.export_function("flush_all", flush_all)?;
+
+    #[cfg(feature = "runtime-metrics")]
+    runtime_metrics::export(&mut cx)?;
+    #[cfg(feature = "test-server")]
+    test_server::export(&mut cx)?;
 
     Ok(())
 }
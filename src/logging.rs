@@ -0,0 +1,60 @@
+use std::fmt;
+
+use once_cell::sync::OnceCell;
+use tracing_subscriber::{
+    layer::SubscriberExt, reload, util::SubscriberInitExt, EnvFilter, Registry,
+};
+
+static FILTER_HANDLE: OnceCell<reload::Handle<EnvFilter, Registry>> = OnceCell::new();
+
+#[derive(Debug)]
+pub enum LoggingError {
+    InvalidFilter(tracing_subscriber::filter::ParseError),
+    AlreadyInitialized,
+    NotInitialized,
+    Reload(reload::Error),
+}
+
+impl fmt::Display for LoggingError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LoggingError::InvalidFilter(e) => write!(f, "Invalid log filter: {e}"),
+            LoggingError::AlreadyInitialized => f.write_str("Logging is already initialized"),
+            LoggingError::NotInitialized => {
+                f.write_str("Logging was not initialized, call init_logging first")
+            }
+            LoggingError::Reload(e) => write!(f, "Unable to change the log filter: {e}"),
+        }
+    }
+}
+
+// Accepts the same syntax as `RUST_LOG`, e.g. "info" or "warn,quinn=trace"
+fn parse_filter(filter: &str) -> Result<EnvFilter, LoggingError> {
+    EnvFilter::try_new(filter).map_err(LoggingError::InvalidFilter)
+}
+
+/// Install a global subscriber writing to stderr. The filter can be changed afterwards with
+///  `set_level`.
+pub fn init(filter: &str) -> Result<(), LoggingError> {
+    let (filter, handle) = reload::Layer::new(parse_filter(filter)?);
+
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(tracing_subscriber::fmt::layer().with_writer(std::io::stderr))
+        .try_init()
+        .map_err(|_| LoggingError::AlreadyInitialized)?;
+
+    FILTER_HANDLE
+        .set(handle)
+        .map_err(|_| LoggingError::AlreadyInitialized)
+}
+
+/// Replace the filter of the installed subscriber. Applies to all subsequent events, including
+///  those of existing connections.
+pub fn set_level(filter: &str) -> Result<(), LoggingError> {
+    let handle = FILTER_HANDLE.get().ok_or(LoggingError::NotInitialized)?;
+
+    handle
+        .reload(parse_filter(filter)?)
+        .map_err(LoggingError::Reload)
+}
@@ -0,0 +1,465 @@
+use std::{net::SocketAddr, sync::Arc, time::Duration};
+
+use neon::prelude::*;
+use tokio::sync::oneshot;
+use tracing::Instrument;
+
+use crate::{
+    args,
+    close_reason::{CloseReason, CloseWatch},
+    connection_handle,
+    errors::{ConnectionErrorKind, ErrorContext, NativeError},
+    instance, next_connection_id, optional_duration, optional_number, quic, runtime,
+    tag::ConnectionTag,
+    unreferenced_channel, CancelToken, ConnectArguments, ConnectOptions, ConnectionCallbacks,
+    ConnectionSetup, KeepAlive, OnClose,
+};
+
+// The `reconnect` argument of `connect_persistent`
+struct ReconnectOptions {
+    // Before the first attempt after a close, doubled after every one that failed up to `max_delay`
+    initial_delay: Duration,
+    max_delay: Duration,
+    // Attempts after a single close before giving up, unlimited if `None`
+    max_attempts: Option<u64>,
+    on_reconnecting: Option<Arc<Root<JsFunction>>>,
+    on_reconnected: Option<Arc<Root<JsFunction>>>,
+}
+
+// Defaults of `reconnect.initialDelayMs` and `reconnect.maxDelayMs`
+const RECONNECT_INITIAL_DELAY: Duration = Duration::from_millis(100);
+const RECONNECT_MAX_DELAY: Duration = Duration::from_secs(10);
+
+impl ReconnectOptions {
+    fn from_object<'a, C: Context<'a>>(cx: &mut C, options: Handle<JsObject>) -> NeonResult<Self> {
+        let initial_delay = optional_duration(
+            cx,
+            "connect_persistent",
+            options,
+            "reconnect",
+            "initialDelayMs",
+        )?
+        .unwrap_or(RECONNECT_INITIAL_DELAY);
+        let max_delay =
+            optional_duration(cx, "connect_persistent", options, "reconnect", "maxDelayMs")?
+                .unwrap_or(RECONNECT_MAX_DELAY);
+
+        let max_attempts = match optional_number(cx, options, "reconnect", "maxAttempts")? {
+            None => None,
+            Some(attempts) if attempts == f64::INFINITY => None,
+            Some(attempts) if attempts.fract() == 0.0 && attempts >= 0.0 => Some(attempts as u64),
+            Some(attempts) => {
+                return NativeError::range_error(format!(
+                    "connect_persistent: expected reconnect.maxAttempts to be a non-negative integer, got {attempts}"
+                ))
+                .throw(cx)
+            }
+        };
+
+        let mut callback = |name| -> NeonResult<_> {
+            Ok(args::optional_property::<_, JsFunction>(
+                cx,
+                "connect_persistent",
+                options,
+                "reconnect",
+                name,
+            )?
+            .map(|callback| Arc::new(callback.root(cx))))
+        };
+        let on_reconnecting = callback("onReconnecting")?;
+        let on_reconnected = callback("onReconnected")?;
+
+        Ok(Self {
+            initial_delay,
+            max_delay,
+            max_attempts,
+            on_reconnecting,
+            on_reconnected,
+        })
+    }
+}
+
+// Handle of `connect_persistent`, `close_persistent` stops it from reconnecting
+#[derive(Clone, Default)]
+struct PersistentConnection {
+    stopped: tokio_util::sync::CancellationToken,
+}
+
+impl Finalize for PersistentConnection {}
+
+impl args::Expected for JsBox<PersistentConnection> {
+    const DESCRIPTION: &'static str = "a persistent connection handle";
+}
+
+// Endpoint every connection of `connect_persistent` is made on. Closed once it stops reconnecting,
+//  unless it was passed as `options.endpoint`
+struct PersistentEndpoint(quic::ClientEndpointInfo);
+
+impl Drop for PersistentEndpoint {
+    fn drop(&mut self) {
+        if self.0.owned {
+            self.0.endpoint.close(0u32.into(), b"");
+        }
+    }
+}
+
+// Closes after which `connect_persistent` doesn't reconnect: those by the application on either
+//  side, and those by `autoCloseIdleMs`
+fn reconnects(reason: &CloseReason) -> bool {
+    !matches!(
+        reason.kind(),
+        ConnectionErrorKind::ApplicationClosed
+            | ConnectionErrorKind::LocallyClosed
+            | ConnectionErrorKind::IdleAutoClose
+    )
+}
+
+// Everything the connections of `connect_persistent` are made with
+struct Persistent {
+    addr: SocketAddr,
+    hostname: String,
+    alpn_protocols: Option<Vec<Vec<u8>>>,
+    certificate_authorities: Option<Vec<Vec<u8>>>,
+    client_auth: Option<(Vec<u8>, Vec<u8>)>,
+    callbacks: ConnectionCallbacks,
+    options: ConnectOptions,
+    reconnect: ReconnectOptions,
+    rt: tokio::runtime::Handle,
+    instance: instance::InstanceHandle,
+    // `on_reconnecting`, `on_reconnected` and the final `on_close` are called on this channel, the
+    //  events of each connection on its own
+    events: Channel,
+    keep_alive: KeepAlive,
+    stopped: tokio_util::sync::CancellationToken,
+}
+
+impl Persistent {
+    fn tag(&self) -> ConnectionTag {
+        ConnectionTag {
+            id: next_connection_id(),
+            label: self.options.label.clone(),
+        }
+    }
+
+    fn setup(&self, tag: ConnectionTag, events: Channel) -> ConnectionSetup {
+        ConnectionSetup {
+            rt: self.rt.clone(),
+            events,
+            span: tag.span(),
+            tag,
+            instance: self.instance.clone(),
+            callbacks: self.callbacks.clone(),
+            options: self.options.clone(),
+            controller: None,
+            on_closed: OnClose::HandOver(self.keep_alive.clone()),
+        }
+    }
+
+    async fn handshake(
+        &self,
+        endpoint: &PersistentEndpoint,
+    ) -> Result<quic::Client, quic::ClientError> {
+        let mut client = quic::get_client(
+            self.addr,
+            &self.hostname,
+            self.alpn_protocols.clone(),
+            self.certificate_authorities.clone(),
+            self.client_auth.clone(),
+            quic::ClientEndpoint::Shared(endpoint.0.endpoint.clone()),
+            &self.options.transport,
+            self.options.connect_timeout,
+        )
+        .await?;
+        // Reported as bound for the connection, even though it's shared with those before and after
+        client.socket = endpoint.0.socket;
+
+        Ok(client)
+    }
+
+    // Keeps connecting again until `reason` is no longer a reason to. Returns why the last
+    //  connection closed, or `None` once the context is gone
+    async fn run(
+        self: Arc<Self>,
+        endpoint: &PersistentEndpoint,
+        mut close_watch: CloseWatch,
+    ) -> Option<CloseReason> {
+        loop {
+            let reason = tokio::select! {
+                reason = close_watch.wait_closed() => reason,
+                _ = self.instance.torn_down() => return None,
+            };
+
+            if self.stopped.is_cancelled() || !reconnects(&reason) {
+                return Some(reason);
+            }
+
+            tracing::debug!(reason = %reason.error, "reconnecting");
+            match self.clone().reconnect(endpoint, &reason).await {
+                Some(reconnected) => close_watch = reconnected,
+                None => return Some(reason),
+            }
+        }
+    }
+
+    // Connects again after the last connection closed because of `reason`. `None` if it gave up,
+    //  was stopped, or the context is gone
+    async fn reconnect(
+        self: Arc<Self>,
+        endpoint: &PersistentEndpoint,
+        reason: &CloseReason,
+    ) -> Option<CloseWatch> {
+        let mut delay = self.reconnect.initial_delay;
+
+        for attempt in 1.. {
+            if self.reconnect.max_attempts.is_some_and(|max| attempt > max) {
+                break;
+            }
+
+            self.reconnecting(attempt, reason);
+
+            let tag = self.tag();
+            let connected = tokio::select! {
+                _ = self.stopped.cancelled() => break,
+                connected = async {
+                    tokio::time::sleep(delay).await;
+                    self.handshake(endpoint).await
+                }.instrument(tag.span()) => connected,
+            };
+
+            match connected {
+                Ok(client) => return self.reconnected(tag, client).await,
+                Err(err) => {
+                    tracing::debug!(attempt, error = %err.to_string(), "unable to reconnect");
+                    delay = delay.saturating_mul(2).min(self.reconnect.max_delay);
+                }
+            }
+        }
+
+        None
+    }
+
+    fn reconnecting(&self, attempt: u64, reason: &CloseReason) {
+        let Some(callback) = self.reconnect.on_reconnecting.clone() else {
+            return;
+        };
+        let reason = reason.clone();
+
+        let _ = self.events.try_send(move |mut cx| {
+            let callback = callback.to_inner(&mut cx);
+            let this = cx.undefined();
+
+            let details = reason.to_object(&mut cx)?;
+            let args = vec![cx.number(attempt as f64).upcast(), details.upcast()];
+
+            callback.call(&mut cx, this, args)?;
+
+            Ok(())
+        });
+    }
+
+    // Hands the new connection to `on_reconnected`
+    async fn reconnected(
+        self: Arc<Self>,
+        tag: ConnectionTag,
+        client: quic::Client,
+    ) -> Option<CloseWatch> {
+        let (sender, close_watch) = oneshot::channel();
+
+        let persistent = self.clone();
+        let sent = self.events.try_send(move |mut cx| {
+            let context = ErrorContext::new("reconnect").connection(&tag);
+            let events = unreferenced_channel(&mut cx);
+            let connection = connection_handle(&mut cx, client, persistent.setup(tag, events))
+                .or_else(|err| err.context(&context).throw(&mut cx))?;
+            let _ = sender.send(connection.close_watch.clone());
+
+            // The connection keeps the event loop open from here on
+            persistent.keep_alive.set_referenced(&mut cx, false);
+
+            let connection = cx.boxed(connection);
+            if let Some(callback) = &persistent.reconnect.on_reconnected {
+                let callback = callback.to_inner(&mut cx);
+                let this = cx.undefined();
+
+                callback.call(&mut cx, this, [connection.upcast()])?;
+            }
+
+            Ok(())
+        });
+
+        match sent {
+            Ok(_) => close_watch.await.ok(),
+            Err(_) => None,
+        }
+    }
+
+    // Calls `on_close` with the details of the last connection's close, once the endpoint drained
+    //  if it was bound for the persistent connection
+    async fn finish(&self, endpoint: PersistentEndpoint, mut reason: CloseReason) {
+        if endpoint.0.owned {
+            endpoint.0.endpoint.close(0u32.into(), b"");
+            reason.drained =
+                tokio::time::timeout(self.options.drain_timeout, endpoint.0.endpoint.wait_idle())
+                    .await
+                    .is_ok();
+        }
+        drop(endpoint);
+
+        let callbacks = self.callbacks.clone();
+        let keep_alive = self.keep_alive.clone();
+        let sent = self.events.try_send(move |mut cx| {
+            keep_alive.release();
+
+            let callback = callbacks.get(&mut cx, |roots| &roots.on_close);
+            let this = cx.undefined();
+
+            let details = reason.to_object(&mut cx)?;
+            let args = vec![
+                cx.string(reason.error.to_string()).upcast(),
+                details.upcast(),
+            ];
+
+            callback.call(&mut cx, this, args)?;
+
+            Ok(())
+        });
+
+        if sent.is_err() {
+            self.keep_alive.release();
+        }
+    }
+}
+
+// Like `connect`, but the connection is made again whenever it closes for any reason other than
+//  an application close, by either side, or `autoCloseIdleMs`. Every connection is made on the
+//  same endpoint, bound once unless `options.endpoint` is given, so TLS sessions are resumed
+//  where the server allows it. Takes the same arguments as `connect` followed by `reconnect`,
+//  returns `{ promise, persistent }`. The promise resolves with the first connection, or rejects
+//  if that attempt fails. `options.cancelToken` only applies to it.
+// `reconnect.onReconnecting` is called with the attempt and the close details before every
+//  attempt, `reconnect.onReconnected` with every connection after the first. Streams don't
+//  survive a reconnect, `on_close` is only called once it gives up or stops
+fn connect_persistent(mut cx: FunctionContext) -> JsResult<JsObject> {
+    let ConnectArguments {
+        addr,
+        hostname,
+        alpn_protocols,
+        certificate_authorities,
+        client_auth,
+        callbacks,
+        options,
+    } = ConnectArguments::from_arguments(&mut cx, "connect_persistent", 0)?;
+    let reconnect = {
+        let reconnect =
+            args::optional_argument::<JsObject>(&mut cx, "connect_persistent", 10, "reconnect")?
+                .unwrap_or_else(|| cx.empty_object());
+
+        ReconnectOptions::from_object(&mut cx, reconnect)?
+    };
+
+    let rt = runtime(&mut cx)?;
+    let instance = instance::current(&mut cx);
+
+    let events = unreferenced_channel(&mut cx);
+    let keep_alive = KeepAlive::new(&mut cx, &events, false);
+    let first_events = unreferenced_channel(&mut cx);
+
+    let channel = cx.channel();
+    let (deferred, promise) = cx.promise();
+
+    let handle = PersistentConnection::default();
+
+    let persistent = Arc::new(Persistent {
+        addr,
+        hostname,
+        alpn_protocols,
+        certificate_authorities,
+        client_auth,
+        callbacks,
+        options,
+        reconnect,
+        rt: rt.clone(),
+        instance,
+        events,
+        keep_alive,
+        stopped: handle.stopped.clone(),
+    });
+
+    let tag = persistent.tag();
+    let span = tag.span();
+
+    let task = async move {
+        let result = CancelToken::run(persistent.options.cancel_token.clone(), async {
+            let endpoint = PersistentEndpoint(
+                quic::client_endpoint(persistent.addr, persistent.options.endpoint.clone()).await?,
+            );
+            let client = persistent.handshake(&endpoint).await?;
+
+            Ok((endpoint, client))
+        })
+        .await
+        .and_then(|result| result.map_err(|err: quic::ClientError| NativeError::from(&err)));
+
+        match &result {
+            Ok(_) => tracing::debug!("connected"),
+            Err(err) => tracing::debug!(error = %err.message, "unable to connect"),
+        }
+
+        let (sender, connected) = oneshot::channel();
+        let _ = deferred.try_settle_with(&channel, {
+            let persistent = persistent.clone();
+
+            move |mut cx| {
+                let context = ErrorContext::new("connect_persistent").connection(&tag);
+                let (endpoint, client) =
+                    result.or_else(|err| err.context(&context).throw(&mut cx))?;
+
+                let setup = persistent.setup(tag, first_events);
+                let connection = connection_handle(&mut cx, client, setup)
+                    .or_else(|err| err.context(&context).throw(&mut cx))?;
+                let _ = sender.send((endpoint, connection.close_watch.clone()));
+
+                Ok(cx.boxed(connection))
+            }
+        });
+
+        let Ok((endpoint, close_watch)) = connected.await else {
+            return;
+        };
+
+        if let Some(reason) = persistent.clone().run(&endpoint, close_watch).await {
+            persistent.finish(endpoint, reason).await;
+        }
+    }
+    .instrument(span);
+    rt.spawn(task);
+
+    let handle = cx.boxed(handle);
+    let result = cx.empty_object();
+    result.set(&mut cx, "promise", promise)?;
+    result.set(&mut cx, "persistent", handle)?;
+
+    Ok(result)
+}
+
+// Stops a persistent connection from reconnecting, the current connection is left open. Returns
+//  `false` if it was already stopped
+fn close_persistent(mut cx: FunctionContext) -> JsResult<JsBoolean> {
+    let persistent = args::argument::<JsBox<PersistentConnection>>(
+        &mut cx,
+        "close_persistent",
+        0,
+        "persistent",
+    )?;
+    let stopped = !persistent.stopped.is_cancelled();
+    persistent.stopped.cancel();
+
+    Ok(cx.boolean(stopped))
+}
+
+pub fn export(cx: &mut ModuleContext) -> NeonResult<()> {
+    cx.export_function("connect_persistent", connect_persistent)?;
+    cx.export_function("close_persistent", close_persistent)?;
+
+    Ok(())
+}
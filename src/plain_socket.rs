@@ -0,0 +1,84 @@
+use std::{
+    fmt,
+    io::{self, IoSliceMut},
+    net::SocketAddr,
+    pin::Pin,
+    sync::Arc,
+    task::{ready, Context, Poll},
+};
+
+use quinn::{
+    udp::{RecvMeta, Transmit},
+    AsyncUdpSocket, UdpPoller,
+};
+use tokio::{io::ReadBuf, net::UdpSocket};
+
+/// Socket which sends and receives datagrams one at a time, without control messages. Unlike
+///  quinn's own socket it doesn't use segmentation offload or ECN, and it doesn't replace the
+///  traffic class set on the socket with the ECN codepoint of every datagram
+pub struct PlainSocket {
+    io: UdpSocket,
+}
+
+impl PlainSocket {
+    /// Has to be called from within the runtime
+    pub fn new(socket: std::net::UdpSocket) -> io::Result<Self> {
+        socket.set_nonblocking(true)?;
+
+        Ok(Self {
+            io: UdpSocket::from_std(socket)?,
+        })
+    }
+}
+
+impl fmt::Debug for PlainSocket {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PlainSocket")
+            .field("local_addr", &self.io.local_addr().ok())
+            .finish()
+    }
+}
+
+impl AsyncUdpSocket for PlainSocket {
+    fn create_io_poller(self: Arc<Self>) -> Pin<Box<dyn UdpPoller>> {
+        Box::pin(Poller(self))
+    }
+
+    fn try_send(&self, transmit: &Transmit) -> io::Result<()> {
+        self.io
+            .try_send_to(transmit.contents, transmit.destination)
+            .map(|_| ())
+    }
+
+    fn poll_recv(
+        &self,
+        cx: &mut Context,
+        bufs: &mut [IoSliceMut<'_>],
+        meta: &mut [RecvMeta],
+    ) -> Poll<io::Result<usize>> {
+        let mut buf = ReadBuf::new(&mut bufs[0]);
+        let from = ready!(self.io.poll_recv_from(cx, &mut buf))?;
+        let len = buf.filled().len();
+
+        meta[0].addr = from;
+        meta[0].len = len;
+        meta[0].stride = len;
+        meta[0].ecn = None;
+        meta[0].dst_ip = None;
+
+        Poll::Ready(Ok(1))
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.io.local_addr()
+    }
+}
+
+#[derive(Debug)]
+struct Poller(Arc<PlainSocket>);
+
+impl UdpPoller for Poller {
+    fn poll_writable(self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+        self.0.io.poll_send_ready(cx)
+    }
+}
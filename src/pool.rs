@@ -0,0 +1,520 @@
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use neon::{prelude::*, types::Deferred};
+use tracing::Instrument;
+
+use crate::{
+    args,
+    close_reason::CloseWatch,
+    connection_handle,
+    errors::{ErrorCode, ErrorContext, NativeError},
+    instance, next_connection_id, optional_duration, optional_number, quic, runtime, socks,
+    tag::ConnectionTag,
+    unreferenced_channel, ConnectArguments, ConnectOptions, Connection, ConnectionSetup, OnClose,
+    StreamBatch,
+};
+
+// What a pooled connection is shared for: the destination, the TLS configuration and the options
+//  the connection behaves differently with. The trusted authorities are part of it, so a connection
+//  which verified the server against one set is never handed to an acquire trusting another
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+struct PoolKey {
+    hostname: String,
+    port: u16,
+    alpn_protocols: Option<Vec<Vec<u8>>>,
+    certificate_authorities: Option<Vec<Vec<u8>>>,
+    // The certificate itself serves as its fingerprint, the key is never exposed
+    client_certificate: Option<Vec<u8>>,
+    options: OptionsKey,
+}
+
+impl PoolKey {
+    fn new(arguments: &ConnectArguments) -> Self {
+        Self {
+            hostname: arguments.hostname.clone(),
+            port: arguments.addr.port(),
+            alpn_protocols: arguments.alpn_protocols.clone(),
+            certificate_authorities: arguments.certificate_authorities.clone(),
+            client_certificate: arguments
+                .client_auth
+                .as_ref()
+                .map(|(certificate, _)| certificate.clone()),
+            options: OptionsKey::new(&arguments.options),
+        }
+    }
+}
+
+// The options of `connect` which change how a connection behaves. Those left out only change what
+//  JavaScript is told about it, or how the attempt itself goes, they're taken from the acquire
+//  which connects
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+struct OptionsKey {
+    endpoint: EndpointKey,
+    // Quinn's configs can't be compared, their debug output covers every setting though
+    transport: String,
+    auto_close_idle: Option<Duration>,
+    active_keep_alive: Option<Duration>,
+    stream_batch: Option<StreamBatch>,
+    max_buffered_bytes: Option<usize>,
+    write_chunk_size: usize,
+}
+
+impl OptionsKey {
+    fn new(options: &ConnectOptions) -> Self {
+        Self {
+            endpoint: EndpointKey::new(&options.endpoint),
+            transport: format!("{:?}", options.transport),
+            auto_close_idle: options.auto_close_idle,
+            active_keep_alive: options.active_keep_alive,
+            stream_batch: options.stream_batch,
+            max_buffered_bytes: options.max_buffered_bytes,
+            write_chunk_size: options.write_chunk_size,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+enum EndpointKey {
+    Bind(quic::SocketOptions),
+    // A shared endpoint by the address it's bound to, which no other endpoint has at the same time
+    Shared(Option<SocketAddr>),
+    Proxy(socks::Proxy),
+}
+
+impl EndpointKey {
+    fn new(endpoint: &quic::ClientEndpoint) -> Self {
+        match endpoint {
+            quic::ClientEndpoint::Bind(options) => EndpointKey::Bind(options.clone()),
+            quic::ClientEndpoint::Shared(endpoint) => {
+                EndpointKey::Shared(endpoint.local_addr().ok())
+            }
+            quic::ClientEndpoint::Proxy(proxy) => EndpointKey::Proxy(proxy.clone()),
+        }
+    }
+}
+
+struct PooledConnection {
+    // Every acquire resolves with the same handle, a handle of its own would close the connection
+    //  when it is garbage collected
+    handle: Root<JsBox<Connection>>,
+    id: u64,
+    close_watch: CloseWatch,
+    connection: Arc<quinn::Connection>,
+    users: usize,
+    // When the last user released it, `None` while it's acquired
+    idle_since: Option<Instant>,
+}
+
+#[derive(Default)]
+struct PoolState {
+    connections: HashMap<PoolKey, PooledConnection>,
+    // Acquires waiting on a connection attempt, which the first of them started
+    pending: HashMap<PoolKey, Vec<Deferred>>,
+    hits: u64,
+    misses: u64,
+}
+
+// Pool created by `create_pool`, see `pool_acquire`. The state is only changed on the JavaScript
+//  thread, apart from evicting idle connections and removing closed ones
+#[derive(Clone)]
+struct Pool {
+    state: Arc<std::sync::Mutex<PoolState>>,
+    max_size: Option<usize>,
+    idle_evict: Duration,
+}
+
+impl Finalize for Pool {}
+
+impl args::Expected for JsBox<Pool> {
+    const DESCRIPTION: &'static str = "a connection pool";
+}
+
+// Default of `idleEvictMs`
+const POOL_IDLE_EVICT: Duration = Duration::from_secs(30);
+
+impl Pool {
+    // Evicts the connection which has been idle the longest when the pool is full. Returns `false`
+    //  if it's full and every connection is acquired
+    fn make_room(&self, state: &mut PoolState) -> bool {
+        let Some(max_size) = self.max_size else {
+            return true;
+        };
+        if state.connections.len() + state.pending.len() < max_size {
+            return true;
+        }
+
+        let idle = state
+            .connections
+            .iter()
+            .filter_map(|(key, pooled)| pooled.idle_since.map(|since| (since, key)))
+            .min_by_key(|(since, _)| *since)
+            .map(|(_, key)| key.clone());
+
+        match idle.and_then(|key| state.connections.remove(&key)) {
+            Some(evicted) => {
+                evicted.connection.close(0u32.into(), b"");
+                true
+            }
+            None => false,
+        }
+    }
+
+    // Closes the connection once it was idle for `idle_evict`, unless it's acquired again before
+    fn evict_after(&self, rt: &tokio::runtime::Handle, key: PoolKey, released: Instant) {
+        let pool = self.clone();
+
+        rt.spawn(async move {
+            tokio::time::sleep(pool.idle_evict).await;
+
+            let mut state = pool.state.lock().unwrap();
+            if state
+                .connections
+                .get(&key)
+                .is_some_and(|pooled| pooled.idle_since == Some(released))
+            {
+                if let Some(evicted) = state.connections.remove(&key) {
+                    evicted.connection.close(0u32.into(), b"");
+                }
+            }
+        });
+    }
+
+    // Removes the connection once it closed, unless it was replaced already
+    fn remove_when_closed(
+        &self,
+        rt: &tokio::runtime::Handle,
+        key: PoolKey,
+        pooled: &PooledConnection,
+    ) {
+        let pool = self.clone();
+        let id = pooled.id;
+        let close_watch = pooled.close_watch.clone();
+
+        rt.spawn(async move {
+            close_watch.wait_closed().await;
+
+            let mut state = pool.state.lock().unwrap();
+            if state
+                .connections
+                .get(&key)
+                .is_some_and(|pooled| pooled.id == id)
+            {
+                state.connections.remove(&key);
+            }
+        });
+    }
+}
+
+fn create_pool(mut cx: FunctionContext) -> JsResult<JsBox<Pool>> {
+    let options = args::optional_argument::<JsObject>(&mut cx, "create_pool", 0, "options")?
+        .unwrap_or_else(|| cx.empty_object());
+
+    let max_size = match optional_number(&mut cx, options, "options", "maxSize")? {
+        None => None,
+        Some(size) if size == f64::INFINITY => None,
+        Some(size) if size.fract() == 0.0 && size >= 1.0 => Some(size as usize),
+        Some(size) => {
+            return NativeError::range_error(format!(
+                "create_pool: expected options.maxSize to be a positive integer, got {size}"
+            ))
+            .throw(&mut cx)
+        }
+    };
+    let idle_evict = optional_duration(&mut cx, "create_pool", options, "options", "idleEvictMs")?
+        .unwrap_or(POOL_IDLE_EVICT);
+
+    Ok(cx.boxed(Pool {
+        state: Default::default(),
+        max_size,
+        idle_evict,
+    }))
+}
+
+// Resolves with the pooled connection for the destination, TLS configuration and options of the
+//  arguments, connecting if there is none. Takes the pool followed by the arguments of `connect`, of which the
+//  callbacks are only used by the acquire that connects. Concurrent acquires of the same key wait
+//  for the same attempt, which is why `options.cancelToken` is ignored. Every acquire has to be
+//  paired with a `pool_release`
+fn pool_acquire(mut cx: FunctionContext) -> JsResult<JsPromise> {
+    let pool = (**args::argument::<JsBox<Pool>>(&mut cx, "pool_acquire", 0, "pool")?).clone();
+    let arguments = ConnectArguments::from_arguments(&mut cx, "pool_acquire", 1)?;
+    let key = PoolKey::new(&arguments);
+
+    let rt = runtime(&mut cx)?;
+
+    {
+        let mut state = pool.state.lock().unwrap();
+
+        // Closed connections stay in the pool until their close task ran, they're skipped here
+        let pooled = state
+            .connections
+            .get_mut(&key)
+            .filter(|pooled| pooled.connection.close_reason().is_none());
+        if let Some(pooled) = pooled {
+            pooled.users += 1;
+            pooled.idle_since = None;
+            let connection = pooled.handle.to_inner(&mut cx);
+            state.hits += 1;
+
+            let (deferred, promise) = cx.promise();
+            deferred.resolve(&mut cx, connection);
+
+            return Ok(promise);
+        }
+
+        if state.pending.contains_key(&key) {
+            let (deferred, promise) = cx.promise();
+            state.pending.entry(key).or_default().push(deferred);
+            state.hits += 1;
+
+            return Ok(promise);
+        }
+
+        state.connections.remove(&key);
+        state.misses += 1;
+
+        if !pool.make_room(&mut state) {
+            let error = NativeError::new(
+                ErrorCode::PoolFull,
+                format!(
+                    "The pool is full, all {} connections are in use",
+                    state.connections.len() + state.pending.len()
+                ),
+            )
+            .context(&ErrorContext::new("pool_acquire"))
+            .to_callback_value(&mut cx);
+
+            let (deferred, promise) = cx.promise();
+            deferred.reject(&mut cx, error);
+
+            return Ok(promise);
+        }
+    }
+
+    let ConnectArguments {
+        addr,
+        hostname,
+        alpn_protocols,
+        certificate_authorities,
+        client_auth,
+        callbacks,
+        options,
+    } = arguments;
+
+    let events = unreferenced_channel(&mut cx);
+
+    let tag = ConnectionTag {
+        id: next_connection_id(),
+        label: options.label.clone(),
+    };
+    let span = tag.span();
+    let connect_span = span.clone();
+
+    let instance = instance::current(&mut cx);
+
+    let channel = cx.channel();
+    let (deferred, promise) = cx.promise();
+    pool.state
+        .lock()
+        .unwrap()
+        .pending
+        .insert(key.clone(), vec![deferred]);
+
+    let spawner = rt.clone();
+    let task = async move {
+        let result = quic::get_client(
+            addr,
+            &hostname,
+            alpn_protocols,
+            certificate_authorities,
+            client_auth,
+            options.endpoint.clone(),
+            &options.transport,
+            options.connect_timeout,
+        )
+        .await;
+
+        match &result {
+            Ok(_) => tracing::debug!("connected"),
+            Err(err) => tracing::debug!(error = %err.to_string(), "unable to connect"),
+        }
+
+        let _ = channel.try_send(move |mut cx| {
+            let waiting = pool
+                .state
+                .lock()
+                .unwrap()
+                .pending
+                .remove(&key)
+                .unwrap_or_default();
+            let context = ErrorContext::new("pool_acquire").connection(&tag);
+
+            let setup = ConnectionSetup {
+                rt: rt.clone(),
+                events,
+                tag,
+                span,
+                instance,
+                callbacks,
+                options,
+                controller: None,
+                on_closed: OnClose::Report,
+            };
+            let connection = match result
+                .map_err(|err| NativeError::from(&err))
+                .and_then(|client| connection_handle(&mut cx, client, setup))
+            {
+                Ok(connection) => connection,
+                Err(err) => {
+                    let error = err.context(&context).to_callback_value(&mut cx);
+                    for deferred in waiting {
+                        deferred.reject(&mut cx, error);
+                    }
+
+                    return Ok(());
+                }
+            };
+
+            let id = connection.tag.id;
+            let close_watch = connection.close_watch.clone();
+            let quinn_connection = connection.connection.clone();
+            let handle = cx.boxed(connection);
+
+            let released = Instant::now();
+            let pooled = PooledConnection {
+                handle: handle.root(&mut cx),
+                id,
+                close_watch,
+                connection: quinn_connection,
+                users: waiting.len(),
+                idle_since: waiting.is_empty().then_some(released),
+            };
+            pool.remove_when_closed(&rt, key.clone(), &pooled);
+            if waiting.is_empty() {
+                pool.evict_after(&rt, key.clone(), released);
+            }
+            pool.state.lock().unwrap().connections.insert(key, pooled);
+
+            for deferred in waiting {
+                deferred.resolve(&mut cx, handle);
+            }
+
+            Ok(())
+        });
+    }
+    .instrument(connect_span);
+    spawner.spawn(task);
+
+    Ok(promise)
+}
+
+// Gives back a connection from `pool_acquire`, it's closed once it was idle for `idleEvictMs`.
+//  Returns `false` if it isn't acquired from the pool
+fn pool_release(mut cx: FunctionContext) -> JsResult<JsBoolean> {
+    let pool = (**args::argument::<JsBox<Pool>>(&mut cx, "pool_release", 0, "pool")?).clone();
+    let connection = args::argument::<JsBox<Connection>>(&mut cx, "pool_release", 1, "connection")?;
+    connection
+        .check("pool_release", false)
+        .or_else(|err| err.throw(&mut cx))?;
+    let id = connection.tag.id;
+    let rt = runtime(&mut cx)?;
+
+    let mut state = pool.state.lock().unwrap();
+    let Some((key, pooled)) = state
+        .connections
+        .iter_mut()
+        .find(|(_, pooled)| pooled.id == id && pooled.users > 0)
+    else {
+        return Ok(cx.boolean(false));
+    };
+
+    pooled.users -= 1;
+    if pooled.users == 0 {
+        let released = Instant::now();
+        pooled.idle_since = Some(released);
+
+        let key = key.clone();
+        drop(state);
+        pool.evict_after(&rt, key, released);
+    }
+
+    Ok(cx.boolean(true))
+}
+
+fn pool_stats(mut cx: FunctionContext) -> JsResult<JsObject> {
+    let pool = args::argument::<JsBox<Pool>>(&mut cx, "pool_stats", 0, "pool")?;
+
+    let (size, idle, pending, hits, misses) = {
+        let state = pool.state.lock().unwrap();
+        let idle = state
+            .connections
+            .values()
+            .filter(|pooled| pooled.users == 0)
+            .count();
+
+        (
+            state.connections.len(),
+            idle,
+            state.pending.len(),
+            state.hits,
+            state.misses,
+        )
+    };
+
+    let result = cx.empty_object();
+    for (key, value) in [
+        ("size", size as f64),
+        ("idle", idle as f64),
+        ("pending", pending as f64),
+        ("hits", hits as f64),
+        ("misses", misses as f64),
+    ] {
+        let value = cx.number(value);
+        result.set(&mut cx, key, value)?;
+    }
+
+    Ok(result)
+}
+
+// Closes every pooled connection, including acquired ones. Resolves once all of them are closed,
+//  attempts still in progress are pooled once they connect
+fn pool_close(mut cx: FunctionContext) -> JsResult<JsPromise> {
+    let pool = (**args::argument::<JsBox<Pool>>(&mut cx, "pool_close", 0, "pool")?).clone();
+    let rt = runtime(&mut cx)?;
+
+    let pooled: Vec<_> = pool.state.lock().unwrap().connections.drain().collect();
+    let mut close_watches = Vec::with_capacity(pooled.len());
+    for (_, pooled) in pooled {
+        pooled.connection.close(0u32.into(), b"");
+        pooled.handle.drop(&mut cx);
+        close_watches.push(pooled.close_watch);
+    }
+
+    let channel = cx.channel();
+    let (deferred, promise) = cx.promise();
+
+    rt.spawn(async move {
+        for close_watch in &close_watches {
+            close_watch.wait_closed().await;
+        }
+
+        let _ = deferred.try_settle_with(&channel, |mut cx| Ok(cx.undefined()));
+    });
+
+    Ok(promise)
+}
+
+pub fn export(cx: &mut ModuleContext) -> NeonResult<()> {
+    cx.export_function("create_pool", create_pool)?;
+    cx.export_function("pool_acquire", pool_acquire)?;
+    cx.export_function("pool_release", pool_release)?;
+    cx.export_function("pool_stats", pool_stats)?;
+    cx.export_function("pool_close", pool_close)?;
+
+    Ok(())
+}
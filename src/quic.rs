@@ -1,14 +1,91 @@
 use std::{
+    borrow::Cow,
     io::Cursor,
-    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
-    sync::Arc,
-    time::Duration,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV6},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
 };
 
-use once_cell::sync::OnceCell;
-use quinn::crypto::rustls::QuicClientConfig;
+use once_cell::sync::{Lazy, OnceCell};
+use quinn::crypto::rustls::{NoInitialCipherSuite, QuicClientConfig};
+use rustls::{
+    client::{
+        danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier},
+        ClientSessionMemoryCache, Resumption, WebPkiServerVerifier,
+    },
+    crypto::CryptoProvider,
+    pki_types::{CertificateDer, ServerName, UnixTime},
+    DigitallySignedStruct, SignatureScheme,
+};
 use rustls_native_certs::CertificateResult;
 
+use crate::{
+    dscp,
+    errors::ErrorCode,
+    plain_socket::PlainSocket,
+    socks::{self, Proxy, ProxyError},
+};
+
+#[cfg(all(feature = "crypto-ring", feature = "crypto-aws-lc"))]
+compile_error!("only one of the crypto-ring and crypto-aws-lc features can be enabled");
+#[cfg(not(any(feature = "crypto-ring", feature = "crypto-aws-lc")))]
+compile_error!("one of the crypto-ring and crypto-aws-lc features has to be enabled");
+
+/// Name of the rustls crypto provider selected by the `crypto-*` features, reported by `build_info`
+#[cfg(feature = "crypto-ring")]
+pub const CRYPTO_PROVIDER: &str = "ring";
+#[cfg(feature = "crypto-aws-lc")]
+pub const CRYPTO_PROVIDER: &str = "aws-lc-rs";
+
+/// Set by the `crypto-fips` feature, which builds aws-lc-rs in FIPS mode and only offers the
+///  FIPS approved cipher suites and key exchange groups
+pub const FIPS: bool = cfg!(feature = "crypto-fips");
+
+/// Provider every TLS configuration is built with. Passed explicitly instead of relying on the
+///  process default, which rustls can't pick when another crate enables a second provider
+pub fn crypto_provider() -> Arc<CryptoProvider> {
+    static PROVIDER: Lazy<Arc<CryptoProvider>> = Lazy::new(|| {
+        #[cfg(feature = "crypto-ring")]
+        let provider = rustls::crypto::ring::default_provider();
+        #[cfg(all(feature = "crypto-aws-lc", not(feature = "crypto-fips")))]
+        let provider = rustls::crypto::aws_lc_rs::default_provider();
+        #[cfg(feature = "crypto-fips")]
+        let provider = rustls::crypto::default_fips_provider();
+
+        Arc::new(provider)
+    });
+
+    PROVIDER.clone()
+}
+
+// Session tickets are shared by all connections, so connecting to the same server again can
+//  resume the previous session
+static SESSION_STORE: Lazy<Arc<ClientSessionMemoryCache>> =
+    Lazy::new(|| Arc::new(ClientSessionMemoryCache::new(256)));
+
+// Tokens servers sent in NEW_TOKEN frames, keyed by server name. Every connection has a client
+//  config of its own, which would otherwise come with a new store that's dropped together with it.
+//  Sending one lets the server skip address validation, saving a round trip
+static TOKEN_STORE: Lazy<Arc<quinn::TokenMemoryCache>> =
+    Lazy::new(|| Arc::new(quinn::TokenMemoryCache::default()));
+
+/// QUIC version offered by the client. Quinn does not support compatible version negotiation, so
+///  a successful handshake always ends up on this version.
+pub const QUIC_VERSION: u32 = 0x0000_0001;
+
+/// Friendly name of well-known QUIC versions
+pub fn version_name(version: u32) -> Option<&'static str> {
+    match version {
+        0x0000_0001 => Some("v1"),
+        0x6b33_43cf => Some("v2"),
+        0xff00_001d => Some("draft-29"),
+        _ => None,
+    }
+}
+
 #[derive(Debug)]
 pub enum GetCertsError {
     NativeLoad(Vec<rustls_native_certs::Error>),
@@ -31,38 +108,139 @@ impl GetCertsError {
     }
 }
 
-fn get_certs(
+// The system's roots, loaded on first use. The verifier built from them is shared by all
+//  connections which don't add their own certificate authorities
+struct NativeRoots {
+    roots: Arc<rustls::RootCertStore>,
+    // `None` if there are no native roots, the builder refuses an empty store
+    verifier: Option<Arc<WebPkiServerVerifier>>,
+}
+
+fn native_roots() -> Result<&'static NativeRoots, GetCertsError> {
+    static ROOTS: OnceCell<NativeRoots> = OnceCell::new();
+
+    ROOTS.get_or_try_init(|| {
+        let mut roots = rustls::RootCertStore::empty();
+
+        let CertificateResult { certs, errors, .. } = rustls_native_certs::load_native_certs();
+
+        if !errors.is_empty() {
+            return Err(GetCertsError::NativeLoad(errors));
+        }
+
+        for cert in certs {
+            roots.add(cert).map_err(GetCertsError::Load)?;
+        }
+
+        let roots = Arc::new(roots);
+        let verifier =
+            WebPkiServerVerifier::builder_with_provider(roots.clone(), crypto_provider())
+                .build()
+                .ok();
+
+        Ok(NativeRoots { roots, verifier })
+    })
+}
+
+// Verifier trusting the native roots, plus `certificate_authorities` if given. Only those
+//  connections pay for copying the native roots and building a verifier of their own
+fn server_verifier(
     certificate_authorities: Option<Vec<Vec<u8>>>,
-) -> Result<rustls::RootCertStore, GetCertsError> {
-    static CERTS: OnceCell<rustls::RootCertStore> = OnceCell::new();
+) -> Result<Arc<WebPkiServerVerifier>, ClientError> {
+    let native = native_roots().map_err(ClientError::CertRootStore)?;
 
-    CERTS
-        .get_or_try_init(|| {
-            let mut roots = rustls::RootCertStore::empty();
+    let Some(certificate_authorities) = certificate_authorities else {
+        if let Some(verifier) = &native.verifier {
+            return Ok(verifier.clone());
+        }
 
-            let CertificateResult { certs, errors, .. } = rustls_native_certs::load_native_certs();
+        // Reports why the store can't be used
+        return WebPkiServerVerifier::builder_with_provider(
+            native.roots.clone(),
+            crypto_provider(),
+        )
+        .build()
+        .map_err(ClientError::Verifier);
+    };
 
-            if !errors.is_empty() {
-                return Err(GetCertsError::NativeLoad(errors));
-            }
+    let mut roots = (*native.roots).clone();
 
-            for cert in certs {
-                roots.add(cert).map_err(GetCertsError::Load)?;
-            }
+    for ca in certificate_authorities {
+        for cert in rustls_pemfile::certs(&mut Cursor::new(ca)) {
+            let cert = cert
+                .map_err(|e| ClientError::CertRootStore(GetCertsError::CertificateAuthority(e)))?;
 
-            if let Some(certificate_authorities) = certificate_authorities {
-                for ca in certificate_authorities {
-                    for cert in rustls_pemfile::certs(&mut Cursor::new(ca)) {
-                        let cert = cert.map_err(GetCertsError::CertificateAuthority)?;
+            roots
+                .add(cert)
+                .map_err(|e| ClientError::CertRootStore(GetCertsError::Load(e)))?;
+        }
+    }
 
-                        roots.add(cert).map_err(GetCertsError::Load)?;
-                    }
-                }
-            }
+    WebPkiServerVerifier::builder_with_provider(Arc::new(roots), crypto_provider())
+        .build()
+        .map_err(ClientError::Verifier)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HandshakeKind {
+    Full,
+    Resumed,
+}
+
+impl HandshakeKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            HandshakeKind::Full => "full",
+            HandshakeKind::Resumed => "resumed",
+        }
+    }
+}
+
+// Wraps the WebPKI verifier to record whether the server certificate was verified. The server
+//  doesn't send its certificate when a session is resumed, so a handshake which completed without
+//  verifying a certificate was a resumption.
+#[derive(Debug)]
+struct TrackingVerifier {
+    inner: Arc<WebPkiServerVerifier>,
+    verified: Arc<AtomicBool>,
+}
+
+impl ServerCertVerifier for TrackingVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        server_name: &ServerName<'_>,
+        ocsp_response: &[u8],
+        now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        self.verified.store(true, Ordering::Release);
 
-            Ok(roots)
-        })
-        .cloned()
+        self.inner
+            .verify_server_cert(end_entity, intermediates, server_name, ocsp_response, now)
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.inner.verify_tls12_signature(message, cert, dss)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.inner.verify_tls13_signature(message, cert, dss)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.inner.supported_verify_schemes()
+    }
 }
 
 #[derive(Debug)]
@@ -73,6 +251,15 @@ pub enum ClientError {
     QuinnConnection(quinn::ConnectionError),
     InvalidClientAuthCertificate(rustls::Error),
     InvalidClientAuthKey(std::io::Error),
+    Verifier(rustls::client::VerifierBuilderError),
+    NoInitialCipherSuite(NoInitialCipherSuite),
+    // The crypto provider doesn't support TLS 1.3
+    CryptoProvider(rustls::Error),
+    // A FIPS build ended up with a configuration rustls doesn't consider FIPS compliant
+    NotFips,
+    Proxy(ProxyError),
+    // The handshake didn't complete within the connect timeout
+    Timeout(Duration),
 }
 
 impl ClientError {
@@ -85,6 +272,41 @@ impl ClientError {
             ClientError::QuinnConnection(v) => v.to_string(),
             ClientError::InvalidClientAuthCertificate(v) => v.to_string(),
             ClientError::InvalidClientAuthKey(v) => v.to_string(),
+            ClientError::Verifier(v) => v.to_string(),
+            ClientError::NoInitialCipherSuite(v) => v.to_string(),
+            ClientError::CryptoProvider(v) => v.to_string(),
+            ClientError::NotFips => String::from(
+                "The TLS configuration is not FIPS compliant, which this FIPS build requires",
+            ),
+            ClientError::Proxy(v) => v.to_string(),
+            ClientError::Timeout(v) => format!("connect timed out after {} ms", v.as_millis()),
+        }
+    }
+
+    /// Stable code of the error, so configuration mistakes can be told apart from failures of the
+    ///  environment without parsing the message
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            ClientError::QuinnConnect(e) => match e {
+                quinn::ConnectError::InvalidServerName(_) => ErrorCode::InvalidServerName,
+                quinn::ConnectError::EndpointStopping => ErrorCode::EndpointClosed,
+                quinn::ConnectError::CidsExhausted => ErrorCode::TooManyConnections,
+                quinn::ConnectError::UnsupportedVersion => ErrorCode::UnsupportedVersion,
+                quinn::ConnectError::InvalidRemoteAddress(_) => ErrorCode::InvalidRemoteAddress,
+                quinn::ConnectError::NoDefaultClientConfig => ErrorCode::Connect,
+            },
+            ClientError::QuinnConnection(quinn::ConnectionError::TimedOut)
+            | ClientError::Timeout(_) => ErrorCode::ConnectTimeout,
+            ClientError::QuinnConnection(_) => ErrorCode::ConnectionLost,
+            ClientError::Io(_) => ErrorCode::Io,
+            ClientError::CertRootStore(_)
+            | ClientError::InvalidClientAuthCertificate(_)
+            | ClientError::InvalidClientAuthKey(_)
+            | ClientError::Verifier(_)
+            | ClientError::NoInitialCipherSuite(_)
+            | ClientError::CryptoProvider(_)
+            | ClientError::NotFips => ErrorCode::Tls,
+            ClientError::Proxy(e) => e.code(),
         }
     }
 }
@@ -107,16 +329,657 @@ impl From<rustls::Error> for ClientError {
     }
 }
 
+/// Monotonic timings of the connection setup
+#[derive(Clone, Copy, Debug)]
+pub struct HandshakeTimings {
+    /// Time spent in the QUIC/TLS handshake
+    pub handshake: Duration,
+    /// Time from the start of `get_client` until the handshake completed. Includes loading the
+    ///  certificates and binding the socket
+    pub total: Duration,
+}
+
+/// Interval of quinn's keep-alive unless configured otherwise
+pub const DEFAULT_KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Congestion controller of a connection, quinn uses Cubic unless configured otherwise
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum CongestionController {
+    #[default]
+    Cubic,
+    NewReno,
+    Bbr,
+}
+
+impl CongestionController {
+    pub const ALL: [CongestionController; 3] = [
+        CongestionController::Cubic,
+        CongestionController::NewReno,
+        CongestionController::Bbr,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CongestionController::Cubic => "cubic",
+            CongestionController::NewReno => "newreno",
+            CongestionController::Bbr => "bbr",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|controller| controller.as_str() == name)
+    }
+
+    // Uses the controller's default initial window unless one is given
+    fn factory(
+        &self,
+        initial_window: Option<u64>,
+    ) -> Arc<dyn quinn::congestion::ControllerFactory + Send + Sync> {
+        match self {
+            CongestionController::Cubic => {
+                let mut config = quinn::congestion::CubicConfig::default();
+                if let Some(initial_window) = initial_window {
+                    config.initial_window(initial_window);
+                }
+
+                Arc::new(config)
+            }
+            CongestionController::NewReno => {
+                let mut config = quinn::congestion::NewRenoConfig::default();
+                if let Some(initial_window) = initial_window {
+                    config.initial_window(initial_window);
+                }
+
+                Arc::new(config)
+            }
+            CongestionController::Bbr => {
+                let mut config = quinn::congestion::BbrConfig::default();
+                if let Some(initial_window) = initial_window {
+                    config.initial_window(initial_window);
+                }
+
+                Arc::new(config)
+            }
+        }
+    }
+}
+
+/// Smallest initial congestion window accepted, the minimum window of RFC 9002 for datagrams of
+///  the smallest size QUIC allows
+pub const MIN_INITIAL_WINDOW: u64 = 2 * 1200;
+
+/// Per-connection settings of the transport config
+#[derive(Clone, Debug)]
+pub struct TransportOptions {
+    /// `None` disables quinn's keep-alive
+    pub keep_alive_interval: Option<Duration>,
+    /// Whether address validation tokens are taken from and added to `TOKEN_STORE`
+    pub reuse_tokens: bool,
+    /// `None` keeps quinn's default, `Some(None)` disables the idle timeout
+    pub max_idle_timeout: Option<Option<quinn::IdleTimeout>>,
+    pub congestion_controller: CongestionController,
+    /// Initial congestion window in bytes, `None` keeps the controller's default
+    pub initial_window: Option<u64>,
+    /// Connection-level flow control windows, `None` keeps quinn's default
+    pub receive_window: Option<quinn::VarInt>,
+    pub send_window: Option<u64>,
+    /// Streams the server may have open at once, `None` keeps quinn's default
+    pub max_concurrent_bidi_streams: Option<quinn::VarInt>,
+    pub max_concurrent_uni_streams: Option<quinn::VarInt>,
+    /// RTT assumed before the first sample, `None` keeps quinn's default of 333ms
+    pub initial_rtt: Option<Duration>,
+    /// Requested from the server with ACK_FREQUENCY frames, `None` doesn't send any
+    pub ack_frequency: Option<quinn::AckFrequencyConfig>,
+    /// UDP payload sizes, `None` keeps quinn's defaults
+    pub initial_mtu: Option<u16>,
+    pub min_mtu: Option<u16>,
+    /// `Some(None)` disables MTU discovery, so nothing larger than the initial MTU is sent
+    pub mtu_discovery: Option<Option<quinn::MtuDiscoveryConfig>>,
+    /// The upper bound set on `mtu_discovery`, which can't be read back from it
+    pub mtu_upper_bound: Option<u16>,
+    /// Whether the datagrams are relayed through a SOCKS5 proxy, whose header takes up part of
+    ///  every UDP payload
+    pub proxied: bool,
+}
+
+impl Default for TransportOptions {
+    fn default() -> Self {
+        Self {
+            keep_alive_interval: Some(DEFAULT_KEEP_ALIVE_INTERVAL),
+            reuse_tokens: true,
+            max_idle_timeout: None,
+            congestion_controller: CongestionController::default(),
+            initial_window: None,
+            receive_window: None,
+            send_window: None,
+            max_concurrent_bidi_streams: None,
+            max_concurrent_uni_streams: None,
+            initial_rtt: None,
+            ack_frequency: None,
+            initial_mtu: None,
+            min_mtu: None,
+            mtu_discovery: None,
+            mtu_upper_bound: None,
+            proxied: false,
+        }
+    }
+}
+
+// Quinn's default of `MtuDiscoveryConfig::upper_bound`
+const DEFAULT_MTU_UPPER_BOUND: u16 = 1452;
+
+impl TransportOptions {
+    /// The config of a connection to `addr`
+    fn config(&self, addr: SocketAddr) -> quinn::TransportConfig {
+        let mut config = quinn::TransportConfig::default();
+        config.keep_alive_interval(self.keep_alive_interval);
+        if let Some(max_idle_timeout) = self.max_idle_timeout {
+            config.max_idle_timeout(max_idle_timeout);
+        }
+        config
+            .congestion_controller_factory(self.congestion_controller.factory(self.initial_window));
+        if let Some(receive_window) = self.receive_window {
+            config.receive_window(receive_window);
+        }
+        if let Some(send_window) = self.send_window {
+            config.send_window(send_window);
+        }
+        if let Some(max_concurrent_bidi_streams) = self.max_concurrent_bidi_streams {
+            config.max_concurrent_bidi_streams(max_concurrent_bidi_streams);
+        }
+        if let Some(max_concurrent_uni_streams) = self.max_concurrent_uni_streams {
+            config.max_concurrent_uni_streams(max_concurrent_uni_streams);
+        }
+        if let Some(initial_rtt) = self.initial_rtt {
+            config.initial_rtt(initial_rtt);
+        }
+        if let Some(ack_frequency) = &self.ack_frequency {
+            config.ack_frequency_config(Some(ack_frequency.clone()));
+        }
+        // The proxy's header goes in front of every datagram on the way to it, QUIC only gets what
+        //  is left of the path's MTU. Quinn raises anything below the 1200 bytes QUIC needs to that
+        let overhead = if self.proxied {
+            socks::header_len(addr)
+        } else {
+            0
+        };
+        if let Some(min_mtu) = self.min_mtu {
+            config.min_mtu(min_mtu.saturating_sub(overhead));
+        }
+        // Quinn starts at the larger of the two, which is made explicit here so a `min_mtu` above
+        //  the default initial MTU can't end up below it
+        if let Some(initial_mtu) = self.initial_mtu.max(self.min_mtu) {
+            config.initial_mtu(initial_mtu.saturating_sub(overhead));
+        }
+        match &self.mtu_discovery {
+            Some(None) => {
+                config.mtu_discovery_config(None);
+            }
+            mtu_discovery if overhead > 0 => {
+                let mut mtu_discovery = mtu_discovery.clone().flatten().unwrap_or_default();
+                let upper_bound = self.mtu_upper_bound.unwrap_or(DEFAULT_MTU_UPPER_BOUND);
+                mtu_discovery.upper_bound(upper_bound.saturating_sub(overhead));
+
+                config.mtu_discovery_config(Some(mtu_discovery));
+            }
+            Some(mtu_discovery) => {
+                config.mtu_discovery_config(mtu_discovery.clone());
+            }
+            None => {}
+        }
+
+        config
+    }
+}
+
+pub struct Client {
+    pub connection: quinn::Connection,
+    pub endpoint: quinn::Endpoint,
+    // `false` when the connection was made on an endpoint shared with other connections
+    pub owns_endpoint: bool,
+    pub timings: HandshakeTimings,
+    pub handshake_kind: HandshakeKind,
+    pub fips: bool,
+    /// Set when the endpoint was bound for this connection, rather than shared or proxied
+    pub socket: Option<SocketInfo>,
+}
+
+/// Settings of the UDP socket a new endpoint is bound on
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct SocketOptions {
+    /// Address the socket is bound on, `None` binds the unspecified address of the remote's family
+    pub local_address: Option<IpAddr>,
+    /// `0` lets the system pick a port
+    pub local_port: u16,
+    /// Family of the socket when there's no `local_address`, `None` takes the remote's. An IPv6
+    ///  socket reaches IPv4 peers through IPv4-mapped addresses
+    pub ipv6: Option<bool>,
+    /// `IPV6_V6ONLY` of an IPv6 socket, `None` keeps the system's default
+    pub ipv6_only: Option<bool>,
+    /// Zone of an IPv6 `local_address`, the index of its interface. Link-local addresses need one
+    pub local_scope_id: u32,
+    /// Network interface the socket sends from, regardless of the routing table
+    pub interface: Option<String>,
+    /// `SO_SNDBUF` and `SO_RCVBUF`, `None` keeps the system's defaults
+    pub send_buffer_size: Option<usize>,
+    pub recv_buffer_size: Option<usize>,
+    /// Differentiated services codepoint of every datagram sent
+    pub dscp: Option<u8>,
+    /// Turns off segmentation and receive offload (GSO and GRO), which quinn uses where supported
+    pub disable_offload: bool,
+}
+
+/// Settings the kernel actually applied to a socket, which may differ from the requested ones,
+///  e.g. buffer sizes are clamped to `net.core.wmem_max` and `net.core.rmem_max` on Linux
+#[derive(Clone, Copy, Debug)]
+pub struct SocketInfo {
+    pub send_buffer_size: usize,
+    pub recv_buffer_size: usize,
+    /// Only read back when one was set
+    pub dscp: Option<u8>,
+    /// Datagrams sent or received with a single system call, more than 1 when offload is used
+    pub max_transmit_segments: usize,
+    pub max_receive_segments: usize,
+}
+
+/// Binds a client endpoint to connect to addresses of the given family on, at the local address of
+///  `options` or else an unspecified one. Has to be called from within the runtime
+pub fn bind_endpoint(
+    remote_ipv6: bool,
+    options: &SocketOptions,
+) -> std::io::Result<(quinn::Endpoint, SocketInfo)> {
+    if let (Some(ip), Some(ipv6)) = (options.local_address, options.ipv6) {
+        if ip.is_ipv6() != ipv6 {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("Unable to bind to {ip}: the address isn't of the requested family"),
+            ));
+        }
+    }
+
+    let ipv6 = match (options.local_address, options.ipv6) {
+        (Some(ip), _) => ip.is_ipv6(),
+        (None, Some(ipv6)) => ipv6,
+        (None, None) => remote_ipv6,
+    };
+    let ip = match options.local_address {
+        Some(ip) => ip,
+        None if ipv6 => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+        None => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+    };
+
+    if !ipv6 && options.ipv6_only.is_some() {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("Unable to bind to {ip}: IPV6_V6ONLY only applies to IPv6 sockets"),
+        ));
+    }
+    if remote_ipv6 && !ipv6 {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("Unable to bind to {ip}: an IPv4 socket can't send to IPv6 addresses"),
+        ));
+    }
+    // IPv4 peers are reached through IPv4-mapped addresses, which only an unspecified address of a
+    //  dual-stack socket can send to
+    let mut options = Cow::Borrowed(options);
+    if ipv6 && !remote_ipv6 {
+        if !ip.is_unspecified() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!(
+                    "Unable to bind to {ip}: a specific IPv6 address can't send to IPv4 addresses"
+                ),
+            ));
+        }
+        if options.ipv6_only == Some(true) {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "Unable to bind to [::]: an IPv6-only socket can't send to IPv4 addresses",
+            ));
+        }
+
+        // The default differs between platforms, e.g. Windows and OpenBSD make sockets IPv6-only
+        options.to_mut().ipv6_only = Some(false);
+    }
+    let addr = match ip {
+        IpAddr::V6(ip) => SocketAddr::V6(SocketAddrV6::new(
+            ip,
+            options.local_port,
+            0,
+            options.local_scope_id,
+        )),
+        ip => SocketAddr::new(ip, options.local_port),
+    };
+
+    let (socket, mut info) = bind_socket(addr, &options)?;
+    let socket = wrap_socket(socket, &options)?;
+    info.max_transmit_segments = socket.max_transmit_segments();
+    info.max_receive_segments = socket.max_receive_segments();
+
+    let endpoint = quinn::Endpoint::new_with_abstract_socket(
+        quinn::EndpointConfig::default(),
+        None,
+        socket,
+        Arc::new(quinn::TokioRuntime),
+    )?;
+
+    Ok((endpoint, info))
+}
+
+/// Socket quinn sends on for a socket bound with `options`. Has to be called from within the
+///  runtime
+pub fn wrap_socket(
+    socket: std::net::UdpSocket,
+    options: &SocketOptions,
+) -> std::io::Result<Arc<dyn quinn::AsyncUdpSocket>> {
+    use quinn::Runtime;
+
+    // Quinn's own socket sets the traffic class of every datagram to its ECN codepoint, which
+    //  would clear the DSCP
+    if options.dscp.is_some() || options.disable_offload {
+        return Ok(Arc::new(PlainSocket::new(socket)?));
+    }
+
+    quinn::TokioRuntime.wrap_udp_socket(socket)
+}
+
+/// Binds a UDP socket on `addr`, applying `options` before. Failing to apply any of them fails the
+///  whole bind rather than leaving the socket with defaults
+pub fn bind_socket(
+    addr: SocketAddr,
+    options: &SocketOptions,
+) -> std::io::Result<(std::net::UdpSocket, SocketInfo)> {
+    let socket = socket2::Socket::new(
+        socket2::Domain::for_address(addr),
+        socket2::Type::DGRAM,
+        Some(socket2::Protocol::UDP),
+    )?;
+
+    // Only applies to IPv6 sockets, e.g. rebinding a dual-stack endpoint to an IPv4 address keeps
+    //  the option for later IPv6 ones
+    if let Some(only_v6) = options.ipv6_only.filter(|_| addr.is_ipv6()) {
+        socket.set_only_v6(only_v6).map_err(|e| {
+            let hint = if only_v6 {
+                ""
+            } else {
+                ", the platform may not support dual-stack sockets"
+            };
+
+            std::io::Error::new(
+                e.kind(),
+                format!("Unable to set IPV6_V6ONLY to {only_v6}: {e}{hint}"),
+            )
+        })?;
+    }
+
+    if let Some(interface) = &options.interface {
+        bind_to_interface(&socket, interface, addr.is_ipv6()).map_err(|e| {
+            std::io::Error::new(
+                e.kind(),
+                format!(
+                    "Unable to bind to interface {interface:?}: {e}{}",
+                    interface_hint(&e)
+                ),
+            )
+        })?;
+    }
+    if let Some(size) = options.send_buffer_size {
+        socket.set_send_buffer_size(size).map_err(|e| {
+            std::io::Error::new(
+                e.kind(),
+                format!("Unable to set the send buffer size to {size}: {e}"),
+            )
+        })?;
+    }
+    if let Some(size) = options.recv_buffer_size {
+        socket.set_recv_buffer_size(size).map_err(|e| {
+            std::io::Error::new(
+                e.kind(),
+                format!("Unable to set the receive buffer size to {size}: {e}"),
+            )
+        })?;
+    }
+
+    if let Some(dscp) = options.dscp {
+        dscp::set(&socket, dscp, addr.is_ipv6()).map_err(|e| {
+            std::io::Error::new(
+                e.kind(),
+                format!("Unable to set the DSCP to {dscp}: {e}{}", dscp_hint(&e)),
+            )
+        })?;
+    }
+
+    socket
+        .bind(&addr.into())
+        .map_err(|e| std::io::Error::new(e.kind(), format!("Unable to bind to {addr}: {e}")))?;
+
+    let info = SocketInfo {
+        send_buffer_size: socket.send_buffer_size()?,
+        recv_buffer_size: socket.recv_buffer_size()?,
+        dscp: options
+            .dscp
+            .map(|_| dscp::get(&socket, addr.is_ipv6()))
+            .transpose()?,
+        // Only known once quinn set up the socket, see `bind_endpoint`
+        max_transmit_segments: 1,
+        max_receive_segments: 1,
+    };
+
+    Ok((socket.into(), info))
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn bind_to_interface(
+    socket: &socket2::Socket,
+    interface: &str,
+    _ipv6: bool,
+) -> std::io::Result<()> {
+    // SO_BINDTODEVICE
+    socket.bind_device(Some(interface.as_bytes()))
+}
+
+#[cfg(any(target_os = "macos", target_os = "ios"))]
+fn bind_to_interface(socket: &socket2::Socket, interface: &str, ipv6: bool) -> std::io::Result<()> {
+    let index = interface_index(interface)?;
+
+    // IP_BOUND_IF, or IPV6_BOUND_IF
+    if ipv6 {
+        socket.bind_device_by_index_v6(Some(index))
+    } else {
+        socket.bind_device_by_index_v4(Some(index))
+    }
+}
+
+#[cfg(not(any(
+    target_os = "linux",
+    target_os = "android",
+    target_os = "macos",
+    target_os = "ios"
+)))]
+fn bind_to_interface(
+    _socket: &socket2::Socket,
+    _interface: &str,
+    _ipv6: bool,
+) -> std::io::Result<()> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        "binding to an interface is not supported on this platform",
+    ))
+}
+
+/// Index of the network interface called `name`, which is also the scope ID of IPv6 link-local
+///  addresses on it
+#[cfg(unix)]
+pub fn interface_index(name: &str) -> std::io::Result<std::num::NonZeroU32> {
+    let name = std::ffi::CString::new(name)
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?;
+    // SAFETY: `name` is a valid C string for the duration of the call
+    std::num::NonZeroU32::new(unsafe { libc::if_nametoindex(name.as_ptr()) })
+        .ok_or_else(std::io::Error::last_os_error)
+}
+
+#[cfg(not(unix))]
+pub fn interface_index(_name: &str) -> std::io::Result<std::num::NonZeroU32> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        "looking up interfaces by name is not supported on this platform, use the numeric index",
+    ))
+}
+
+// Some platforms only let privileged processes set the values of the higher priority classes
+fn dscp_hint(error: &std::io::Error) -> &'static str {
+    if error.kind() == std::io::ErrorKind::PermissionDenied {
+        " (requires elevated privileges, e.g. CAP_NET_ADMIN on Linux)"
+    } else {
+        ""
+    }
+}
+
+/// Endpoint a connection to `addr` is made on
+pub struct ClientEndpointInfo {
+    pub endpoint: quinn::Endpoint,
+    /// Whether it was bound or proxied for the connection, rather than shared
+    pub owned: bool,
+    /// Set when it was bound, see `Client::socket`
+    pub socket: Option<SocketInfo>,
+}
+
+/// Binds a new endpoint for a connection to `addr`, unless `endpoint` is a shared one
+pub async fn client_endpoint(
+    addr: SocketAddr,
+    endpoint: ClientEndpoint,
+) -> Result<ClientEndpointInfo, ClientError> {
+    let (endpoint, owned, socket) = match endpoint {
+        ClientEndpoint::Bind(options) => {
+            let (endpoint, info) =
+                bind_endpoint(addr.is_ipv6(), &options).map_err(ClientError::Io)?;
+
+            (endpoint, true, Some(info))
+        }
+        ClientEndpoint::Shared(endpoint) => (endpoint, false, None),
+        ClientEndpoint::Proxy(proxy) => {
+            let endpoint = socks::bind_endpoint(&proxy)
+                .await
+                .map_err(ClientError::Proxy)?;
+
+            (endpoint, true, None)
+        }
+    };
+
+    Ok(ClientEndpointInfo {
+        endpoint,
+        owned,
+        socket,
+    })
+}
+
+// Binding to an interface is a privileged operation on older Linux kernels
+fn interface_hint(error: &std::io::Error) -> &'static str {
+    if error.kind() != std::io::ErrorKind::PermissionDenied {
+        return "";
+    }
+
+    if cfg!(any(target_os = "linux", target_os = "android")) {
+        " (requires CAP_NET_RAW or root)"
+    } else {
+        " (requires elevated privileges)"
+    }
+}
+
+/// Raw handle of a UDP socket created outside of this module, like a socket passed by a supervisor
+#[cfg(unix)]
+pub type RawSocket = std::os::fd::RawFd;
+#[cfg(windows)]
+pub type RawSocket = std::os::windows::io::RawSocket;
+
+/// Creates a client endpoint on an existing UDP socket. The handle is duplicated, the caller keeps
+///  ownership of `raw` and may close it without affecting the endpoint. The duplicate shares the
+///  open file description with `raw` though, so `raw` is switched to non-blocking mode as well,
+///  quinn can't drive a blocking socket. Has to be called from within the runtime
+pub fn endpoint_from_socket(raw: RawSocket) -> std::io::Result<quinn::Endpoint> {
+    let invalid = |e: std::io::Error| {
+        std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("Not a valid socket: {e}"),
+        )
+    };
+
+    // SAFETY: the handle is only borrowed to duplicate it, an invalid one makes that fail
+    #[cfg(unix)]
+    let owned = unsafe { std::os::fd::BorrowedFd::borrow_raw(raw) }
+        .try_clone_to_owned()
+        .map_err(invalid)?;
+    #[cfg(windows)]
+    let owned = unsafe { std::os::windows::io::BorrowedSocket::borrow_raw(raw) }
+        .try_clone_to_owned()
+        .map_err(invalid)?;
+
+    let socket = socket2::Socket::from(owned);
+
+    // Checked before quinn gets the socket, it assumes a datagram socket
+    if socket.r#type().map_err(invalid)? != socket2::Type::DGRAM {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "Not a UDP socket",
+        ));
+    }
+    // A Unix datagram socket passes the type check, quinn only handles IP addresses
+    if socket.local_addr().map_err(invalid)?.as_socket().is_none() {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "Not an IP socket",
+        ));
+    }
+    socket.set_nonblocking(true)?;
+
+    quinn::Endpoint::new(
+        quinn::EndpointConfig::default(),
+        None,
+        socket.into(),
+        Arc::new(quinn::TokioRuntime),
+    )
+}
+
+/// Endpoint `get_client` connects on
+#[derive(Clone)]
+pub enum ClientEndpoint {
+    /// A new endpoint is bound for this connection only
+    Bind(SocketOptions),
+    /// An endpoint shared with other connections
+    Shared(quinn::Endpoint),
+    /// A new endpoint for this connection only, relaying its datagrams through a SOCKS5 proxy
+    Proxy(Proxy),
+}
+
+/// Connects to `addr` on `endpoint`, giving up once the handshake took longer than `timeout`
+#[allow(clippy::too_many_arguments)]
 pub async fn get_client(
     addr: SocketAddr,
     hostname: &str,
     alpn_protocols: Option<Vec<Vec<u8>>>,
     certificate_authorities: Option<Vec<Vec<u8>>>,
     client_auth: Option<(Vec<u8>, Vec<u8>)>,
-) -> Result<(quinn::Connection, quinn::Endpoint), ClientError> {
-    let roots = get_certs(certificate_authorities).map_err(ClientError::CertRootStore)?;
+    endpoint: ClientEndpoint,
+    transport: &TransportOptions,
+    timeout: Option<Duration>,
+) -> Result<Client, ClientError> {
+    let started_at = Instant::now();
+
+    let verified = Arc::new(AtomicBool::new(false));
+    let verifier = TrackingVerifier {
+        inner: server_verifier(certificate_authorities)?,
+        verified: verified.clone(),
+    };
 
-    let client_crypto = rustls::ClientConfig::builder().with_root_certificates(roots);
+    // QUIC only works with TLS 1.3
+    let client_crypto = rustls::ClientConfig::builder_with_provider(crypto_provider())
+        .with_protocol_versions(&[&rustls::version::TLS13])
+        .map_err(ClientError::CryptoProvider)?
+        .dangerous()
+        .with_custom_certificate_verifier(Arc::new(verifier));
 
     let mut client_crypto = match client_auth {
         None => client_crypto.with_no_client_auth(),
@@ -155,26 +1018,276 @@ pub async fn get_client(
     }
 
     client_crypto.key_log = Arc::new(rustls::KeyLogFile::new());
+    client_crypto.resumption = Resumption::store(SESSION_STORE.clone());
 
-    let mut transport_config = quinn::TransportConfig::default();
-    transport_config.keep_alive_interval(Some(Duration::from_secs(1)));
+    // Options added to the configuration later on mustn't silently weaken a FIPS build
+    let fips = client_crypto.fips();
+    if FIPS && !fips {
+        return Err(ClientError::NotFips);
+    }
 
-    let client_config = QuicClientConfig::try_from(client_crypto).unwrap();
+    let client_config =
+        QuicClientConfig::try_from(client_crypto).map_err(ClientError::NoInitialCipherSuite)?;
     let mut client_config = quinn::ClientConfig::new(Arc::new(client_config));
-    client_config.transport_config(Arc::new(transport_config));
+    client_config.transport_config(Arc::new(transport.config(addr)));
+    if transport.reuse_tokens {
+        client_config.token_store(TOKEN_STORE.clone());
+    } else {
+        client_config.token_store(Arc::new(quinn::NoneTokenStore));
+    }
+    client_config.version(QUIC_VERSION);
 
-    let mut endpoint = quinn::Endpoint::client(SocketAddr::new(
-        if addr.is_ipv6() {
-            IpAddr::V6(Ipv6Addr::UNSPECIFIED)
-        } else {
-            IpAddr::V4(Ipv4Addr::UNSPECIFIED)
+    let ClientEndpointInfo {
+        endpoint,
+        owned: owns_endpoint,
+        socket,
+    } = client_endpoint(addr, endpoint).await?;
+
+    let handshake_started_at = Instant::now();
+    let connecting = endpoint.connect_with(client_config, addr, hostname)?;
+    let connection = match timeout {
+        None => connecting.await?,
+        Some(timeout) => match tokio::time::timeout(timeout, connecting).await {
+            Ok(connection) => connection?,
+            Err(_) => {
+                // Dropping the attempt abandons the connection, an endpoint bound for it is
+                //  closed right away instead of once the last handle is gone
+                if owns_endpoint {
+                    endpoint.close(0u32.into(), b"");
+                }
+
+                return Err(ClientError::Timeout(timeout));
+            }
         },
-        0,
-    ))
-    .map_err(ClientError::Io)?;
-    endpoint.set_default_client_config(client_config);
+    };
+    let finished_at = Instant::now();
+
+    let handshake_kind = if verified.load(Ordering::Acquire) {
+        HandshakeKind::Full
+    } else {
+        HandshakeKind::Resumed
+    };
+
+    Ok(Client {
+        connection,
+        endpoint,
+        owns_endpoint,
+        timings: HandshakeTimings {
+            handshake: finished_at - handshake_started_at,
+            total: finished_at - started_at,
+        },
+        handshake_kind,
+        fips,
+        socket,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Compares the debug output, the transport config can't be inspected otherwise
+    fn assert_config(
+        options: TransportOptions,
+        expected: impl FnOnce(&mut quinn::TransportConfig),
+    ) {
+        let addr = SocketAddr::from((Ipv4Addr::LOCALHOST, 443));
+        let mut config = TransportOptions::default().config(addr);
+        expected(&mut config);
+
+        assert_eq!(format!("{:?}", options.config(addr)), format!("{config:?}"));
+    }
+
+    #[test]
+    fn config_applies_windows() {
+        assert_config(
+            TransportOptions {
+                receive_window: Some(quinn::VarInt::from_u32(1 << 20)),
+                send_window: Some(1 << 21),
+                ..Default::default()
+            },
+            |config| {
+                config.receive_window(quinn::VarInt::from_u32(1 << 20));
+                config.send_window(1 << 21);
+            },
+        );
+    }
+
+    #[test]
+    fn native_roots_are_shared_by_every_connection() {
+        let Ok(native) = native_roots() else {
+            return;
+        };
+        assert!(std::ptr::eq(native, native_roots().unwrap()));
+
+        // Without native roots there's no shared verifier, connections have to add authorities
+        if native.verifier.is_some() {
+            assert!(Arc::ptr_eq(
+                &server_verifier(None).unwrap(),
+                &server_verifier(None).unwrap()
+            ));
+        }
+    }
+
+    #[test]
+    fn config_applies_max_concurrent_bidi_streams() {
+        assert_config(
+            TransportOptions {
+                max_concurrent_bidi_streams: Some(0u8.into()),
+                ..Default::default()
+            },
+            |config| {
+                config.max_concurrent_bidi_streams(0u8.into());
+            },
+        );
+    }
+
+    #[test]
+    fn config_applies_max_concurrent_uni_streams() {
+        assert_config(
+            TransportOptions {
+                max_concurrent_uni_streams: Some(16u8.into()),
+                ..Default::default()
+            },
+            |config| {
+                config.max_concurrent_uni_streams(16u8.into());
+            },
+        );
+    }
+
+    #[test]
+    fn config_applies_initial_rtt() {
+        assert_config(
+            TransportOptions {
+                initial_rtt: Some(Duration::from_millis(20)),
+                ..Default::default()
+            },
+            |config| {
+                config.initial_rtt(Duration::from_millis(20));
+            },
+        );
+    }
+
+    #[test]
+    fn config_applies_ack_frequency() {
+        let mut ack_frequency = quinn::AckFrequencyConfig::default();
+        ack_frequency.ack_eliciting_threshold(10u8.into());
+        ack_frequency.max_ack_delay(Some(Duration::from_millis(50)));
+
+        assert_config(
+            TransportOptions {
+                ack_frequency: Some(ack_frequency.clone()),
+                ..Default::default()
+            },
+            |config| {
+                config.ack_frequency_config(Some(ack_frequency));
+            },
+        );
+    }
+
+    #[test]
+    fn config_applies_mtu() {
+        let mut discovery = quinn::MtuDiscoveryConfig::default();
+        discovery.upper_bound(1360);
+
+        assert_config(
+            TransportOptions {
+                initial_mtu: Some(1350),
+                min_mtu: Some(1280),
+                mtu_discovery: Some(Some(discovery.clone())),
+                ..Default::default()
+            },
+            |config| {
+                config.initial_mtu(1350);
+                config.min_mtu(1280);
+                config.mtu_discovery_config(Some(discovery));
+            },
+        );
+    }
+
+    #[test]
+    fn config_raises_the_initial_mtu_to_the_min_mtu() {
+        assert_config(
+            TransportOptions {
+                min_mtu: Some(1400),
+                ..Default::default()
+            },
+            |config| {
+                config.initial_mtu(1400);
+                config.min_mtu(1400);
+            },
+        );
+    }
+
+    #[test]
+    fn config_leaves_room_for_the_proxy_header() {
+        let mut discovery = quinn::MtuDiscoveryConfig::default();
+        discovery.upper_bound(1500 - 10);
+
+        assert_config(
+            TransportOptions {
+                initial_mtu: Some(1400),
+                min_mtu: Some(1300),
+                mtu_upper_bound: Some(1500),
+                mtu_discovery: Some(Some(quinn::MtuDiscoveryConfig::default())),
+                proxied: true,
+                ..Default::default()
+            },
+            |config| {
+                config.initial_mtu(1400 - 10);
+                config.min_mtu(1300 - 10);
+                config.mtu_discovery_config(Some(discovery));
+            },
+        );
+
+        // Discovery still stops short of quinn's default upper bound
+        let mut discovery = quinn::MtuDiscoveryConfig::default();
+        discovery.upper_bound(DEFAULT_MTU_UPPER_BOUND - 10);
+
+        assert_config(
+            TransportOptions {
+                proxied: true,
+                ..Default::default()
+            },
+            |config| {
+                config.mtu_discovery_config(Some(discovery));
+            },
+        );
+    }
 
-    let connection = endpoint.connect(addr, hostname)?.await?;
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn endpoint_from_socket_uses_a_udp_socket() {
+        use std::os::fd::AsRawFd;
 
-    Ok((connection, endpoint))
+        let socket = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        let endpoint = endpoint_from_socket(socket.as_raw_fd()).unwrap();
+        assert_eq!(endpoint.local_addr().unwrap(), socket.local_addr().unwrap());
+
+        // The duplicate shares the file description, the caller's socket doesn't block anymore
+        let error = socket.recv(&mut [0; 16]).unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::WouldBlock);
+
+        // The endpoint keeps its own handle
+        drop(socket);
+        assert!(endpoint.local_addr().is_ok());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn endpoint_from_socket_rejects_other_handles() {
+        use std::os::fd::AsRawFd;
+
+        let assert_invalid = |raw: RawSocket, message: &str| {
+            let error = endpoint_from_socket(raw).unwrap_err();
+            assert_eq!(error.kind(), std::io::ErrorKind::InvalidInput);
+            assert!(error.to_string().starts_with(message), "{error}");
+        };
+
+        let tcp = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        assert_invalid(tcp.as_raw_fd(), "Not a UDP socket");
+
+        let unix = std::os::unix::net::UnixDatagram::unbound().unwrap();
+        assert_invalid(unix.as_raw_fd(), "Not an IP socket");
+    }
 }
@@ -0,0 +1,107 @@
+use std::{
+    future::Future,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc, OnceLock,
+    },
+};
+
+use futures_util::{stream::FuturesUnordered, StreamExt};
+use tokio::{runtime::Handle, sync::mpsc};
+use tokio_util::sync::CancellationToken;
+
+// Tasks the read loops of a connection's streams are spread over. Most of the time a read loop is
+//  parked waiting for data, a task for each of them mostly adds scheduling and allocation overhead
+const READ_DRIVERS: usize = 4;
+
+type ReadFuture = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+/// Read loop of a single stream, running on one of the connection's drivers
+#[derive(Debug, Default)]
+pub struct ReadHandle {
+    finished: AtomicBool,
+    aborted: CancellationToken,
+}
+
+impl ReadHandle {
+    pub fn is_finished(&self) -> bool {
+        self.finished.load(Ordering::Acquire)
+    }
+
+    /// Drops the read loop the next time its driver runs, like `JoinHandle::abort`
+    pub fn abort(&self) {
+        self.aborted.cancel();
+    }
+}
+
+/// Drivers of a connection. They end once every clone was dropped and the read loops they were
+///  given finished
+#[derive(Clone)]
+pub struct Readers {
+    rt: Handle,
+    // Spawned when a read loop is first given to them, a connection with a single stream never
+    //  has more than one
+    drivers: Arc<[OnceLock<mpsc::UnboundedSender<ReadFuture>>; READ_DRIVERS]>,
+    next: Arc<AtomicUsize>,
+}
+
+impl Readers {
+    pub fn new(rt: &Handle) -> Self {
+        Self {
+            rt: rt.clone(),
+            drivers: Default::default(),
+            next: Default::default(),
+        }
+    }
+
+    /// Runs `read` on one of the drivers. Every future given to a driver is polled in order, so
+    ///  a read loop behaves the same as it would on a task of its own
+    pub fn spawn<F: Future<Output = ()> + Send + 'static>(&self, read: F) -> Arc<ReadHandle> {
+        let handle = Arc::new(ReadHandle::default());
+
+        let future = Box::pin({
+            let handle = handle.clone();
+
+            async move {
+                tokio::select! {
+                    _ = read => {},
+                    _ = handle.aborted.cancelled() => {},
+                }
+
+                handle.finished.store(true, Ordering::Release);
+            }
+        });
+
+        let driver = self.drivers[self.next.fetch_add(1, Ordering::Relaxed) % READ_DRIVERS]
+            .get_or_init(|| {
+                let (sender, queue) = mpsc::unbounded_channel();
+                self.rt.spawn(drive(queue));
+
+                sender
+            });
+        // Only fails once the runtime shut down, the read loop is dropped then like a task would be
+        if driver.send(future).is_err() {
+            handle.finished.store(true, Ordering::Release);
+        }
+
+        handle
+    }
+}
+
+async fn drive(mut queue: mpsc::UnboundedReceiver<ReadFuture>) {
+    let mut reads = FuturesUnordered::new();
+
+    loop {
+        tokio::select! {
+            read = queue.recv() => match read {
+                Some(read) => reads.push(read),
+                None => break,
+            },
+            Some(()) = reads.next(), if !reads.is_empty() => {},
+        }
+    }
+
+    // Nothing is added anymore, the remaining streams are still read until they end
+    while reads.next().await.is_some() {}
+}
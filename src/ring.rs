@@ -0,0 +1,235 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use neon::{prelude::*, types::buffer::TypedArray};
+use once_cell::sync::Lazy;
+use tokio::sync::Notify;
+
+use crate::{args, errors::NativeError};
+
+// The buffer starts with four 32-bit slots, followed by the data. `HEAD` and `TAIL` count the
+//  bytes written and read, wrapping around at 2^32, so the data capacity has to be a power of two
+const HEAD: usize = 0;
+const TAIL: usize = 1;
+const STATE: usize = 2;
+// Flags of who waits for the other side. JavaScript sets `DATA_WAITING` before it waits for
+//  `on_data`, the read loop sets `ROOM_WAITING` while the buffer is full
+const WAITING: usize = 3;
+const DATA_WAITING: u32 = 1;
+const ROOM_WAITING: u32 = 2;
+const HEADER: usize = 4 * std::mem::size_of::<u32>();
+
+/// Values of the `STATE` slot
+pub const STATE_OPEN: u32 = 0;
+pub const STATE_ENDED: u32 = 1;
+pub const STATE_FAILED: u32 = 2;
+
+// Read loops waiting for room, by the address of their buffer's slots. `ring_buffer_drained` is
+//  called with the buffer, which is all JavaScript knows about
+static WRITERS: Lazy<Mutex<HashMap<usize, Arc<Notify>>>> = Lazy::new(Default::default);
+
+// Bytes which can be written. `TAIL` is written by JavaScript, a tail past `HEAD` or more than
+//  `capacity` behind it leaves no room rather than overflowing
+fn free(head: u32, tail: u32, capacity: usize) -> usize {
+    capacity.saturating_sub(head.wrapping_sub(tail) as usize)
+}
+
+/// `SharedArrayBuffer` the read loop of a stream writes to instead of calling `on_data` for every
+///  chunk. Only a single read loop may write to it
+pub struct RingBuffer {
+    slots: *const AtomicU32,
+    data: *mut u8,
+    capacity: usize,
+    // Notified by `ring_buffer_drained` once JavaScript made room
+    drained: Arc<Notify>,
+    // Keeps the memory alive, a `SharedArrayBuffer` can't be detached so it never moves
+    _buffer: Root<JsUint8Array>,
+}
+
+// SAFETY: the memory is shared with JavaScript, which only reads the data between `TAIL` and
+//  `HEAD`. It's never written to by JavaScript while the read loop may write, and the slots are
+//  only accessed atomically
+unsafe impl Send for RingBuffer {}
+unsafe impl Sync for RingBuffer {}
+
+impl RingBuffer {
+    /// `view` is a `Uint8Array` of the `SharedArrayBuffer`, or the part of it used as the buffer
+    pub fn new<'a, C: Context<'a>>(cx: &mut C, mut view: Handle<JsUint8Array>) -> NeonResult<Self> {
+        let buffer: Handle<JsValue> = view.get(cx, "buffer")?;
+        let constructor: Handle<JsValue> = match buffer.downcast::<JsObject, _>(cx) {
+            Ok(buffer) => buffer.get(cx, "constructor")?,
+            Err(_) => cx.undefined().upcast(),
+        };
+        let shared: Handle<JsValue> = cx.global("SharedArrayBuffer")?;
+
+        if !constructor.strict_equals(cx, shared) {
+            return NativeError::type_error(
+                "initialize_stream: expected options.ringBuffer to be a SharedArrayBuffer",
+            )
+            .throw(cx);
+        }
+
+        let bytes = view.as_mut_slice(cx);
+        if bytes.as_ptr() as usize % std::mem::align_of::<AtomicU32>() != 0 {
+            return NativeError::range_error(
+                "initialize_stream: expected options.ringBuffer to start at a multiple of 4 bytes",
+            )
+            .throw(cx);
+        }
+
+        let capacity = bytes.len().saturating_sub(HEADER);
+        if !capacity.is_power_of_two() || capacity > 1 << 31 {
+            return NativeError::range_error(format!(
+                "initialize_stream: expected the data of options.ringBuffer to be a power of two up to 2^31 bytes, got {capacity}"
+            ))
+            .throw(cx);
+        }
+
+        let slots = bytes.as_mut_ptr() as *const AtomicU32;
+        let data = bytes[HEADER..].as_mut_ptr();
+
+        let drained = Arc::new(Notify::new());
+        {
+            let mut writers = WRITERS.lock().unwrap();
+            if writers.contains_key(&(slots as usize)) {
+                return NativeError::range_error(
+                    "initialize_stream: expected options.ringBuffer not to be used by another stream",
+                )
+                .throw(cx);
+            }
+            writers.insert(slots as usize, drained.clone());
+        }
+
+        let ring = Self {
+            slots,
+            data,
+            capacity,
+            drained,
+            _buffer: view.root(cx),
+        };
+
+        ring.slot(HEAD).store(0, Ordering::SeqCst);
+        ring.slot(TAIL).store(0, Ordering::SeqCst);
+        ring.slot(STATE).store(STATE_OPEN, Ordering::SeqCst);
+        // Nothing was read yet, so the first data is announced
+        ring.slot(WAITING).store(DATA_WAITING, Ordering::SeqCst);
+
+        Ok(ring)
+    }
+
+    fn slot(&self, slot: usize) -> &AtomicU32 {
+        // SAFETY: the start of the view was checked to be aligned, and it's longer than the slots
+        unsafe { &*self.slots.add(slot) }
+    }
+
+    /// Copies all of `data` into the buffer, waiting for JavaScript to make room when it's full.
+    ///  `wake` is called when JavaScript was waiting for data, which it returns `false` from once
+    ///  nobody is left to wake up
+    pub async fn write<F: Fn() -> bool>(&self, mut data: &[u8], wake: F) -> bool {
+        while !data.is_empty() {
+            let head = self.slot(HEAD).load(Ordering::Relaxed);
+            let tail = self.slot(TAIL).load(Ordering::Acquire);
+            let free = free(head, tail, self.capacity);
+
+            if free == 0 {
+                self.wait_for_room(head).await;
+                continue;
+            }
+
+            let start = head as usize & (self.capacity - 1);
+            let n = data.len().min(free).min(self.capacity - start);
+
+            // SAFETY: `start + n` is within the data region, and JavaScript doesn't read past
+            //  `HEAD`, which is only moved after the copy
+            unsafe { std::ptr::copy_nonoverlapping(data.as_ptr(), self.data.add(start), n) };
+            self.slot(HEAD)
+                .store(head.wrapping_add(n as u32), Ordering::SeqCst);
+            data = &data[n..];
+
+            let waiting = self
+                .slot(WAITING)
+                .fetch_and(!DATA_WAITING, Ordering::SeqCst);
+            if waiting & DATA_WAITING != 0 && !wake() {
+                return false;
+            }
+        }
+
+        true
+    }
+
+    // Flags the read loop as waiting, JavaScript calls `ring_buffer_drained` when it finds the
+    //  flag after moving `TAIL`. The tail is checked again after setting it, JavaScript may have
+    //  read everything in between without seeing the flag
+    async fn wait_for_room(&self, head: u32) {
+        self.slot(WAITING).fetch_or(ROOM_WAITING, Ordering::SeqCst);
+
+        if free(head, self.slot(TAIL).load(Ordering::SeqCst), self.capacity) == 0 {
+            // A notification sent before this point is kept as a permit
+            self.drained.notified().await;
+        }
+
+        self.slot(WAITING)
+            .fetch_and(!ROOM_WAITING, Ordering::SeqCst);
+    }
+
+    /// Marks the buffer as no longer written to. JavaScript still reads what's left
+    pub fn close(&self, state: u32) {
+        self.slot(STATE).store(state, Ordering::SeqCst);
+        self.slot(WAITING).store(0, Ordering::SeqCst);
+    }
+}
+
+impl Drop for RingBuffer {
+    fn drop(&mut self) {
+        WRITERS.lock().unwrap().remove(&(self.slots as usize));
+    }
+}
+
+// Wakes the read loop of the stream writing to the buffer once JavaScript made room in it. Does
+//  nothing when no stream writes to it anymore
+fn ring_buffer_drained(mut cx: FunctionContext) -> JsResult<JsUndefined> {
+    let view = args::argument::<JsUint8Array>(&mut cx, "ring_buffer_drained", 0, "buffer")?;
+    let key = view.as_slice(&cx).as_ptr() as usize;
+
+    if let Some(drained) = WRITERS.lock().unwrap().get(&key) {
+        drained.notify_one();
+    }
+
+    Ok(cx.undefined())
+}
+
+pub fn export(cx: &mut ModuleContext) -> NeonResult<()> {
+    cx.export_function("ring_buffer_drained", ring_buffer_drained)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn free_counts_the_bytes_between_head_and_tail() {
+        assert_eq!(free(0, 0, 1024), 1024);
+        assert_eq!(free(1000, 24, 1024), 48);
+        assert_eq!(free(1024, 0, 1024), 0);
+    }
+
+    #[test]
+    fn free_handles_wrapping_counters() {
+        assert_eq!(free(10, u32::MAX - 5, 1024), 1008);
+        assert_eq!(free(1018, u32::MAX - 5, 1024), 0);
+    }
+
+    #[test]
+    fn free_leaves_no_room_for_a_corrupt_tail() {
+        // JavaScript moved the tail past the head, or too far behind it
+        assert_eq!(free(100, 200, 1024), 0);
+        assert_eq!(free(5000, 0, 1024), 0);
+    }
+}
@@ -0,0 +1,195 @@
+use std::{fmt, future::Future, sync::Mutex, thread, time::Duration};
+
+use tokio::{
+    runtime::{Builder, Handle, Runtime},
+    sync::oneshot,
+};
+
+/// Options for the global runtime, set with `init` before it is first used
+#[derive(Clone, Debug, Default)]
+pub struct RuntimeConfig {
+    /// `Some(0)` runs everything on a single driver thread. Defaults to one worker per core
+    pub worker_threads: Option<usize>,
+    pub thread_name: Option<String>,
+    pub thread_stack_size: Option<usize>,
+}
+
+impl RuntimeConfig {
+    fn build(&self) -> std::io::Result<ClientRuntime> {
+        let mut builder = match self.worker_threads {
+            Some(0) => Builder::new_current_thread(),
+            Some(workers) => {
+                let mut builder = Builder::new_multi_thread();
+                builder.worker_threads(workers);
+                builder
+            }
+            None => Builder::new_multi_thread(),
+        };
+
+        builder.enable_all();
+        if let Some(name) = &self.thread_name {
+            builder.thread_name(name);
+        }
+        if let Some(size) = self.thread_stack_size {
+            builder.thread_stack_size(size);
+        }
+
+        let rt = builder.build()?;
+
+        if self.worker_threads != Some(0) {
+            return Ok(ClientRuntime::MultiThread(rt));
+        }
+
+        // A current thread runtime only makes progress while something blocks on it
+        let handle = rt.handle().clone();
+        let (stop, stopped) = oneshot::channel();
+
+        let mut driver = thread::Builder::new().name(
+            self.thread_name
+                .clone()
+                .unwrap_or_else(|| String::from("tokio-runtime-driver")),
+        );
+        if let Some(size) = self.thread_stack_size {
+            driver = driver.stack_size(size);
+        }
+
+        let driver = driver.spawn(move || {
+            rt.block_on(async {
+                let _ = stopped.await;
+            });
+
+            rt
+        })?;
+
+        Ok(ClientRuntime::CurrentThread {
+            handle,
+            stop,
+            driver,
+        })
+    }
+}
+
+pub enum ClientRuntime {
+    MultiThread(Runtime),
+    CurrentThread {
+        handle: Handle,
+        stop: oneshot::Sender<()>,
+        driver: thread::JoinHandle<Runtime>,
+    },
+}
+
+impl ClientRuntime {
+    pub fn handle(&self) -> &Handle {
+        match self {
+            ClientRuntime::MultiThread(rt) => rt.handle(),
+            ClientRuntime::CurrentThread { handle, .. } => handle,
+        }
+    }
+
+    /// Runs `drain` to completion and then stops the runtime, giving its remaining tasks up to
+    ///  `timeout` to finish. Blocks, so it can't be called from within the runtime.
+    pub fn shutdown<F: Future>(self, drain: F, timeout: Duration) {
+        let rt = match self {
+            ClientRuntime::MultiThread(rt) => {
+                rt.block_on(drain);
+                rt
+            }
+            ClientRuntime::CurrentThread {
+                handle,
+                stop,
+                driver,
+            } => {
+                // The driver thread keeps polling the IO and timer drivers meanwhile
+                handle.block_on(drain);
+                let _ = stop.send(());
+
+                match driver.join() {
+                    Ok(rt) => rt,
+                    Err(_) => return,
+                }
+            }
+        };
+
+        rt.shutdown_timeout(timeout);
+    }
+}
+
+enum RuntimeState {
+    Uninitialized(RuntimeConfig),
+    Running(ClientRuntime),
+    // The runtime is not recreated after `shutdown`, so handles created before can't end up on a
+    //  runtime which doesn't know about their connections
+    ShutDown,
+}
+
+static RUNTIME: Mutex<RuntimeState> = Mutex::new(RuntimeState::Uninitialized(RuntimeConfig {
+    worker_threads: None,
+    thread_name: None,
+    thread_stack_size: None,
+}));
+
+#[derive(Debug)]
+pub enum RuntimeError {
+    Build(std::io::Error),
+    AlreadyStarted,
+    ShutDown,
+}
+
+impl fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RuntimeError::Build(e) => write!(f, "Unable to start the runtime: {e}"),
+            RuntimeError::AlreadyStarted => {
+                f.write_str("The runtime is already running, init has to be called first")
+            }
+            RuntimeError::ShutDown => f.write_str("The client has been shut down"),
+        }
+    }
+}
+
+/// Replace the options used to build the runtime. Fails once it has been started
+pub fn configure(config: RuntimeConfig) -> Result<(), RuntimeError> {
+    let mut state = RUNTIME.lock().unwrap();
+
+    match &*state {
+        RuntimeState::Uninitialized(_) => {
+            *state = RuntimeState::Uninitialized(config);
+
+            Ok(())
+        }
+        RuntimeState::Running(_) => Err(RuntimeError::AlreadyStarted),
+        RuntimeState::ShutDown => Err(RuntimeError::ShutDown),
+    }
+}
+
+/// Handle to the global runtime, which is started on first use
+pub fn handle() -> Result<Handle, RuntimeError> {
+    let mut state = RUNTIME.lock().unwrap();
+
+    let rt = match &*state {
+        RuntimeState::Running(rt) => return Ok(rt.handle().clone()),
+        RuntimeState::ShutDown => return Err(RuntimeError::ShutDown),
+        RuntimeState::Uninitialized(config) => config.build().map_err(RuntimeError::Build)?,
+    };
+    let handle = rt.handle().clone();
+
+    *state = RuntimeState::Running(rt);
+
+    Ok(handle)
+}
+
+/// Handle to the global runtime if it is running, without starting it
+pub fn running() -> Option<Handle> {
+    match &*RUNTIME.lock().unwrap() {
+        RuntimeState::Running(rt) => Some(rt.handle().clone()),
+        _ => None,
+    }
+}
+
+/// Marks the runtime as shut down and returns it, `None` if it wasn't running
+pub fn take() -> Option<ClientRuntime> {
+    match std::mem::replace(&mut *RUNTIME.lock().unwrap(), RuntimeState::ShutDown) {
+        RuntimeState::Running(rt) => Some(rt),
+        _ => None,
+    }
+}
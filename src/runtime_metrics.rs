@@ -0,0 +1,59 @@
+use neon::prelude::*;
+
+use crate::runtime;
+
+#[cfg(not(tokio_unstable))]
+compile_error!(
+    "the runtime-metrics feature requires building with RUSTFLAGS=\"--cfg tokio_unstable\""
+);
+
+// Snapshot of the runtime's scheduler, to tell apart callbacks being late because the runtime is
+//  busy from JavaScript not getting to them. `null` while the runtime isn't running
+fn runtime_metrics(mut cx: FunctionContext) -> JsResult<JsValue> {
+    let Some(rt) = runtime::running() else {
+        return Ok(cx.null().upcast());
+    };
+
+    let metrics = rt.metrics();
+    let workers = metrics.num_workers();
+
+    let (parks, unparks, local_queue_depth) =
+        (0..workers).fold((0, 0, 0), |(parks, unparks, depth), worker| {
+            (
+                parks + metrics.worker_park_count(worker),
+                unparks + metrics.worker_park_unpark_count(worker),
+                depth + metrics.worker_local_queue_depth(worker),
+            )
+        });
+
+    let result = cx.empty_object();
+
+    let worker_count = cx.number(workers as f64);
+    let park_count = cx.number(parks as f64);
+    let unpark_count = cx.number(unparks as f64);
+    let global_queue_depth = cx.number(metrics.global_queue_depth() as f64);
+    let local_queue_depth = cx.number(local_queue_depth as f64);
+    let blocking_threads = cx.number(metrics.num_blocking_threads() as f64);
+    let idle_blocking_threads = cx.number(metrics.num_idle_blocking_threads() as f64);
+    let blocking_queue_depth = cx.number(metrics.blocking_queue_depth() as f64);
+    // The runtime only runs the addon's own tasks, so these are exactly the ones it spawned
+    let alive_tasks = cx.number(metrics.num_alive_tasks() as f64);
+
+    result.set(&mut cx, "workers", worker_count)?;
+    result.set(&mut cx, "parkCount", park_count)?;
+    result.set(&mut cx, "unparkCount", unpark_count)?;
+    result.set(&mut cx, "globalQueueDepth", global_queue_depth)?;
+    result.set(&mut cx, "localQueueDepth", local_queue_depth)?;
+    result.set(&mut cx, "blockingThreads", blocking_threads)?;
+    result.set(&mut cx, "idleBlockingThreads", idle_blocking_threads)?;
+    result.set(&mut cx, "blockingQueueDepth", blocking_queue_depth)?;
+    result.set(&mut cx, "aliveTasks", alive_tasks)?;
+
+    Ok(result.upcast())
+}
+
+pub fn export(cx: &mut ModuleContext) -> NeonResult<()> {
+    cx.export_function("runtime_metrics", runtime_metrics)?;
+
+    Ok(())
+}
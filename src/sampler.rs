@@ -0,0 +1,299 @@
+use crate::histogram::{self, Histogram};
+use std::{
+    collections::VecDeque,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+/// How often the statistics of a connection are sampled
+pub const SAMPLE_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Window used for the throughput estimate when none is configured
+pub const DEFAULT_THROUGHPUT_WINDOW: Duration = Duration::from_secs(1);
+
+/// How long samples are kept around. This is the longest window that can be queried
+pub const HISTORY: Duration = Duration::from_secs(60);
+
+#[derive(Clone, Copy, Debug)]
+pub struct Sample {
+    pub at: Instant,
+    pub sent_packets: u64,
+    pub lost_packets: u64,
+    pub udp_tx_bytes: u64,
+    pub udp_rx_bytes: u64,
+    pub cwnd: u64,
+    pub rtt: Duration,
+    pub congestion_events: u64,
+}
+
+impl Sample {
+    pub fn new(stats: &quinn::ConnectionStats) -> Self {
+        Self {
+            at: Instant::now(),
+            sent_packets: stats.path.sent_packets,
+            lost_packets: stats.path.lost_packets,
+            udp_tx_bytes: stats.udp_tx.bytes,
+            udp_rx_bytes: stats.udp_rx.bytes,
+            cwnd: stats.path.cwnd,
+            rtt: stats.path.rtt,
+            congestion_events: stats.path.congestion_events,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CongestionEventKind {
+    SlowStartExit,
+    CongestionEvent,
+    Recovered,
+}
+
+impl CongestionEventKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CongestionEventKind::SlowStartExit => "slowStartExit",
+            CongestionEventKind::CongestionEvent => "congestionEvent",
+            CongestionEventKind::Recovered => "recovered",
+        }
+    }
+}
+
+/// Classifies congestion controller transitions by diffing consecutive samples.
+///
+/// Quinn doesn't report these transitions directly, so the rules are deliberately conservative;
+///  missing an event is preferred over reporting one that didn't happen:
+/// - `congestionEvent`: the `congestion_events` counter increased since the previous sample.
+///    Multiple events within a single sample interval are reported once.
+/// - `slowStartExit`: the first congestion event of the connection, reported right before it.
+///    The loss based controllers leave slow start on their first congestion event.
+/// - `recovered`: the congestion window grew back to at least the size it had right before the
+///    congestion event that started the recovery.
+#[derive(Default)]
+struct CongestionTracker {
+    previous: Option<Sample>,
+    left_slow_start: bool,
+    recovery_target: Option<u64>,
+}
+
+impl CongestionTracker {
+    fn update(&mut self, sample: Sample, events: &mut Vec<Event>) {
+        if let Some(previous) = self.previous {
+            if sample.congestion_events > previous.congestion_events {
+                if !self.left_slow_start {
+                    self.left_slow_start = true;
+                    events.push(Event::Congestion(
+                        CongestionEventKind::SlowStartExit,
+                        sample,
+                    ));
+                }
+
+                events.push(Event::Congestion(
+                    CongestionEventKind::CongestionEvent,
+                    sample,
+                ));
+
+                // Another event during recovery doesn't lower the window we have to get back to
+                self.recovery_target.get_or_insert(previous.cwnd);
+            } else if let Some(target) = self.recovery_target {
+                if sample.cwnd >= target {
+                    self.recovery_target = None;
+                    events.push(Event::Congestion(CongestionEventKind::Recovered, sample));
+                }
+            }
+        }
+
+        self.previous = Some(sample);
+    }
+}
+
+/// Bytes per second sent and received on the UDP socket
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Throughput {
+    pub send: f64,
+    pub recv: f64,
+}
+
+/// Configuration of the congestion window collapse warning
+#[derive(Clone, Copy, Debug)]
+pub struct CwndWarning {
+    pub threshold: u64,
+    pub sustain: Duration,
+}
+
+// Fires once the congestion window stayed below the threshold for the sustain duration.
+//  Rearms once the window is at or above the threshold again, so it fires at most once per dip.
+struct CwndWatch {
+    config: CwndWarning,
+    below_since: Option<Instant>,
+    fired: bool,
+}
+
+impl CwndWatch {
+    fn update(&mut self, sample: Sample, events: &mut Vec<Event>) {
+        if sample.cwnd >= self.config.threshold {
+            self.below_since = None;
+            self.fired = false;
+            return;
+        }
+
+        let below_since = *self.below_since.get_or_insert(sample.at);
+
+        if !self.fired && sample.at.duration_since(below_since) >= self.config.sustain {
+            self.fired = true;
+            events.push(Event::CwndWarning(sample));
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum Event {
+    Congestion(CongestionEventKind, Sample),
+    CwndWarning(Sample),
+}
+
+#[derive(Default)]
+struct State {
+    history: VecDeque<Sample>,
+    congestion: CongestionTracker,
+    rtt_histogram: Option<Histogram>,
+    cwnd_watch: Option<CwndWatch>,
+}
+
+impl State {
+    fn push(&mut self, sample: Sample) {
+        self.history.push_back(sample);
+
+        // Keep a single sample older than `HISTORY` so a window of exactly `HISTORY` still has a
+        //  starting point
+        while self.history.len() > 1 && sample.at.duration_since(self.history[1].at) >= HISTORY {
+            self.history.pop_front();
+        }
+    }
+
+    // Returns the newest sample together with the newest sample that is at least `window` older.
+    //  Without a window the starting point is the start of the connection.
+    fn window(&self, window: Option<Duration>) -> Option<(Option<&Sample>, &Sample)> {
+        let newest = self.history.back()?;
+
+        match window {
+            None => Some((None, newest)),
+            Some(window) => self
+                .history
+                .iter()
+                .rev()
+                .find(|sample| newest.at.duration_since(sample.at) >= window)
+                .map(|start| (Some(start), newest)),
+        }
+    }
+}
+
+/// Per connection state derived from periodic statistics samples.
+///  Shared between the sampling task and the exports reading from it.
+pub struct Sampler {
+    throughput_window: Duration,
+    state: Mutex<State>,
+}
+
+impl Sampler {
+    pub fn new(
+        throughput_window: Duration,
+        rtt_histogram: bool,
+        cwnd_warning: Option<CwndWarning>,
+    ) -> Self {
+        Self {
+            throughput_window,
+            state: Mutex::new(State {
+                rtt_histogram: rtt_histogram.then(Histogram::default),
+                cwnd_watch: cwnd_warning.map(|config| CwndWatch {
+                    config,
+                    below_since: None,
+                    fired: false,
+                }),
+                ..State::default()
+            }),
+        }
+    }
+
+    /// Record a new sample, returning the events it triggered
+    pub fn record(&self, stats: &quinn::ConnectionStats) -> Vec<Event> {
+        let sample = Sample::new(stats);
+        let mut events = Vec::new();
+
+        let mut state = self.state.lock().unwrap();
+        state.push(sample);
+        state.congestion.update(sample, &mut events);
+
+        if let Some(histogram) = state.rtt_histogram.as_mut() {
+            histogram.record(sample.rtt);
+        }
+
+        if let Some(cwnd_watch) = state.cwnd_watch.as_mut() {
+            cwnd_watch.update(sample, &mut events);
+        }
+
+        events
+    }
+
+    /// Percentage of the sent packets that got lost within the trailing `window`, or during the
+    ///  whole connection without a window. `None` when there aren't enough samples to cover the
+    ///  window yet.
+    pub fn loss_rate(&self, window: Option<Duration>) -> Option<f64> {
+        let state = self.state.lock().unwrap();
+        let (start, end) = state.window(window)?;
+
+        let (sent, lost) = match start {
+            None => (end.sent_packets, end.lost_packets),
+            Some(start) => (
+                end.sent_packets - start.sent_packets,
+                end.lost_packets - start.lost_packets,
+            ),
+        };
+
+        if sent == 0 {
+            return Some(0.0);
+        }
+
+        Some(lost as f64 / sent as f64 * 100.0)
+    }
+
+    /// Summary of the RTT observations, `None` when the histogram wasn't enabled.
+    ///  When `reset` is set the histogram is cleared while holding the same lock, so no
+    ///  observations get lost between taking the summary and the reset.
+    pub fn rtt_histogram(&self, reset: bool) -> Option<histogram::Summary> {
+        let mut state = self.state.lock().unwrap();
+        let histogram = state.rtt_histogram.as_mut()?;
+        let summary = histogram.summary();
+
+        if reset {
+            *histogram = Histogram::default();
+        }
+
+        Some(summary)
+    }
+
+    /// Throughput over the configured window. Uses the oldest sample when the connection is
+    ///  younger than the window, and reports 0 until there are at least two samples.
+    pub fn throughput(&self) -> Throughput {
+        let state = self.state.lock().unwrap();
+
+        let Some(end) = state.history.back() else {
+            return Throughput::default();
+        };
+
+        let start = match state.window(Some(self.throughput_window)) {
+            Some((Some(start), _)) => start,
+            _ => &state.history[0],
+        };
+
+        let elapsed = end.at.duration_since(start.at).as_secs_f64();
+
+        if elapsed == 0.0 {
+            return Throughput::default();
+        }
+
+        Throughput {
+            send: (end.udp_tx_bytes - start.udp_tx_bytes) as f64 / elapsed,
+            recv: (end.udp_rx_bytes - start.udp_rx_bytes) as f64 / elapsed,
+        }
+    }
+}
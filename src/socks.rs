@@ -0,0 +1,375 @@
+use std::{
+    fmt,
+    io::{self, IoSliceMut},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    pin::Pin,
+    sync::Arc,
+    task::{ready, Context, Poll},
+};
+
+use quinn::{
+    udp::{RecvMeta, Transmit},
+    AsyncUdpSocket, UdpPoller,
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt, ReadBuf},
+    net::{TcpStream, UdpSocket},
+};
+
+use crate::errors::ErrorCode;
+
+const VERSION: u8 = 5;
+const NO_AUTHENTICATION: u8 = 0x00;
+const USERNAME_PASSWORD: u8 = 0x02;
+const NO_ACCEPTABLE_METHOD: u8 = 0xff;
+// Version of the username/password sub-negotiation, RFC 1929
+const USERNAME_PASSWORD_VERSION: u8 = 1;
+const UDP_ASSOCIATE: u8 = 0x03;
+const SUCCEEDED: u8 = 0x00;
+
+const ATYP_IPV4: u8 = 0x01;
+const ATYP_DOMAIN: u8 = 0x03;
+const ATYP_IPV6: u8 = 0x04;
+
+/// SOCKS5 proxy the UDP traffic of an endpoint is relayed through, using UDP ASSOCIATE
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Proxy {
+    pub host: String,
+    pub port: u16,
+    pub credentials: Option<(String, String)>,
+}
+
+#[derive(Debug)]
+pub enum ProxyError {
+    /// The proxy couldn't be reached, or closed the control connection during the handshake
+    Connect(io::Error),
+    /// None of the offered authentication methods was accepted
+    NoAcceptableMethod,
+    /// The username and password were rejected
+    AuthRejected,
+    /// UDP ASSOCIATE was refused with the given reply code
+    Rejected(u8),
+    /// The proxy sent something which isn't valid SOCKS5
+    Protocol(&'static str),
+    /// The local UDP socket couldn't be set up
+    Io(io::Error),
+}
+
+impl ProxyError {
+    #[allow(clippy::inherent_to_string)]
+    pub fn to_string(&self) -> String {
+        match self {
+            ProxyError::Connect(e) => format!("Unable to connect to the proxy: {e}"),
+            ProxyError::NoAcceptableMethod => {
+                String::from("The proxy accepts none of the offered authentication methods")
+            }
+            ProxyError::AuthRejected => String::from("The proxy rejected the username or password"),
+            ProxyError::Rejected(code) => {
+                format!("The proxy refused UDP ASSOCIATE: {}", reply_message(*code))
+            }
+            ProxyError::Protocol(message) => format!("Invalid response from the proxy: {message}"),
+            ProxyError::Io(e) => e.to_string(),
+        }
+    }
+
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            ProxyError::Connect(_) => ErrorCode::ProxyConnect,
+            ProxyError::NoAcceptableMethod | ProxyError::AuthRejected => ErrorCode::ProxyAuth,
+            ProxyError::Rejected(_) => ErrorCode::ProxyRejected,
+            ProxyError::Protocol(_) => ErrorCode::ProxyProtocol,
+            ProxyError::Io(_) => ErrorCode::Io,
+        }
+    }
+
+    /// The reply code sent by the proxy, if it refused the request
+    pub fn reply_code(&self) -> Option<u8> {
+        match self {
+            ProxyError::Rejected(code) => Some(*code),
+            _ => None,
+        }
+    }
+}
+
+// RFC 1928, section 6
+fn reply_message(code: u8) -> &'static str {
+    match code {
+        0x01 => "general SOCKS server failure",
+        0x02 => "connection not allowed by ruleset",
+        0x03 => "network unreachable",
+        0x04 => "host unreachable",
+        0x05 => "connection refused",
+        0x06 => "TTL expired",
+        0x07 => "command not supported",
+        0x08 => "address type not supported",
+        _ => "unknown reply code",
+    }
+}
+
+/// Binds an endpoint whose datagrams are relayed by `proxy`. Has to be called from within the
+///  runtime
+pub async fn bind_endpoint(proxy: &Proxy) -> Result<quinn::Endpoint, ProxyError> {
+    let (control, relay) = associate(proxy).await?;
+
+    let unspecified = match relay {
+        SocketAddr::V4(_) => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+        SocketAddr::V6(_) => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+    };
+    let io = std::net::UdpSocket::bind(SocketAddr::new(unspecified, 0)).map_err(ProxyError::Io)?;
+    io.set_nonblocking(true).map_err(ProxyError::Io)?;
+    let io = UdpSocket::from_std(io).map_err(ProxyError::Io)?;
+
+    let socket = SocksSocket {
+        io,
+        relay,
+        _control: control,
+    };
+
+    quinn::Endpoint::new_with_abstract_socket(
+        quinn::EndpointConfig::default(),
+        None,
+        Arc::new(socket),
+        Arc::new(quinn::TokioRuntime),
+    )
+    .map_err(ProxyError::Io)
+}
+
+// Negotiates authentication and asks for a UDP relay, returning the control connection and the
+//  address datagrams have to be sent to
+async fn associate(proxy: &Proxy) -> Result<(TcpStream, SocketAddr), ProxyError> {
+    let mut control = TcpStream::connect((proxy.host.as_str(), proxy.port))
+        .await
+        .map_err(ProxyError::Connect)?;
+    let proxy_addr = control.peer_addr().map_err(ProxyError::Connect)?;
+
+    let methods: &[u8] = match proxy.credentials {
+        None => &[NO_AUTHENTICATION],
+        Some(_) => &[NO_AUTHENTICATION, USERNAME_PASSWORD],
+    };
+    let mut greeting = vec![VERSION, methods.len() as u8];
+    greeting.extend_from_slice(methods);
+    control
+        .write_all(&greeting)
+        .await
+        .map_err(ProxyError::Connect)?;
+
+    let mut choice = [0; 2];
+    control
+        .read_exact(&mut choice)
+        .await
+        .map_err(ProxyError::Connect)?;
+    if choice[0] != VERSION {
+        return Err(ProxyError::Protocol("unexpected SOCKS version"));
+    }
+
+    match (choice[1], &proxy.credentials) {
+        (NO_AUTHENTICATION, _) => {}
+        (USERNAME_PASSWORD, Some((username, password))) => {
+            let mut request = vec![USERNAME_PASSWORD_VERSION, username.len() as u8];
+            request.extend_from_slice(username.as_bytes());
+            request.push(password.len() as u8);
+            request.extend_from_slice(password.as_bytes());
+            control
+                .write_all(&request)
+                .await
+                .map_err(ProxyError::Connect)?;
+
+            let mut status = [0; 2];
+            control
+                .read_exact(&mut status)
+                .await
+                .map_err(ProxyError::Connect)?;
+            if status[1] != SUCCEEDED {
+                return Err(ProxyError::AuthRejected);
+            }
+        }
+        (NO_ACCEPTABLE_METHOD, _) => return Err(ProxyError::NoAcceptableMethod),
+        _ => return Err(ProxyError::Protocol("unexpected authentication method")),
+    }
+
+    // The address datagrams are sent from isn't known before they pass any NAT, all zeros lets
+    //  the proxy accept them from whichever address they arrive from
+    control
+        .write_all(&[VERSION, UDP_ASSOCIATE, 0, ATYP_IPV4, 0, 0, 0, 0, 0, 0])
+        .await
+        .map_err(ProxyError::Connect)?;
+
+    let mut reply = [0; 3];
+    control
+        .read_exact(&mut reply)
+        .await
+        .map_err(ProxyError::Connect)?;
+    if reply[0] != VERSION {
+        return Err(ProxyError::Protocol("unexpected SOCKS version"));
+    }
+    if reply[1] != SUCCEEDED {
+        return Err(ProxyError::Rejected(reply[1]));
+    }
+
+    let mut relay = read_address(&mut control).await?;
+    // Proxies commonly answer with the unspecified address to mean their own
+    if relay.ip().is_unspecified() {
+        relay.set_ip(proxy_addr.ip());
+    }
+
+    Ok((control, relay))
+}
+
+async fn read_address(control: &mut TcpStream) -> Result<SocketAddr, ProxyError> {
+    let ip = match control.read_u8().await.map_err(ProxyError::Connect)? {
+        ATYP_IPV4 => {
+            let mut ip = [0; 4];
+            control
+                .read_exact(&mut ip)
+                .await
+                .map_err(ProxyError::Connect)?;
+
+            IpAddr::from(ip)
+        }
+        ATYP_IPV6 => {
+            let mut ip = [0; 16];
+            control
+                .read_exact(&mut ip)
+                .await
+                .map_err(ProxyError::Connect)?;
+
+            IpAddr::from(ip)
+        }
+        ATYP_DOMAIN => return Err(ProxyError::Protocol("the relay address is a domain name")),
+        _ => return Err(ProxyError::Protocol("unknown address type")),
+    };
+    let port = control.read_u16().await.map_err(ProxyError::Connect)?;
+
+    Ok(SocketAddr::new(ip, port))
+}
+
+/// Bytes of the header in front of every datagram to or from `addr`, which QUIC can't use
+pub fn header_len(addr: SocketAddr) -> u16 {
+    match addr {
+        SocketAddr::V4(_) => 4 + 4 + 2,
+        SocketAddr::V6(_) => 4 + 16 + 2,
+    }
+}
+
+// Every datagram starts with RSV (2 bytes), FRAG and the address it's sent to, or came from
+fn encode_header(packet: &mut Vec<u8>, addr: SocketAddr) {
+    packet.extend_from_slice(&[0, 0, 0]);
+
+    match addr.ip() {
+        IpAddr::V4(ip) => {
+            packet.push(ATYP_IPV4);
+            packet.extend_from_slice(&ip.octets());
+        }
+        IpAddr::V6(ip) => {
+            packet.push(ATYP_IPV6);
+            packet.extend_from_slice(&ip.octets());
+        }
+    }
+
+    packet.extend_from_slice(&addr.port().to_be_bytes());
+}
+
+// The source address and the length of the header. Fragmented datagrams aren't supported, QUIC
+//  packets always fit in a single one
+fn decode_header(packet: &[u8]) -> Option<(SocketAddr, usize)> {
+    let (&[0, 0, 0, atyp], rest) = packet.split_first_chunk::<4>()? else {
+        return None;
+    };
+
+    let (ip, rest, len) = match atyp {
+        ATYP_IPV4 => {
+            let (ip, rest) = rest.split_first_chunk::<4>()?;
+            (IpAddr::from(*ip), rest, 4 + 4)
+        }
+        ATYP_IPV6 => {
+            let (ip, rest) = rest.split_first_chunk::<16>()?;
+            (IpAddr::from(*ip), rest, 4 + 16)
+        }
+        _ => return None,
+    };
+    let (port, _) = rest.split_first_chunk::<2>()?;
+
+    Some((SocketAddr::new(ip, u16::from_be_bytes(*port)), len + 2))
+}
+
+// Socket quinn sends through instead of its own, wrapping every datagram for the relay. Datagrams
+//  are reported as coming from the address in their header, so the connection only ever sees the
+//  server's address
+struct SocksSocket {
+    io: UdpSocket,
+    relay: SocketAddr,
+    // The proxy ends the association once it's closed, it's kept open as long as the endpoint
+    //  uses the socket
+    _control: TcpStream,
+}
+
+impl fmt::Debug for SocksSocket {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SocksSocket")
+            .field("relay", &self.relay)
+            .finish_non_exhaustive()
+    }
+}
+
+impl AsyncUdpSocket for SocksSocket {
+    fn create_io_poller(self: Arc<Self>) -> Pin<Box<dyn UdpPoller>> {
+        Box::pin(Poller(self))
+    }
+
+    fn try_send(&self, transmit: &Transmit) -> io::Result<()> {
+        let mut packet = Vec::with_capacity(
+            usize::from(header_len(transmit.destination)) + transmit.contents.len(),
+        );
+        encode_header(&mut packet, transmit.destination);
+        packet.extend_from_slice(transmit.contents);
+
+        self.io.try_send_to(&packet, self.relay).map(|_| ())
+    }
+
+    fn poll_recv(
+        &self,
+        cx: &mut Context,
+        bufs: &mut [IoSliceMut<'_>],
+        meta: &mut [RecvMeta],
+    ) -> Poll<io::Result<usize>> {
+        loop {
+            let (from, len) = {
+                let mut buf = ReadBuf::new(&mut bufs[0]);
+                let from = ready!(self.io.poll_recv_from(cx, &mut buf))?;
+
+                (from, buf.filled().len())
+            };
+
+            // Anything else is dropped, like a corrupted packet would be
+            if from.ip() != self.relay.ip() {
+                continue;
+            }
+            let Some((source, header)) = decode_header(&bufs[0][..len]) else {
+                continue;
+            };
+
+            bufs[0].copy_within(header..len, 0);
+
+            meta[0].addr = source;
+            meta[0].len = len - header;
+            meta[0].stride = len - header;
+            meta[0].ecn = None;
+            meta[0].dst_ip = None;
+
+            return Poll::Ready(Ok(1));
+        }
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.io.local_addr()
+    }
+}
+
+#[derive(Debug)]
+struct Poller(Arc<SocksSocket>);
+
+impl UdpPoller for Poller {
+    fn poll_writable(self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+        self.0.io.poll_send_ready(cx)
+    }
+}
@@ -0,0 +1,113 @@
+use neon::prelude::*;
+
+fn set_numbers<'a, C: Context<'a>>(
+    cx: &mut C,
+    object: Handle<JsObject>,
+    values: &[(&str, f64)],
+) -> NeonResult<()> {
+    for (key, value) in values {
+        let value = cx.number(*value);
+        object.set(cx, *key, value)?;
+    }
+
+    Ok(())
+}
+
+fn udp_stats<'a, C: Context<'a>>(cx: &mut C, stats: &quinn::UdpStats) -> JsResult<'a, JsObject> {
+    let result = cx.empty_object();
+
+    set_numbers(
+        cx,
+        result,
+        &[
+            ("datagrams", stats.datagrams as f64),
+            ("bytes", stats.bytes as f64),
+            ("ios", stats.ios as f64),
+        ],
+    )?;
+
+    Ok(result)
+}
+
+fn frame_stats<'a, C: Context<'a>>(
+    cx: &mut C,
+    stats: &quinn::FrameStats,
+) -> JsResult<'a, JsObject> {
+    let result = cx.empty_object();
+
+    set_numbers(
+        cx,
+        result,
+        &[
+            ("acks", stats.acks as f64),
+            ("crypto", stats.crypto as f64),
+            ("connectionClose", stats.connection_close as f64),
+            ("dataBlocked", stats.data_blocked as f64),
+            ("datagram", stats.datagram as f64),
+            ("handshakeDone", stats.handshake_done as f64),
+            ("maxData", stats.max_data as f64),
+            ("maxStreamData", stats.max_stream_data as f64),
+            ("maxStreamsBidi", stats.max_streams_bidi as f64),
+            ("maxStreamsUni", stats.max_streams_uni as f64),
+            ("newConnectionId", stats.new_connection_id as f64),
+            ("newToken", stats.new_token as f64),
+            ("pathChallenge", stats.path_challenge as f64),
+            ("pathResponse", stats.path_response as f64),
+            ("ping", stats.ping as f64),
+            ("resetStream", stats.reset_stream as f64),
+            ("retireConnectionId", stats.retire_connection_id as f64),
+            ("streamDataBlocked", stats.stream_data_blocked as f64),
+            ("streamsBlockedBidi", stats.streams_blocked_bidi as f64),
+            ("streamsBlockedUni", stats.streams_blocked_uni as f64),
+            ("stopSending", stats.stop_sending as f64),
+            ("stream", stats.stream as f64),
+        ],
+    )?;
+
+    Ok(result)
+}
+
+fn path_stats<'a, C: Context<'a>>(cx: &mut C, stats: &quinn::PathStats) -> JsResult<'a, JsObject> {
+    let result = cx.empty_object();
+
+    set_numbers(
+        cx,
+        result,
+        &[
+            ("rttMs", stats.rtt.as_secs_f64() * 1000.0),
+            ("cwnd", stats.cwnd as f64),
+            ("congestionEvents", stats.congestion_events as f64),
+            ("lostPackets", stats.lost_packets as f64),
+            ("lostBytes", stats.lost_bytes as f64),
+            ("sentPackets", stats.sent_packets as f64),
+            ("sentPlpmtudProbes", stats.sent_plpmtud_probes as f64),
+            ("lostPlpmtudProbes", stats.lost_plpmtud_probes as f64),
+            ("blackHolesDetected", stats.black_holes_detected as f64),
+            ("currentMtu", stats.current_mtu as f64),
+        ],
+    )?;
+
+    Ok(result)
+}
+
+/// Convert the statistics of a connection into a plain JavaScript object
+pub fn connection_stats<'a, C: Context<'a>>(
+    cx: &mut C,
+    stats: &quinn::ConnectionStats,
+) -> JsResult<'a, JsObject> {
+    let result = cx.empty_object();
+
+    let udp_tx = udp_stats(cx, &stats.udp_tx)?;
+    let udp_rx = udp_stats(cx, &stats.udp_rx)?;
+    let frame_tx = frame_stats(cx, &stats.frame_tx)?;
+    let frame_rx = frame_stats(cx, &stats.frame_rx)?;
+    let path = path_stats(cx, &stats.path)?;
+
+    result.set(cx, "udpTx", udp_tx)?;
+    result.set(cx, "udpRx", udp_rx)?;
+    result.set(cx, "frameTx", frame_tx)?;
+    result.set(cx, "frameRx", frame_rx)?;
+    result.set(cx, "path", path)?;
+
+    Ok(result)
+}
@@ -0,0 +1,32 @@
+use std::sync::Arc;
+
+use neon::prelude::*;
+
+/// Identifies a connection in logs and in the payloads passed to JavaScript
+#[derive(Clone, Debug)]
+pub struct ConnectionTag {
+    pub id: u64,
+    /// Opaque to the native layer, may be empty
+    pub label: Arc<str>,
+}
+
+impl ConnectionTag {
+    /// Set the `connectionId` and `label` properties on the given object
+    pub fn apply<'a, C: Context<'a>, O: Object>(
+        &self,
+        cx: &mut C,
+        object: Handle<O>,
+    ) -> NeonResult<()> {
+        let id = cx.number(self.id as f64);
+        let label = cx.string(&*self.label);
+
+        object.set(cx, "connectionId", id)?;
+        object.set(cx, "label", label)?;
+
+        Ok(())
+    }
+
+    pub fn span(&self) -> tracing::Span {
+        tracing::info_span!("connection", id = self.id, label = %self.label)
+    }
+}
@@ -14,16 +14,17 @@ where
         }
     }
 
-    pub fn peek<F, R>(&self, f: F) -> R
+    // Both return `None` once the value has been taken
+    pub fn peek<F, R>(&self, f: F) -> Option<R>
     where
         F: FnOnce(&T) -> R,
     {
         let guard = self.value.lock().unwrap();
 
-        guard.as_ref().map(f).unwrap()
+        guard.as_ref().map(f)
     }
 
-    pub fn take(&self) -> T {
-        self.value.lock().unwrap().take().unwrap()
+    pub fn take(&self) -> Option<T> {
+        self.value.lock().unwrap().take()
     }
 }
@@ -0,0 +1,265 @@
+use std::{
+    future::Future,
+    ops::Range,
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc,
+    },
+};
+
+use quinn::{ClosedStream, SendStream, VarInt, WriteError};
+use tokio::{
+    runtime::Handle,
+    sync::{mpsc, oneshot},
+};
+use tokio_util::sync::CancellationToken;
+
+use crate::{errors::NativeError, CancelToken};
+
+type WriteResult = Result<Result<(), WriteError>, NativeError>;
+
+enum Command {
+    Write {
+        data: Vec<u8>,
+        cancel_token: Option<CancelToken>,
+        done: Box<dyn FnOnce(WriteResult) + Send>,
+    },
+    Finish(oneshot::Sender<Result<(), ClosedStream>>),
+    Reset(VarInt, oneshot::Sender<Result<(), ClosedStream>>),
+}
+
+/// Send side of a stream, owned by a task which runs the commands in the order they were given.
+///  Commands are queued as soon as a method is called, the returned future only waits for the
+///  result, so calls made one after the other from JavaScript are also written in that order.
+///  A reset is the exception, it doesn't wait for the writes before it, see `reset`
+#[derive(Clone)]
+pub struct Writer {
+    commands: mpsc::UnboundedSender<Command>,
+    stopped: CancellationToken,
+    // Cancelled by `reset`, before its command is queued
+    reset_requested: CancellationToken,
+    // Bytes of the writes which didn't complete yet
+    buffered: Arc<AtomicUsize>,
+    // Bytes ever queued, the offset the next write starts at
+    queued: Arc<AtomicU64>,
+}
+
+impl Writer {
+    /// Writes larger than `chunk_size` are split up, see `write_chunked`
+    pub fn spawn(rt: &Handle, send: SendStream, chunk_size: usize) -> Self {
+        let (commands, queue) = mpsc::unbounded_channel();
+        let stopped = CancellationToken::new();
+        let reset_requested = CancellationToken::new();
+
+        let buffered = Arc::new(AtomicUsize::new(0));
+
+        rt.spawn(run(
+            send,
+            queue,
+            stopped.clone(),
+            reset_requested.clone(),
+            buffered.clone(),
+            chunk_size,
+        ));
+
+        Self {
+            commands,
+            stopped,
+            reset_requested,
+            buffered,
+            queued: Default::default(),
+        }
+    }
+
+    /// Writes all of `data`. A cancelled write may have been partially written already
+    pub fn write(
+        &self,
+        data: Vec<u8>,
+        cancel_token: Option<CancelToken>,
+    ) -> impl Future<Output = WriteResult> {
+        let (done, result) = oneshot::channel();
+        self.write_with(data, cancel_token, move |_, result| {
+            let _ = done.send(result);
+        });
+
+        async move { result.await.unwrap_or(Ok(Err(WriteError::ClosedStream))) }
+    }
+
+    /// Like `write`, calling `done` on the writer's task instead. It's given the range of the
+    ///  stream `data` was written to, counting every byte queued before it
+    pub fn write_with<F: FnOnce(Range<u64>, WriteResult) + Send + 'static>(
+        &self,
+        data: Vec<u8>,
+        cancel_token: Option<CancelToken>,
+        done: F,
+    ) {
+        let len = data.len();
+        let start = self.queued.fetch_add(len as u64, Ordering::Relaxed);
+        let range = start..start + len as u64;
+
+        self.buffered.fetch_add(len, Ordering::AcqRel);
+        let queued = self.commands.send(Command::Write {
+            data,
+            cancel_token,
+            done: Box::new(move |result| done(range, result)),
+        });
+
+        if let Err(mpsc::error::SendError(Command::Write { done, .. })) = queued {
+            self.buffered.fetch_sub(len, Ordering::AcqRel);
+            done(Ok(Err(WriteError::ClosedStream)));
+        }
+    }
+
+    /// Bytes which were queued but not written yet
+    pub fn buffered(&self) -> usize {
+        self.buffered.load(Ordering::Acquire)
+    }
+
+    /// Fails when the stream was already finished or reset
+    pub fn finish(&self) -> impl Future<Output = Result<(), ClosedStream>> {
+        let (done, result) = oneshot::channel();
+        let queued = self.commands.send(Command::Finish(done));
+
+        closed_unless_done(queued.is_ok(), result)
+    }
+
+    /// Like `finish`, without waiting for the result
+    pub fn finish_later(&self) {
+        let (done, _) = oneshot::channel();
+        let _ = self.commands.send(Command::Finish(done));
+    }
+
+    /// Resets the stream without waiting for the commands queued before it. A write which waits
+    ///  for flow control is abandoned, it fails with `ClosedStream` like the writes and finish
+    ///  queued between it and the reset
+    pub fn reset(&self, error_code: VarInt) -> impl Future<Output = Result<(), ClosedStream>> {
+        let (done, result) = oneshot::channel();
+        self.reset_requested.cancel();
+        let queued = self.commands.send(Command::Reset(error_code, done));
+
+        closed_unless_done(queued.is_ok(), result)
+    }
+
+    /// Resolves once the stream was finished or reset and the peer acknowledged everything
+    ///  written to it, or stopped it. Never resolves before either happened
+    pub async fn stopped(&self) {
+        self.stopped.cancelled().await
+    }
+}
+
+// Commands which are left after the task ended, e.g. because the runtime shut down, are rejected
+//  as if the stream was already closed
+async fn closed_unless_done(
+    queued: bool,
+    result: oneshot::Receiver<Result<(), ClosedStream>>,
+) -> Result<(), ClosedStream> {
+    if !queued {
+        return Err(ClosedStream::default());
+    }
+
+    result
+        .await
+        .unwrap_or_else(|_| Err(ClosedStream::default()))
+}
+
+// Quinn takes as much of a write as flow control allows at once, a large write would keep the
+//  writes of other streams waiting until it's buffered. Between chunks they get a chance to run
+async fn write_chunked(
+    send: &mut SendStream,
+    data: &[u8],
+    chunk_size: usize,
+) -> Result<(), WriteError> {
+    let mut chunks = data.chunks(chunk_size).peekable();
+
+    while let Some(chunk) = chunks.next() {
+        send.write_all(chunk).await?;
+
+        if chunks.peek().is_some() {
+            tokio::task::yield_now().await;
+        }
+    }
+
+    Ok(())
+}
+
+async fn run(
+    mut send: SendStream,
+    mut queue: mpsc::UnboundedReceiver<Command>,
+    stopped: CancellationToken,
+    reset_requested: CancellationToken,
+    buffered: Arc<AtomicUsize>,
+    chunk_size: usize,
+) {
+    // Set once `finish` or `reset` succeeded, nothing can be written afterwards
+    let mut closed = false;
+
+    loop {
+        let command = if closed {
+            tokio::select! {
+                command = queue.recv() => command,
+                _ = send.stopped() => {
+                    stopped.cancel();
+                    break;
+                }
+            }
+        } else {
+            queue.recv().await
+        };
+
+        let Some(command) = command else {
+            // Every handle is gone, which finishes the stream like dropping it did before
+            if !closed {
+                let _ = send.finish();
+            }
+            return;
+        };
+
+        match command {
+            Command::Write {
+                data,
+                cancel_token,
+                done,
+            } => {
+                // The reset is queued behind this write, which may never get the flow control
+                //  credit it waits for. Everything up to the reset is skipped once it's requested
+                let result = tokio::select! {
+                    biased;
+                    _ = reset_requested.cancelled() => Ok(Err(WriteError::ClosedStream)),
+                    result = CancelToken::run(
+                        cancel_token,
+                        write_chunked(&mut send, &data, chunk_size),
+                    ) => result,
+                };
+                buffered.fetch_sub(data.len(), Ordering::AcqRel);
+                done(result);
+            }
+            Command::Finish(done) => {
+                let result = if reset_requested.is_cancelled() {
+                    Err(ClosedStream::default())
+                } else {
+                    send.finish()
+                };
+                closed |= result.is_ok();
+                let _ = done.send(result);
+            }
+            Command::Reset(error_code, done) => {
+                let result = send.reset(error_code);
+                closed |= result.is_ok();
+                let _ = done.send(result);
+            }
+        }
+    }
+
+    // The stream is done, later commands fail right away
+    while let Some(command) = queue.recv().await {
+        match command {
+            Command::Write { data, done, .. } => {
+                buffered.fetch_sub(data.len(), Ordering::AcqRel);
+                done(Ok(Err(WriteError::ClosedStream)));
+            }
+            Command::Finish(done) | Command::Reset(_, done) => {
+                let _ = done.send(Err(ClosedStream::default()));
+            }
+        }
+    }
+}
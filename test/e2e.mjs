@@ -0,0 +1,2025 @@
+// Drives the native exports against the test server. Needs the test server and `--expose-gc`,
+// see the `test-e2e` script
+import assert from "node:assert/strict";
+import { execFileSync } from "node:child_process";
+import dgram from "node:dgram";
+import { readdirSync } from "node:fs";
+import { createRequire } from "node:module";
+import net from "node:net";
+import { after, before, describe, test } from "node:test";
+import { Worker } from "node:worker_threads";
+
+const require = createRequire(import.meta.url);
+const LIB = require.resolve(process.env.QUIC_LIB ?? "../dist/lib.node");
+const lib = require(LIB);
+
+const encoder = new TextEncoder();
+const decoder = new TextDecoder();
+
+const sleep = (ms) => new Promise((resolve) => setTimeout(resolve, ms));
+
+// Polls `condition` for up to `timeoutMs`
+const waitFor = async (condition, timeoutMs = 1000) => {
+  for (let i = 0; i < timeoutMs / 10 && !condition(); i++) {
+    await sleep(10);
+  }
+};
+
+// Server of a single test, closed once it ended
+const startServer = (t, options) => {
+  const started = lib.__test_server_start(options);
+  t.after(() => lib.__test_server_close(started.server));
+
+  return started;
+};
+
+// Arguments of the native `connect`, `callbacks` and `options` override the defaults
+const connectArguments = (
+  { port, certificate },
+  {
+    hostname = "localhost",
+    ipAddress = "127.0.0.1",
+    onStream,
+    onClose,
+    onError,
+    alpnProtocols,
+    clientAuthentication,
+  } = {},
+  options = {}
+) => [
+  port,
+  ipAddress,
+  hostname,
+  onStream ?? (() => {}),
+  onClose ?? (() => {}),
+  onError ?? (() => {}),
+  alpnProtocols,
+  [encoder.encode(certificate)],
+  clientAuthentication,
+  options,
+];
+
+const connect = (server, callbacks, options) =>
+  lib.connect(...connectArguments(server, callbacks, options));
+
+// Initializes a stream, `closed` resolves with everything it received once it closes
+const initialize = (partial, { onClose } = {}) => {
+  const chunks = [];
+  let resolve, reject;
+  const closed = new Promise((...settle) => ([resolve, reject] = settle));
+  closed.catch(() => {});
+
+  const stream = lib.initialize_stream(
+    partial,
+    (chunk) => chunks.push(Buffer.from(chunk)),
+    // Refers to `stream`, which keeps it from being garbage collected and stopped before it
+    // closed
+    (reason, error) => {
+      onClose?.(reason, error, stream);
+      error ? reject(error) : resolve(Buffer.concat(chunks));
+    },
+    reject
+  );
+
+  return { stream, closed };
+};
+
+// Partial streams passed to `onStream`, which are batched when `streamBatch` is set
+const partialStreams = (raw) =>
+  Array.isArray(raw) ? raw.map(({ stream }) => stream) : [raw];
+
+const request = async (connection, data) => {
+  const { stream, closed } = initialize(await lib.create_stream(connection));
+
+  await lib.write_stream(stream, data);
+  await lib.close_write(stream);
+
+  return closed;
+};
+
+const isQuicError = (code) => (error) => {
+  assert.equal(error.code, code, error.message);
+  assert.ok(Object.values(lib.errorCodes).includes(error.code));
+
+  return true;
+};
+
+// Relays UDP between clients and `server` while `forwarding` is set, so a test can cut a
+// connection off without either side closing it. Connect to the relay's `port` with the server's
+// `certificate`. `received` has the time every datagram of a client arrived at, forwarded or not
+const startRelay = async (t, server) => {
+  const relay = dgram.createSocket("udp4");
+  const upstreams = new Map();
+  const started = { forwarding: true, certificate: server.certificate, received: [] };
+
+  relay.on("message", (message, client) => {
+    started.received.push(performance.now());
+    if (!started.forwarding) {
+      return;
+    }
+
+    const key = `${client.address}:${client.port}`;
+    let upstream = upstreams.get(key);
+    if (!upstream) {
+      upstream = dgram.createSocket("udp4");
+      upstream.on("message", (reply) => {
+        if (started.forwarding) {
+          relay.send(reply, client.port, client.address);
+        }
+      });
+      upstreams.set(key, upstream);
+    }
+    upstream.send(message, server.port, "127.0.0.1");
+  });
+
+  await new Promise((resolve) => relay.bind(0, "127.0.0.1", resolve));
+  started.port = relay.address().port;
+  t.after(() => {
+    relay.close();
+    for (const upstream of upstreams.values()) {
+      upstream.close();
+    }
+  });
+
+  return started;
+};
+
+// Reads exactly `length` bytes of `socket` at a time
+const socketReader = (socket) => {
+  let buffered = Buffer.alloc(0);
+  let wake;
+  socket.on("data", (chunk) => {
+    buffered = Buffer.concat([buffered, chunk]);
+    wake?.();
+  });
+
+  return async (length) => {
+    while (buffered.length < length) {
+      await new Promise((resolve) => (wake = resolve));
+    }
+    const read = buffered.subarray(0, length);
+    buffered = buffered.subarray(length);
+
+    return read;
+  };
+};
+
+// Minimal SOCKS5 proxy, RFC 1928, which only does UDP ASSOCIATE to IPv4 addresses and relays for
+// a single client. Asks for `username` and `password` if given. Records the largest datagram the
+// client sent to it, header included
+const startSocksProxy = async (t, { username, password } = {}) => {
+  const started = { associations: 0, largestDatagram: 0 };
+  const relay = dgram.createSocket("udp4");
+  const controls = new Set();
+  let client;
+
+  relay.on("message", (message, from) => {
+    client ??= from;
+
+    if (from.address === client.address && from.port === client.port) {
+      started.largestDatagram = Math.max(started.largestDatagram, message.length);
+      const address = [...message.subarray(4, 8)].join(".");
+      relay.send(message.subarray(10), message.readUInt16BE(8), address);
+    } else {
+      // RSV, FRAG, ATYP and the address it came from
+      const header = Buffer.from([0, 0, 0, 1, ...from.address.split(".").map(Number), 0, 0]);
+      header.writeUInt16BE(from.port, 8);
+      relay.send(Buffer.concat([header, message]), client.port, client.address);
+    }
+  });
+
+  const control = net.createServer(async (socket) => {
+    controls.add(socket);
+    socket.on("error", () => {});
+    const read = socketReader(socket);
+
+    const [, count] = await read(2);
+    const methods = [...(await read(count))];
+    const method = username === undefined ? 0x00 : 0x02;
+    if (!methods.includes(method)) {
+      socket.end(Buffer.from([5, 0xff]));
+      return;
+    }
+    socket.write(Buffer.from([5, method]));
+
+    if (method === 0x02) {
+      const [, usernameLength] = await read(2);
+      const given = (await read(usernameLength)).toString();
+      const [passwordLength] = await read(1);
+      const accepted =
+        given === username && (await read(passwordLength)).toString() === password;
+
+      socket.write(Buffer.from([1, accepted ? 0 : 1]));
+      if (!accepted) {
+        socket.end();
+        return;
+      }
+    }
+
+    // VER, CMD, RSV and the IPv4 address the client sends from, which it leaves empty
+    const request = await read(10);
+    assert.equal(request[1], 0x03);
+    started.associations++;
+
+    // The unspecified address stands for the proxy's own
+    const reply = Buffer.from([5, 0, 0, 1, 0, 0, 0, 0, 0, 0]);
+    reply.writeUInt16BE(relay.address().port, 8);
+    socket.write(reply);
+  });
+
+  await new Promise((resolve) => relay.bind(0, "127.0.0.1", resolve));
+  await new Promise((resolve) => control.listen(0, "127.0.0.1", resolve));
+  started.port = control.address().port;
+  t.after(() => {
+    relay.close();
+    control.close();
+    for (const socket of controls) {
+      socket.destroy();
+    }
+  });
+
+  return started;
+};
+
+// A UDP port nothing is bound to right now
+const freePort = async () => {
+  const socket = dgram.createSocket("udp4");
+  await new Promise((resolve) => socket.bind(0, "127.0.0.1", resolve));
+  const { port } = socket.address();
+  await new Promise((resolve) => socket.close(resolve));
+
+  return port;
+};
+
+// `/proc` is read to count sockets and threads
+const linuxOnly = { skip: process.platform !== "linux" };
+
+const openFds = () => readdirSync("/proc/self/fd").length;
+
+const collectGarbage = async () => {
+  // Roots dropped off the main thread are released by a queued callback, so it takes a few
+  // rounds
+  for (let i = 0; i < 10; i++) {
+    await sleep(50);
+    globalThis.gc();
+  }
+};
+
+describe("test server", () => {
+  let server;
+  before(() => (server = lib.__test_server_start()));
+  after(() => lib.__test_server_close(server.server));
+
+  test("echoes bidirectional streams", async () => {
+    const connection = await connect(server);
+
+    const echoed = await request(connection, encoder.encode("hello"));
+    assert.equal(decoder.decode(echoed), "hello");
+
+    await lib.close_connection(connection, 0, new Uint8Array());
+  });
+
+  test("echoes many streams at once", async () => {
+    const connection = await connect(server);
+
+    const data = Array.from({ length: 500 }, (_, i) => `stream ${i}`);
+    const echoed = await Promise.all(
+      data.map((text) => request(connection, encoder.encode(text)))
+    );
+    assert.deepEqual(
+      echoed.map((chunk) => decoder.decode(chunk)),
+      data
+    );
+
+    await lib.close_connection(connection, 0, new Uint8Array());
+  });
+
+  test("negotiates ALPN", async (t) => {
+    const server = startServer(t, { alpnProtocols: ["test/1"] });
+    const connection = await connect(server, {
+      alpnProtocols: [encoder.encode("test/1")],
+    });
+
+    assert.equal(lib.connection_details(connection).alpnProtocol, "test/1");
+
+    await lib.close_connection(connection, 0, new Uint8Array());
+  });
+
+  test("authenticates with a client certificate", async (t) => {
+    const { authority, client } = lib.__test_server_client_certificate();
+    const server = startServer(t, { clientAuthorities: authority.certificate });
+
+    // The server only rejects a missing certificate after the handshake completed on the
+    // client, so the first request fails. Tried first, the session of the authenticated
+    // connection would be resumed without a certificate
+    await assert.rejects(
+      connect(server).then((connection) =>
+        request(connection, encoder.encode("hi"))
+      )
+    );
+
+    const connection = await connect(server, {
+      clientAuthentication: [
+        encoder.encode(client.certificate),
+        encoder.encode(client.key),
+      ],
+    });
+    assert.equal(decoder.decode(await request(connection, encoder.encode("hi"))), "hi");
+    await lib.close_connection(connection, 0, new Uint8Array());
+  });
+});
+
+describe("errors", () => {
+  let server;
+  before(() => (server = lib.__test_server_start()));
+  after(() => lib.__test_server_close(server.server));
+
+  test("exports unique codes", () => {
+    const { ConnectionErrorKind, ...errorCodes } = lib.errorCodes;
+    const codes = Object.values(errorCodes);
+
+    assert.equal(new Set(codes).size, codes.length);
+    for (const [name, code] of Object.entries(errorCodes)) {
+      assert.equal(name, code);
+      assert.match(code, /^ERR_/);
+    }
+    assert.deepEqual(ConnectionErrorKind, lib.ConnectionErrorKind);
+    assert.equal(ConnectionErrorKind.APPLICATION_CLOSED, "applicationClosed");
+  });
+
+  test("rejects a certificate for another hostname with a TLS error", async () => {
+    await assert.rejects(
+      connect(server, { hostname: "example.com" }),
+      (error) => isQuicError("ERR_QUIC_TLS")(error) && error.operation === "connect"
+    );
+  });
+
+  test("sets the transport error details of a failed handshake", async () => {
+    await assert.rejects(connect(server, { hostname: "example.com" }), (error) => {
+      assert.equal(error.kind, lib.ConnectionErrorKind.TRANSPORT_ERROR);
+      // TLS alerts are sent as 0x100 plus the alert, `bad_certificate` for a name mismatch
+      assert.equal(error.transportErrorCode, 0x100n + 42n);
+      assert.equal(error.transportErrorName, "CRYPTO_ERROR");
+      assert.equal(error.remote, false);
+      assert.equal(typeof error.transportReason, "string");
+
+      return isQuicError("ERR_QUIC_TLS")(error);
+    });
+  });
+
+  test("times out connecting to nobody", async () => {
+    const nobody = { ...server, port: 9 };
+
+    await assert.rejects(
+      connect(nobody, {}, { timeoutMs: 200 }),
+      isQuicError("ERR_QUIC_CONNECT_TIMEOUT")
+    );
+  });
+
+  test("throws TypeErrors naming the argument", () => {
+    const args = connectArguments(server);
+
+    const invalid = [
+      [
+        () => lib.connect(...args.with(0, "80")),
+        /^connect: expected options\.port to be a number, got string$/,
+      ],
+      [
+        () => lib.connect(...args.with(3, undefined)),
+        /^connect: expected options\.onStream to be a function/,
+      ],
+      [
+        () => lib.connect(...args.with(6, ["h3"])),
+        /^connect: expected options\.alpnProtocols\[0\] to be a Uint8Array/,
+      ],
+      [
+        () => lib.connect(...args.with(9, "options")),
+        /^connect: expected options to be an object/,
+      ],
+      [() => lib.create_stream({}), /^create_stream: expected connection/],
+      [() => lib.write_stream(), /^write_stream: expected stream/],
+    ];
+    for (const [call, message] of invalid) {
+      assert.throws(call, (error) => {
+        assert.ok(error instanceof TypeError);
+        assert.match(error.message, message);
+
+        return isQuicError("ERR_QUIC_INVALID_ARGUMENT")(error);
+      });
+    }
+  });
+
+  test("throws TypeErrors naming the argument of the stream and connection exports", async () => {
+    const connection = await connect(server);
+    const partial = await lib.create_stream(connection);
+
+    const invalid = [
+      [
+        () => lib.initialize_stream(partial, "onData"),
+        /^initialize_stream: expected options\.onData to be a function, got string$/,
+      ],
+      [
+        () => lib.initialize_stream(connection),
+        /^initialize_stream: expected partialStream to be a partial stream handle, got object$/,
+      ],
+      [
+        () => lib.close_write(connection),
+        /^close_write: expected stream to be a stream handle, got object$/,
+      ],
+      [
+        () => lib.close_stream(),
+        /^close_stream: expected stream to be a stream handle, got undefined$/,
+      ],
+      [() => lib.stop_read(null), /^stop_read: expected stream to be a stream handle, got null$/],
+      [
+        () => lib.close_connection(connection, 0, "bye"),
+        /^close_connection: expected reason to be a Uint8Array, got string$/,
+      ],
+      [
+        () => lib.close_connection(connection, "0"),
+        /^close_connection: expected errorCode to be a number or a bigint, got string$/,
+      ],
+      [
+        () => lib.set_stream_limits(connection, 2),
+        /^set_stream_limits: expected limits to be an object, got number$/,
+      ],
+      [
+        () => lib.connection_details(partial),
+        /^connection_details: expected connection to be a connection handle, got object$/,
+      ],
+    ];
+    for (const [call, message] of invalid) {
+      assert.throws(call, (error) => {
+        assert.ok(error instanceof TypeError);
+        assert.match(error.message, message);
+
+        return isQuicError("ERR_QUIC_INVALID_ARGUMENT")(error);
+      });
+    }
+
+    // None of the calls consumed the partial stream
+    const { stream } = initialize(partial);
+    await lib.close_write(stream);
+    await lib.close_connection(connection, 0, new Uint8Array());
+  });
+
+  test("throws instead of aborting on invalid input", () => {
+    const args = connectArguments(server).with(1, "not an ip");
+
+    assert.throws(
+      () => lib.connect(...args),
+      isQuicError("ERR_QUIC_INVALID_ARGUMENT")
+    );
+  });
+
+  test("sets the code and context on errors of the stream exports", async () => {
+    const connection = await connect(server);
+    const { stream } = initialize(await lib.create_stream(connection));
+    const { id } = lib.stream_details(stream);
+    const connectionId = lib.connection_id(connection);
+
+    const hasContext = (code, operation) => (error) =>
+      isQuicError(code)(error) &&
+      error.operation === operation &&
+      error.streamId === id &&
+      error.connectionId === connectionId;
+
+    await lib.write_stream(stream, encoder.encode("hello"));
+    assert.deepEqual(await lib.close_write(stream), {
+      sendFinished: true,
+      alreadyClosed: false,
+      receiveStopped: false,
+    });
+    assert.throws(
+      () => lib.write_stream(stream, encoder.encode("again")),
+      hasContext("ERR_STREAM_WRITE_AFTER_FINISH", "write_stream")
+    );
+    assert.throws(
+      () => lib.close_stream(stream, 2n ** 62n),
+      (error) =>
+        error instanceof RangeError && isQuicError("ERR_QUIC_INVALID_ARGUMENT")(error)
+    );
+
+    await lib.destroy_stream(stream);
+    assert.throws(
+      () => lib.close_stream(stream, 0),
+      hasContext("ERR_HANDLE_CONSUMED", "close_stream")
+    );
+
+    await lib.close_connection(connection, 0, new Uint8Array());
+  });
+
+  test("closes a stream reset by the peer with its code", async (t) => {
+    const server = startServer(t, { response: { reset: 7 } });
+    const connection = await connect(server);
+
+    await assert.rejects(
+      request(connection, encoder.encode("hello")),
+      (error) =>
+        isQuicError("ERR_STREAM_RESET")(error) &&
+        error.errorCode === 7n &&
+        error.operation === "read_stream"
+    );
+
+    await lib.close_connection(connection, 0, new Uint8Array());
+  });
+
+  test("closes the streams of a lost connection with its kind", async (t) => {
+    const server = startServer(t, {
+      response: "silent",
+      closeAfter: { ms: 200, errorCode: 3, reason: "bye" },
+    });
+    const connection = await connect(server);
+
+    const { stream, closed } = initialize(await lib.create_stream(connection));
+    await lib.write_stream(stream, encoder.encode("hello"));
+
+    await assert.rejects(
+      closed,
+      (error) =>
+        isQuicError("ERR_QUIC_CONNECTION_LOST")(error) &&
+        error.kind === lib.ConnectionErrorKind.APPLICATION_CLOSED
+    );
+  });
+
+  test("survives inputs which used to abort the process", async (t) => {
+    const { port, certificate } = startServer(t);
+
+    // A panic would abort the whole process, which fails `execFileSync`
+    const script = `
+      const lib = require(${JSON.stringify(LIB)});
+      const certificate = new TextEncoder().encode(${JSON.stringify(certificate)});
+      const connect = (ip) =>
+        lib.connect(${port}, ip, "localhost", () => {}, () => {}, () => {}, undefined, [certificate], undefined, {});
+      const initialize = (partial) => lib.initialize_stream(partial, () => {}, () => {}, () => {});
+      const codes = [];
+
+      try {
+        connect("not an ip");
+      } catch (error) {
+        codes.push(error.code);
+      }
+
+      connect("127.0.0.1").then(async (connection) => {
+        const partial = await lib.create_stream(connection);
+        const stream = initialize(partial);
+        try {
+          initialize(partial);
+        } catch (error) {
+          codes.push(error.code);
+        }
+
+        await lib.close_write(stream);
+        await lib.close_connection(connection, 0, new Uint8Array());
+        console.log(codes.join(","));
+      });
+    `;
+    const output = execFileSync(process.execPath, ["-e", script], {
+      encoding: "utf8",
+    });
+
+    assert.equal(output.trim(), "ERR_QUIC_INVALID_ARGUMENT,ERR_HANDLE_CONSUMED");
+  });
+
+  test("sets the context on a failed write", async (t) => {
+    const server = startServer(t, { response: { stop: 9 } });
+    const connection = await connect(server);
+    const { stream } = initialize(await lib.create_stream(connection));
+
+    // Writes succeed until the peer's STOP_SENDING arrived
+    const chunk = new Uint8Array(16 * 1024);
+    const failed = await (async () => {
+      for (let i = 0; i < 1000; i++) {
+        await lib.write_stream(stream, chunk);
+      }
+    })().then(
+      () => assert.fail("the peer never stopped the stream"),
+      (error) => error
+    );
+
+    assert.ok(isQuicError("ERR_STREAM_STOPPED")(failed));
+    assert.equal(failed.errorCode, 9n);
+    assert.equal(failed.operation, "write_stream");
+    assert.equal(failed.streamId, lib.stream_details(stream).id);
+    assert.equal(failed.connectionId, lib.connection_id(connection));
+    assert.equal(typeof failed.label, "string");
+
+    await lib.close_connection(connection, 0, new Uint8Array());
+  });
+
+  test("rejects a partial stream initialized twice", async () => {
+    const connection = await connect(server);
+    const partial = await lib.create_stream(connection);
+
+    const { stream } = initialize(partial);
+    assert.throws(
+      () => initialize(partial),
+      isQuicError("ERR_HANDLE_CONSUMED")
+    );
+
+    await lib.close_write(stream);
+    await lib.close_connection(connection, 0, new Uint8Array());
+  });
+
+  test("rejects every use of a consumed handle", async () => {
+    const connection = await connect(server);
+    const partial = await lib.create_stream(connection);
+    const { stream } = initialize(partial);
+    await lib.destroy_stream(stream);
+
+    const calls = {
+      initialize_stream: () => initialize(partial),
+      discard_stream: () => lib.discard_stream(partial, 0),
+      write_stream: () => lib.write_stream(stream, encoder.encode("hi")),
+      write_stream_nowait: () => lib.write_stream_nowait(stream, encoder.encode("hi")),
+      close_write: () => lib.close_write(stream),
+      close_stream: () => lib.close_stream(stream, 0),
+      reset_stream: () => lib.reset_stream(stream, 0),
+      destroy_stream: () => lib.destroy_stream(stream),
+      stop_read: () => lib.stop_read(stream, 0),
+      stream_details: () => lib.stream_details(stream),
+    };
+    for (const [operation, call] of Object.entries(calls)) {
+      assert.throws(
+        call,
+        (error) =>
+          isQuicError("ERR_HANDLE_CONSUMED")(error) &&
+          error.operation === operation &&
+          error.handleState === "consumed",
+        operation
+      );
+    }
+
+    await lib.close_connection(connection, 0, new Uint8Array());
+  });
+
+  test("rejects exports needing an open connection once it closed", async () => {
+    const connection = await connect(server);
+    const { stream, closed } = initialize(await lib.create_stream(connection));
+
+    await lib.close_connection(connection, 0, new Uint8Array());
+    await assert.rejects(closed);
+    await waitFor(() => lib.connection_state(connection) === "closed");
+
+    const rejected = {
+      create_stream: () => lib.create_stream(connection),
+      set_stream_limits: () => lib.set_stream_limits(connection, { bidi: 1 }),
+      set_receive_window: () => lib.set_receive_window(connection, 1 << 16),
+      rebind: () => lib.rebind(connection),
+      write_stream: () => lib.write_stream(stream, encoder.encode("hi")),
+      reset_stream: () => lib.reset_stream(stream, 0),
+    };
+    for (const [operation, call] of Object.entries(rejected)) {
+      assert.throws(
+        call,
+        (error) =>
+          isQuicError("ERR_CONNECTION_CLOSED")(error) && error.operation === operation,
+        operation
+      );
+    }
+
+    // Inspecting and closing a closed connection or its streams still works
+    assert.equal(typeof lib.connection_id(connection), "number");
+    assert.equal(typeof lib.get_remote(connection), "string");
+    assert.equal(lib.debug_dump(connection).state, "closed");
+    assert.equal(typeof lib.stream_details(stream).id, "bigint");
+    lib.stop_read(stream, 0);
+    assert.equal((await lib.close_write(stream)).sendFinished, false);
+    assert.equal((await lib.close_connection(connection, 0, new Uint8Array())).alreadyClosed, true);
+  });
+
+  test("rejects stream operations on a closed connection", async () => {
+    const connection = await connect(server);
+    const { stream, closed } = initialize(await lib.create_stream(connection));
+    await lib.write_stream(stream, encoder.encode("hello"));
+
+    await lib.close_connection(connection, 0, new Uint8Array());
+    await assert.rejects(closed);
+
+    assert.throws(
+      () => lib.write_stream(stream, encoder.encode("again")),
+      isQuicError("ERR_CONNECTION_CLOSED")
+    );
+    assert.throws(
+      () => lib.create_stream(connection),
+      isQuicError("ERR_CONNECTION_CLOSED")
+    );
+  });
+
+});
+
+describe("connections", () => {
+  let server;
+  before(() => (server = lib.__test_server_start()));
+  after(() => lib.__test_server_close(server.server));
+
+  test("closes with 62-bit application codes", async (t) => {
+    const server = startServer(t);
+    const codes = [7, 2n ** 33n + 1n, 2n ** 62n - 1n];
+
+    for (const code of codes) {
+      const connection = await connect(server);
+      await request(connection, encoder.encode("confirm"));
+      await lib.close_connection(connection, code, encoder.encode("done"));
+    }
+
+    // The server sees the close once the packet arrived
+    await waitFor(() => lib.__test_server_closes(server.server).length === codes.length);
+
+    assert.deepEqual(
+      lib.__test_server_closes(server.server),
+      codes.map((code) => ({ errorCode: BigInt(code), reason: "done" }))
+    );
+  });
+
+  test("rejects codes above 2^62 - 1 before closing", async () => {
+    const connection = await connect(server);
+
+    for (const code of [2n ** 62n, -1, 1.5]) {
+      assert.throws(
+        () => lib.close_connection(connection, code, new Uint8Array()),
+        RangeError
+      );
+    }
+    assert.equal(lib.connection_state(connection), "connected");
+
+    await lib.close_connection(connection, 0, new Uint8Array());
+  });
+
+  test("sends a close code above 2^32 without truncating it", async (t) => {
+    const server = startServer(t);
+    const connection = await connect(server);
+    await request(connection, encoder.encode("confirm"));
+
+    // Given as a number, it still fits in the safe integer range
+    await lib.close_connection(connection, 2 ** 33, new Uint8Array());
+    await waitFor(() => lib.__test_server_closes(server.server).length === 1);
+
+    assert.equal(lib.__test_server_closes(server.server)[0].errorCode, 2n ** 33n);
+  });
+
+  test("rejects close codes as numbers beyond the safe integer range", async () => {
+    const connection = await connect(server);
+
+    assert.throws(
+      () => lib.close_connection(connection, 2 ** 53, new Uint8Array()),
+      (error) =>
+        error instanceof RangeError && isQuicError("ERR_QUIC_INVALID_ARGUMENT")(error)
+    );
+    assert.throws(
+      () => lib.close_connection(connection, "7", new Uint8Array()),
+      TypeError
+    );
+
+    await lib.close_connection(connection, 2n ** 53n, new Uint8Array());
+  });
+
+  test("drains the streams before closing gracefully", async (t) => {
+    const server = startServer(t, { delayMs: 200 });
+    const connection = await connect(server);
+    const { stream, closed } = initialize(await lib.create_stream(connection));
+    await lib.write_stream(stream, encoder.encode("in flight"));
+    await lib.close_write(stream);
+
+    const result = await lib.close_connection_graceful(connection, 4, encoder.encode("done"), {
+      timeoutMs: 5000,
+    });
+    assert.deepEqual(result, { graceful: true, remainingStreams: 0, alreadyClosed: false });
+    assert.equal(decoder.decode(await closed), "in flight");
+
+    await waitFor(() => lib.__test_server_closes(server.server).length === 1);
+    assert.deepEqual(lib.__test_server_closes(server.server), [
+      { errorCode: 4n, reason: "done" },
+    ]);
+
+    assert.deepEqual(
+      await lib.close_connection_graceful(connection, 0, new Uint8Array()),
+      { graceful: false, remainingStreams: 0, alreadyClosed: true }
+    );
+  });
+
+  test("closes gracefully with the streams left when the timeout expires", async (t) => {
+    const server = startServer(t, { response: "silent" });
+    const connection = await connect(server);
+    const { stream, closed } = initialize(await lib.create_stream(connection));
+    await lib.write_stream(stream, encoder.encode("unanswered"));
+    await lib.close_write(stream);
+
+    const result = await lib.close_connection_graceful(connection, 0, new Uint8Array(), {
+      timeoutMs: 100,
+    });
+    assert.deepEqual(result, { graceful: false, remainingStreams: 1, alreadyClosed: false });
+    await assert.rejects(closed);
+  });
+
+  test("resets a stream while a write waits for flow control", async (t) => {
+    // The server reads the first chunk and then nothing for a while, the stream's flow control
+    // window fills up long before the write is done
+    const server = startServer(t, { delayMs: 10_000 });
+    const connection = await connect(server);
+
+    for (const reset of [
+      (stream) => lib.reset_stream(stream, 3),
+      (stream) => lib.destroy_stream(stream, { errorCode: 3 }),
+    ]) {
+      const { stream } = initialize(await lib.create_stream(connection));
+      const blocked = lib.write_stream(stream, new Uint8Array(16 * 2 ** 20));
+      const queued = lib.write_stream(stream, encoder.encode("queued"));
+      await sleep(200);
+
+      const started = performance.now();
+      await reset(stream);
+      assert.ok(performance.now() - started < 1000);
+      await assert.rejects(blocked, isQuicError("ERR_STREAM_FINISHED"));
+      await assert.rejects(queued, isQuicError("ERR_STREAM_FINISHED"));
+    }
+
+    await lib.close_connection(connection, 0, new Uint8Array());
+  });
+
+  test("keeps only active connections alive, without sending datagrams", async (t) => {
+    const server = startServer(t, { response: "silent", datagrams: true });
+    const options = { idleTimeoutMs: 300, keepAlive: { intervalMs: 50, onlyWhenActive: true } };
+
+    // The silent server never answers, the keep-alive is all that's sent while the stream is open
+    const active = await connect(server, {}, options);
+    const { stream } = initialize(await lib.create_stream(active));
+    await lib.write_stream(stream, encoder.encode("unanswered"));
+    await sleep(1000);
+    assert.equal(lib.connection_state(active), "connected");
+    assert.equal(lib.__test_server_datagrams(server.server), 0);
+
+    const idle = await connect(server, {}, options);
+    await sleep(1000);
+    assert.equal(lib.connection_state(idle), "closed");
+    assert.equal(lib.close_reason(idle).kind, lib.ConnectionErrorKind.TIMED_OUT);
+
+    await lib.close_connection(active, 0, new Uint8Array());
+  });
+
+  test("delivers data received before the stream was initialized", async (t) => {
+    const greeting = encoder.encode("welcome");
+    const server = startServer(t, { openBidiStreams: 1, openUniStreams: 1, greeting });
+
+    const partials = [];
+    const connection = await connect(server, {
+      onStream: (raw) => partials.push(...partialStreams(raw)),
+    });
+    await waitFor(() => partials.length === 2);
+
+    // The server finished both streams by now
+    await sleep(200);
+
+    for (const partial of partials) {
+      const kinds = [];
+      const { closed } = initialize(partial, {
+        onClose: (kind) => kinds.push(kind),
+      });
+
+      assert.deepEqual(await closed, Buffer.from(greeting));
+      assert.deepEqual(kinds, ["closed"]);
+    }
+
+    await lib.close_connection(connection, 0, new Uint8Array());
+  });
+
+  test("raises the stream limit at runtime", async (t) => {
+    const greeting = encoder.encode("welcome");
+    const server = startServer(t, { openBidiStreams: 2, greeting });
+
+    let accepted = 0;
+    const onStream = (raw) => {
+      for (const partial of partialStreams(raw)) {
+        accepted++;
+        initialize(partial);
+      }
+    };
+    const connection = await connect(
+      server,
+      { onStream },
+      { maxConcurrentBidiStreams: 0 }
+    );
+
+    await sleep(200);
+    assert.equal(accepted, 0);
+
+    lib.set_stream_limits(connection, { bidi: 2 });
+    await waitFor(() => accepted === 2);
+    assert.equal(accepted, 2);
+
+    assert.throws(() => lib.set_stream_limits(connection, { bidi: -1 }), RangeError);
+
+    await lib.close_connection(connection, 0, new Uint8Array());
+  });
+
+  test("shrinks the receive window at runtime", async (t) => {
+    const response = new Uint8Array(1 << 20);
+    const server = startServer(t, { response });
+    const connection = await connect(server, {}, { receiveWindowBytes: 1 << 22 });
+
+    lib.set_receive_window(connection, 1 << 14);
+    assert.equal(lib.connection_details(connection).receiveWindow, 1n << 14n);
+
+    // Still arrives, only slower
+    const received = await request(connection, encoder.encode("send"));
+    assert.equal(received.length, response.length);
+
+    assert.throws(() => lib.set_receive_window(connection, 2 ** 62), RangeError);
+
+    await lib.close_connection(connection, 0, new Uint8Array());
+  });
+
+  test("applies the initial window", async () => {
+    const cwnd = (connection) => lib.debug_dump(connection).stats.path.cwnd;
+
+    // The window only grows during the handshake
+    const connection = await connect(server, {}, { initialWindowBytes: 1 << 20 });
+    assert.ok(cwnd(connection) >= 1 << 20, `${cwnd(connection)} bytes`);
+    assert.equal(decoder.decode(await request(connection, encoder.encode("hi"))), "hi");
+    await lib.close_connection(connection, 0, new Uint8Array());
+
+    const fallback = await connect(server);
+    assert.ok(cwnd(fallback) < 1 << 20, `${cwnd(fallback)} bytes`);
+    await lib.close_connection(fallback, 0, new Uint8Array());
+
+    for (const initialWindowBytes of [0, 1000, -1]) {
+      const args = connectArguments(server, {}, { initialWindowBytes });
+      assert.throws(() => lib.connect(...args), RangeError);
+    }
+  });
+
+  test("applies the initial RTT", async (t) => {
+    // Time until the first Initial is sent again, when nothing came back. The probe timeout before
+    // the first RTT sample is three times the initial RTT
+    const retransmittedAfter = async (options) => {
+      const relay = await startRelay(t, server);
+      relay.forwarding = false;
+      const connecting = connect(relay, {}, options);
+
+      // The first flight may take more than one datagram, those arrive at once
+      const { received } = relay;
+      const retransmitted = () => received.find((at) => at - received[0] > 50);
+      await waitFor(() => retransmitted() !== undefined, 3000);
+      relay.forwarding = true;
+
+      const connection = await connecting;
+      assert.equal(decoder.decode(await request(connection, encoder.encode("hi"))), "hi");
+      await lib.close_connection(connection, 0, new Uint8Array());
+
+      return retransmitted() - received[0];
+    };
+
+    const short = await retransmittedAfter({ initialRttMs: 50 });
+    assert.ok(short > 100 && short < 400, `${short} ms`);
+    // Quinn's default of 333 ms
+    const fallback = await retransmittedAfter({});
+    assert.ok(fallback > 700, `${fallback} ms`);
+
+    for (const initialRttMs of [0, 0.5, 2 ** 40]) {
+      const args = connectArguments(server, {}, { initialRttMs });
+      assert.throws(() => lib.connect(...args), RangeError);
+    }
+  });
+
+  test("dumps the streams, queues and events", async () => {
+    const connection = await connect(server, {}, { eventLogSize: 4 });
+    const { stream } = initialize(await lib.create_stream(connection));
+    await lib.write_stream(stream, encoder.encode("hello"));
+
+    const dump = lib.debug_dump(connection);
+    assert.equal(dump.state, "connected");
+    assert.equal(dump.details.is0rtt, undefined);
+    assert.equal(dump.streams.length, 1);
+    assert.equal(dump.streams[0].id, lib.stream_details(stream).id);
+    assert.equal(dump.streams[0].unidirectional, false);
+    assert.equal(dump.streams[0].writeFinished, false);
+    assert.equal(dump.queues.maxBufferedBytes, null);
+    assert.deepEqual(
+      dump.events.map(({ name }) => name),
+      ["connected", "streamOpened"]
+    );
+
+    await lib.close_write(stream);
+    await lib.close_connection(connection, 0, new Uint8Array());
+
+    await waitFor(() => lib.connection_state(connection) === "closed");
+    assert.equal(lib.debug_dump(connection).events.at(-1).name, "closed");
+
+    const withoutLog = await connect(server);
+    assert.equal(lib.debug_dump(withoutLog).events, null);
+    await lib.close_connection(withoutLog, 0, new Uint8Array());
+
+    const args = connectArguments(server, {}, { eventLogSize: 0 });
+    assert.throws(() => lib.connect(...args), RangeError);
+  });
+
+  test("aborts a pending connect when its controller is cancelled", async (t) => {
+    // The Retry keeps the handshake pending long enough to cancel it
+    const server = startServer(t, { retryDelayMs: 300 });
+    const controller = lib.create_connect_controller();
+    let closed = false;
+    const pending = connect(server, { onClose: () => (closed = true) }, { controller });
+
+    assert.equal(lib.cancel_connect(controller), true);
+    assert.equal(lib.cancel_connect(controller), false);
+    await assert.rejects(pending, isQuicError("ERR_CANCELLED"));
+
+    // Nothing is left running which could settle the promise again or report a close
+    await sleep(500);
+    assert.equal(closed, false);
+    assert.deepEqual(lib.__test_server_closes(server.server), []);
+  });
+
+  test("closes the connection when its controller is cancelled after connecting", async (t) => {
+    const server = startServer(t);
+    const controller = lib.create_connect_controller();
+    const connection = await connect(server, {}, { controller });
+
+    assert.equal(lib.cancel_connect(controller), true);
+    assert.equal(lib.cancel_connect(controller), false);
+    await lib.await_close(connection);
+    assert.equal(lib.connection_state(connection), "closed");
+
+    await waitFor(() => lib.__test_server_closes(server.server).length === 1);
+    assert.deepEqual(lib.__test_server_closes(server.server), [
+      { errorCode: 0n, reason: "aborted" },
+    ]);
+  });
+
+  test("settles a connect cancelled at any point exactly once", async (t) => {
+    const server = startServer(t);
+
+    for (let delayMs = 0; delayMs < 20; delayMs++) {
+      const controller = lib.create_connect_controller();
+      const settled = [];
+      const pending = connect(server, {}, { controller }).then(
+        (connection) => settled.push(connection),
+        (error) => settled.push(error)
+      );
+
+      await sleep(delayMs);
+      lib.cancel_connect(controller);
+      await pending;
+      await sleep(10);
+
+      assert.equal(settled.length, 1);
+      const [result] = settled;
+      if (result instanceof Error) {
+        assert.ok(isQuicError("ERR_CANCELLED")(result));
+      } else {
+        // Resolved before the cancel, which closed it instead
+        await lib.await_close(result);
+      }
+    }
+  });
+
+  test("rejects durations a Duration can't hold, and caps huge ones", async () => {
+    for (const timeoutMs of [Infinity, 1e300, -1]) {
+      assert.throws(() => lib.flush_all({ timeoutMs }), RangeError);
+
+      const args = connectArguments(server, {}, { timeoutMs });
+      assert.throws(() => lib.connect(...args), RangeError);
+    }
+
+    // Far in the future, but the deadline computed from it doesn't overflow
+    const connection = await connect(server, {}, { timeoutMs: 1e15 });
+    await lib.flush_all({ timeoutMs: 1e15 });
+    await lib.close_connection(connection, 0, new Uint8Array());
+  });
+
+  test("rejects close timeouts a Duration can't hold", async () => {
+    const connection = await connect(server);
+
+    for (const timeoutMs of [Infinity, 1e300, -1]) {
+      assert.throws(
+        () => lib.close_connection(connection, 0, new Uint8Array(), timeoutMs),
+        RangeError
+      );
+    }
+
+    const { alreadyClosed } = await lib.close_connection(connection, 0, new Uint8Array(), 1e15);
+    assert.equal(alreadyClosed, false);
+  });
+
+  test("rejects cwndWarning values which aren't finite", () => {
+    for (const cwndWarning of [
+      { thresholdBytes: Infinity },
+      { thresholdBytes: -1 },
+      { thresholdBytes: 1000, sustainMs: Infinity },
+      { thresholdBytes: 1000, sustainMs: 1e300 },
+    ]) {
+      const args = connectArguments(server, {}, { cwndWarning });
+      assert.throws(() => lib.connect(...args), RangeError);
+    }
+  });
+
+  test("never sends datagrams above a pinned size", async (t) => {
+    const data = new Uint8Array(256 * 1024);
+
+    const pinned = startServer(t);
+    const connection = await connect(pinned, {}, { mtu: { initial: 1280, discovery: false } });
+    await request(connection, data);
+    assert.equal(lib.__test_server_largest_datagram(pinned.server), 1280);
+    await lib.close_connection(connection, 0, new Uint8Array());
+
+    // With discovery the datagrams grow past it on loopback, which the capture sees
+    const probed = startServer(t);
+    const probing = await connect(probed, {}, { mtu: { initial: 1280 } });
+    for (let i = 0; i < 20 && lib.__test_server_largest_datagram(probed.server) <= 1280; i++) {
+      await request(probing, data);
+    }
+    assert.ok(lib.__test_server_largest_datagram(probed.server) > 1280);
+    await lib.close_connection(probing, 0, new Uint8Array());
+
+    for (const mtu of [{ initial: 1100, discovery: false }, { discovery: false }]) {
+      const args = connectArguments(server, {}, { mtu });
+      assert.throws(() => lib.connect(...args), mtu.initial ? RangeError : TypeError);
+    }
+  });
+
+  const notOnWindows = { skip: process.platform === "win32" };
+
+  test("marks packets with the DSCP", notOnWindows, async () => {
+    const connection = await connect(server, {}, { dscp: 46 });
+
+    assert.equal(lib.connection_details(connection).socket.dscp, 46);
+    assert.equal(decoder.decode(await request(connection, encoder.encode("hi"))), "hi");
+
+    await lib.close_connection(connection, 0, new Uint8Array());
+
+    const args = connectArguments(server, {}, { dscp: 64 });
+    assert.throws(() => lib.connect(...args), RangeError);
+  });
+
+  test("closes a shared endpoint and waits for it to become idle", async (t) => {
+    const server = startServer(t);
+    const endpoint = lib.create_endpoint({});
+    const closes = [];
+    const connections = await Promise.all(
+      [1, 2].map(() =>
+        connect(server, { onClose: (reason) => closes.push(reason) }, { endpoint })
+      )
+    );
+
+    lib.close_endpoint(endpoint, 5, encoder.encode("shutdown"));
+    await lib.wait_endpoint_idle(endpoint);
+    await waitFor(() => closes.length === 2);
+
+    for (const connection of connections) {
+      assert.equal(lib.connection_state(connection), "closed");
+      assert.equal(lib.close_reason(connection).kind, lib.ConnectionErrorKind.LOCALLY_CLOSED);
+    }
+    await waitFor(() => lib.__test_server_closes(server.server).length === 2);
+    assert.deepEqual(
+      lib.__test_server_closes(server.server),
+      [1, 2].map(() => ({ errorCode: 5n, reason: "shutdown" }))
+    );
+
+    // Both are safe to call again, and work on a connection of the endpoint as well
+    lib.close_endpoint(endpoint, 0, new Uint8Array());
+    lib.close_endpoint(connections[0], 0, new Uint8Array());
+    await lib.wait_endpoint_idle(endpoint);
+    await lib.wait_endpoint_idle(connections[1]);
+    await assert.rejects(
+      connect(server, {}, { endpoint }),
+      isQuicError("ERR_ENDPOINT_CLOSED")
+    );
+  });
+
+  test("releases the sockets of dropped connections", linuxOnly, async () => {
+    let connections = [];
+    for (let i = 0; i < 100; i++) {
+      connections.push(await connect(server));
+    }
+    // Sockets of earlier tests may still be closing, only those of these connections count
+    const opened = openFds();
+
+    // `close_connection` is never called, finalizing the handles closes them
+    connections = null;
+    await collectGarbage();
+    for (let i = 0; i < 100 && openFds() > opened - 100; i++) {
+      await sleep(50);
+    }
+    const left = openFds() - (opened - 100);
+    assert.ok(left <= 0, `${left} sockets left open`);
+  });
+
+  test("skips the server's address validation with a token it sent before", async (t) => {
+    const retryDelayMs = 300;
+    const server = startServer(t, { retryDelayMs });
+    const handshake = async (options) => {
+      const connection = await connect(server, {}, options);
+      await request(connection, encoder.encode("hi"));
+      const { handshakeMs } = lib.handshake_timings(connection);
+      // The tokens arrive with the first packets after the handshake
+      await sleep(100);
+      await lib.close_connection(connection, 0, new Uint8Array());
+
+      return handshakeMs;
+    };
+
+    // Tokens other servers sent for `localhost` are invalid here, and replaced by this one's
+    assert.ok((await handshake()) >= retryDelayMs);
+    assert.ok((await handshake()) < retryDelayMs);
+    assert.ok((await handshake({ reuseTokens: false })) >= retryDelayMs);
+  });
+
+  test("releases the callbacks of closed connections and streams", async () => {
+    const callbacks = [];
+    const track = (callback) => {
+      callbacks.push(new WeakRef(callback));
+      return callback;
+    };
+
+    // Nothing outside of this function refers to the callbacks
+    await (async () => {
+      let warned;
+      const warning = new Promise((resolve) => (warned = resolve));
+      const connection = await connect(
+        server,
+        { onStream: track(() => {}), onClose: track(() => {}), onError: track(() => {}) },
+        // Any window is below the threshold, so the sampler calls back on its first sample
+        { onCwndWarning: track(() => warned()), cwndWarning: { thresholdBytes: 2 ** 40 } }
+      );
+      await warning;
+
+      let finished;
+      const closed = new Promise((resolve) => (finished = resolve));
+      const stream = lib.initialize_stream(
+        await lib.create_stream(connection),
+        track(() => {}),
+        track(() => finished()),
+        track(() => {})
+      );
+      await lib.write_stream(stream, encoder.encode("hi"));
+      await lib.close_write(stream);
+      await closed;
+
+      await lib.close_connection(connection, 0, new Uint8Array());
+    })();
+
+    await collectGarbage();
+    const left = callbacks.filter((callback) => callback.deref() !== undefined).length;
+    assert.equal(left, 0, `${left} of ${callbacks.length} callbacks still rooted`);
+  });
+  test("closes a connection once it went without streams for autoCloseIdleMs", async (t) => {
+    const server = startServer(t);
+    let details;
+    const connection = await connect(
+      server,
+      { onClose: (reason, closeDetails) => (details = closeDetails) },
+      { autoCloseIdleMs: 200 }
+    );
+
+    // An open stream keeps it open, however long that takes
+    const { stream, closed } = initialize(await lib.create_stream(connection));
+    await sleep(400);
+    assert.equal(lib.connection_state(connection), "connected");
+
+    await lib.write_stream(stream, encoder.encode("last"));
+    await lib.close_write(stream);
+    await closed;
+    await waitFor(() => details !== undefined);
+    assert.equal(details.kind, lib.ConnectionErrorKind.IDLE_AUTO_CLOSE);
+
+    await waitFor(() => lib.__test_server_closes(server.server).length === 1);
+    assert.deepEqual(lib.__test_server_closes(server.server), [{ errorCode: 0n, reason: "idle" }]);
+    await assert.rejects(lib.create_stream(connection), isQuicError("ERR_CONNECTION_CLOSED"));
+  });
+
+  test("rebinds a connection to a new socket, keeping the old one if that fails", async () => {
+    const connection = await connect(server);
+    const bound = lib.local_address(connection);
+
+    const rebound = await lib.rebind(connection, { localAddress: "127.0.0.1" });
+    assert.equal(rebound.address, "127.0.0.1");
+    assert.notEqual(rebound.port, bound.port);
+    assert.deepEqual(lib.local_address(connection), rebound);
+    assert.equal(decoder.decode(await request(connection, encoder.encode("moved"))), "moved");
+
+    // Not an address of this host
+    await assert.rejects(
+      lib.rebind(connection, { localAddress: "192.0.2.1" }),
+      isQuicError("ERR_QUIC_IO")
+    );
+    assert.deepEqual(lib.local_address(connection), rebound);
+    assert.equal(decoder.decode(await request(connection, encoder.encode("stayed"))), "stayed");
+
+    await lib.close_connection(connection, 0, new Uint8Array());
+  });
+
+  test("rejects the operations of a cancelled token with ERR_CANCELLED", async (t) => {
+    // Reads the first chunk of a stream and then nothing for a while, so writes wait for flow
+    // control
+    const slow = startServer(t, { delayMs: 10_000 });
+    const connection = await connect(slow);
+    const token = lib.create_cancel_token();
+
+    const nobody = { ...slow, port: 9 };
+    const connecting = connect(nobody, {}, { cancelToken: token });
+    const { stream } = initialize(await lib.create_stream(connection, token));
+    const writing = lib.write_stream(stream, new Uint8Array(16 * 2 ** 20), token);
+    await sleep(200);
+
+    assert.equal(lib.cancel_token(token, "shutting down"), true);
+    assert.equal(lib.cancel_token(token, "again"), false);
+
+    for (const pending of [connecting, writing]) {
+      await assert.rejects(
+        pending,
+        (error) => isQuicError("ERR_CANCELLED")(error) && error.reason === "shutting down"
+      );
+    }
+
+    // Only what the token was passed to is cancelled
+    assert.equal(decoder.decode(await request(connection, encoder.encode("hi"))), "hi");
+    await lib.close_connection(connection, 0, new Uint8Array());
+  });
+
+  test("binds to the given local address and port", async () => {
+    const port = await freePort();
+    const connection = await connect(server, {}, { localAddress: "127.0.0.1", localPort: port });
+    assert.deepEqual(lib.local_address(connection), { address: "127.0.0.1", port });
+    assert.equal(decoder.decode(await request(connection, encoder.encode("hi"))), "hi");
+
+    // The port is taken by the connection now
+    await assert.rejects(
+      connect(server, {}, { localAddress: "127.0.0.1", localPort: port }),
+      isQuicError("ERR_QUIC_IO")
+    );
+    // An IPv6 address can't send to the IPv4 server
+    await assert.rejects(connect(server, {}, { localAddress: "::1" }), isQuicError("ERR_QUIC_IO"));
+
+    await lib.close_connection(connection, 0, new Uint8Array());
+  });
+
+  test("reaches an IPv4 server from a dual-stack socket, but not an IPv6-only one", async () => {
+    const connection = await connect(server, {}, { ipv6: true, ipv6Only: false });
+    assert.equal(lib.local_address(connection).address, "::");
+    assert.equal(decoder.decode(await request(connection, encoder.encode("hi"))), "hi");
+    await lib.close_connection(connection, 0, new Uint8Array());
+
+    for (const options of [
+      { ipv6: true, ipv6Only: true },
+      { ipv6: false, ipv6Only: false },
+    ]) {
+      await assert.rejects(connect(server, {}, options), isQuicError("ERR_QUIC_IO"));
+    }
+  });
+
+  test("connects over IPv6, binding to addresses with a zone", linuxOnly, async (t) => {
+    const server = startServer(t, { ipv6: true });
+
+    // The zone of `::1` is ignored when sending, `1` is the loopback interface
+    const connection = await connect(server, { ipAddress: "::1" }, { localAddress: "::1%1" });
+    assert.equal(lib.local_address(connection).address, "::1");
+    assert.equal(lib.get_remote(connection), `[::1]:${server.port}`);
+    assert.equal(decoder.decode(await request(connection, encoder.encode("hi"))), "hi");
+    await lib.close_connection(connection, 0, new Uint8Array());
+
+    for (const [name, ipAddress, options] of [
+      ["a remote address", "fe80::1%nonexistent0", {}],
+      ["options.localAddress", "::1", { localAddress: "fe80::1%nonexistent0" }],
+    ]) {
+      const args = connectArguments(server, { ipAddress }, options);
+      assert.throws(
+        () => lib.connect(...args),
+        (error) =>
+          error instanceof TypeError &&
+          error.message.includes('unable to look up the interface "nonexistent0"'),
+        name
+      );
+    }
+  });
+});
+
+describe("ring buffer", () => {
+  const HEAD = 0;
+  const TAIL = 1;
+  const STATE = 2;
+  const WAITING = 3;
+  const [DATA_WAITING, ROOM_WAITING] = [1, 2];
+
+  // Same as `readRingBuffer` of the wrapper
+  const readRing = (buffer) => {
+    const slots = new Int32Array(buffer, 0, 4);
+    const data = new Uint8Array(buffer, 16);
+
+    const tail = Atomics.load(slots, TAIL) >>> 0;
+    let head = Atomics.load(slots, HEAD) >>> 0;
+
+    if (head === tail) {
+      Atomics.or(slots, WAITING, DATA_WAITING);
+      head = Atomics.load(slots, HEAD) >>> 0;
+
+      if (head === tail) {
+        return null;
+      }
+
+      Atomics.and(slots, WAITING, ~DATA_WAITING);
+    }
+
+    const length = (head - tail) >>> 0;
+    const start = tail % data.length;
+    const first = Math.min(length, data.length - start);
+
+    const result = new Uint8Array(length);
+    result.set(data.subarray(start, start + first));
+    result.set(data.subarray(0, length - first), first);
+
+    Atomics.store(slots, TAIL, (tail + length) | 0);
+    if (Atomics.and(slots, WAITING, ~ROOM_WAITING) & ROOM_WAITING) {
+      lib.ring_buffer_drained(new Uint8Array(buffer, 0, 16));
+    }
+
+    return result;
+  };
+
+  test("reads a stream through a buffer smaller than its data", async (t) => {
+    const server = startServer(t);
+    const connection = await connect(server);
+    const buffer = new SharedArrayBuffer(16 + 1024);
+
+    const chunks = [];
+    const drain = () => {
+      for (let chunk; (chunk = readRing(buffer)); ) {
+        chunks.push(chunk);
+      }
+    };
+
+    let resolve, reject;
+    const closed = new Promise((...settle) => ([resolve, reject] = settle));
+    const stream = lib.initialize_stream(
+      await lib.create_stream(connection),
+      drain,
+      (reason, error) => {
+        drain();
+        error ? reject(error) : resolve(stream);
+      },
+      reject,
+      new Uint8Array(buffer)
+    );
+
+    // The buffer fills up many times over, the stream only continues once it's drained
+    const data = Uint8Array.from({ length: 64 * 1024 }, (_, i) => i % 251);
+    await lib.write_stream(stream, data);
+    await lib.close_write(stream);
+    await closed;
+
+    assert.deepEqual(Buffer.concat(chunks), Buffer.from(data));
+    assert.equal(Atomics.load(new Int32Array(buffer, 0, 4), STATE), 1);
+
+    await lib.close_connection(connection, 0, new Uint8Array());
+  });
+
+  test("rejects a buffer another stream writes to", async (t) => {
+    const server = startServer(t, { response: "silent" });
+    const connection = await connect(server);
+    const buffer = new SharedArrayBuffer(16 + 1024);
+    const initialize = async () =>
+      lib.initialize_stream(
+        await lib.create_stream(connection),
+        () => {},
+        () => {},
+        () => {},
+        new Uint8Array(buffer)
+      );
+
+    await initialize();
+    await assert.rejects(initialize(), RangeError);
+
+    await lib.close_connection(connection, 0, new Uint8Array());
+  });
+});
+
+describe("pool", () => {
+  let server;
+  before(() => (server = lib.__test_server_start()));
+  after(() => lib.__test_server_close(server.server));
+
+  test("shares connections between acquires", async () => {
+    const pool = lib.create_pool({ maxSize: 1, idleEvictMs: 100 });
+
+    const [first, second] = await Promise.all([
+      lib.pool_acquire(pool, ...connectArguments(server)),
+      lib.pool_acquire(pool, ...connectArguments(server)),
+    ]);
+    assert.equal(first, second);
+    assert.deepEqual(lib.pool_stats(pool), {
+      size: 1,
+      idle: 0,
+      pending: 0,
+      hits: 1,
+      misses: 1,
+    });
+
+    await assert.rejects(
+      lib.pool_acquire(
+        pool,
+        ...connectArguments(server, { hostname: "127.0.0.1" })
+      ),
+      isQuicError("ERR_QUIC_POOL_FULL")
+    );
+
+    assert.equal(lib.pool_release(pool, first), true);
+    assert.equal(lib.pool_release(pool, second), true);
+    assert.equal(lib.pool_release(pool, first), false);
+
+    await sleep(300);
+    assert.equal(lib.pool_stats(pool).size, 0);
+    assert.equal(lib.connection_state(first), "closed");
+
+    await lib.pool_close(pool);
+  });
+
+  test("shares connections only between acquires with the same options", async () => {
+    const pool = lib.create_pool();
+    const acquire = (options) => lib.pool_acquire(pool, ...connectArguments(server, {}, options));
+
+    const plain = await acquire({ label: "first" });
+    assert.equal(await acquire({ label: "second", timeoutMs: 5000 }), plain);
+
+    const distinct = await Promise.all(
+      [
+        { idleTimeoutMs: 5000 },
+        { writeChunkBytes: 1000 },
+        { autoCloseIdleMs: 5000 },
+        { streamBatch: {} },
+        { localAddress: "127.0.0.1" },
+      ].map(acquire)
+    );
+    assert.equal(new Set([plain, ...distinct]).size, 6);
+    assert.equal(lib.pool_stats(pool).size, 6);
+    assert.equal(await acquire({ writeChunkBytes: 1000 }), distinct[1]);
+
+    await lib.pool_close(pool);
+  });
+});
+
+describe("streams", () => {
+  let server;
+  before(() => (server = lib.__test_server_start()));
+  after(() => lib.__test_server_close(server.server));
+
+  const byId = (a, b) => (a < b ? -1 : a > b ? 1 : 0);
+
+  test("hands over streams opened in a burst in batches, in the order they were accepted", async (t) => {
+    const greeting = encoder.encode("welcome");
+    const burst = startServer(t, { openBidiStreams: 20, greeting });
+
+    const batches = [];
+    const connection = await connect(
+      burst,
+      { onStream: (batch) => batches.push(batch) },
+      { streamBatch: { maxStreams: 8 } }
+    );
+    await waitFor(() => batches.flat().length === 20);
+
+    assert.ok(batches.every((batch) => batch.length >= 1 && batch.length <= 8));
+    assert.ok(batches.length < 20);
+    const ids = batches.flat().map(({ details }) => details.id);
+    assert.deepEqual(ids, [...ids].sort(byId));
+
+    const received = await Promise.all(
+      batches.flat().map(({ stream }) => initialize(stream).closed)
+    );
+    assert.ok(received.every((data) => data.equals(Buffer.from(greeting))));
+
+    await lib.close_connection(connection, 0, new Uint8Array());
+  });
+
+  test("hands over a single stream without waiting for the batch delay", async (t) => {
+    const single = startServer(t, { openBidiStreams: 1, greeting: encoder.encode("welcome") });
+
+    const batches = [];
+    const connection = await connect(
+      single,
+      { onStream: (batch) => batches.push(batch) },
+      { streamBatch: { maxDelayMs: 10_000 } }
+    );
+    await waitFor(() => batches.length === 1);
+
+    assert.equal(batches.length, 1);
+    assert.equal(batches[0].length, 1);
+    lib.discard_stream(batches[0][0].stream, 0);
+
+    await lib.close_connection(connection, 0, new Uint8Array());
+  });
+
+  test("pipelines writes without promises and calls onDrain below the high-water mark", async () => {
+    const connection = await connect(server);
+
+    const chunks = [];
+    const errors = [];
+    let drains = 0;
+    let resolve;
+    const closed = new Promise((settle) => (resolve = settle));
+    const stream = lib.initialize_stream(
+      await lib.create_stream(connection),
+      (chunk) => chunks.push(Buffer.from(chunk)),
+      () => resolve(Buffer.concat(chunks)),
+      (error) => errors.push(error),
+      undefined,
+      () => drains++
+    );
+
+    assert.equal(lib.write_stream_nowait(stream, encoder.encode("small")), true);
+    // At least 64 KiB are queued right after this one
+    assert.equal(lib.write_stream_nowait(stream, new Uint8Array(128 * 1024)), false);
+    await waitFor(() => drains === 1);
+    assert.equal(drains, 1);
+
+    await lib.close_write(stream);
+    assert.equal((await closed).length, 5 + 128 * 1024);
+    assert.deepEqual(errors, []);
+
+    await lib.close_connection(connection, 0, new Uint8Array());
+  });
+
+  test("reports a failed pipelined write to onError with its range", async (t) => {
+    const stopping = startServer(t, { response: { stop: 5 } });
+    const connection = await connect(stopping);
+
+    const errors = [];
+    const stream = lib.initialize_stream(
+      await lib.create_stream(connection),
+      () => {},
+      () => {},
+      (error) => errors.push(error)
+    );
+
+    assert.equal(lib.write_stream_nowait(stream, encoder.encode("abc")), true);
+    await sleep(200);
+    // The server stopped the stream by now
+    lib.write_stream_nowait(stream, encoder.encode("defg"));
+    lib.write_stream_nowait(stream, encoder.encode("hij"));
+    await waitFor(() => errors.length > 0);
+
+    // Only the first failure is reported
+    await sleep(100);
+    assert.equal(errors.length, 1);
+    assert.ok(isQuicError("ERR_STREAM_STOPPED")(errors[0]));
+    assert.equal(errors[0].errorCode, 5n);
+    assert.equal(errors[0].byteStart, 3n);
+    assert.equal(errors[0].byteEnd, 7n);
+
+    await lib.close_connection(connection, 0, new Uint8Array());
+  });
+
+  test("discards streams from inside onStream", async (t) => {
+    const greeting = encoder.encode("welcome");
+    const opening = startServer(t, { openBidiStreams: 2, openUniStreams: 2, greeting });
+
+    const discarded = [];
+    const connection = await connect(opening, {
+      onStream: (raw) => {
+        for (const partial of partialStreams(raw)) {
+          lib.discard_stream(partial, 7);
+          discarded.push(partial);
+        }
+      },
+    });
+    await waitFor(() => discarded.length === 4);
+    await waitFor(() => lib.open_streams(connection) === 0);
+
+    assert.equal(discarded.length, 4);
+    assert.equal(lib.open_streams(connection), 0);
+    for (const partial of discarded) {
+      assert.throws(() => initialize(partial), isQuicError("ERR_HANDLE_CONSUMED"));
+      assert.throws(() => lib.discard_stream(partial, 7), isQuicError("ERR_HANDLE_CONSUMED"));
+    }
+
+    // The connection itself is untouched
+    assert.equal(decoder.decode(await request(connection, encoder.encode("hi"))), "hi");
+    await lib.close_connection(connection, 0, new Uint8Array());
+  });
+
+  test("stops reading once maxBufferedBytes are waiting for JavaScript", async () => {
+    const maxBufferedBytes = 16 * 1024;
+    const warnings = [];
+    const connection = await connect(
+      server,
+      {},
+      { maxBufferedBytes, onBufferedBytesWarning: (warning) => warnings.push(warning) }
+    );
+    const { stream, closed } = initialize(await lib.create_stream(connection));
+    const data = new Uint8Array(8 * 2 ** 20);
+    const written = lib.write_stream(stream, data).then(() => lib.close_write(stream));
+
+    // Nothing runs on the event loop meanwhile, the echoed data waits on the native side. Without
+    // the limit it would only be held back by the stream's flow control window of over a MiB
+    const until = Date.now() + 500;
+    while (Date.now() < until) {}
+    const buffered = lib.connection_details(connection).bufferedBytes;
+    assert.ok(buffered >= maxBufferedBytes, `${buffered} bytes buffered`);
+    assert.ok(buffered < 256 * 1024, `${buffered} bytes buffered`);
+
+    await written;
+    assert.equal((await closed).length, data.length);
+    assert.equal(warnings.length, 1);
+    assert.equal(warnings[0].maxBufferedBytes, maxBufferedBytes);
+
+    await lib.close_connection(connection, 0, new Uint8Array());
+  });
+
+  test("writes large writes in chunks of writeChunkBytes, in order", async () => {
+    const connection = await connect(server, {}, { writeChunkBytes: 1000 });
+
+    const data = new Uint8Array(2 ** 20).map((_, i) => i % 251);
+    const echoed = await request(connection, data);
+    assert.ok(echoed.equals(Buffer.from(data)));
+
+    await lib.close_connection(connection, 0, new Uint8Array());
+
+    for (const writeChunkBytes of [0, 1.5]) {
+      const args = connectArguments(server, {}, { writeChunkBytes });
+      assert.throws(() => lib.connect(...args), RangeError);
+    }
+  });
+
+  test("writes the part of a buffer selected by byteOffset and byteLength", async () => {
+    const connection = await connect(server);
+    const { stream, closed } = initialize(await lib.create_stream(connection));
+    const buffer = encoder.encode("0123456789").slice();
+
+    for (const write of [
+      () => lib.write_stream(stream, buffer, undefined, 11),
+      () => lib.write_stream(stream, buffer, undefined, 4, 7),
+      () => lib.write_stream(stream, buffer.buffer, undefined, -1),
+      () => lib.write_stream_nowait(stream, buffer.buffer, 2, 9),
+    ]) {
+      assert.throws(write, RangeError);
+    }
+
+    await lib.write_stream(stream, buffer, undefined, 2, 3);
+    lib.write_stream_nowait(stream, buffer.buffer, 7);
+    await lib.close_write(stream);
+    assert.equal(decoder.decode(await closed), "234789");
+
+    await lib.close_connection(connection, 0, new Uint8Array());
+  });
+});
+
+describe("persistent connections", () => {
+  test("reconnects with exponential backoff once the connection is lost", async (t) => {
+    const server = startServer(t);
+    const relay = await startRelay(t, server);
+
+    const attempts = [];
+    const reconnected = [];
+    const closes = [];
+    const { promise, persistent } = lib.connect_persistent(
+      ...connectArguments(
+        relay,
+        { onClose: (reason, details) => closes.push(details) },
+        { idleTimeoutMs: 300, keepAlive: { intervalMs: 50 }, timeoutMs: 50 }
+      ),
+      {
+        initialDelayMs: 50,
+        maxDelayMs: 200,
+        onReconnecting: (attempt, details) =>
+          attempts.push({ attempt, kind: details.kind, at: performance.now() }),
+        onReconnected: (connection) => reconnected.push(connection),
+      }
+    );
+    const first = await promise;
+    assert.equal(decoder.decode(await request(first, encoder.encode("first"))), "first");
+
+    // Every attempt times out while the relay drops everything
+    relay.forwarding = false;
+    await waitFor(() => attempts.length === 5, 3000);
+    assert.deepEqual(
+      attempts.map(({ attempt, kind }) => ({ attempt, kind })),
+      [1, 2, 3, 4, 5].map((attempt) => ({ attempt, kind: lib.ConnectionErrorKind.TIMED_OUT }))
+    );
+    // 50, 100, 200 and 200 ms of delay, each plus the 50 ms the attempt took to time out
+    const gaps = attempts.slice(1).map(({ at }, i) => at - attempts[i].at);
+    assert.ok(gaps[1] - gaps[0] > 30, gaps.join(", "));
+    assert.ok(gaps[2] - gaps[1] > 60, gaps.join(", "));
+    assert.ok(Math.abs(gaps[3] - gaps[2]) < 60, gaps.join(", "));
+    assert.equal(lib.connection_state(first), "closed");
+
+    relay.forwarding = true;
+    await waitFor(() => reconnected.length === 1, 2000);
+    const [second] = reconnected;
+    assert.equal(decoder.decode(await request(second, encoder.encode("second"))), "second");
+    assert.deepEqual(closes, []);
+
+    // Closing the connection after stopping calls `onClose` instead of reconnecting
+    assert.equal(lib.close_persistent(persistent), true);
+    assert.equal(lib.close_persistent(persistent), false);
+    await lib.close_connection(second, 0, new Uint8Array());
+    await waitFor(() => closes.length === 1);
+    assert.equal(closes.length, 1);
+    assert.equal(closes[0].kind, lib.ConnectionErrorKind.LOCALLY_CLOSED);
+    assert.equal(reconnected.length, 1);
+  });
+
+  test("gives up after maxAttempts", async (t) => {
+    const server = startServer(t);
+    const relay = await startRelay(t, server);
+
+    const attempts = [];
+    const closes = [];
+    const { promise } = lib.connect_persistent(
+      ...connectArguments(
+        relay,
+        { onClose: (reason, details) => closes.push(details) },
+        { idleTimeoutMs: 300, keepAlive: { intervalMs: 50 }, timeoutMs: 50 }
+      ),
+      { initialDelayMs: 10, maxAttempts: 2, onReconnecting: (attempt) => attempts.push(attempt) }
+    );
+    await promise;
+
+    relay.forwarding = false;
+    await waitFor(() => closes.length === 1, 2000);
+    assert.deepEqual(attempts, [1, 2]);
+    assert.equal(closes[0].kind, lib.ConnectionErrorKind.TIMED_OUT);
+  });
+});
+
+describe("bundles", () => {
+  // Built from lib/index.ts by the `test-e2e` script, it loads the same native library
+  const wrapper = () => import("../dist/index.js");
+
+  test("spreads streams across the members and closes once all of them did", async (t) => {
+    const { connectBundle, Endpoint } = await wrapper();
+    const server = startServer(t, { response: "silent" });
+    // Makes `localhost` resolve to the IPv4 address the server listens on
+    const endpoint = new Endpoint();
+
+    const memberCloses = [];
+    const closes = [];
+    const bundle = await connectBundle(
+      {
+        hostname: "localhost",
+        port: server.port,
+        certificateAuthorities: [encoder.encode(server.certificate)],
+        endpoint,
+        onClose: (reason) => closes.push(reason),
+        onError: () => {},
+        onStream: () => {},
+      },
+      { connections: 3, onMemberClose: (connection) => memberCloses.push(connection) }
+    );
+    assert.equal(bundle.connections.length, 3);
+
+    for (let i = 0; i < 6; i++) {
+      await bundle.createStream({ onData: () => {}, onClose: () => {}, onError: () => {} });
+    }
+    assert.deepEqual(
+      bundle.connections.map((connection) => connection.openStreams),
+      [2, 2, 2]
+    );
+
+    const [first, ...rest] = bundle.connections;
+    await first.close();
+    await waitFor(() => memberCloses.length === 1);
+    assert.deepEqual(memberCloses, [first]);
+    assert.equal(bundle.connections.length, 2);
+    assert.deepEqual(closes, []);
+
+    await Promise.all(rest.map((connection) => connection.close()));
+    await waitFor(() => closes.length === 1);
+    assert.equal(memberCloses.length, 3);
+    assert.equal(closes.length, 1);
+    await assert.rejects(
+      bundle.createStream({ onData: () => {}, onClose: () => {}, onError: () => {} }),
+      isQuicError("ERR_CONNECTION_CLOSED")
+    );
+
+    endpoint.close();
+  });
+});
+
+describe("proxy", () => {
+  test("relays through a SOCKS5 proxy, leaving room for its header in every datagram", async (t) => {
+    const server = startServer(t);
+    const credentials = { username: "user", password: "secret" };
+    const socks = await startSocksProxy(t, credentials);
+    const proxy = { host: "127.0.0.1", port: socks.port, ...credentials };
+
+    const connection = await connect(server, {}, { proxy, mtu: { upperBound: 1400 } });
+    assert.equal(socks.associations, 1);
+
+    // MTU discovery probes up to the upper bound less the 10 bytes of an IPv4 header, and settles
+    // close to it on loopback
+    const data = new Uint8Array(256 * 1024);
+    const largest = () => lib.__test_server_largest_datagram(server.server);
+    for (let i = 0; i < 20 && largest() < 1370; i++) {
+      assert.equal((await request(connection, data)).length, data.length);
+    }
+    assert.ok(largest() >= 1370 && largest() <= 1390, `settled at ${largest()}`);
+    assert.equal(socks.largestDatagram, largest() + 10);
+    await lib.close_connection(connection, 0, new Uint8Array());
+
+    await assert.rejects(
+      connect(server, {}, { proxy: { ...proxy, password: "wrong" } }),
+      isQuicError("ERR_PROXY_AUTH")
+    );
+  });
+});
+
+describe("runtime", () => {
+  test("configures the runtime with init", linuxOnly, () => {
+    const script = `
+      const lib = require(${JSON.stringify(LIB)});
+      lib.init({ workerThreads: 2, threadName: "quic-e2e" });
+      lib.create_endpoint({});
+      try {
+        lib.init({});
+      } catch (error) {
+        console.log(error.code);
+      }
+      const { readdirSync, readFileSync } = require("node:fs");
+      const names = readdirSync("/proc/self/task").map((task) =>
+        readFileSync(\`/proc/self/task/\${task}/comm\`, "utf8").trim()
+      );
+      console.log(names.filter((name) => name === "quic-e2e").length);
+    `;
+    const output = execFileSync(process.execPath, ["-e", script], {
+      encoding: "utf8",
+    });
+
+    assert.deepEqual(output.trim().split("\n"), ["ERR_QUIC_INVALID_STATE", "2"]);
+  });
+
+  test("exits with only an unref'd connection open", async (t) => {
+    const { port, certificate } = startServer(t);
+
+    // The timer is unref'd as well, it only fires while the connection keeps the process alive
+    const run = (toggle) =>
+      execFileSync(
+        process.execPath,
+        [
+          "-e",
+          `
+            const lib = require(${JSON.stringify(LIB)});
+            const certificate = new TextEncoder().encode(${JSON.stringify(certificate)});
+            lib
+              .connect(${port}, "127.0.0.1", "localhost", () => {}, () => {}, () => {}, undefined, [certificate], undefined, {})
+              .then((connection) => {
+                ${toggle}
+                console.log("connected");
+                setTimeout(() => {
+                  console.log("kept alive");
+                  lib.close_connection(connection, 0, new Uint8Array());
+                }, 200).unref();
+              });
+          `,
+        ],
+        { encoding: "utf8", timeout: 10_000 }
+      )
+        .trim()
+        .split("\n");
+
+    assert.deepEqual(run("lib.unref_connection(connection);"), ["connected"]);
+    assert.deepEqual(
+      run("lib.unref_connection(connection); lib.ref_connection(connection);"),
+      ["connected", "kept alive"]
+    );
+  });
+
+  test("survives a worker terminated with an open connection", async (t) => {
+    const server = startServer(t);
+
+    const worker = new Worker(
+      `
+        const { parentPort, workerData } = require("node:worker_threads");
+        const lib = require(workerData.lib);
+        const { port, certificate } = workerData;
+        const promise = lib.connect(
+          port,
+          "127.0.0.1",
+          "localhost",
+          () => {},
+          () => {},
+          () => {},
+          undefined,
+          [new TextEncoder().encode(certificate)],
+          undefined,
+          {}
+        );
+        promise.then(() => parentPort.postMessage("connected"));
+      `,
+      {
+        eval: true,
+        workerData: {
+          lib: LIB,
+          port: server.port,
+          certificate: server.certificate,
+        },
+      }
+    );
+    await new Promise((resolve, reject) => {
+      worker.once("message", resolve);
+      worker.once("error", reject);
+    });
+
+    assert.equal(await worker.terminate(), 1);
+
+    const connection = await connect(server);
+    assert.equal(decoder.decode(await request(connection, encoder.encode("hi"))), "hi");
+    await lib.close_connection(connection, 0, new Uint8Array());
+  });
+});